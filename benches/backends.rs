@@ -0,0 +1,116 @@
+//! Benchmarks comparing the latency and throughput of the storage backends.
+//!
+//! Each backend is measured storing and retrieving values of several sizes,
+//! and listing the keys of a populated store. The persistent backend is the
+//! platform's `User` scope, a directory store on Linux and macOS and the
+//! registry on Windows, redirected to a temporary location for the run.
+//!
+//! Run with `cargo bench --features testing`, adding `rayon` to include the
+//! bulk operations.
+
+use std::hint::black_box;
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use zep_kvs::prelude::*;
+use zep_kvs::testing::TestScopeGuard;
+
+/// Value sizes to measure, from a small setting to a large blob.
+const VALUE_SIZES: [usize; 3] = [16, 1024, 64 * 1024];
+
+/// Number of keys in the store when listing keys.
+const KEY_COUNT: usize = 1000;
+
+/// Name of the persistent backend on this platform.
+const PERSISTENT: &str = if cfg!(feature = "ephemeral-scopes") {
+    "ephemeral-scopes"
+} else if cfg!(target_os = "windows") {
+    "registry"
+} else {
+    "directory"
+};
+
+fn value(size: usize) -> Vec<u8> {
+    (0..size).map(|i| i as u8).collect()
+}
+
+fn bench_store<S: Scope>(c: &mut Criterion, backend: &str) {
+    let _guard = TestScopeGuard::new();
+    let mut store = KeyValueStore::<S>::new().unwrap();
+    let mut group = c.benchmark_group("store");
+    for size in VALUE_SIZES {
+        let value = value(size);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new(backend, size), &value, |b, value| {
+            b.iter(|| store.store("key", black_box(value.as_slice())).unwrap());
+        });
+    }
+    group.finish();
+}
+
+fn bench_retrieve<S: Scope>(c: &mut Criterion, backend: &str) {
+    let _guard = TestScopeGuard::new();
+    let mut store = KeyValueStore::<S>::new().unwrap();
+    let mut group = c.benchmark_group("retrieve");
+    for size in VALUE_SIZES {
+        store.store("key", value(size).as_slice()).unwrap();
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_function(BenchmarkId::new(backend, size), |b| {
+            b.iter(|| store.retrieve::<_, Vec<u8>>(black_box("key")).unwrap());
+        });
+    }
+    group.finish();
+}
+
+fn bench_keys<S: Scope>(c: &mut Criterion, backend: &str) {
+    let _guard = TestScopeGuard::new();
+    let mut store = KeyValueStore::<S>::new().unwrap();
+    for i in 0..KEY_COUNT {
+        store.store(format!("key_{i}"), i as u32).unwrap();
+    }
+    let mut group = c.benchmark_group("keys");
+    group.throughput(Throughput::Elements(KEY_COUNT as u64));
+    group.bench_function(BenchmarkId::new(backend, KEY_COUNT), |b| {
+        b.iter(|| store.keys().unwrap());
+    });
+    group.finish();
+}
+
+/// Compares storing a batch of keys one at a time with the bulk operation.
+#[cfg(feature = "rayon")]
+fn bench_store_many<S: Scope>(c: &mut Criterion, backend: &str) {
+    const BATCH: usize = 100;
+
+    let _guard = TestScopeGuard::new();
+    let mut store = KeyValueStore::<S>::new().unwrap();
+    let entries: Vec<(String, u32)> = (0..BATCH).map(|i| (format!("key_{i}"), i as u32)).collect();
+    let mut group = c.benchmark_group("store_many");
+    group.throughput(Throughput::Elements(BATCH as u64));
+    group.bench_function(BenchmarkId::new(backend, "serial"), |b| {
+        b.iter(|| {
+            for (key, value) in &entries {
+                store.store(key, *value).unwrap();
+            }
+        });
+    });
+    group.bench_function(BenchmarkId::new(backend, "parallel"), |b| {
+        b.iter(|| store.par_store_many(entries.iter().cloned()).unwrap());
+    });
+    group.finish();
+}
+
+fn backends(c: &mut Criterion) {
+    bench_store::<scope::Ephemeral>(c, "ephemeral");
+    bench_store::<scope::User>(c, PERSISTENT);
+    bench_retrieve::<scope::Ephemeral>(c, "ephemeral");
+    bench_retrieve::<scope::User>(c, PERSISTENT);
+    bench_keys::<scope::Ephemeral>(c, "ephemeral");
+    bench_keys::<scope::User>(c, PERSISTENT);
+    #[cfg(feature = "rayon")]
+    {
+        bench_store_many::<scope::Ephemeral>(c, "ephemeral");
+        bench_store_many::<scope::User>(c, PERSISTENT);
+    }
+}
+
+criterion_group!(benches, backends);
+criterion_main!(benches);
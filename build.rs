@@ -1,8 +1,9 @@
 //! Build script for the Zep Key-Value Store library.
 //!
-//! This build script extracts the package name from Cargo.toml and makes it
-//! available as a compile-time environment variable. This allows the library
-//! to create storage directories and registry keys using the actual package name.
+//! This build script extracts the package name and version from Cargo.toml and
+//! makes them available as compile-time environment variables. This allows the
+//! library to create storage directories and registry keys using the actual
+//! package name, and to record which application version wrote a store.
 
 use cargo::core::SourceId;
 use cargo::sources::path::PathSource;
@@ -13,11 +14,17 @@ use std::env;
 ///
 /// This function:
 /// 1. Reads the current package information from Cargo.toml
-/// 2. Extracts the package name
-/// 3. Sets `ZEP_KVS_APP_NAME` as a compile-time environment variable
+/// 2. Extracts the package name and version
+/// 3. Sets `ZEP_KVS_APP_NAME` and `ZEP_KVS_APP_VERSION` as compile-time
+///    environment variables
 ///
 /// The `ZEP_KVS_APP_NAME` variable is used throughout the library to create
-/// platform-appropriate storage paths.
+/// platform-appropriate storage paths. `ZEP_KVS_APP_VERSION` is recorded in
+/// store metadata.
+///
+/// With the `ffi` feature enabled, it also generates the C header for the
+/// foreign function interface in `OUT_DIR`, and warns if the copy checked in
+/// as `include/zep_kvs.h` is out of date.
 fn main() {
     #[cfg(not(target_os = "windows"))]
     let pwd = std::path::PathBuf::from(env!("PWD"));
@@ -33,4 +40,42 @@ fn main() {
 
     // Make the package name available at compile time for storage path construction
     println!("cargo:rustc-env=ZEP_KVS_APP_NAME={}", pkg.name().as_str());
+    println!("cargo:rustc-env=ZEP_KVS_APP_VERSION={}", pkg.version());
+
+    #[cfg(feature = "ffi")]
+    generate_c_header();
+}
+
+/// Generates the C header for the `ffi` module using cbindgen.
+///
+/// The header is written to `OUT_DIR`, never into the source tree, which may
+/// be read-only or shared when the crate is a dependency.
+#[cfg(feature = "ffi")]
+fn generate_c_header() {
+    let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let generated = std::path::Path::new(&env::var("OUT_DIR").unwrap()).join("zep_kvs.h");
+
+    let config = cbindgen::Config {
+        language: cbindgen::Language::C,
+        include_guard: Some("ZEP_KVS_H".to_string()),
+        autogen_warning: Some(
+            "/* Generated by cbindgen from src/ffi.rs. Do not edit by hand. */".to_string(),
+        ),
+        cpp_compat: true,
+        ..Default::default()
+    };
+    cbindgen::Builder::new()
+        .with_src(std::path::Path::new(&crate_dir).join("src/ffi.rs"))
+        .with_config(config)
+        .generate()
+        .expect("unable to generate C header")
+        .write_to_file(&generated);
+
+    let checked_in = std::path::Path::new(&crate_dir).join("include/zep_kvs.h");
+    if std::fs::read(&generated).ok() != std::fs::read(&checked_in).ok() {
+        println!(
+            "cargo:warning=include/zep_kvs.h is out of date, regenerated header is at {}",
+            generated.display()
+        );
+    }
 }
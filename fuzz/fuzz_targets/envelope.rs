@@ -0,0 +1,50 @@
+//! Retrieves arbitrary stored bytes from a store with type tags enabled.
+//!
+//! Exercises the value envelope parser and the decoding behind it, which
+//! must reject malformed envelopes with `SerializationError`, envelopes of
+//! another type with `TypeMismatch`, and invalid values with a decoding
+//! error, never panic.
+
+#![no_main]
+
+use std::time::SystemTime;
+
+use libfuzzer_sys::fuzz_target;
+use zep_kvs::api::BackingStore;
+use zep_kvs::error::KvsError;
+use zep_kvs::prelude::*;
+
+/// Retrieves the value of `key` as each type, panicking on any error other
+/// than a decoding error or `TypeMismatch`.
+macro_rules! retrieve_as {
+    ($store:expr, $key:expr, $($t:ty),* $(,)?) => {
+        $(
+            match $store.retrieve::<_, $t>($key) {
+                Ok(_)
+                | Err(KvsError::SerializationError(_))
+                | Err(KvsError::StringDecodeError(_))
+                | Err(KvsError::TypeMismatch { .. }) => {}
+                Err(e) => panic!("{} returned {e:?}", stringify!($t)),
+            }
+        )*
+    };
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut backend = scope::Ephemeral::new().unwrap();
+    backend.store("value", data).unwrap();
+    let store = KeyValueStore::<scope::Ephemeral>::with_backing_store(backend)
+        .unwrap()
+        .with_type_tags();
+    retrieve_as!(
+        store,
+        "value",
+        String,
+        Vec<u8>,
+        u32,
+        i64,
+        f64,
+        char,
+        SystemTime
+    );
+});
@@ -0,0 +1,95 @@
+//! Feeds arbitrary bytes to every `InBytes` implementation.
+//!
+//! Stored data can be corrupted or written by another program, so decoding
+//! it must never panic, only fail with `SerializationError`, or
+//! `StringDecodeError` for text that isn't valid UTF-8.
+
+#![no_main]
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::num::{
+    NonZeroI8, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI128, NonZeroIsize, NonZeroU8,
+    NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU128, NonZeroUsize, Saturating, Wrapping,
+};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use libfuzzer_sys::fuzz_target;
+use zep_kvs::convert::Json;
+use zep_kvs::error::KvsError;
+use zep_kvs::prelude::*;
+use zep_kvs::sensitive::{Redacted, Sensitive};
+
+/// Decodes `bytes` as each type, panicking on any error other than a
+/// decoding error.
+macro_rules! decode_as {
+    ($bytes:expr, $($t:ty),* $(,)?) => {
+        $(
+            match <$t as InBytes>::in_bytes($bytes) {
+                Ok(_)
+                | Err(KvsError::SerializationError(_))
+                | Err(KvsError::StringDecodeError(_)) => {}
+                Err(e) => panic!("{} returned {e:?}", stringify!($t)),
+            }
+        )*
+    };
+}
+
+fuzz_target!(|data: &[u8]| {
+    decode_as!(
+        data,
+        String,
+        Vec<u8>,
+        bool,
+        char,
+        i8,
+        i16,
+        i32,
+        i64,
+        i128,
+        isize,
+        u8,
+        u16,
+        u32,
+        u64,
+        u128,
+        usize,
+        f32,
+        f64,
+        NonZeroI8,
+        NonZeroI16,
+        NonZeroI32,
+        NonZeroI64,
+        NonZeroI128,
+        NonZeroIsize,
+        NonZeroU8,
+        NonZeroU16,
+        NonZeroU32,
+        NonZeroU64,
+        NonZeroU128,
+        NonZeroUsize,
+        Wrapping<u32>,
+        Saturating<i64>,
+        [u8; 1],
+        [u8; 16],
+        [u8; 32],
+        [u8; 64],
+        Ipv4Addr,
+        Ipv6Addr,
+        IpAddr,
+        SocketAddr,
+        OsString,
+        PathBuf,
+        SystemTime,
+        chrono::DateTime<chrono::Utc>,
+        time::OffsetDateTime,
+        uuid::Uuid,
+        serde_json::Value,
+        Json<BTreeMap<String, Vec<u32>>>,
+        Sensitive<String>,
+        Sensitive<Vec<u8>>,
+        Redacted<u64>,
+    );
+});
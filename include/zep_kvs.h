@@ -0,0 +1,204 @@
+#ifndef ZEP_KVS_H
+#define ZEP_KVS_H
+
+/* Generated by cbindgen from src/ffi.rs. Do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Scope identifier for user-specific storage.
+ */
+#define ZEP_KVS_SCOPE_USER 0
+
+/**
+ * Scope identifier for system-wide storage.
+ */
+#define ZEP_KVS_SCOPE_MACHINE 1
+
+/**
+ * Scope identifier for in-memory storage.
+ */
+#define ZEP_KVS_SCOPE_EPHEMERAL 2
+
+/**
+ * The operation completed successfully.
+ */
+#define ZEP_KVS_OK 0
+
+/**
+ * The requested key does not exist.
+ */
+#define ZEP_KVS_NOT_FOUND 1
+
+/**
+ * A null pointer, unknown scope, or non UTF-8 key was passed.
+ */
+#define ZEP_KVS_ERR_INVALID_ARGUMENT -1
+
+/**
+ * The requested storage scope is not available.
+ */
+#define ZEP_KVS_ERR_NO_SCOPE -2
+
+/**
+ * The storage backend failed to read or write data.
+ */
+#define ZEP_KVS_ERR_IO -3
+
+/**
+ * Stored data could not be converted.
+ */
+#define ZEP_KVS_ERR_SERIALIZATION -4
+
+/**
+ * The store was written with a newer, unsupported format version.
+ */
+#define ZEP_KVS_ERR_UNSUPPORTED_FORMAT -5
+
+/**
+ * The write would take the store over its quota.
+ */
+#define ZEP_KVS_ERR_QUOTA_EXCEEDED -6
+
+/**
+ * The value is larger than the store accepts.
+ */
+#define ZEP_KVS_ERR_VALUE_TOO_LARGE -7
+
+/**
+ * Encrypted data could not be decrypted with the given passphrase or key,
+ * or an encrypted store was opened without one.
+ */
+#define ZEP_KVS_ERR_DECRYPTION_FAILED -8
+
+/**
+ * Data in an authenticated store failed verification, or a machine-bound
+ * value was stored on another machine.
+ */
+#define ZEP_KVS_ERR_INTEGRITY -9
+
+/**
+ * The disk holding the store is full.
+ */
+#define ZEP_KVS_ERR_STORAGE_FULL -10
+
+/**
+ * Opaque handle to an open key-value store.
+ */
+typedef struct ZepKvsStore ZepKvsStore;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Returns a description of the most recent error on the calling thread.
+ *
+ * Returns null if no error has occurred. The returned string remains valid
+ * until the next failing call on the same thread.
+ */
+const char *zep_kvs_last_error(void);
+
+/**
+ * Opens the store for the given scope.
+ *
+ * On success, `*out_store` receives a handle that must be released with
+ * `zep_kvs_close`.
+ *
+ * # Safety
+ *
+ * `out_store` must be a valid pointer to writable memory for a handle.
+ */
+int zep_kvs_open(int scope, struct ZepKvsStore **out_store);
+
+/**
+ * Closes a store handle. Passing null is a no-op.
+ *
+ * # Safety
+ *
+ * `store` must be null or a handle returned by `zep_kvs_open` that has not
+ * already been closed.
+ */
+void zep_kvs_close(struct ZepKvsStore *store);
+
+/**
+ * Stores `value_len` bytes from `value` under `key`.
+ *
+ * # Safety
+ *
+ * `store` must be a valid open handle, `key` a valid NUL-terminated string,
+ * and `value` must point to at least `value_len` readable bytes (it may be
+ * null only if `value_len` is zero).
+ */
+int zep_kvs_store(struct ZepKvsStore *store,
+                  const char *key,
+                  const uint8_t *value,
+                  uintptr_t value_len);
+
+/**
+ * Retrieves the value stored under `key`.
+ *
+ * On success, `*out_value` and `*out_len` receive a buffer that must be
+ * released with `zep_kvs_free_buffer`. Returns `ZEP_KVS_NOT_FOUND` and
+ * leaves the outputs untouched if the key does not exist.
+ *
+ * # Safety
+ *
+ * `store` must be a valid open handle, `key` a valid NUL-terminated string,
+ * and `out_value` and `out_len` valid pointers to writable memory.
+ */
+int zep_kvs_retrieve(const struct ZepKvsStore *store,
+                     const char *key,
+                     uint8_t **out_value,
+                     uintptr_t *out_len);
+
+/**
+ * Removes `key` and its value.
+ *
+ * # Safety
+ *
+ * `store` must be a valid open handle and `key` a valid NUL-terminated string.
+ */
+int zep_kvs_remove(struct ZepKvsStore *store, const char *key);
+
+/**
+ * Lists all keys in the store.
+ *
+ * On success, `*out_keys` receives an array of `*out_count` NUL-terminated
+ * strings that must be released with `zep_kvs_free_keys`.
+ *
+ * # Safety
+ *
+ * `store` must be a valid open handle and `out_keys` and `out_count` valid
+ * pointers to writable memory.
+ */
+int zep_kvs_keys(const struct ZepKvsStore *store, char ***out_keys, uintptr_t *out_count);
+
+/**
+ * Releases a buffer returned by `zep_kvs_retrieve`. Passing null is a no-op.
+ *
+ * # Safety
+ *
+ * `value` and `len` must be exactly as returned by `zep_kvs_retrieve`, and
+ * the buffer must not already have been released.
+ */
+void zep_kvs_free_buffer(uint8_t *value, uintptr_t len);
+
+/**
+ * Releases a key array returned by `zep_kvs_keys`. Passing null is a no-op.
+ *
+ * # Safety
+ *
+ * `keys` and `count` must be exactly as returned by `zep_kvs_keys`, and the
+ * array must not already have been released.
+ */
+void zep_kvs_free_keys(char **keys, uintptr_t count);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* ZEP_KVS_H */
@@ -0,0 +1,49 @@
+//! Runtime selection of a store's scope.
+//!
+//! Language bindings cannot use the generic `KeyValueStore` directly, so this
+//! module provides a store whose scope is chosen when it is opened.
+
+use crate::api::KeyValueStore;
+use crate::api::scope::{Ephemeral, Machine, User};
+use crate::error::KvsError;
+
+/// A key-value store of any of the built-in scopes.
+pub(crate) enum AnyStore {
+    User(KeyValueStore<User>),
+    Machine(KeyValueStore<Machine>),
+    Ephemeral(KeyValueStore<Ephemeral>),
+}
+
+impl AnyStore {
+    pub(crate) fn keys(&self) -> Result<Vec<String>, KvsError> {
+        match self {
+            AnyStore::User(store) => store.keys(),
+            AnyStore::Machine(store) => store.keys(),
+            AnyStore::Ephemeral(store) => store.keys(),
+        }
+    }
+
+    pub(crate) fn store(&mut self, key: &str, value: &[u8]) -> Result<(), KvsError> {
+        match self {
+            AnyStore::User(store) => store.store(key, value),
+            AnyStore::Machine(store) => store.store(key, value),
+            AnyStore::Ephemeral(store) => store.store(key, value),
+        }
+    }
+
+    pub(crate) fn retrieve(&self, key: &str) -> Result<Option<Vec<u8>>, KvsError> {
+        match self {
+            AnyStore::User(store) => store.retrieve(key),
+            AnyStore::Machine(store) => store.retrieve(key),
+            AnyStore::Ephemeral(store) => store.retrieve(key),
+        }
+    }
+
+    pub(crate) fn remove(&mut self, key: &str) -> Result<(), KvsError> {
+        match self {
+            AnyStore::User(store) => store.remove(key),
+            AnyStore::Machine(store) => store.remove(key),
+            AnyStore::Ephemeral(store) => store.remove(key),
+        }
+    }
+}
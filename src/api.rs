@@ -3,10 +3,89 @@
 //! This module provides the main interfaces for storing and retrieving data
 //! across different scopes (User, Machine, Ephemeral) on various platforms.
 
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::convert::AsRef;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
+use rand::random;
+
+use crate::clock::{Clock, SystemClock};
+use crate::codec::ValueCodec;
 use crate::convert::{InBytes, OutBytes};
-use crate::error::KvsError;
+#[cfg(feature = "encryption")]
+use crate::crypto::KdfParams;
+use crate::dynamic::DynBackingStore;
+#[cfg(feature = "encryption")]
+use crate::encrypted::{
+    Encrypted, EncryptedKeys, EncryptedStore, PartlyEncrypted, PartlyEncryptedStore, Protection,
+};
+use crate::envelope;
+use crate::ephemeral::EphemeralStore;
+use crate::error::{KvsError, Operation};
+#[cfg(feature = "integrity")]
+use crate::integrity::{Authenticated, Coverage, IntegrityStore};
+use crate::maintenance::Maintenance;
+use crate::manifest::KeyManifest;
+use crate::migrate::{Migrating, Migrations};
+use crate::retry::RetryPolicy;
+use crate::sensitive::wipe;
+use crate::slow::{SlowOperation, SlowOperations};
+use crate::watch::ChangeListener;
+
+/// The current version of the store format.
+///
+/// The format version is recorded in every store when it is first written
+/// after being opened. It is incremented whenever the way values are written changes incompatibly, so
+/// that stores written by older application versions can be recognised and
+/// upgraded. Stores written before format versioning was introduced are
+/// treated as version 0, which stores values exactly as version 1 does.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Prefix of keys reserved for the store's internal bookkeeping.
+///
+/// Keys with this prefix are hidden from [`KeyValueStore::keys`], and
+/// attempts to store or remove them fail with `KvsError::ReservedKey`.
+pub const RESERVED_PREFIX: &str = "__zep_";
+
+/// Key under which the format version of a store is recorded.
+const FORMAT_KEY: &str = "__zep_format";
+
+/// Key under which the time a store was first written is recorded.
+const CREATED_KEY: &str = "__zep_created";
+
+/// Key under which the version of the application that last wrote a store
+/// is recorded.
+const APP_VERSION_KEY: &str = "__zep_app_version";
+
+/// Key under which the application data version of a store is recorded.
+const DATA_VERSION_KEY: &str = "__zep_data_version";
+
+/// Key held while migrations run, recording when the lock was taken
+/// followed by 8 random bytes that tell holders apart.
+const MIGRATION_LOCK_KEY: &str = "__zep_migration_lock";
+
+/// Age after which a migration lock is taken to be left behind by a crash.
+const MIGRATION_LOCK_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Interval at which a held migration lock is checked again.
+const MIGRATION_LOCK_POLL: Duration = Duration::from_millis(50);
+
+/// Returns whether `key` is in the reserved namespace.
+pub(crate) fn is_reserved(key: &str) -> bool {
+    key.starts_with(RESERVED_PREFIX)
+}
+
+/// Fails with `ReservedKey` if `key` is in the reserved namespace.
+fn check_not_reserved(key: &str) -> Result<(), KvsError> {
+    if is_reserved(key) {
+        return Err(KvsError::ReservedKey(key.to_string()));
+    }
+    Ok(())
+}
 
 /// Defines a storage scope for key-value data.
 ///
@@ -48,6 +127,86 @@ pub mod scope {
     /// - macOS: `~/Library/Application Support`
     /// - Windows: `HKEY_CURRENT_USER\Software`
     pub struct User();
+
+    /// User-specific storage that lasts for the current login session.
+    ///
+    /// Data is discarded when the user logs out or the machine restarts,
+    /// which suits state such as the window positions of the current
+    /// session:
+    /// - Linux: `$XDG_RUNTIME_DIR`, usually the tmpfs at `/run/user/{uid}`
+    /// - macOS: a directory under `$TMPDIR` named after the boot time
+    /// - Windows: a volatile `Session` subkey of the `User` store's key
+    pub struct Session();
+}
+
+/// Limits on the contents of a store.
+///
+/// Both limits count only application keys, not the store's internal
+/// bookkeeping. Sizes include the key names as well as the stored values.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::api::Quota;
+///
+/// let quota = Quota {
+///     max_keys: Some(1000),
+///     max_bytes: Some(1024 * 1024),
+/// };
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quota {
+    /// The maximum number of keys, or `None` for no limit.
+    pub max_keys: Option<usize>,
+    /// The maximum total size of keys and values in bytes, or `None` for no limit.
+    pub max_bytes: Option<u64>,
+}
+
+/// The amount of a quota in use.
+#[derive(Debug, Clone, Copy, Default)]
+struct Usage {
+    keys: usize,
+    bytes: u64,
+}
+
+/// Returns the number of bytes an entry counts against a quota.
+fn entry_size(key: &str, value: &[u8]) -> u64 {
+    (key.len() + value.len()) as u64
+}
+
+/// Metadata describing a store's history.
+///
+/// Returned by [`KeyValueStore::info`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoreInfo {
+    /// When the store was first written, or `None` if it never has been.
+    ///
+    /// Stores last written before metadata was introduced report the time
+    /// of their first write since.
+    pub created: Option<SystemTime>,
+    /// The version of the application that last wrote the store, or `None`
+    /// if it never has been.
+    pub app_version: Option<String>,
+}
+
+/// The values of several keys, as returned by
+/// [`KeyValueStore::retrieve_many`].
+#[derive(Debug)]
+pub struct Retrieved<V> {
+    /// The values of the keys that exist.
+    pub found: BTreeMap<String, V>,
+    /// The keys that don't exist, in the order given.
+    pub missing: Vec<String>,
+    /// The keys whose values couldn't be converted, such as values stored
+    /// as another type, with the errors.
+    pub failed: BTreeMap<String, KvsError>,
+}
+
+impl<V> Retrieved<V> {
+    /// Returns whether every key was found and converted.
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty() && self.failed.is_empty()
+    }
 }
 
 /// A type-safe key-value store with configurable storage scope.
@@ -76,8 +235,85 @@ pub mod scope {
 /// store.remove("count")?;
 /// # Ok::<(), Box<dyn std::error::Error>>(())
 /// ```
+///
+/// # Configuration
+///
+/// A store is opened with [`new`](Self::new),
+/// [`new_with_format_upgrade`](Self::new_with_format_upgrade) or
+/// [`with_backing_store`](Self::with_backing_store), and then configured by
+/// chaining `with_` methods:
+///
+/// - [`with_type_tags`](Self::with_type_tags) records each value's type
+/// - [`with_quota`](Self::with_quota) limits the number and size of entries
+/// - [`with_max_value_size`](Self::with_max_value_size) limits single values
+/// - [`with_space_check`](Self::with_space_check) checks for free space
+///   before large writes
+/// - [`with_retry`](Self::with_retry) retries transient I/O errors
+/// - [`with_slow_operation_threshold`](Self::with_slow_operation_threshold)
+///   and [`with_slow_operation_handler`](Self::with_slow_operation_handler)
+///   report slow operations
+/// - [`with_clock`](Self::with_clock) replaces the clock used for timestamps
+/// - [`with_codec`](Self::with_codec) transforms values on their way to and
+///   from storage
+/// - [`with_manifest`](Self::with_manifest) declares the keys in use, so
+///   orphaned keys can be found
+///
+/// The storage location is chosen by the scope type `S`, and the application
+/// name under it is fixed at build time.
+///
+/// ```
+/// use zep_kvs::api::Quota;
+/// use zep_kvs::prelude::*;
+/// use zep_kvs::retry::RetryPolicy;
+///
+/// let store = KeyValueStore::<scope::Ephemeral>::new()?
+///     .with_type_tags()
+///     .with_max_value_size(4096)
+///     .with_retry(RetryPolicy::default())
+///     .with_quota(Quota {
+///         max_keys: Some(100),
+///         max_bytes: None,
+///     })?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
 pub struct KeyValueStore<S: Scope> {
     inner: S::Store,
+    type_tags: bool,
+    quota: Option<(Quota, Usage)>,
+    max_value_size: Option<usize>,
+    /// The size from which values are checked against the free space.
+    space_check: Option<usize>,
+    clock: Arc<dyn Clock>,
+    retry: Option<RetryPolicy>,
+    codecs: Vec<Arc<dyn ValueCodec>>,
+    slow: SlowOperations,
+    manifest: Option<Arc<KeyManifest>>,
+    /// Whether this instance has recorded its write metadata.
+    stamped: bool,
+    /// Whether the format version is still to be recorded by the next
+    /// write, so that stores are never written just by opening them.
+    format_pending: bool,
+}
+
+/// Shows the store's location and configuration, never its values.
+impl<S: Scope> fmt::Debug for KeyValueStore<S>
+where
+    S::Store: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyValueStore")
+            .field("scope", &std::any::type_name::<S>())
+            .field("store", &self.inner)
+            .field("type_tags", &self.type_tags)
+            .field("quota", &self.quota.map(|(quota, _)| quota))
+            .field("max_value_size", &self.max_value_size)
+            .field("space_check", &self.space_check)
+            .field("retry", &self.retry)
+            .field("codecs", &self.codecs.len())
+            .field("slow", &self.slow)
+            .field("manifest", &self.manifest)
+            .finish_non_exhaustive()
+    }
 }
 
 impl<S: Scope> KeyValueStore<S> {
@@ -97,85 +333,198 @@ impl<S: Scope> KeyValueStore<S> {
     /// # Ok::<(), Box<dyn std::error::Error>>(())
     /// ```
     pub fn new() -> Result<Self, KvsError> {
-        Ok(Self { inner: S::new()? })
+        Self::new_with_format_upgrade(|_, _, _| Ok(()))
     }
 
-    /// Returns all keys currently stored in this store.
+    /// Creates a new key-value store, running a hook if its format is outdated.
+    ///
+    /// When the store was last written with a format version older than
+    /// [`FORMAT_VERSION`], `on_format_upgrade` is called with the store and
+    /// the old and new format versions. The new version is recorded as soon
+    /// as the store is written, by the hook or afterwards, so the hook runs
+    /// again each time a store that is only read is opened. If the hook
+    /// fails, the old version is kept and the hook will run again the next
+    /// time the store is opened.
     ///
     /// # Errors
     ///
-    /// Returns an error if the storage backend cannot be accessed.
+    /// Returns an error if the storage backend cannot be initialized, if the
+    /// store was written by a newer, unsupported format version, or if the
+    /// upgrade hook fails.
     ///
     /// # Examples
     ///
     /// ```
     /// use zep_kvs::prelude::*;
     ///
-    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
-    /// store.store("key1", "value1")?;
-    /// store.store("key2", "value2")?;
-    ///
-    /// let keys = store.keys()?;
-    /// assert_eq!(keys.len(), 2);
-    /// assert!(keys.contains(&"key1".to_string()));
-    /// assert!(keys.contains(&"key2".to_string()));
+    /// let store = KeyValueStore::<scope::Ephemeral>::new_with_format_upgrade(
+    ///     |_store, from, to| {
+    ///         println!("Upgrading store format from {from} to {to}");
+    ///         Ok(())
+    ///     },
+    /// )?;
     /// # Ok::<(), Box<dyn std::error::Error>>(())
     /// ```
-    pub fn keys(&self) -> Result<Vec<String>, KvsError> {
-        self.inner.keys()
+    pub fn new_with_format_upgrade<F>(on_format_upgrade: F) -> Result<Self, KvsError>
+    where
+        F: FnOnce(&mut Self, u32, u32) -> Result<(), KvsError>,
+    {
+        Self::open(S::new()?, on_format_upgrade)
     }
 
-    /// Stores a value under the given key.
+    /// Creates a key-value store over an existing backing store instance.
     ///
-    /// If the key already exists, its value will be overwritten.
-    /// The value can be any type that implements `OutBytes`, including
-    /// strings, integers, and byte arrays.
+    /// This allows a preconfigured backing store, such as a scripted
+    /// `MockStore` from the `testing` module, to be used through the regular
+    /// `KeyValueStore` API.
     ///
-    /// # Arguments
+    /// # Errors
     ///
-    /// * `key` - The key to store the value under. Can be any type that
-    ///           converts to a string reference.
-    /// * `value` - The value to store. Must implement `OutBytes`.
+    /// Returns an error if the backing store cannot be accessed or was
+    /// written by a newer, unsupported format version.
+    pub fn with_backing_store(inner: S::Store) -> Result<Self, KvsError> {
+        Self::open(inner, |_, _, _| Ok(()))
+    }
+
+    /// Wraps a backing store, checking and recording its format version.
+    fn open<F>(inner: S::Store, on_format_upgrade: F) -> Result<Self, KvsError>
+    where
+        F: FnOnce(&mut Self, u32, u32) -> Result<(), KvsError>,
+    {
+        let mut store = Self {
+            inner,
+            type_tags: false,
+            quota: None,
+            max_value_size: None,
+            space_check: None,
+            clock: Arc::new(SystemClock),
+            retry: None,
+            codecs: Vec::new(),
+            slow: SlowOperations::default(),
+            manifest: None,
+            stamped: false,
+            format_pending: false,
+        };
+        let version = match store.inner.retrieve(FORMAT_KEY)? {
+            Some(bytes) => Some(u32::in_bytes(&bytes)?),
+            None if store.inner.keys()?.is_empty() => None,
+            None => Some(0),
+        };
+        match version {
+            Some(version) if version > FORMAT_VERSION => {
+                return Err(KvsError::UnsupportedFormat {
+                    found: version,
+                    supported: FORMAT_VERSION,
+                });
+            }
+            Some(FORMAT_VERSION) => {}
+            Some(version) => {
+                on_format_upgrade(&mut store, version, FORMAT_VERSION)?;
+                store.format_pending = true;
+                // Values the hook wrote are only valid under the new version
+                if store.stamped {
+                    store.record_format()?;
+                }
+            }
+            None => store.format_pending = true,
+        }
+        Ok(store)
+    }
+
+    /// Enables self-describing values tagged with their type.
     ///
-    /// # Errors
+    /// With type tags enabled, every stored value is prefixed with a small
+    /// header recording its type tag and length. Retrieving a value as a type
+    /// with a different tag fails with `KvsError::TypeMismatch` rather than an
+    /// obscure byte length error.
     ///
-    /// Returns an error if the value cannot be serialized or if the
-    /// storage backend fails to write the data.
+    /// Tagged and untagged values are not interchangeable, so a store should
+    /// consistently be opened either with or without type tags.
     ///
     /// # Examples
     ///
     /// ```
+    /// use zep_kvs::error::KvsError;
     /// use zep_kvs::prelude::*;
     ///
-    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?.with_type_tags();
+    /// store.store("name", "alice")?;
     ///
-    /// // Store different types
-    /// store.store("name", "Alice")?;
-    /// store.store("age", 30u32)?;
-    /// store.store("data", vec![1u8, 2u8, 3u8].as_slice())?;
+    /// let result = store.retrieve::<_, u32>("name");
+    /// assert!(matches!(result, Err(KvsError::TypeMismatch { .. })));
     /// # Ok::<(), Box<dyn std::error::Error>>(())
     /// ```
-    pub fn store<K: AsRef<str>, V: OutBytes>(&mut self, key: K, value: V) -> Result<(), KvsError> {
-        self.inner.store(key.as_ref(), &value.out_bytes()?)
+    pub fn with_type_tags(mut self) -> Self {
+        self.type_tags = true;
+        self
     }
 
-    /// Retrieves a value by key, if it exists.
+    /// Enforces a quota on the contents of the store.
     ///
-    /// Returns `None` if the key is not found. The return type must be
-    /// specified and implement `InBytes` for deserialization.
+    /// Writes that would take the store over either limit fail with
+    /// `KvsError::QuotaExceeded` and leave the store unchanged. Overwriting
+    /// or removing keys frees their share of the quota. A store that is
+    /// already over its quota can still shrink, but cannot grow.
     ///
-    /// # Arguments
+    /// The current usage is measured when the quota is applied and then
+    /// tracked as this instance writes, so changes made concurrently by other
+    /// processes are not accounted for until the store is reopened.
     ///
-    /// * `key` - The key to look up. Can be any type that converts to a string reference.
+    /// # Errors
     ///
-    /// # Type Parameters
+    /// Returns an error if the existing contents cannot be read to measure
+    /// the current usage.
     ///
-    /// * `V` - The expected type of the stored value. Must implement `InBytes`.
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::api::Quota;
+    /// use zep_kvs::error::KvsError;
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let quota = Quota {
+    ///     max_keys: Some(1),
+    ///     ..Quota::default()
+    /// };
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?.with_quota(quota)?;
+    /// store.store("first", "value")?;
+    ///
+    /// let result = store.store("second", "value");
+    /// assert!(matches!(result, Err(KvsError::QuotaExceeded(_))));
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn with_quota(mut self, quota: Quota) -> Result<Self, KvsError> {
+        self.quota = Some((quota, self.measure_usage()?));
+        Ok(self)
+    }
+
+    /// Measures how much of a quota the store's contents use.
+    fn measure_usage(&self) -> Result<Usage, KvsError> {
+        let mut usage = Usage::default();
+        for key in self.keys()? {
+            if let Some(value) = self.inner.retrieve(&key)? {
+                usage.keys += 1;
+                usage.bytes += entry_size(&key, &value);
+            }
+        }
+        Ok(usage)
+    }
+
+    /// Replaces the contents of another store with a copy of this one.
+    ///
+    /// Every entry is copied as stored, together with the store's metadata
+    /// such as its creation time and data version, so the copy is
+    /// indistinguishable from the original. Entries already in `target` are
+    /// removed first. This is the building block for exporting a profile or
+    /// rehearsing an upgrade on a copy of real data.
+    ///
+    /// Quota and value size limits on `target` are not enforced for the
+    /// copied entries.
     ///
     /// # Errors
     ///
-    /// Returns an error if the storage backend fails to read the data
-    /// or if the stored data cannot be deserialized to the requested type.
+    /// Returns an error if either store cannot be accessed. The target may
+    /// then hold a partial copy.
     ///
     /// # Examples
     ///
@@ -183,99 +532,2428 @@ impl<S: Scope> KeyValueStore<S> {
     /// use zep_kvs::prelude::*;
     ///
     /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
-    /// store.store("count", 42u32)?;
+    /// store.store("theme", "dark")?;
     ///
-    /// // Retrieve with explicit type annotation
-    /// let count: u32 = store.retrieve("count")?.unwrap();
-    /// assert_eq!(count, 42);
+    /// let mut rehearsal = KeyValueStore::<scope::Ephemeral>::new()?;
+    /// store.clone_to(&mut rehearsal)?;
+    /// assert_eq!(rehearsal.retrieve("theme")?, Some(String::from("dark")));
+    /// assert_eq!(rehearsal.info()?, store.info()?);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn clone_to<T: Scope>(&self, target: &mut KeyValueStore<T>) -> Result<(), KvsError> {
+        target.record_format()?;
+        for key in target.inner.keys()? {
+            if key != FORMAT_KEY {
+                target.inner.remove(&key)?;
+            }
+        }
+        for key in self.inner.keys()? {
+            if key == FORMAT_KEY {
+                continue;
+            }
+            if let Some(value) = self.inner.retrieve(&key)? {
+                target.inner.store(&key, &value)?;
+            }
+        }
+        if let Some((quota, _)) = target.quota {
+            target.quota = Some((quota, target.measure_usage()?));
+        }
+        Ok(())
+    }
+
+    /// Limits the size of individual values.
     ///
-    /// // Check for non-existent key
-    /// let missing: Option<String> = store.retrieve("missing")?;
-    /// assert!(missing.is_none());
+    /// Storing a value whose serialized size exceeds `limit` bytes fails with
+    /// `KvsError::ValueTooLarge` before anything is written to the backing
+    /// store. On stores with type tags enabled, the size includes the type
+    /// tag header.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::error::KvsError;
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?.with_max_value_size(4);
+    /// store.store("small", 42u32)?;
+    ///
+    /// let result = store.store("large", 42u64);
+    /// assert!(matches!(result, Err(KvsError::ValueTooLarge { size: 8, limit: 4 })));
     /// # Ok::<(), Box<dyn std::error::Error>>(())
     /// ```
-    pub fn retrieve<K: AsRef<str>, V: InBytes>(&self, key: K) -> Result<Option<V>, KvsError> {
-        Ok(match self.inner.retrieve(key.as_ref())? {
-            Some(data) => Some(V::in_bytes(&data)?),
-            None => None,
-        })
+    pub fn with_max_value_size(mut self, limit: usize) -> Self {
+        self.max_value_size = Some(limit);
+        self
     }
 
-    /// Removes a key and its associated value from the store.
+    /// Checks for free space before writing values of at least `min_size`
+    /// bytes.
     ///
-    /// Does nothing if the key doesn't exist.
+    /// Writes that find less free space at the store's location than their
+    /// values need fail with [`KvsError::StorageFull`] before anything is
+    /// written, rather than leaving the disk full. Small values are written
+    /// without a check, to avoid querying the file system for each write.
+    /// Stores whose free space isn't known, such as registry-backed and
+    /// ephemeral stores, are never checked.
     ///
-    /// # Arguments
+    /// Writes that run out of space regardless fail with
+    /// [`KvsError::StorageFull`] too.
+    pub fn with_space_check(mut self, min_size: usize) -> Self {
+        self.space_check = Some(min_size);
+        self
+    }
+
+    /// Retries operations that fail with transient I/O errors.
     ///
-    /// * `key` - The key to remove. Can be any type that converts to a string reference.
+    /// Reads, writes, removals and renames that fail with errors such as
+    /// interrupted system calls or, on Windows, sharing violations caused by
+    /// antivirus and indexing services are retried according to `policy`.
+    /// Other errors are returned immediately.
     ///
-    /// # Errors
+    /// Retries sleep on the calling thread.
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Sets how long an operation may take before it is reported as slow.
     ///
-    /// Returns an error if the storage backend fails to remove the key.
+    /// Slow operations are logged as warnings, with the operation, key and
+    /// duration, when the `log` feature is enabled, and passed to the
+    /// handler set with
+    /// [`with_slow_operation_handler`](Self::with_slow_operation_handler).
+    /// Defaults to 100 ms; `Duration::MAX` turns reporting off.
+    pub fn with_slow_operation_threshold(mut self, threshold: Duration) -> Self {
+        self.slow.threshold = threshold;
+        self
+    }
+
+    /// Calls `handler` for each operation that takes longer than the slow
+    /// operation threshold.
+    ///
+    /// The handler runs on the calling thread once the operation has
+    /// finished, whether or not it succeeded.
     ///
     /// # Examples
     ///
     /// ```
+    /// use std::time::Duration;
     /// use zep_kvs::prelude::*;
     ///
-    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
-    /// store.store("temp", "value")?;
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?
+    ///     .with_slow_operation_threshold(Duration::from_millis(500))
+    ///     .with_slow_operation_handler(|slow| {
+    ///         eprintln!("{} {:?} took {:?}", slow.operation, slow.key, slow.duration);
+    ///     });
+    /// store.store("theme", "dark")?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn with_slow_operation_handler(
+        mut self,
+        handler: impl Fn(&SlowOperation) + Send + Sync + 'static,
+    ) -> Self {
+        self.slow.set_handler(handler);
+        self
+    }
+
+    /// Sets the clock used for time-dependent features such as metadata.
     ///
-    /// assert!(store.retrieve::<_, String>("temp")?.is_some());
-    /// store.remove("temp")?;
-    /// assert!(store.retrieve::<_, String>("temp")?.is_none());
+    /// Defaults to the system clock. Tests can substitute a
+    /// `testing::MockClock` to control the recorded times.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Adds a stage to the pipeline that transforms values on their way to
+    /// and from storage.
+    ///
+    /// Stages run in the order they were added when values are stored, and
+    /// in reverse when they are retrieved. See the [`codec`](crate::codec)
+    /// module for the built-in stages.
+    ///
+    /// The store's quota and value size limit apply to the transformed
+    /// values. Snapshots, exports and journals hold transformed values, so
+    /// read them through a store with the same pipeline.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::codec::Checksum;
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?.with_codec(Checksum);
+    /// store.store("theme", "dark")?;
     /// # Ok::<(), Box<dyn std::error::Error>>(())
     /// ```
-    pub fn remove<K: AsRef<str>>(&mut self, key: K) -> Result<(), KvsError> {
-        self.inner.remove(key.as_ref())
+    pub fn with_codec(mut self, codec: impl ValueCodec + 'static) -> Self {
+        self.codecs.push(Arc::new(codec));
+        self
     }
-}
 
-/// Low-level interface for key-value storage backends.
-///
-/// This trait is implemented by platform-specific storage mechanisms
-/// and handles the actual persistence of data. Most users should use
-/// `KeyValueStore` instead of implementing this trait directly.
-pub trait BackingStore {
-    /// Returns all keys currently stored.
+    /// Declares the keys the application uses, so that keys left behind by
+    /// older versions can be found with [`orphans`](Self::orphans) and
+    /// removed with [`prune_orphans`](Self::prune_orphans).
+    pub fn with_manifest(mut self, manifest: KeyManifest) -> Self {
+        self.manifest = Some(Arc::new(manifest));
+        self
+    }
+
+    /// Configures this store to read values written by `other`, with the
+    /// same type tag setting and codec pipeline.
+    pub(crate) fn with_value_format_of<T: Scope>(mut self, other: &KeyValueStore<T>) -> Self {
+        self.type_tags = other.type_tags;
+        self.codecs = other.codecs.clone();
+        self
+    }
+
+    /// Returns metadata describing the store's history.
+    ///
+    /// The creation time and application version are recorded by the first
+    /// write each time a store is opened, using the version from the
+    /// application's `Cargo.toml`. Call this before writing to learn about
+    /// previous runs: a store that has never been written has no creation
+    /// time, and an application version other than the running one indicates
+    /// an upgrade or a downgrade.
     ///
     /// # Errors
     ///
-    /// Returns an error if the storage backend cannot be accessed.
-    fn keys(&self) -> Result<Vec<String>, KvsError>;
-
-    /// Stores raw bytes under the given key.
+    /// Returns an error if the storage backend cannot be accessed or the
+    /// metadata cannot be decoded.
     ///
-    /// # Arguments
+    /// # Examples
     ///
-    /// * `key` - The key to store the data under
-    /// * `value` - The raw bytes to store
+    /// ```
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+    /// if store.info()?.created.is_none() {
+    ///     println!("First run");
+    /// }
+    /// store.store("seen", true)?;
+    /// assert!(store.info()?.created.is_some());
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn info(&self) -> Result<StoreInfo, KvsError> {
+        Ok(StoreInfo {
+            created: self.retrieve_internal(CREATED_KEY)?,
+            app_version: self.retrieve_internal(APP_VERSION_KEY)?,
+        })
+    }
+
+    /// Returns the application data version of the store.
+    ///
+    /// This is the version of the last migration applied by
+    /// [`migrate_to`](Self::migrate_to), or 0 if none has been.
     ///
     /// # Errors
     ///
-    /// Returns an error if the storage backend fails to write the data.
-    fn store(&mut self, key: &str, value: &[u8]) -> Result<(), KvsError>;
+    /// Returns an error if the storage backend cannot be accessed or the
+    /// recorded version cannot be decoded.
+    pub fn data_version(&self) -> Result<u32, KvsError> {
+        Ok(self.retrieve_internal(DATA_VERSION_KEY)?.unwrap_or(0))
+    }
 
-    /// Retrieves raw bytes by key, if the key exists.
+    /// Runs pending migrations to bring the store to data version `target`.
     ///
-    /// # Arguments
+    /// Each migration registered for a version above the store's current
+    /// data version, up to and including `target`, runs in version order.
+    /// The version is recorded after each migration succeeds, so if one
+    /// fails, the migrations before it are not repeated next time. A
+    /// migration is not transactional: if it fails part way through, its
+    /// partial changes remain, so migrations should be written to be safely
+    /// re-run.
     ///
-    /// * `key` - The key to look up
+    /// Migrations run under a lock kept in the store, so instances in other
+    /// processes wait for them to finish and then find the store migrated.
+    /// If a process crashes while migrating, its lock is left behind and
+    /// other instances wait until it is ten minutes old before taking it
+    /// over. The migration that was interrupted then runs again.
     ///
-    /// # Returns
+    /// Returns the store's data version afterwards. This is greater than
+    /// `target` if the store was migrated by a newer application version,
+    /// in which case nothing is run.
     ///
-    /// Returns `Ok(Some(bytes))` if the key exists, `Ok(None)` if it doesn't,
-    /// or an error if the storage backend fails.
-    fn retrieve(&self, key: &str) -> Result<Option<Vec<u8>>, KvsError>;
+    /// # Errors
+    ///
+    /// Returns the first error from a migration, or an error if the
+    /// storage backend cannot be accessed.
+    pub fn migrate_to(&mut self, target: u32, migrations: &Migrations<S>) -> Result<u32, KvsError> {
+        let lock = self.lock_migrations()?;
+        let result = self.run_migrations(target, migrations);
+        let unlocked = self.inner.remove_if(MIGRATION_LOCK_KEY, &lock);
+        let version = result?;
+        unlocked?;
+        Ok(version)
+    }
 
-    /// Removes a key and its associated data.
+    /// Takes the migration lock, waiting while another instance holds it.
     ///
-    /// # Arguments
+    /// Returns the value stored under the lock key, which releases the lock
+    /// with `remove_if`.
+    fn lock_migrations(&mut self) -> Result<Vec<u8>, KvsError> {
+        let mut lock = self.clock.now().out_bytes()?.into_owned();
+        lock.extend_from_slice(&random::<u64>().to_be_bytes());
+        while !self.inner.store_if_absent(MIGRATION_LOCK_KEY, &lock)? {
+            let Some(held) = self.inner.retrieve(MIGRATION_LOCK_KEY)? else {
+                continue;
+            };
+            let taken = held
+                .len()
+                .checked_sub(8)
+                .and_then(|len| SystemTime::in_bytes(&held[..len]).ok());
+            let abandoned = taken.is_none_or(|taken| {
+                self.clock
+                    .now()
+                    .duration_since(taken)
+                    .is_ok_and(|age| age >= MIGRATION_LOCK_TIMEOUT)
+            });
+            if abandoned {
+                warn!("taking over a migration lock left behind by a crash");
+                self.inner.remove_if(MIGRATION_LOCK_KEY, &held)?;
+            } else {
+                std::thread::sleep(MIGRATION_LOCK_POLL);
+            }
+        }
+        Ok(lock)
+    }
+
+    /// Runs the pending migrations with the migration lock held.
+    fn run_migrations(&mut self, target: u32, migrations: &Migrations<S>) -> Result<u32, KvsError> {
+        let mut version = self.data_version()?;
+        for (next, migration) in migrations.pending(version, target) {
+            migration(self)?;
+            self.stamp()?;
+            self.inner.store(DATA_VERSION_KEY, &next.out_bytes()?)?;
+            version = next;
+        }
+        if version < target {
+            self.stamp()?;
+            self.inner.store(DATA_VERSION_KEY, &target.out_bytes()?)?;
+            version = target;
+        }
+        Ok(version)
+    }
+
+    /// Records the application data version without running migrations.
+    #[cfg(feature = "archive")]
+    pub(crate) fn set_data_version(&mut self, version: u32) -> Result<(), KvsError> {
+        self.stamp()?;
+        self.inner.store(DATA_VERSION_KEY, &version.out_bytes()?)
+    }
+
+    /// Returns the keys declared with `with_manifest`, if any.
+    pub(crate) fn manifest(&self) -> Option<&KeyManifest> {
+        self.manifest.as_deref()
+    }
+
+    /// Returns the backing store.
+    pub(crate) fn backing_store(&self) -> &S::Store {
+        &self.inner
+    }
+
+    /// Returns the backing store.
+    pub(crate) fn backing_store_mut(&mut self) -> &mut S::Store {
+        &mut self.inner
+    }
+
+    /// Retrieves the bytes stored under `key`, exactly as written to the
+    /// backing store.
+    pub(crate) fn retrieve_raw(&self, key: &str) -> Result<Option<Vec<u8>>, KvsError> {
+        self.inner.retrieve(key)
+    }
+
+    /// Retrieves a value from the reserved namespace.
+    fn retrieve_internal<V: InBytes>(&self, key: &str) -> Result<Option<V>, KvsError> {
+        self.inner
+            .retrieve(key)?
+            .map(|bytes| V::in_bytes(&bytes))
+            .transpose()
+    }
+
+    /// Records the format version if this instance hasn't yet.
+    fn record_format(&mut self) -> Result<(), KvsError> {
+        if self.format_pending {
+            self.inner.store(FORMAT_KEY, &FORMAT_VERSION.out_bytes()?)?;
+            self.format_pending = false;
+        }
+        Ok(())
+    }
+
+    /// Records the creation time, if missing, and the application version
+    /// before this instance first writes to the store.
+    fn stamp(&mut self) -> Result<(), KvsError> {
+        if self.stamped {
+            return Ok(());
+        }
+        self.record_format()?;
+        if self.inner.retrieve(CREATED_KEY)?.is_none() {
+            self.inner
+                .store(CREATED_KEY, &self.clock.now().out_bytes()?)?;
+        }
+        let version = env!("ZEP_KVS_APP_VERSION");
+        if self
+            .retrieve_internal::<String>(APP_VERSION_KEY)?
+            .as_deref()
+            != Some(version)
+        {
+            self.inner.store(APP_VERSION_KEY, version.as_bytes())?;
+        }
+        self.stamped = true;
+        Ok(())
+    }
+
+    /// Returns the quota usage after applying `changes`, each replacing the
+    /// value of a key with a new value or removing it, or `None` if the
+    /// store has no quota.
     ///
-    /// * `key` - The key to remove
+    /// # Errors
+    ///
+    /// Returns `QuotaExceeded` if the changes would grow the store beyond
+    /// its quota.
+    fn usage_after(&self, changes: &[(&str, Option<&[u8]>)]) -> Result<Option<Usage>, KvsError> {
+        let Some((quota, mut usage)) = self.quota else {
+            return Ok(None);
+        };
+        let before = usage;
+        for (key, value) in changes {
+            if let Some(old) = self.inner.retrieve(key)? {
+                // Usage can be stale if another process wrote to the store
+                usage.keys = usage.keys.saturating_sub(1);
+                usage.bytes = usage.bytes.saturating_sub(entry_size(key, &old));
+            }
+            if let Some(value) = value {
+                usage.keys += 1;
+                usage.bytes += entry_size(key, value);
+            }
+        }
+        if let Some(max_keys) = quota.max_keys
+            && usage.keys > max_keys
+            && usage.keys > before.keys
+        {
+            return Err(KvsError::QuotaExceeded(format!(
+                "store would hold {} keys, limit is {max_keys}",
+                usage.keys
+            )));
+        }
+        if let Some(max_bytes) = quota.max_bytes
+            && usage.bytes > max_bytes
+            && usage.bytes > before.bytes
+        {
+            return Err(KvsError::QuotaExceeded(format!(
+                "store would hold {} bytes, limit is {max_bytes}",
+                usage.bytes
+            )));
+        }
+        Ok(Some(usage))
+    }
+
+    /// Records the quota usage computed by `usage_after` once a write succeeds.
+    fn set_usage(&mut self, usage: Option<Usage>) {
+        if let (Some((_, current)), Some(usage)) = (&mut self.quota, usage) {
+            *current = usage;
+        }
+    }
+
+    /// Returns all keys currently stored in this store.
+    ///
+    /// Keys in the reserved namespace (see [`RESERVED_PREFIX`]) are not
+    /// included.
     ///
     /// # Errors
     ///
-    /// Returns an error if the storage backend fails to remove the key.
-    fn remove(&mut self, key: &str) -> Result<(), KvsError>;
+    /// Returns an error if the storage backend cannot be accessed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+    /// store.store("key1", "value1")?;
+    /// store.store("key2", "value2")?;
+    ///
+    /// let keys = store.keys()?;
+    /// assert_eq!(keys.len(), 2);
+    /// assert!(keys.contains(&"key1".to_string()));
+    /// assert!(keys.contains(&"key2".to_string()));
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn keys(&self) -> Result<Vec<String>, KvsError> {
+        let mut keys = run_operation(self.retry, &self.slow, Operation::Keys, None, || {
+            self.inner.keys()
+        })?;
+        keys.retain(|k| !is_reserved(k));
+        Ok(keys)
+    }
+
+    /// Lists the keys starting with `prefix`.
+    ///
+    /// Like [`keys`](Self::keys), keys in the reserved namespace are not
+    /// listed. Backends that keep keys ordered, and stores opened with the
+    /// [`Indexed`](crate::index::Indexed) scope, answer without listing
+    /// every key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend cannot be accessed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+    /// store.store("cache/a", "1")?;
+    /// store.store("cache/b", "2")?;
+    /// store.store("config", "3")?;
+    ///
+    /// assert_eq!(store.keys_with_prefix("cache/")?, ["cache/a", "cache/b"]);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn keys_with_prefix<P: AsRef<str>>(&self, prefix: P) -> Result<Vec<String>, KvsError> {
+        let mut keys = run_operation(self.retry, &self.slow, Operation::Keys, None, || {
+            self.inner.keys_with_prefix(prefix.as_ref())
+        })?;
+        keys.retain(|k| !is_reserved(k));
+        Ok(keys)
+    }
+
+    /// Returns whether a key exists, without retrieving its value.
+    ///
+    /// Keys in the reserved namespace are never reported as existing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend cannot be accessed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+    /// store.store("theme", "dark")?;
+    ///
+    /// assert!(store.contains_key("theme")?);
+    /// assert!(!store.contains_key("missing")?);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn contains_key<K: AsRef<str>>(&self, key: K) -> Result<bool, KvsError> {
+        if is_reserved(key.as_ref()) {
+            return Ok(false);
+        }
+        run_operation(
+            self.retry,
+            &self.slow,
+            Operation::Retrieve,
+            Some(key.as_ref()),
+            || self.inner.contains(key.as_ref()),
+        )
+    }
+
+    /// Returns whether each of several keys exists, in the order given.
+    ///
+    /// Backends that keep keys in directories or the registry list them in
+    /// one pass for large batches, instead of checking each key, which
+    /// suits sync tools deciding which of thousands of items to upload.
+    /// Keys in the reserved namespace are never reported as existing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend cannot be accessed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+    /// store.store("a", "1")?;
+    ///
+    /// assert_eq!(store.contains_many(["a", "b"])?, [true, false]);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn contains_many<K, I>(&self, keys: I) -> Result<Vec<bool>, KvsError>
+    where
+        I: IntoIterator<Item = K>,
+        K: AsRef<str>,
+    {
+        let keys: Vec<K> = keys.into_iter().collect();
+        let keys: Vec<&str> = keys.iter().map(AsRef::as_ref).collect();
+        let mut found = run_operation(self.retry, &self.slow, Operation::Retrieve, None, || {
+            self.inner.contains_many(&keys)
+        })?;
+        for (key, found) in keys.iter().zip(&mut found) {
+            *found &= !is_reserved(key);
+        }
+        Ok(found)
+    }
+
+    /// Stores a value under the given key.
+    ///
+    /// If the key already exists, its value will be overwritten.
+    /// The value can be any type that implements `OutBytes`, including
+    /// strings, integers, and byte arrays.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key to store the value under. Can be any type that
+    ///   converts to a string reference.
+    /// * `value` - The value to store. Must implement `OutBytes`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value cannot be serialized or if the
+    /// storage backend fails to write the data. On stores with a quota,
+    /// returns `QuotaExceeded` if the write would exceed it, and on stores
+    /// with a maximum value size, returns `ValueTooLarge` if the value
+    /// exceeds it. Returns `ReservedKey` if the key starts with
+    /// [`RESERVED_PREFIX`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+    ///
+    /// // Store different types
+    /// store.store("name", "Alice")?;
+    /// store.store("age", 30u32)?;
+    /// store.store("data", vec![1u8, 2u8, 3u8].as_slice())?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn store<K: AsRef<str>, V: OutBytes>(&mut self, key: K, value: V) -> Result<(), KvsError> {
+        let mut bytes = self.encode(key.as_ref(), &value)?;
+        let result = self.store_raw(key.as_ref(), &bytes);
+        if let (Cow::Owned(bytes), true) = (&mut bytes, V::is_sensitive()) {
+            wipe(bytes);
+        }
+        result
+    }
+
+    /// Stores a value under the given key only if the key doesn't exist,
+    /// returning whether it was stored.
+    ///
+    /// When several processes race to initialize the same key, exactly one
+    /// of them stores its value; the others get `false` and can read the
+    /// winner's value. Stores kept in directories are atomic across
+    /// processes; the Windows registry has no such primitive, so there it is
+    /// only atomic within a process.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`store`](Self::store).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+    /// assert!(store.store_if_absent("installation_id", "a1b2")?);
+    /// assert!(!store.store_if_absent("installation_id", "c3d4")?);
+    /// assert_eq!(store.retrieve::<_, String>("installation_id")?.as_deref(), Some("a1b2"));
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn store_if_absent<K: AsRef<str>, V: OutBytes>(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> Result<bool, KvsError> {
+        let key = key.as_ref();
+        let mut bytes = self.encode(key, &value)?;
+        let result = self.store_raw_if_absent(key, &bytes);
+        if let (Cow::Owned(bytes), true) = (&mut bytes, V::is_sensitive()) {
+            wipe(bytes);
+        }
+        result
+    }
+
+    /// Fails with `StorageFull` if a write of `size` bytes is large enough
+    /// to be checked and there isn't room for it.
+    fn check_space(&self, key: Option<&str>, size: usize) -> Result<(), KvsError> {
+        if self.space_check.is_none_or(|min_size| size < min_size) {
+            return Ok(());
+        }
+        let free = self.inner.free_space()?;
+        if free.is_some_and(|free| free < size as u64) {
+            return Err(KvsError::StorageFull {
+                path: self
+                    .inner
+                    .location()
+                    .map(Path::to_path_buf)
+                    .unwrap_or_default(),
+                source: None,
+                operation: Some(Operation::Store),
+                key: key.map(String::from),
+            });
+        }
+        Ok(())
+    }
+
+    /// Stores bytes under `key` exactly as given, if the key doesn't exist,
+    /// subject to the store's limits.
+    fn store_raw_if_absent(&mut self, key: &str, bytes: &[u8]) -> Result<bool, KvsError> {
+        check_not_reserved(key)?;
+        if let Some(limit) = self.max_value_size
+            && bytes.len() > limit
+        {
+            return Err(KvsError::ValueTooLarge {
+                size: bytes.len(),
+                limit,
+            });
+        }
+        let usage = self.usage_after(&[(key, Some(bytes))])?;
+        self.check_space(Some(key), bytes.len())?;
+        self.stamp()?;
+        let stored = run_operation(self.retry, &self.slow, Operation::Store, Some(key), || {
+            self.inner.store_if_absent(key, bytes)
+        })?;
+        if stored {
+            self.set_usage(usage);
+        }
+        Ok(stored)
+    }
+
+    /// Converts a value to the bytes written to the backing store under
+    /// `key`.
+    pub(crate) fn encode<'a, V: OutBytes>(
+        &self,
+        key: &str,
+        value: &'a V,
+    ) -> Result<Cow<'a, [u8]>, KvsError> {
+        let mut bytes = self.tagged(value)?;
+        for codec in &self.codecs {
+            let coded = codec.encode(key, &bytes);
+            if let (Cow::Owned(bytes), true) = (&mut bytes, V::is_sensitive()) {
+                wipe(bytes);
+            }
+            bytes = coded?.into();
+        }
+        Ok(bytes)
+    }
+
+    /// Serializes a value, wrapped with its type tag if the store has type
+    /// tags.
+    fn tagged<'a, V: OutBytes>(&self, value: &'a V) -> Result<Cow<'a, [u8]>, KvsError> {
+        let mut bytes = value.out_bytes()?;
+        if !self.type_tags {
+            return Ok(bytes);
+        }
+        let wrapped = envelope::wrap(V::type_tag(), &bytes);
+        if let (Cow::Owned(bytes), true) = (&mut bytes, V::is_sensitive()) {
+            wipe(bytes);
+        }
+        Ok(wrapped?.into())
+    }
+
+    /// Converts bytes read from the backing store under `key` to a value.
+    pub(crate) fn decode<V: InBytes>(&self, key: &str, mut data: Vec<u8>) -> Result<V, KvsError> {
+        for codec in self.codecs.iter().rev() {
+            let decoded = codec.decode(key, &data);
+            if V::is_sensitive() {
+                wipe(&mut data);
+            }
+            data = decoded?;
+        }
+        let value = match self.type_tags {
+            true => envelope::unwrap(V::type_tag(), &data).and_then(V::in_bytes),
+            false => V::in_bytes(&data),
+        };
+        if V::is_sensitive() {
+            wipe(&mut data);
+        }
+        value
+    }
+
+    /// Stores bytes under `key` exactly as given, subject to the store's
+    /// limits.
+    pub(crate) fn store_raw(&mut self, key: &str, bytes: &[u8]) -> Result<(), KvsError> {
+        check_not_reserved(key)?;
+        if let Some(limit) = self.max_value_size
+            && bytes.len() > limit
+        {
+            return Err(KvsError::ValueTooLarge {
+                size: bytes.len(),
+                limit,
+            });
+        }
+        let usage = self.usage_after(&[(key, Some(bytes))])?;
+        self.check_space(Some(key), bytes.len())?;
+        self.stamp()?;
+        run_operation(self.retry, &self.slow, Operation::Store, Some(key), || {
+            self.inner.store(key, bytes)
+        })?;
+        self.set_usage(usage);
+        Ok(())
+    }
+
+    /// Applies `changes`, each storing bytes under a key exactly as given or
+    /// removing it.
+    ///
+    /// The store's limits are checked for all changes before any is
+    /// applied. Values are stored first, as one batch, and then keys are
+    /// removed; keys that don't exist are skipped.
+    pub(crate) fn apply_raw(&mut self, changes: &[(&str, Option<&[u8]>)]) -> Result<(), KvsError> {
+        for (key, value) in changes {
+            check_not_reserved(key)?;
+            if let (Some(limit), Some(value)) = (self.max_value_size, value)
+                && value.len() > limit
+            {
+                return Err(KvsError::ValueTooLarge {
+                    size: value.len(),
+                    limit,
+                });
+            }
+        }
+        let usage = self.usage_after(changes)?;
+        let size = changes
+            .iter()
+            .filter_map(|(_, value)| *value)
+            .map(<[u8]>::len);
+        self.check_space(None, size.sum())?;
+        self.stamp()?;
+        let batch: Vec<(&str, &[u8])> = changes
+            .iter()
+            .filter_map(|&(key, value)| Some((key, value?)))
+            .collect();
+        if !batch.is_empty() {
+            run_operation(self.retry, &self.slow, Operation::Store, None, || {
+                self.inner.store_many(&batch)
+            })?;
+        }
+        for (key, _) in changes.iter().filter(|(_, value)| value.is_none()) {
+            run_operation(
+                self.retry,
+                &self.slow,
+                Operation::Remove,
+                Some(key),
+                || match self.inner.contains(key)? {
+                    true => self.inner.remove(key),
+                    false => Ok(()),
+                },
+            )?;
+        }
+        self.set_usage(usage);
+        Ok(())
+    }
+
+    /// Retrieves a value by key, if it exists.
+    ///
+    /// Returns `None` if the key is not found. The return type must be
+    /// specified and implement `InBytes` for deserialization.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key to look up. Can be any type that converts to a string reference.
+    ///
+    /// # Type Parameters
+    ///
+    /// * `V` - The expected type of the stored value. Must implement `InBytes`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend fails to read the data
+    /// or if the stored data cannot be deserialized to the requested type.
+    /// On stores with type tags enabled, returns `TypeMismatch` if the value
+    /// was stored as a different type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+    /// store.store("count", 42u32)?;
+    ///
+    /// // Retrieve with explicit type annotation
+    /// let count: u32 = store.retrieve("count")?.unwrap();
+    /// assert_eq!(count, 42);
+    ///
+    /// // Check for non-existent key
+    /// let missing: Option<String> = store.retrieve("missing")?;
+    /// assert!(missing.is_none());
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn retrieve<K: AsRef<str>, V: InBytes>(&self, key: K) -> Result<Option<V>, KvsError> {
+        Ok(
+            match run_operation(
+                self.retry,
+                &self.slow,
+                Operation::Retrieve,
+                Some(key.as_ref()),
+                || self.inner.retrieve(key.as_ref()),
+            )? {
+                Some(data) => Some(self.decode(key.as_ref(), data)?),
+                None => None,
+            },
+        )
+    }
+
+    /// Stores several values at once, writing them in parallel where the
+    /// backend supports it.
+    ///
+    /// Behaves like calling [`store`](Self::store) for each entry, but on
+    /// Linux and macOS the files are written and flushed concurrently, and
+    /// each directory is synced once rather than after every file, which is
+    /// much faster when importing many keys. Other backends, and stores
+    /// opened with a scope wrapper that transforms values, such as
+    /// encryption, store the entries one at a time. If a key appears more
+    /// than once, its last value is stored.
+    ///
+    /// Quota and value size limits are checked for all entries before any
+    /// is written.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`store`](Self::store). If writing fails,
+    /// some entries may already have been stored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+    /// let entries = (0..100).map(|i| (format!("item/{i}"), i as u32));
+    /// store.par_store_many(entries)?;
+    /// assert_eq!(store.retrieve("item/42")?, Some(42u32));
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    #[cfg(feature = "rayon")]
+    pub fn par_store_many<K, V, I>(&mut self, entries: I) -> Result<(), KvsError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: OutBytes,
+    {
+        let entries: Vec<(K, V)> = entries.into_iter().collect();
+        let mut encoded = Vec::with_capacity(entries.len());
+        let mut result = Ok(());
+        for (key, value) in &entries {
+            match self.encode(key.as_ref(), value) {
+                Ok(bytes) => encoded.push((key.as_ref(), bytes)),
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        if result.is_ok() {
+            result = self.store_raw_many(&encoded);
+        }
+        if V::is_sensitive() {
+            for (_, bytes) in &mut encoded {
+                if let Cow::Owned(bytes) = bytes {
+                    wipe(bytes);
+                }
+            }
+        }
+        result
+    }
+
+    /// Stores several byte values exactly as given, subject to the store's
+    /// limits.
+    #[cfg(feature = "rayon")]
+    fn store_raw_many(&mut self, entries: &[(&str, Cow<'_, [u8]>)]) -> Result<(), KvsError> {
+        // Keep only the last value of each key, so the outcome doesn't
+        // depend on the order of concurrent writes
+        let mut seen = std::collections::HashSet::new();
+        let mut batch: Vec<(&str, &[u8])> = Vec::with_capacity(entries.len());
+        for (key, bytes) in entries.iter().rev() {
+            if seen.insert(*key) {
+                batch.push((key, bytes));
+            }
+        }
+        batch.reverse();
+        for (key, bytes) in &batch {
+            check_not_reserved(key)?;
+            if let Some(limit) = self.max_value_size
+                && bytes.len() > limit
+            {
+                return Err(KvsError::ValueTooLarge {
+                    size: bytes.len(),
+                    limit,
+                });
+            }
+        }
+        let changes: Vec<(&str, Option<&[u8]>)> = batch
+            .iter()
+            .map(|&(key, bytes)| (key, Some(bytes)))
+            .collect();
+        let usage = self.usage_after(&changes)?;
+        self.check_space(None, batch.iter().map(|(_, bytes)| bytes.len()).sum())?;
+        self.stamp()?;
+        run_operation(self.retry, &self.slow, Operation::Store, None, || {
+            self.inner.store_many(&batch)
+        })?;
+        self.set_usage(usage);
+        Ok(())
+    }
+
+    /// Retrieves the values of several keys at once, reporting which were
+    /// found, missing, or couldn't be converted.
+    ///
+    /// The keys are read as one batch. A value that fails to convert
+    /// doesn't stop the others from being returned, so settings can be
+    /// loaded at startup with a single call, falling back to defaults for
+    /// the keys that are missing or failed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend fails to read the keys.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?.with_type_tags();
+    /// store.store("theme", "dark")?;
+    /// store.store("font_size", 12u32)?;
+    ///
+    /// let settings = store.retrieve_many::<_, String, _>(["theme", "font_size", "lang"])?;
+    /// assert_eq!(settings.found["theme"], "dark");
+    /// assert_eq!(settings.missing, ["lang"]);
+    /// assert!(settings.failed.contains_key("font_size"));
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn retrieve_many<K, V, I>(&self, keys: I) -> Result<Retrieved<V>, KvsError>
+    where
+        I: IntoIterator<Item = K>,
+        K: AsRef<str>,
+        V: InBytes,
+    {
+        let keys: Vec<K> = keys.into_iter().collect();
+        let keys: Vec<&str> = keys.iter().map(AsRef::as_ref).collect();
+        let values = run_operation(self.retry, &self.slow, Operation::Retrieve, None, || {
+            self.inner.retrieve_many(&keys)
+        })?;
+        let mut retrieved = Retrieved {
+            found: BTreeMap::new(),
+            missing: Vec::new(),
+            failed: BTreeMap::new(),
+        };
+        for (key, data) in keys.into_iter().zip(values) {
+            match data.map(|data| self.decode(key, data)) {
+                Some(Ok(value)) => {
+                    retrieved.found.insert(key.to_string(), value);
+                }
+                Some(Err(e)) => {
+                    retrieved.failed.insert(key.to_string(), e);
+                }
+                None => retrieved.missing.push(key.to_string()),
+            }
+        }
+        Ok(retrieved)
+    }
+
+    /// Retrieves the values of several keys at once, reading them in
+    /// parallel where the backend supports it.
+    ///
+    /// Returns one entry per key, in the order given, which is `None` for
+    /// keys that don't exist. Like [`par_store_many`](Self::par_store_many),
+    /// only stores kept in directories read in parallel.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`retrieve`](Self::retrieve), for the
+    /// first key that fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+    /// store.store("a", "1")?;
+    /// store.store("b", "2")?;
+    ///
+    /// let values: Vec<Option<String>> = store.par_retrieve_many(["a", "missing", "b"])?;
+    /// assert_eq!(values, [Some("1".into()), None, Some("2".into())]);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    #[cfg(feature = "rayon")]
+    pub fn par_retrieve_many<K, V, I>(&self, keys: I) -> Result<Vec<Option<V>>, KvsError>
+    where
+        I: IntoIterator<Item = K>,
+        K: AsRef<str>,
+        V: InBytes,
+    {
+        let keys: Vec<K> = keys.into_iter().collect();
+        let keys: Vec<&str> = keys.iter().map(AsRef::as_ref).collect();
+        let values = run_operation(self.retry, &self.slow, Operation::Retrieve, None, || {
+            self.inner.retrieve_many(&keys)
+        })?;
+        // Decode every value, even after a failure, so sensitive ones are wiped
+        let values: Vec<Result<Option<V>, KvsError>> = values
+            .into_iter()
+            .zip(&keys)
+            .map(|(data, key)| data.map(|data| self.decode(key, data)).transpose())
+            .collect();
+        values.into_iter().collect()
+    }
+
+    /// Removes a key and its associated value from the store.
+    ///
+    /// Does nothing if the key doesn't exist.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key to remove. Can be any type that converts to a string reference.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend fails to remove the key, or
+    /// `ReservedKey` if the key starts with [`RESERVED_PREFIX`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+    /// store.store("temp", "value")?;
+    ///
+    /// assert!(store.retrieve::<_, String>("temp")?.is_some());
+    /// store.remove("temp")?;
+    /// assert!(store.retrieve::<_, String>("temp")?.is_none());
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn remove<K: AsRef<str>>(&mut self, key: K) -> Result<(), KvsError> {
+        self.remove_with(key.as_ref(), S::Store::remove)
+    }
+
+    /// Removes a key, overwriting its value before it is deleted.
+    ///
+    /// Use this for credentials and other values that must not linger in
+    /// freed storage. On the file system the file's contents are
+    /// overwritten with zeros and flushed before it is unlinked; in the
+    /// registry the value is overwritten before it is deleted; in memory
+    /// the value's buffer is zeroed unless a fork or snapshot still
+    /// shares it.
+    ///
+    /// This is best effort. Copy-on-write and journaling file systems,
+    /// SSD wear levelling and backups can all keep copies of the data, as
+    /// can earlier versions of the value, since each write replaces the
+    /// previous file rather than overwriting it.
+    ///
+    /// Does nothing if the key doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend fails to overwrite or remove
+    /// the key, or `ReservedKey` if the key starts with [`RESERVED_PREFIX`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+    /// store.store("api_token", "s3cr3t")?;
+    /// store.remove_secure("api_token")?;
+    /// assert!(store.retrieve::<_, String>("api_token")?.is_none());
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn remove_secure<K: AsRef<str>>(&mut self, key: K) -> Result<(), KvsError> {
+        self.remove_with(key.as_ref(), S::Store::remove_secure)
+    }
+
+    fn remove_with(
+        &mut self,
+        key: &str,
+        remove: impl Fn(&mut S::Store, &str) -> Result<(), KvsError>,
+    ) -> Result<(), KvsError> {
+        check_not_reserved(key)?;
+        let usage = self.usage_after(&[(key, None)])?;
+        self.stamp()?;
+        run_operation(self.retry, &self.slow, Operation::Remove, Some(key), || {
+            remove(&mut self.inner, key)
+        })?;
+        self.set_usage(usage);
+        Ok(())
+    }
+
+    /// Removes a key only if its current value equals `expected`, returning
+    /// whether it was removed.
+    ///
+    /// Use this to give up something another process may have replaced,
+    /// such as a cached token: a process still holding the old value can't
+    /// remove the one another process just refreshed. Stores kept in
+    /// directories compare and remove atomically across processes; the
+    /// Windows registry has no such primitive, so there it is only atomic
+    /// within a process.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend fails to read or remove the
+    /// key, if the current value can't be decoded, or `ReservedKey` if the
+    /// key starts with [`RESERVED_PREFIX`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+    /// store.store("token", "refreshed")?;
+    /// assert!(!store.remove_if("token", "expired")?);
+    /// assert!(store.remove_if("token", "refreshed")?);
+    /// assert!(store.retrieve::<_, String>("token")?.is_none());
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn remove_if<K: AsRef<str>, V: OutBytes>(
+        &mut self,
+        key: K,
+        expected: V,
+    ) -> Result<bool, KvsError> {
+        let key = key.as_ref();
+        check_not_reserved(key)?;
+        let Some(current) = self.retrieve_raw_if(key, &expected)? else {
+            return Ok(false);
+        };
+        let usage = self.usage_after(&[(key, None)])?;
+        self.stamp()?;
+        let removed = run_operation(self.retry, &self.slow, Operation::Remove, Some(key), || {
+            self.inner.remove_if(key, &current)
+        })?;
+        if removed {
+            self.set_usage(usage);
+        }
+        Ok(removed)
+    }
+
+    /// Retrieves the bytes stored under `key`, exactly as written to the
+    /// backing store, if they hold `expected`.
+    ///
+    /// Codecs may encode the same value differently each time, so the
+    /// stored bytes are decoded and compared rather than matched against a
+    /// fresh encoding of `expected`.
+    fn retrieve_raw_if<V: OutBytes>(
+        &self,
+        key: &str,
+        expected: &V,
+    ) -> Result<Option<Vec<u8>>, KvsError> {
+        let Some(raw) = self.inner.retrieve(key)? else {
+            return Ok(None);
+        };
+        let mut data = raw.clone();
+        for codec in self.codecs.iter().rev() {
+            let decoded = codec.decode(key, &data);
+            if V::is_sensitive() {
+                wipe(&mut data);
+            }
+            data = decoded?;
+        }
+        let mut expected = self.tagged(expected)?;
+        let matches = data == *expected;
+        if V::is_sensitive() {
+            wipe(&mut data);
+            if let Cow::Owned(expected) = &mut expected {
+                wipe(expected);
+            }
+        }
+        Ok(matches.then_some(raw))
+    }
+
+    /// Renames a key, keeping its value.
+    ///
+    /// Any value already stored under `to` is replaced. On backends that
+    /// support it, such as the file system, the rename is atomic, so the
+    /// value is never missing from or present under both keys. Elsewhere it
+    /// is copied and then removed. On stores with codecs, which may bind
+    /// values to their key, the value is decoded and encoded again under
+    /// `to`, stored, and then removed from `from`.
+    ///
+    /// Returns `false` if `from` does not exist, in which case nothing is
+    /// changed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend fails, `ReservedKey` if
+    /// either key starts with [`RESERVED_PREFIX`], or on stores with a quota,
+    /// `QuotaExceeded` if the longer key would exceed it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+    /// store.store("colour", "blue")?;
+    ///
+    /// assert!(store.rename("colour", "ui.colour")?);
+    /// assert_eq!(store.retrieve("ui.colour")?, Some(String::from("blue")));
+    /// assert!(!store.rename("colour", "ui.colour")?);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn rename<K: AsRef<str>, L: AsRef<str>>(
+        &mut self,
+        from: K,
+        to: L,
+    ) -> Result<bool, KvsError> {
+        let (from, to) = (from.as_ref(), to.as_ref());
+        check_not_reserved(from)?;
+        check_not_reserved(to)?;
+        if from == to {
+            return Ok(self.inner.retrieve(from)?.is_some());
+        }
+        if !self.codecs.is_empty() {
+            return self.rename_recoded(from, to);
+        }
+        let usage = match self.quota {
+            Some(_) => match self.inner.retrieve(from)? {
+                Some(value) => self.usage_after(&[(from, None), (to, Some(&value))])?,
+                None => return Ok(false),
+            },
+            None => None,
+        };
+        self.stamp()?;
+        let renamed = run_operation(
+            self.retry,
+            &self.slow,
+            Operation::Rename,
+            Some(from),
+            || self.inner.rename(from, to),
+        )?;
+        self.set_usage(usage);
+        Ok(renamed)
+    }
+
+    /// Renames a key on a store with codecs, decoding its value under `from`
+    /// and encoding it again under `to`.
+    fn rename_recoded(&mut self, from: &str, to: &str) -> Result<bool, KvsError> {
+        let Some(mut data) = self.inner.retrieve(from)? else {
+            return Ok(false);
+        };
+        for codec in self.codecs.iter().rev() {
+            let decoded = codec.decode(from, &data);
+            wipe(&mut data);
+            data = decoded?;
+        }
+        for codec in &self.codecs {
+            let coded = codec.encode(to, &data);
+            wipe(&mut data);
+            data = coded?;
+        }
+        let usage = self.usage_after(&[(from, None), (to, Some(&data))])?;
+        self.stamp()?;
+        run_operation(self.retry, &self.slow, Operation::Store, Some(to), || {
+            self.inner.store(to, &data)
+        })?;
+        run_operation(
+            self.retry,
+            &self.slow,
+            Operation::Remove,
+            Some(from),
+            || self.inner.remove(from),
+        )?;
+        self.set_usage(usage);
+        Ok(true)
+    }
+
+    /// Returns when the value stored under `key` was last modified.
+    ///
+    /// Modification times are kept by the backing store: file modification
+    /// times on Linux and macOS, and the time of each write for the
+    /// ephemeral scope. The registry backend does not track them.
+    ///
+    /// Returns `None` if the key doesn't exist or its backend does not track
+    /// modification times.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend cannot be accessed, or
+    /// `ReservedKey` if the key starts with [`RESERVED_PREFIX`].
+    pub fn modified<K: AsRef<str>>(&self, key: K) -> Result<Option<SystemTime>, KvsError> {
+        check_not_reserved(key.as_ref())?;
+        run_operation(
+            self.retry,
+            &self.slow,
+            Operation::Modified,
+            Some(key.as_ref()),
+            || self.inner.modified(key.as_ref()),
+        )
+    }
+
+    /// Updates the modification time of `key` to now, without rewriting
+    /// its value.
+    ///
+    /// The time is taken from the store's clock. This lets cleanup policies
+    /// based on [`modified`](Self::modified) treat a key as recently used.
+    /// Returns `false` if the key doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend fails, or `ReservedKey` if
+    /// the key starts with [`RESERVED_PREFIX`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::{Duration, SystemTime};
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+    /// store.store("thumbnail", [1u8, 2, 3].as_slice())?;
+    ///
+    /// assert!(store.touch("thumbnail")?);
+    /// let age = SystemTime::now().duration_since(store.modified("thumbnail")?.unwrap());
+    /// assert!(age.unwrap_or_default() < Duration::from_secs(60));
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn touch<K: AsRef<str>>(&mut self, key: K) -> Result<bool, KvsError> {
+        check_not_reserved(key.as_ref())?;
+        let now = self.clock.now();
+        run_operation(
+            self.retry,
+            &self.slow,
+            Operation::Touch,
+            Some(key.as_ref()),
+            || self.inner.touch(key.as_ref(), now),
+        )
+    }
+
+    /// Returns the keys whose values have not been modified since `since`.
+    ///
+    /// This lets cleanup jobs find stale entries without retrieving every
+    /// value. Keys whose backend does not track modification times are
+    /// never reported.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend cannot be accessed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::{Duration, SystemTime};
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+    /// store.store("fresh", "value")?;
+    ///
+    /// let week_ago = SystemTime::now() - Duration::from_secs(7 * 24 * 60 * 60);
+    /// assert!(store.keys_not_modified_since(week_ago)?.is_empty());
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn keys_not_modified_since(&self, since: SystemTime) -> Result<Vec<String>, KvsError> {
+        let mut stale = Vec::new();
+        for key in self.keys()? {
+            if self.inner.modified(&key)?.is_some_and(|m| m < since) {
+                stale.push(key);
+            }
+        }
+        Ok(stale)
+    }
+
+    /// Returns a handle for running housekeeping tasks on the store.
+    ///
+    /// See [`Maintenance`] for the available tasks.
+    pub fn maintenance(&mut self) -> Maintenance<'_, S> {
+        Maintenance::new(self)
+    }
+
+    /// Reopens the store over a backing store derived from this one's,
+    /// keeping its configuration.
+    ///
+    /// The quota's usage is measured again, since the new backing store
+    /// may hold different keys.
+    pub(crate) fn map_backing_store<T, F>(self, f: F) -> Result<KeyValueStore<T>, KvsError>
+    where
+        T: Scope,
+        F: FnOnce(S::Store) -> Result<T::Store, KvsError>,
+    {
+        let mut store = KeyValueStore::<T>::with_backing_store(f(self.inner)?)?;
+        store.type_tags = self.type_tags;
+        store.max_value_size = self.max_value_size;
+        store.space_check = self.space_check;
+        store.clock = self.clock;
+        store.retry = self.retry;
+        store.codecs = self.codecs;
+        store.slow = self.slow;
+        store.manifest = self.manifest;
+        match self.quota {
+            Some((quota, _)) => store.with_quota(quota),
+            None => Ok(store),
+        }
+    }
+
+    /// Returns the current time according to the store's clock.
+    pub(crate) fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
+
+    /// Runs the backing store's own housekeeping.
+    pub(crate) fn maintain_backing_store(&mut self) -> Result<(), KvsError> {
+        run_operation(None, &self.slow, Operation::Maintain, None, || {
+            self.inner.maintain()
+        })
+    }
+}
+
+impl<S: Scope<Store = EphemeralStore>> KeyValueStore<S> {
+    /// Returns an independent copy of this in-memory store and its
+    /// configuration.
+    ///
+    /// See [`EphemeralStore::fork`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut fixture = KeyValueStore::<scope::Ephemeral>::new()?;
+    /// fixture.store("user", "alice")?;
+    ///
+    /// let mut case = fixture.fork();
+    /// case.store("user", "bob")?;
+    /// assert_eq!(fixture.retrieve::<_, String>("user")?.as_deref(), Some("alice"));
+    /// # Ok::<(), zep_kvs::error::KvsError>(())
+    /// ```
+    pub fn fork(&self) -> Self {
+        Self {
+            inner: self.inner.fork(),
+            type_tags: self.type_tags,
+            quota: self.quota,
+            max_value_size: self.max_value_size,
+            space_check: self.space_check,
+            clock: Arc::clone(&self.clock),
+            retry: self.retry,
+            codecs: self.codecs.clone(),
+            slow: self.slow.clone(),
+            manifest: self.manifest.clone(),
+            stamped: self.stamped,
+            format_pending: self.format_pending,
+        }
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl<S: Scope> KeyValueStore<Encrypted<S>> {
+    /// Opens an encrypted store with its passphrase.
+    ///
+    /// The first unlock of a store that holds no data sets its passphrase.
+    /// The key is derived from the passphrase with Argon2id, which takes a
+    /// noticeable fraction of a second by design.
+    ///
+    /// # Errors
+    ///
+    /// Returns `KvsError::DecryptionFailed` if the passphrase is wrong,
+    /// an error if the store already holds unencrypted data, or an error if
+    /// the underlying scope cannot be opened.
+    pub fn unlock(passphrase: &str) -> Result<Self, KvsError> {
+        let protection = Protection::Passphrase(passphrase, KdfParams::default());
+        Self::with_backing_store(EncryptedStore::unlock(S::new()?, protection)?)
+    }
+
+    /// Opens an encrypted store whose key is protected by the platform.
+    ///
+    /// On Windows the data key is protected with DPAPI for the current
+    /// user, so only that user, logged on to this machine, can open the
+    /// store, and no passphrase is needed. The first unlock of a store that
+    /// holds no data sets up this protection.
+    ///
+    /// A store protected this way can be given a passphrase with
+    /// [`change_password`](Self::change_password), after which it is opened
+    /// with [`unlock`](Self::unlock) instead.
+    ///
+    /// # Errors
+    ///
+    /// Returns `KvsError::DecryptionFailed` if the store is protected by a
+    /// passphrase or for a different user, an error if the store already
+    /// holds unencrypted data, or an error if the underlying scope cannot
+    /// be opened.
+    #[cfg(windows)]
+    pub fn unlock_with_platform_key() -> Result<Self, KvsError> {
+        Self::with_backing_store(EncryptedStore::unlock(S::new()?, Protection::Platform)?)
+    }
+
+    /// Changes the passphrase of the store.
+    ///
+    /// Only the data key is re-encrypted, so this is fast regardless of the
+    /// size of the store. The old passphrase stops working immediately.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the new encryption header cannot be written, in
+    /// which case the old passphrase remains valid.
+    pub fn change_password(&mut self, new_passphrase: &str) -> Result<(), KvsError> {
+        self.inner.change_password(new_passphrase)
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl<S: Scope> KeyValueStore<PartlyEncrypted<S>> {
+    /// Opens a partly encrypted store with its passphrase, encrypting the
+    /// values of the `encrypted` keys.
+    ///
+    /// The first unlock of a store sets its passphrase, and encrypts the
+    /// values of the chosen keys it already holds. The key is derived from
+    /// the passphrase with Argon2id, which takes a noticeable fraction of a
+    /// second by design.
+    ///
+    /// # Errors
+    ///
+    /// Returns `KvsError::DecryptionFailed` if the passphrase is wrong, an
+    /// error if the store is fully encrypted, or an error if the underlying
+    /// scope cannot be opened.
+    pub fn unlock(passphrase: &str, encrypted: EncryptedKeys) -> Result<Self, KvsError> {
+        let protection = Protection::Passphrase(passphrase, KdfParams::default());
+        Self::with_backing_store(PartlyEncryptedStore::unlock(
+            S::new()?,
+            protection,
+            encrypted,
+        )?)
+    }
+
+    /// Changes the passphrase of the store.
+    ///
+    /// Only the data key is re-encrypted, so this is fast regardless of the
+    /// size of the store. The old passphrase stops working immediately.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the new encryption header cannot be written, in
+    /// which case the old passphrase remains valid.
+    pub fn change_password(&mut self, new_passphrase: &str) -> Result<(), KvsError> {
+        self.inner.change_password(new_passphrase)
+    }
+}
+
+#[cfg(feature = "integrity")]
+impl<S: Scope> KeyValueStore<Authenticated<S>> {
+    /// Opens an authenticated store, verifying it with `key`.
+    ///
+    /// The first open of a store that holds no data protects it with `key`.
+    /// Every value read afterwards is verified, and with
+    /// [`Coverage::ValuesAndKeys`] so is the set of keys, both here and
+    /// whenever keys are listed or added.
+    ///
+    /// The key should be kept outside the store, for example in a
+    /// location only the application's service account can read.
+    ///
+    /// # Errors
+    ///
+    /// Returns `KvsError::IntegrityViolation` if the key is wrong or the
+    /// key set has been modified, an error if the store already holds
+    /// unauthenticated data, or an error if the underlying scope cannot be
+    /// opened.
+    pub fn with_integrity_key(key: &[u8], coverage: Coverage) -> Result<Self, KvsError> {
+        Self::with_backing_store(IntegrityStore::open(S::new()?, key, coverage)?)
+    }
+}
+
+impl<Old: Scope, New: Scope> KeyValueStore<Migrating<Old, New>> {
+    /// Completes a migration between locations.
+    ///
+    /// Copies every key that only exists in the old location, including
+    /// the store's metadata, to the new location, then removes every key
+    /// from the old location. The store keeps working afterwards, but new
+    /// writes still go to both locations, so once the migration is complete
+    /// applications should open the new scope directly.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if either location cannot be read or written. The
+    /// migration can be finalized again after a failure.
+    pub fn finalize_migration(&mut self) -> Result<(), KvsError> {
+        self.inner.finalize()
+    }
+}
+
+/// Low-level interface for key-value storage backends.
+///
+/// This trait is implemented by platform-specific storage mechanisms
+/// and handles the actual persistence of data. Most users should use
+/// `KeyValueStore` instead of implementing this trait directly.
+pub trait BackingStore {
+    /// Returns all keys currently stored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend cannot be accessed.
+    fn keys(&self) -> Result<Vec<String>, KvsError>;
+
+    /// Stores raw bytes under the given key.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key to store the data under
+    /// * `value` - The raw bytes to store
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend fails to write the data.
+    fn store(&mut self, key: &str, value: &[u8]) -> Result<(), KvsError>;
+
+    /// Retrieves raw bytes by key, if the key exists.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key to look up
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(Some(bytes))` if the key exists, `Ok(None)` if it doesn't,
+    /// or an error if the storage backend fails.
+    fn retrieve(&self, key: &str) -> Result<Option<Vec<u8>>, KvsError>;
+
+    /// Removes a key and its associated data.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key to remove
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend fails to remove the key.
+    fn remove(&mut self, key: &str) -> Result<(), KvsError>;
+
+    /// Moves the data stored under `from` to `to`, replacing any data
+    /// already under `to`.
+    ///
+    /// The default implementation copies the data and then removes the old
+    /// key. Backends that can rename atomically should override it.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(false)` without changing anything if `from` doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend fails to move the data.
+    fn rename(&mut self, from: &str, to: &str) -> Result<bool, KvsError> {
+        match self.retrieve(from)? {
+            Some(value) => {
+                self.store(to, &value)?;
+                self.remove(from)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Stores several values, each under its own key.
+    ///
+    /// The default implementation stores them one at a time. Backends that
+    /// can write independent keys concurrently should override it. Values
+    /// stored before an error remain stored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend fails to write any value.
+    fn store_many(&mut self, entries: &[(&str, &[u8])]) -> Result<(), KvsError> {
+        for (key, value) in entries {
+            self.store(key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Retrieves the values of several keys, in the order given.
+    ///
+    /// The default implementation retrieves them one at a time. Backends
+    /// that can read independent keys concurrently should override it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend fails to read any value.
+    fn retrieve_many(&self, keys: &[&str]) -> Result<Vec<Option<Vec<u8>>>, KvsError> {
+        keys.iter().map(|key| self.retrieve(key)).collect()
+    }
+
+    /// Returns whether `key` exists.
+    ///
+    /// The default implementation retrieves the value. Backends that can
+    /// check for a key more cheaply should override it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend cannot be accessed.
+    fn contains(&self, key: &str) -> Result<bool, KvsError> {
+        Ok(self.retrieve(key)?.is_some())
+    }
+
+    /// Stores `value` under `key` unless the key already exists, returning
+    /// whether it was stored.
+    ///
+    /// The default implementation checks for the key and then stores the
+    /// value, which is only atomic within this process. Backends shared
+    /// between processes should override it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend fails to write the value.
+    fn store_if_absent(&mut self, key: &str, value: &[u8]) -> Result<bool, KvsError> {
+        if self.contains(key)? {
+            return Ok(false);
+        }
+        self.store(key, value)?;
+        Ok(true)
+    }
+
+    /// Removes `key` if its value is `expected`, returning whether it was
+    /// removed.
+    ///
+    /// The default implementation reads the value and then removes the key,
+    /// which is only atomic within this process. Backends shared between
+    /// processes should override it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend fails to read or remove the
+    /// key.
+    fn remove_if(&mut self, key: &str, expected: &[u8]) -> Result<bool, KvsError> {
+        if self.retrieve(key)?.as_deref() != Some(expected) {
+            return Ok(false);
+        }
+        self.remove(key)?;
+        Ok(true)
+    }
+
+    /// Returns whether each of `keys` exists, in the order given.
+    ///
+    /// The default implementation checks the keys one at a time. Backends
+    /// that can list their keys in one pass should override it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend cannot be accessed.
+    fn contains_many(&self, keys: &[&str]) -> Result<Vec<bool>, KvsError> {
+        keys.iter().map(|key| self.contains(key)).collect()
+    }
+
+    /// Lists the keys starting with `prefix`.
+    ///
+    /// The default implementation filters [`keys`](Self::keys). Backends
+    /// that keep keys ordered should override it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend cannot be accessed.
+    fn keys_with_prefix(&self, prefix: &str) -> Result<Vec<String>, KvsError> {
+        let mut keys = self.keys()?;
+        keys.retain(|key| key.starts_with(prefix));
+        Ok(keys)
+    }
+
+    /// Removes a key, overwriting its data first where the backend can.
+    ///
+    /// The default implementation just calls [`remove`](Self::remove).
+    /// Backends override it to overwrite the stored data, best effort,
+    /// before deleting it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend fails to overwrite or remove
+    /// the key.
+    fn remove_secure(&mut self, key: &str) -> Result<(), KvsError> {
+        self.remove(key)
+    }
+
+    /// Subscribes to the backend's change notifications, which wake
+    /// watchers as soon as another instance of the store changes it.
+    ///
+    /// The default implementation returns `Ok(None)`, for backends without
+    /// a notification channel, whose watchers rely on polling alone.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend cannot be accessed.
+    fn listen(&self) -> Result<Option<Box<dyn ChangeListener>>, KvsError> {
+        Ok(None)
+    }
+
+    /// Opens a second handle on the same data, for a watcher to poll from
+    /// a thread of its own.
+    ///
+    /// The handle reads keys and values as the backend holds them, so
+    /// wrappers that only transform values can return their inner store's
+    /// handle. The default implementation returns `Ok(None)`, in which case
+    /// watchers open a new instance of the store's scope instead.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend cannot be accessed.
+    fn reopen(&self) -> Result<Option<DynBackingStore>, KvsError> {
+        Ok(None)
+    }
+
+    /// Returns when the data stored under `key` was last modified.
+    ///
+    /// The default implementation does not track modification times and
+    /// always returns `Ok(None)`.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(None)` if the key doesn't exist or the backend does not
+    /// track modification times.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend cannot be accessed.
+    fn modified(&self, _key: &str) -> Result<Option<SystemTime>, KvsError> {
+        Ok(None)
+    }
+
+    /// Sets the modification time of `key` to `at` without rewriting its data.
+    ///
+    /// The default implementation does nothing, for backends that do not
+    /// track modification times.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(false)` if the key doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend fails to update the time.
+    fn touch(&mut self, key: &str, _at: SystemTime) -> Result<bool, KvsError> {
+        Ok(self.retrieve(key)?.is_some())
+    }
+    /// Performs backend housekeeping, such as removing files left behind by
+    /// interrupted writes.
+    ///
+    /// The default implementation does nothing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend cannot be accessed.
+    fn maintain(&mut self) -> Result<(), KvsError> {
+        Ok(())
+    }
+
+    /// Returns the directory holding the store, for backends kept in files.
+    ///
+    /// The default implementation returns `None`.
+    fn location(&self) -> Option<&Path> {
+        None
+    }
+
+    /// Returns the number of bytes available for the store to grow into.
+    ///
+    /// The default implementation returns `Ok(None)`, for backends that
+    /// can't tell.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend cannot be accessed.
+    fn free_space(&self) -> Result<Option<u64>, KvsError> {
+        Ok(None)
+    }
+}
+
+/// Runs an operation on a backing store, retrying transient failures
+/// according to `retry`, recording `operation` and `key` in I/O errors and
+/// reporting the operation to `slow` if it takes too long.
+fn run_operation<T>(
+    retry: Option<RetryPolicy>,
+    slow: &SlowOperations,
+    operation: Operation,
+    key: Option<&str>,
+    f: impl FnMut() -> Result<T, KvsError>,
+) -> Result<T, KvsError> {
+    let started = Instant::now();
+    let result = RetryPolicy::run(retry, f).map_err(|e| e.during(operation, key));
+    slow.check(operation, key, started.elapsed());
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::scope::Ephemeral;
+    use crate::ephemeral::EphemeralStore;
+    use crate::testing::{Mock, MockClock, MockStore};
+    use std::time::Duration;
+
+    #[test]
+    fn test_new_store_records_format_version() {
+        let mut store = KeyValueStore::<Ephemeral>::new().unwrap();
+        assert_eq!(store.inner.retrieve(FORMAT_KEY).unwrap(), None);
+        store.store("a", "1").unwrap();
+        assert_eq!(
+            store.inner.retrieve(FORMAT_KEY).unwrap(),
+            Some(FORMAT_VERSION.to_be_bytes().to_vec())
+        );
+        assert_eq!(store.keys().unwrap(), ["a"]);
+    }
+
+    #[test]
+    fn test_legacy_store_runs_format_upgrade() {
+        let mut inner = Ephemeral::new().unwrap();
+        inner.store("legacy", b"value").unwrap();
+
+        let mut upgrades = Vec::new();
+        let mut store = KeyValueStore::<Ephemeral>::open(inner, |store, from, to| {
+            assert_eq!(store.retrieve::<_, String>("legacy")?.unwrap(), "value");
+            upgrades.push((from, to));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(upgrades, [(0, FORMAT_VERSION)]);
+        store.store("new", "value").unwrap();
+        assert_eq!(
+            store.inner.retrieve(FORMAT_KEY).unwrap(),
+            Some(FORMAT_VERSION.to_be_bytes().to_vec())
+        );
+    }
+
+    #[test]
+    fn test_legacy_store_opens_without_writing() {
+        use crate::testing::Operation as MockOperation;
+
+        let mut mock = MockStore::new();
+        mock.store("legacy", b"value").unwrap();
+        mock.script_reserved_keys()
+            .fail_always(MockOperation::Store, std::io::ErrorKind::PermissionDenied);
+
+        let store = KeyValueStore::<Mock>::with_backing_store(mock).unwrap();
+        assert_eq!(
+            store.retrieve::<_, String>("legacy").unwrap().as_deref(),
+            Some("value")
+        );
+        assert_eq!(store.inner.retrieve(FORMAT_KEY).unwrap(), None);
+    }
+
+    #[test]
+    fn test_failed_format_upgrade_keeps_old_version() {
+        let mut inner = Ephemeral::new().unwrap();
+        inner.store("legacy", b"value").unwrap();
+
+        let result = KeyValueStore::<Ephemeral>::open(inner, |_, _, _| {
+            Err(KvsError::SerializationError("upgrade failed".to_string()))
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_newer_format_is_rejected() {
+        let mut inner = Ephemeral::new().unwrap();
+        inner
+            .store(FORMAT_KEY, &(FORMAT_VERSION + 1).to_be_bytes())
+            .unwrap();
+
+        let result = KeyValueStore::<Ephemeral>::open(inner, |_, _, _| {
+            panic!("upgrade hook must not run for newer formats")
+        });
+        assert!(matches!(
+            result,
+            Err(KvsError::UnsupportedFormat { found, supported })
+                if found == FORMAT_VERSION + 1 && supported == FORMAT_VERSION
+        ));
+    }
+
+    #[test]
+    fn test_quota_limits_bytes() {
+        let quota = Quota {
+            max_bytes: Some(10),
+            ..Quota::default()
+        };
+        let mut store = KeyValueStore::<Ephemeral>::new().unwrap();
+        store.store("a", "1234").unwrap();
+        let mut store = store.with_quota(quota).unwrap();
+        store.store("b", "1234").unwrap();
+        assert!(matches!(
+            store.store("c", "1"),
+            Err(KvsError::QuotaExceeded(_))
+        ));
+        assert_eq!(store.retrieve::<_, String>("c").unwrap(), None);
+        // Overwriting with a smaller value and removing free space
+        store.store("b", "12").unwrap();
+        store.store("c", "1").unwrap();
+        store.remove("a").unwrap();
+        store.store("d", "1234").unwrap();
+    }
+
+    #[test]
+    fn test_quota_limits_keys() {
+        let quota = Quota {
+            max_keys: Some(2),
+            ..Quota::default()
+        };
+        let mut store = KeyValueStore::<Ephemeral>::new()
+            .unwrap()
+            .with_quota(quota)
+            .unwrap();
+        store.store("a", "1").unwrap();
+        store.store("b", "1").unwrap();
+        store.store("b", "2").unwrap();
+        assert!(matches!(
+            store.store("c", "1"),
+            Err(KvsError::QuotaExceeded(_))
+        ));
+        store.remove("a").unwrap();
+        store.store("c", "1").unwrap();
+    }
+
+    #[test]
+    fn test_over_quota_store_can_shrink() {
+        let mut store = KeyValueStore::<Ephemeral>::new().unwrap();
+        store.store("a", "123456").unwrap();
+        let quota = Quota {
+            max_bytes: Some(4),
+            ..Quota::default()
+        };
+        let mut store = store.with_quota(quota).unwrap();
+        assert!(store.store("a", "1234567").is_err());
+        store.store("a", "12345").unwrap();
+        store.remove("a").unwrap();
+        store.store("b", "1").unwrap();
+    }
+
+    #[test]
+    fn test_max_value_size() {
+        let mut store = KeyValueStore::<Ephemeral>::new()
+            .unwrap()
+            .with_max_value_size(3);
+        store.store("key", "abc").unwrap();
+        match store.store("key", "abcd") {
+            Err(KvsError::ValueTooLarge { size, limit }) => {
+                assert_eq!(size, 4);
+                assert_eq!(limit, 3);
+            }
+            _ => panic!("Expected ValueTooLarge"),
+        }
+        assert_eq!(store.retrieve("key").unwrap(), Some(String::from("abc")));
+    }
+
+    #[test]
+    fn test_space_check_rejects_values_larger_than_free_space() {
+        let mock = MockStore::new();
+        let mut store = KeyValueStore::<Mock>::with_backing_store(mock.clone())
+            .unwrap()
+            .with_space_check(4);
+        mock.set_free_space(Some(5));
+        store.store("small", "abc").unwrap();
+        store.store("large", "abcde").unwrap();
+        match store.store("large", "abcdef") {
+            Err(KvsError::StorageFull {
+                source: None,
+                operation: Some(Operation::Store),
+                key,
+                ..
+            }) => assert_eq!(key.as_deref(), Some("large")),
+            _ => panic!("Expected StorageFull"),
+        }
+        assert_eq!(
+            store.retrieve("large").unwrap(),
+            Some(String::from("abcde"))
+        );
+
+        // Small values and unknown free space aren't checked
+        mock.set_free_space(Some(0));
+        store.store("small", "abc").unwrap();
+        mock.set_free_space(None);
+        store.store("large", "abcdef").unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn test_par_store_many_checks_limits_before_writing() {
+        let quota = Quota {
+            max_keys: Some(3),
+            ..Quota::default()
+        };
+        let mut store = KeyValueStore::<Ephemeral>::new()
+            .unwrap()
+            .with_quota(quota)
+            .unwrap();
+        store
+            .par_store_many([("a", "1"), ("b", "2"), ("a", "3")])
+            .unwrap();
+        assert_eq!(store.retrieve("a").unwrap(), Some(String::from("3")));
+        assert!(matches!(
+            store.par_store_many([("c", "1"), ("d", "1")]),
+            Err(KvsError::QuotaExceeded(_))
+        ));
+        assert!(matches!(
+            store.par_store_many([("c", "1"), (FORMAT_KEY, "1")]),
+            Err(KvsError::ReservedKey(_))
+        ));
+        assert_eq!(store.keys().unwrap().len(), 2);
+        store.par_store_many([("c", "1")]).unwrap();
+    }
+
+    #[test]
+    fn test_reserved_keys_are_hidden_and_protected() {
+        assert!(is_reserved(FORMAT_KEY));
+        let mut inner = EphemeralStore::new();
+        inner.store("__zep_other", b"internal").unwrap();
+        inner.store("user", b"data").unwrap();
+        let mut store = KeyValueStore::<Ephemeral>::with_backing_store(inner).unwrap();
+        assert_eq!(store.keys().unwrap(), ["user"]);
+        assert!(matches!(
+            store.store("__zep_other", "value"),
+            Err(KvsError::ReservedKey(_))
+        ));
+        assert!(matches!(
+            store.remove(FORMAT_KEY),
+            Err(KvsError::ReservedKey(_))
+        ));
+        assert_eq!(
+            store.inner.retrieve("__zep_other").unwrap(),
+            Some(b"internal".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_info_records_first_write() {
+        let clock = MockClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1000));
+        let mut store = KeyValueStore::<Ephemeral>::new()
+            .unwrap()
+            .with_clock(clock.clone());
+        let info = store.info().unwrap();
+        assert_eq!(info.created, None);
+        assert_eq!(info.app_version, None);
+
+        store.store("key", "value").unwrap();
+        clock.advance(Duration::from_secs(5));
+        store.store("key", "value").unwrap();
+        let info = store.info().unwrap();
+        assert_eq!(info.created, Some(clock.now() - Duration::from_secs(5)));
+        assert_eq!(
+            info.app_version.as_deref(),
+            Some(env!("ZEP_KVS_APP_VERSION"))
+        );
+        assert_eq!(store.keys().unwrap(), ["key"]);
+    }
+
+    #[test]
+    fn test_info_updates_app_version_but_not_creation() {
+        let mut inner = EphemeralStore::new();
+        inner
+            .store(CREATED_KEY, &SystemTime::UNIX_EPOCH.out_bytes().unwrap())
+            .unwrap();
+        inner.store(APP_VERSION_KEY, b"0.0.1").unwrap();
+        let mut store = KeyValueStore::<Ephemeral>::with_backing_store(inner).unwrap();
+        assert_eq!(store.info().unwrap().app_version.as_deref(), Some("0.0.1"));
+
+        store.remove("missing").unwrap();
+        let info = store.info().unwrap();
+        assert_eq!(info.created, Some(SystemTime::UNIX_EPOCH));
+        assert_eq!(
+            info.app_version.as_deref(),
+            Some(env!("ZEP_KVS_APP_VERSION"))
+        );
+    }
+
+    #[test]
+    fn test_migrations_run_once_in_order() {
+        let migrations = Migrations::new()
+            .add(2, |store: &mut KeyValueStore<Ephemeral>| {
+                store.store("log", "12")
+            })
+            .add(1, |store| store.store("log", "1"))
+            .add(3, |store| store.store("log", "123"));
+        let mut store = KeyValueStore::<Ephemeral>::new().unwrap();
+        assert_eq!(store.data_version().unwrap(), 0);
+
+        assert_eq!(store.migrate_to(2, &migrations).unwrap(), 2);
+        assert_eq!(store.retrieve("log").unwrap(), Some(String::from("12")));
+        store.remove("log").unwrap();
+        assert_eq!(store.migrate_to(2, &migrations).unwrap(), 2);
+        assert_eq!(store.retrieve::<_, String>("log").unwrap(), None);
+
+        assert_eq!(store.migrate_to(3, &migrations).unwrap(), 3);
+        assert_eq!(store.retrieve("log").unwrap(), Some(String::from("123")));
+        assert_eq!(store.migrate_to(1, &migrations).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_failed_migration_keeps_previous_version() {
+        let migrations = Migrations::new()
+            .add(1, |store: &mut KeyValueStore<Ephemeral>| {
+                store.store("a", "1")
+            })
+            .add(2, |_| {
+                Err(KvsError::SerializationError("failed".to_string()))
+            });
+        let mut store = KeyValueStore::<Ephemeral>::new().unwrap();
+        assert!(store.migrate_to(2, &migrations).is_err());
+        assert_eq!(store.data_version().unwrap(), 1);
+        assert_eq!(store.keys().unwrap(), ["a"]);
+    }
+
+    #[test]
+    fn test_migration_lock_is_released() {
+        let migrations = Migrations::new()
+            .add(1, |store: &mut KeyValueStore<Ephemeral>| {
+                store.store("a", "1")
+            })
+            .add(2, |_| {
+                Err(KvsError::SerializationError("failed".to_string()))
+            });
+        let mut store = KeyValueStore::<Ephemeral>::new().unwrap();
+        assert!(store.migrate_to(2, &migrations).is_err());
+        assert!(!store.inner.contains(MIGRATION_LOCK_KEY).unwrap());
+        assert_eq!(store.migrate_to(1, &migrations).unwrap(), 1);
+        assert!(!store.inner.contains(MIGRATION_LOCK_KEY).unwrap());
+    }
+
+    #[test]
+    fn test_abandoned_migration_lock_is_taken_over() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let mut store = KeyValueStore::<Ephemeral>::new()
+            .unwrap()
+            .with_clock(MockClock::new(now));
+        let mut lock = (now - MIGRATION_LOCK_TIMEOUT)
+            .out_bytes()
+            .unwrap()
+            .into_owned();
+        lock.extend_from_slice(&[0; 8]);
+        store.inner.store(MIGRATION_LOCK_KEY, &lock).unwrap();
+
+        assert_eq!(store.migrate_to(1, &Migrations::new()).unwrap(), 1);
+        assert!(!store.inner.contains(MIGRATION_LOCK_KEY).unwrap());
+    }
+
+    #[test]
+    fn test_migrate_without_steps_records_target() {
+        let mut store = KeyValueStore::<Ephemeral>::new().unwrap();
+        assert_eq!(store.migrate_to(5, &Migrations::new()).unwrap(), 5);
+        assert_eq!(store.data_version().unwrap(), 5);
+    }
+
+    #[test]
+    fn test_clone_to_replaces_contents_and_metadata() {
+        let mut source = KeyValueStore::<Ephemeral>::new()
+            .unwrap()
+            .with_clock(MockClock::default());
+        source.store("a", "1").unwrap();
+        source.migrate_to(3, &Migrations::new()).unwrap();
+
+        let mut target = KeyValueStore::<Ephemeral>::new()
+            .unwrap()
+            .with_quota(Quota {
+                max_keys: Some(1),
+                ..Quota::default()
+            })
+            .unwrap();
+        target.store("b", "2").unwrap();
+        source.clone_to(&mut target).unwrap();
+
+        assert_eq!(target.keys().unwrap(), ["a"]);
+        assert_eq!(target.info().unwrap(), source.info().unwrap());
+        assert_eq!(target.info().unwrap().created, Some(SystemTime::UNIX_EPOCH));
+        assert_eq!(target.data_version().unwrap(), 3);
+        assert!(matches!(
+            target.store("c", "3"),
+            Err(KvsError::QuotaExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut store = KeyValueStore::<Ephemeral>::new()
+            .unwrap()
+            .with_quota(Quota {
+                max_keys: Some(2),
+                max_bytes: Some(6),
+            })
+            .unwrap();
+        store.store("a", "1").unwrap();
+        store.store("b", "2").unwrap();
+        // Renaming over an existing key frees its share of the quota
+        assert!(store.rename("a", "b").unwrap());
+        assert_eq!(store.keys().unwrap(), ["b"]);
+        assert_eq!(store.retrieve("b").unwrap(), Some(String::from("1")));
+        assert!(!store.rename("a", "c").unwrap());
+        assert!(store.rename("b", "b").unwrap());
+        assert!(matches!(
+            store.rename("b", "longer"),
+            Err(KvsError::QuotaExceeded(_))
+        ));
+        assert!(matches!(
+            store.rename("b", FORMAT_KEY),
+            Err(KvsError::ReservedKey(_))
+        ));
+    }
+
+    #[test]
+    fn test_rename_reencodes_key_bound_values() {
+        /// Prefixes each value with its key, as associated data would bind it.
+        struct BoundToKey;
+
+        impl ValueCodec for BoundToKey {
+            fn encode(&self, key: &str, value: &[u8]) -> Result<Vec<u8>, KvsError> {
+                Ok([key.as_bytes(), b"\0", value].concat())
+            }
+
+            fn decode(&self, key: &str, value: &[u8]) -> Result<Vec<u8>, KvsError> {
+                value
+                    .strip_prefix(key.as_bytes())
+                    .and_then(|rest| rest.strip_prefix(b"\0"))
+                    .map(<[u8]>::to_vec)
+                    .ok_or_else(|| KvsError::SerializationError("bound to other key".to_string()))
+            }
+        }
+
+        let mut store = KeyValueStore::<Ephemeral>::new()
+            .unwrap()
+            .with_codec(BoundToKey);
+        store.store("a", "1").unwrap();
+        store.store("b", "2").unwrap();
+        assert!(store.rename("a", "b").unwrap());
+        assert_eq!(store.keys().unwrap(), ["b"]);
+        assert_eq!(store.retrieve("b").unwrap(), Some(String::from("1")));
+        assert!(!store.rename("a", "c").unwrap());
+    }
+
+    #[test]
+    fn test_touch_uses_store_clock() {
+        let clock = MockClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(100));
+        let mut store = KeyValueStore::<Ephemeral>::new()
+            .unwrap()
+            .with_clock(clock.clone());
+        store.store("key", "value").unwrap();
+        assert!(store.modified("key").unwrap().is_some());
+        assert!(store.touch("key").unwrap());
+        assert_eq!(store.modified("key").unwrap(), Some(clock.now()));
+        assert!(!store.touch("missing").unwrap());
+        assert_eq!(store.modified("missing").unwrap(), None);
+    }
+
+    #[test]
+    fn test_keys_not_modified_since() {
+        let clock = MockClock::new(SystemTime::UNIX_EPOCH);
+        let mut store = KeyValueStore::<Ephemeral>::new()
+            .unwrap()
+            .with_clock(clock.clone());
+        store.store("old", "1").unwrap();
+        store.store("touched", "2").unwrap();
+        store.touch("old").unwrap();
+        store.touch("touched").unwrap();
+        clock.advance(Duration::from_secs(10));
+        store.touch("touched").unwrap();
+
+        let cutoff = SystemTime::UNIX_EPOCH + Duration::from_secs(5);
+        assert_eq!(store.keys_not_modified_since(cutoff).unwrap(), ["old"]);
+    }
+
+    #[test]
+    fn test_io_errors_record_operation_and_key() {
+        use crate::testing::{Mock, MockStore, Operation as MockOperation};
+
+        let mock = MockStore::new();
+        let mut store = KeyValueStore::<Mock>::with_backing_store(mock.clone()).unwrap();
+        mock.fail_always(MockOperation::Remove, std::io::ErrorKind::PermissionDenied);
+        let error = store.remove("settings").unwrap_err();
+        assert!(matches!(
+            &error,
+            KvsError::IoError {
+                operation: Some(Operation::Remove),
+                key: Some(key),
+                ..
+            } if key == "settings"
+        ));
+        assert!(
+            error
+                .to_string()
+                .contains("while removing key \"settings\"")
+        );
+    }
 }
@@ -0,0 +1,439 @@
+//! Single-file compressed backups.
+//!
+//! [`KeyValueStore::backup_to`] writes a store to one `.tar.zst` file that
+//! can be attached to a support ticket or kept as a backup, and
+//! [`KeyValueStore::restore_from`] reads it back, verifying its checksum
+//! first.
+//!
+//! The archive contains two files, in this order:
+//!
+//! - `manifest.txt`: one `name: value` line per field, describing the
+//!   backup. The fields are `format`, `zep_kvs_version`, `app_version`,
+//!   `created`, `data_version`, `entries` and `sha256`, where `created` is
+//!   the store's creation time in seconds since the Unix epoch, and
+//!   `sha256` is the hex SHA-256 digest of `entries.zepkvs`. Fields that
+//!   the store has no value for are omitted.
+//! - `entries.zepkvs`: the store's entries in the [stream format](crate::stream).
+//!
+//! With the `encryption` feature, [`KeyValueStore::backup_to_encrypted`]
+//! writes the same archive encrypted with a passphrase, using Argon2id key
+//! derivation and ChaCha20-Poly1305 authenticated encryption.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rand::random;
+use sha2::{Digest, Sha256};
+
+use crate::api::{BackingStore, KeyValueStore, Scope};
+#[cfg(feature = "encryption")]
+use crate::crypto::{self, KdfParams};
+use crate::error::KvsError;
+
+/// The current archive format version.
+const FORMAT: u32 = 1;
+const MANIFEST: &str = "manifest.txt";
+const ENTRIES: &str = "entries.zepkvs";
+
+impl<S: Scope> KeyValueStore<S> {
+    /// Writes a compressed backup of the store to `path`.
+    ///
+    /// The backup holds every entry along with the store's metadata. The
+    /// entries are staged in a temporary file, so memory use does not grow
+    /// with the size of the store. The file is only readable by its owner
+    /// and is kept in the store's own directory where it has one. Returns
+    /// the number of entries written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be read, or if the temporary
+    /// file or the backup cannot be written.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let store = KeyValueStore::<scope::User>::new()?;
+    /// store.backup_to("settings-backup.tar.zst")?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn backup_to(&self, path: impl AsRef<Path>) -> Result<usize, KvsError> {
+        let staged = TempFile::new(self.backing_store().location())?;
+        let mut writer = Hashing::new(BufWriter::new(
+            File::create(&staged.0).map_err(|e| KvsError::io_at(e, &staged.0))?,
+        ));
+        let entries = self.export_to(&mut writer)?;
+        let sha256 = writer.finish();
+
+        let info = self.info()?;
+        let mut manifest = format!(
+            "format: {FORMAT}\nzep_kvs_version: {}\n",
+            env!("CARGO_PKG_VERSION")
+        );
+        if let Some(app_version) = info.app_version {
+            manifest.push_str(&format!("app_version: {app_version}\n"));
+        }
+        if let Some(created) = info.created.and_then(|t| t.duration_since(UNIX_EPOCH).ok()) {
+            manifest.push_str(&format!("created: {}\n", created.as_secs()));
+        }
+        manifest.push_str(&format!(
+            "data_version: {}\nentries: {entries}\nsha256: {sha256}\n",
+            self.data_version()?
+        ));
+
+        write_archive(path.as_ref(), &manifest, &staged.0)?;
+        Ok(entries)
+    }
+
+    /// Stores the entries of a backup written by
+    /// [`backup_to`](Self::backup_to).
+    ///
+    /// The backup is verified against its checksum before anything is
+    /// stored. Its entries then replace existing values for the same keys,
+    /// and the store's data version is set to the backup's, so that
+    /// [`migrate_to`](Self::migrate_to) brings restored data from an older
+    /// application version up to date. Keys not in the backup are kept, so
+    /// restore into an empty store for an exact copy.
+    ///
+    /// Returns the number of entries restored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the backup cannot be read, is corrupt, or was
+    /// written in a newer format, or if a write is rejected by this store.
+    pub fn restore_from(&mut self, path: impl AsRef<Path>) -> Result<usize, KvsError> {
+        let path = path.as_ref();
+        let io_error = |e| KvsError::io_at(e, path);
+        let file = File::open(path).map_err(io_error)?;
+        let decoder = zstd::Decoder::new(file).map_err(io_error)?;
+        let mut archive = tar::Archive::new(decoder);
+        let mut files = archive.entries().map_err(io_error)?;
+
+        let mut next = |name: &str| -> Result<_, KvsError> {
+            let file = files
+                .next()
+                .ok_or_else(|| invalid(&format!("missing {name}")))?
+                .map_err(io_error)?;
+            if file.path().map_err(io_error)?.as_os_str() != name {
+                return Err(invalid(&format!("expected {name}")));
+            }
+            Ok(file)
+        };
+
+        let mut manifest = String::new();
+        next(MANIFEST)?
+            .read_to_string(&mut manifest)
+            .map_err(io_error)?;
+        let manifest = Manifest::parse(&manifest)?;
+        if manifest.format > FORMAT {
+            return Err(KvsError::UnsupportedFormat {
+                found: manifest.format,
+                supported: FORMAT,
+            });
+        }
+
+        let staged = TempFile::new(self.backing_store().location())?;
+        let mut writer = Hashing::new(BufWriter::new(
+            File::create(&staged.0).map_err(|e| KvsError::io_at(e, &staged.0))?,
+        ));
+        io::copy(&mut next(ENTRIES)?, &mut writer).map_err(io_error)?;
+        if writer.finish() != manifest.sha256 {
+            return Err(invalid("checksum mismatch"));
+        }
+
+        let reader =
+            BufReader::new(File::open(&staged.0).map_err(|e| KvsError::io_at(e, &staged.0))?);
+        let entries = self.import_from(reader, |_| {})?;
+        if entries != manifest.entries {
+            return Err(invalid("entry count mismatch"));
+        }
+        self.set_data_version(manifest.data_version)?;
+        Ok(entries)
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl<S: Scope> KeyValueStore<S> {
+    /// Writes a compressed backup of the store to `path`, encrypted with a
+    /// key derived from `passphrase`.
+    ///
+    /// The backup is the same as written by [`backup_to`](Self::backup_to),
+    /// encrypted so that it can be emailed or kept in cloud storage. Without
+    /// the passphrase its contents, including key names, cannot be read,
+    /// and any modification is detected when it is restored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be read, or if the temporary
+    /// file or the backup cannot be written.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let store = KeyValueStore::<scope::User>::new()?;
+    /// store.backup_to_encrypted("settings.tar.zst.enc", "correct horse battery staple")?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn backup_to_encrypted(
+        &self,
+        path: impl AsRef<Path>,
+        passphrase: &str,
+    ) -> Result<usize, KvsError> {
+        self.backup_encrypted_with(path.as_ref(), passphrase, KdfParams::default())
+    }
+
+    fn backup_encrypted_with(
+        &self,
+        path: &Path,
+        passphrase: &str,
+        params: KdfParams,
+    ) -> Result<usize, KvsError> {
+        let staged = TempFile::new(self.backing_store().location())?;
+        let entries = self.backup_to(&staged.0)?;
+        let reader =
+            BufReader::new(File::open(&staged.0).map_err(|e| KvsError::io_at(e, &staged.0))?);
+        let mut writer = BufWriter::new(File::create(path).map_err(|e| KvsError::io_at(e, path))?);
+        crypto::encrypt(reader, &mut writer, passphrase, params)?;
+        let file = writer
+            .into_inner()
+            .map_err(|e| KvsError::io_at(e.into_error(), path))?;
+        file.sync_all().map_err(|e| KvsError::io_at(e, path))?;
+        Ok(entries)
+    }
+
+    /// Stores the entries of a backup written by
+    /// [`backup_to_encrypted`](Self::backup_to_encrypted).
+    ///
+    /// The backup is decrypted and authenticated in full before anything is
+    /// stored, and then restored as by [`restore_from`](Self::restore_from).
+    ///
+    /// # Errors
+    ///
+    /// Returns `KvsError::DecryptionFailed` if the passphrase is wrong or
+    /// the backup has been modified, and otherwise the errors of
+    /// [`restore_from`](Self::restore_from).
+    pub fn restore_from_encrypted(
+        &mut self,
+        path: impl AsRef<Path>,
+        passphrase: &str,
+    ) -> Result<usize, KvsError> {
+        let path = path.as_ref();
+        let reader = BufReader::new(File::open(path).map_err(|e| KvsError::io_at(e, path))?);
+        let staged = TempFile::new(self.backing_store().location())?;
+        let mut writer =
+            BufWriter::new(File::create(&staged.0).map_err(|e| KvsError::io_at(e, &staged.0))?);
+        crypto::decrypt(reader, &mut writer, passphrase)?;
+        drop(writer);
+        self.restore_from(&staged.0)
+    }
+}
+
+/// Writes an archive holding `manifest` and the staged entries.
+fn write_archive(path: &Path, manifest: &str, entries: &Path) -> Result<(), KvsError> {
+    let write = || {
+        let encoder = zstd::Encoder::new(File::create(path)?, 0)?;
+        let mut builder = tar::Builder::new(encoder);
+        let mtime = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+
+        let mut header = tar::Header::new_gnu();
+        header.set_size(manifest.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        builder.append_data(&mut header, MANIFEST, manifest.as_bytes())?;
+
+        let mut file = File::open(entries)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(file.metadata()?.len());
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        builder.append_data(&mut header, ENTRIES, &mut file)?;
+
+        builder.into_inner()?.finish()?.sync_all()
+    };
+    write().map_err(|e| KvsError::io_at(e, path))
+}
+
+/// Returns an error describing an invalid backup.
+fn invalid(reason: &str) -> KvsError {
+    KvsError::SerializationError(format!("Invalid backup: {reason}"))
+}
+
+/// The fields of a backup manifest needed to restore it.
+struct Manifest {
+    format: u32,
+    data_version: u32,
+    entries: usize,
+    sha256: String,
+}
+
+impl Manifest {
+    fn parse(text: &str) -> Result<Self, KvsError> {
+        Ok(Self {
+            format: Self::number(text, "format")?,
+            data_version: Self::number(text, "data_version")?,
+            entries: Self::number(text, "entries")?,
+            sha256: Self::field(text, "sha256")?.to_string(),
+        })
+    }
+
+    fn field<'a>(text: &'a str, name: &str) -> Result<&'a str, KvsError> {
+        text.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(": "))
+            .ok_or_else(|| invalid(&format!("manifest has no {name}")))
+    }
+
+    fn number<T: FromStr>(text: &str, name: &str) -> Result<T, KvsError> {
+        Self::field(text, name)?
+            .parse()
+            .map_err(|_| invalid(&format!("manifest has an invalid {name}")))
+    }
+}
+
+/// A writer computing the SHA-256 digest of the bytes written through it.
+struct Hashing<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> Hashing<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Returns the hex digest of everything written.
+    fn finish(self) -> String {
+        self.hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+}
+
+impl<W: Write> Write for Hashing<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A temporary file that is removed when dropped.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    /// Creates an empty temporary file in `dir`, or in the system's
+    /// temporary directory if `None`.
+    ///
+    /// Staged files hold store data in plaintext, so they are created
+    /// readable only by their owner, and belong in the store's own
+    /// directory rather than one shared with other users. Their name marks
+    /// them as temporary files of a directory store, so one left behind by
+    /// a crash is never listed as a key and is removed with other stale
+    /// temporary files.
+    fn new(dir: Option<&Path>) -> Result<Self, KvsError> {
+        let dir = dir.map_or_else(std::env::temp_dir, Path::to_path_buf);
+        let path = dir.join(format!(".tmp_backup_{}", random::<u64>()));
+        let mut options = File::options();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(&path).map_err(|e| KvsError::io_at(e, &path))?;
+        Ok(Self(path))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::scope::Ephemeral;
+
+    #[test]
+    fn test_backup_round_trip() {
+        let backup = TempFile::new(None).unwrap();
+        let path = &backup.0;
+        let mut store = KeyValueStore::<Ephemeral>::new().unwrap();
+        store.store("theme", "dark").unwrap();
+        store.store("volume", 7u8).unwrap();
+        store.migrate_to(3, &Default::default()).unwrap();
+        assert_eq!(store.backup_to(path).unwrap(), 2);
+
+        let mut restored = KeyValueStore::<Ephemeral>::new().unwrap();
+        assert_eq!(restored.restore_from(path).unwrap(), 2);
+        assert_eq!(restored.keys().unwrap(), ["theme", "volume"]);
+        assert_eq!(restored.retrieve::<_, u8>("volume").unwrap(), Some(7));
+        assert_eq!(restored.data_version().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_restore_verifies_checksum() {
+        let entries = TempFile::new(None).unwrap();
+        KeyValueStore::<Ephemeral>::new()
+            .unwrap()
+            .export_to(File::create(&entries.0).unwrap())
+            .unwrap();
+        let backup = TempFile::new(None).unwrap();
+        let path = &backup.0;
+        let manifest = format!(
+            "format: 1\ndata_version: 0\nentries: 0\nsha256: {}\n",
+            "0".repeat(64)
+        );
+        write_archive(path, &manifest, &entries.0).unwrap();
+
+        let mut store = KeyValueStore::<Ephemeral>::new().unwrap();
+        assert!(matches!(
+            store.restore_from(path),
+            Err(KvsError::SerializationError(e)) if e.contains("checksum")
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_encrypted_backup_round_trip() {
+        let backup = TempFile::new(None).unwrap();
+        let mut store = KeyValueStore::<Ephemeral>::new().unwrap();
+        store.store("token", "s3cr3t").unwrap();
+        store
+            .backup_encrypted_with(&backup.0, "passphrase", crypto::tests::FAST)
+            .unwrap();
+        let sealed = fs::read(&backup.0).unwrap();
+        assert!(!sealed.windows(5).any(|w| w == b"token"));
+
+        let mut restored = KeyValueStore::<Ephemeral>::new().unwrap();
+        assert!(matches!(
+            restored.restore_from_encrypted(&backup.0, "wrong"),
+            Err(KvsError::DecryptionFailed)
+        ));
+        assert_eq!(
+            restored
+                .restore_from_encrypted(&backup.0, "passphrase")
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            restored.retrieve::<_, String>("token").unwrap().as_deref(),
+            Some("s3cr3t")
+        );
+    }
+}
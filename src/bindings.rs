@@ -0,0 +1,119 @@
+//! UniFFI bindings for Kotlin, Swift and other foreign languages.
+//!
+//! This module exposes a store whose scope is chosen at runtime through
+//! UniFFI, so that the Kotlin or Swift layers of an application with a Rust
+//! core can read and write the same stores as the Rust code.
+//!
+//! Bindings are generated from the compiled library with the bundled
+//! `uniffi-bindgen` tool:
+//!
+//! ```text
+//! cargo build --features uniffi
+//! cargo run --features uniffi-cli --bin uniffi-bindgen -- generate \
+//!     --library target/debug/libzep_kvs.so --language kotlin --out-dir bindings
+//! ```
+
+use std::sync::{Arc, Mutex};
+
+use crate::any::AnyStore;
+use crate::api::KeyValueStore;
+use crate::convert::InBytes;
+use crate::error::KvsError;
+
+/// Storage scope of a [`Store`].
+#[derive(uniffi::Enum)]
+pub enum StoreScope {
+    /// User-specific storage that persists between program runs.
+    User,
+    /// System-wide storage shared across all users.
+    Machine,
+    /// In-memory storage that doesn't persist between program runs.
+    Ephemeral,
+}
+
+/// A key-value store exposed to foreign languages.
+///
+/// Values are passed as raw bytes, with convenience methods for strings.
+/// The store is safe to share between threads.
+#[derive(uniffi::Object)]
+pub struct Store(Mutex<AnyStore>);
+
+impl Store {
+    fn lock(&self) -> std::sync::MutexGuard<'_, AnyStore> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[uniffi::export]
+impl Store {
+    /// Opens the store for the given scope.
+    #[uniffi::constructor]
+    pub fn new(scope: StoreScope) -> Result<Arc<Self>, KvsError> {
+        let store = match scope {
+            StoreScope::User => AnyStore::User(KeyValueStore::new()?),
+            StoreScope::Machine => AnyStore::Machine(KeyValueStore::new()?),
+            StoreScope::Ephemeral => AnyStore::Ephemeral(KeyValueStore::new()?),
+        };
+        Ok(Arc::new(Self(Mutex::new(store))))
+    }
+
+    /// Returns all keys currently stored in this store.
+    pub fn keys(&self) -> Result<Vec<String>, KvsError> {
+        self.lock().keys()
+    }
+
+    /// Stores raw bytes under the given key.
+    pub fn store(&self, key: String, value: Vec<u8>) -> Result<(), KvsError> {
+        self.lock().store(&key, &value)
+    }
+
+    /// Retrieves raw bytes by key, if the key exists.
+    pub fn retrieve(&self, key: String) -> Result<Option<Vec<u8>>, KvsError> {
+        self.lock().retrieve(&key)
+    }
+
+    /// Stores a string under the given key.
+    pub fn store_string(&self, key: String, value: String) -> Result<(), KvsError> {
+        self.lock().store(&key, value.as_bytes())
+    }
+
+    /// Retrieves a string by key, if the key exists.
+    pub fn retrieve_string(&self, key: String) -> Result<Option<String>, KvsError> {
+        self.lock()
+            .retrieve(&key)?
+            .map(|bytes| String::in_bytes(&bytes))
+            .transpose()
+    }
+
+    /// Removes a key and its associated value from the store.
+    pub fn remove(&self, key: String) -> Result<(), KvsError> {
+        self.lock().remove(&key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_round_trip() {
+        let store = Store::new(StoreScope::Ephemeral).unwrap();
+        store.store("bytes".to_string(), vec![1, 2, 3]).unwrap();
+        store
+            .store_string("name".to_string(), "alice".to_string())
+            .unwrap();
+
+        assert_eq!(
+            store.retrieve("bytes".to_string()).unwrap(),
+            Some(vec![1, 2, 3])
+        );
+        assert_eq!(
+            store.retrieve_string("name".to_string()).unwrap(),
+            Some("alice".to_string())
+        );
+        assert_eq!(store.keys().unwrap().len(), 2);
+
+        store.remove("name".to_string()).unwrap();
+        assert_eq!(store.retrieve_string("name".to_string()).unwrap(), None);
+    }
+}
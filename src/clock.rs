@@ -0,0 +1,33 @@
+//! Time source abstraction.
+//!
+//! Features that depend on the current time, such as store metadata, read
+//! it through the [`Clock`] trait rather than calling `SystemTime::now()`
+//! directly, so tests can substitute a controllable clock like
+//! `testing::MockClock` instead of sleeping.
+
+use std::time::SystemTime;
+
+/// A source of the current time.
+pub trait Clock: Send + Sync {
+    /// Returns the current time.
+    fn now(&self) -> SystemTime;
+}
+
+/// The real system clock.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::SystemTime;
+/// use zep_kvs::clock::{Clock, SystemClock};
+///
+/// assert!(SystemClock.now() > SystemTime::UNIX_EPOCH);
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
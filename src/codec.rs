@@ -0,0 +1,227 @@
+//! Custom transforms applied to values on their way to and from storage.
+//!
+//! A [`ValueCodec`] is one stage of a pipeline installed on a store with
+//! [`KeyValueStore::with_codec`](crate::api::KeyValueStore::with_codec).
+//! Stages run in the order they were installed when values are written,
+//! and in reverse when they are read, so a pipeline built as compress,
+//! then encrypt, then checksum verifies the checksum first on the way back.
+//!
+//! Codecs see the serialized value, including its type tag if the store
+//! has type tags, and never the store's reserved metadata. Values written
+//! with one pipeline can only be read with the same pipeline.
+
+use crate::error::KvsError;
+
+/// One stage of a value transform pipeline.
+///
+/// `decode` must reverse `encode` for the same key. The key is passed so
+/// that codecs can bind values to it, for example as associated data when
+/// encrypting.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::codec::ValueCodec;
+/// use zep_kvs::error::KvsError;
+/// use zep_kvs::prelude::*;
+///
+/// /// Flips every bit, standing in for a corporate crypto library.
+/// struct Invert;
+///
+/// impl ValueCodec for Invert {
+///     fn encode(&self, _key: &str, value: &[u8]) -> Result<Vec<u8>, KvsError> {
+///         Ok(value.iter().map(|b| !b).collect())
+///     }
+///
+///     fn decode(&self, _key: &str, value: &[u8]) -> Result<Vec<u8>, KvsError> {
+///         Ok(value.iter().map(|b| !b).collect())
+///     }
+/// }
+///
+/// let mut store = KeyValueStore::<scope::Ephemeral>::new()?.with_codec(Invert);
+/// store.store("theme", "dark")?;
+/// assert_eq!(store.retrieve::<_, String>("theme")?.as_deref(), Some("dark"));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub trait ValueCodec: Send + Sync {
+    /// Transforms a value about to be stored under `key`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value cannot be transformed; nothing is
+    /// stored.
+    fn encode(&self, key: &str, value: &[u8]) -> Result<Vec<u8>, KvsError>;
+
+    /// Reverses [`encode`](Self::encode) for a value read from `key`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value is not one this codec encoded, or
+    /// fails verification.
+    fn decode(&self, key: &str, value: &[u8]) -> Result<Vec<u8>, KvsError>;
+}
+
+/// Appends a CRC-32 checksum to each value and verifies it when read.
+///
+/// This detects accidental corruption such as truncated files, not
+/// deliberate tampering; see the `integrity` feature for that.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Checksum;
+
+impl ValueCodec for Checksum {
+    fn encode(&self, _key: &str, value: &[u8]) -> Result<Vec<u8>, KvsError> {
+        let mut coded = Vec::with_capacity(value.len() + 4);
+        coded.extend_from_slice(value);
+        coded.extend_from_slice(&crc32(value).to_le_bytes());
+        Ok(coded)
+    }
+
+    fn decode(&self, key: &str, value: &[u8]) -> Result<Vec<u8>, KvsError> {
+        let violation = || KvsError::IntegrityViolation {
+            key: Some(key.to_string()),
+        };
+        let split = value.len().checked_sub(4).ok_or_else(violation)?;
+        let (data, sum) = value.split_at(split);
+        if sum != crc32(data).to_le_bytes() {
+            return Err(violation());
+        }
+        Ok(data.to_vec())
+    }
+}
+
+/// Compresses each value with zstd.
+///
+/// Compression is worth it for large, repetitive values; small values grow
+/// by the frame overhead. Place it before any encryption stage, since
+/// encrypted data doesn't compress.
+#[cfg(feature = "archive")]
+#[derive(Debug, Clone, Copy)]
+pub struct Compressed {
+    /// The zstd compression level, from 1 (fastest) to 22 (smallest).
+    pub level: i32,
+}
+
+#[cfg(feature = "archive")]
+impl Default for Compressed {
+    fn default() -> Self {
+        Self { level: 3 }
+    }
+}
+
+#[cfg(feature = "archive")]
+impl ValueCodec for Compressed {
+    fn encode(&self, _key: &str, value: &[u8]) -> Result<Vec<u8>, KvsError> {
+        zstd::encode_all(value, self.level)
+            .map_err(|e| KvsError::SerializationError(format!("Compression failed: {e}")))
+    }
+
+    fn decode(&self, _key: &str, value: &[u8]) -> Result<Vec<u8>, KvsError> {
+        zstd::decode_all(value)
+            .map_err(|e| KvsError::SerializationError(format!("Decompression failed: {e}")))
+    }
+}
+
+/// Computes the CRC-32 (IEEE) checksum of `data`.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::scope::Ephemeral;
+    use crate::api::{BackingStore, KeyValueStore};
+
+    /// Prefixes values with a marker, to observe the order of stages.
+    struct Mark(u8);
+
+    impl ValueCodec for Mark {
+        fn encode(&self, _key: &str, value: &[u8]) -> Result<Vec<u8>, KvsError> {
+            Ok([&[self.0], value].concat())
+        }
+
+        fn decode(&self, _key: &str, value: &[u8]) -> Result<Vec<u8>, KvsError> {
+            match value.split_first() {
+                Some((&mark, rest)) if mark == self.0 => Ok(rest.to_vec()),
+                _ => Err(KvsError::SerializationError("Missing mark".to_string())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_stages_run_in_order() {
+        let mut store = KeyValueStore::<Ephemeral>::new()
+            .unwrap()
+            .with_codec(Mark(1))
+            .with_codec(Mark(2));
+        store.store("key", "value").unwrap();
+        assert_eq!(
+            store.retrieve_raw("key").unwrap().unwrap(),
+            b"\x02\x01value"
+        );
+        assert_eq!(
+            store.retrieve::<_, String>("key").unwrap().as_deref(),
+            Some("value")
+        );
+        assert_eq!(
+            store
+                .snapshot()
+                .unwrap()
+                .retrieve::<_, String>("key")
+                .unwrap()
+                .as_deref(),
+            Some("value")
+        );
+
+        // Rewrapping the backing store keeps the pipeline
+        let store = store.map_backing_store::<Ephemeral, _>(Ok).unwrap();
+        assert_eq!(
+            store.retrieve::<_, String>("key").unwrap().as_deref(),
+            Some("value")
+        );
+    }
+
+    #[test]
+    fn test_checksum_detects_corruption() {
+        let mut store = KeyValueStore::<Ephemeral>::new()
+            .unwrap()
+            .with_type_tags()
+            .with_codec(Checksum);
+        store.store("count", 7u32).unwrap();
+        assert_eq!(store.retrieve::<_, u32>("count").unwrap(), Some(7));
+
+        let mut raw = store.retrieve_raw("count").unwrap().unwrap();
+        raw[0] ^= 1;
+        store.backing_store_mut().store("count", &raw).unwrap();
+        assert!(matches!(
+            store.retrieve::<_, u32>("count"),
+            Err(KvsError::IntegrityViolation { key: Some(key) }) if key == "count"
+        ));
+    }
+
+    #[cfg(feature = "archive")]
+    #[test]
+    fn test_compressed_round_trip() {
+        let mut store = KeyValueStore::<Ephemeral>::new()
+            .unwrap()
+            .with_codec(Compressed::default())
+            .with_codec(Checksum);
+        let value = "a".repeat(10_000);
+        store.store("big", value.as_str()).unwrap();
+        assert!(store.retrieve_raw("big").unwrap().unwrap().len() < 1_000);
+        assert_eq!(store.retrieve::<_, String>("big").unwrap(), Some(value));
+    }
+}
@@ -0,0 +1,154 @@
+//! Storage locations overridden by a configuration file.
+//!
+//! Administrators can move a scope's data, for example the machine scope to
+//! another volume, without rebuilding the application, by naming a new base
+//! directory for it in `zep-kvs.toml`:
+//!
+//! ```toml
+//! [machine]
+//! path = "/srv/data"
+//! ```
+//!
+//! The store is then kept in `/srv/data/{package_name}/{app_name}/`, just
+//! as it would be under the scope's usual base directory. The file is read
+//! from `/etc/{app_name}/zep-kvs.toml`; for the user and session scopes,
+//! `$XDG_CONFIG_HOME/{app_name}/zep-kvs.toml` (or `~/.config/...`) is read
+//! first and takes precedence.
+
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::error::KvsError;
+
+const FILE_NAME: &str = "zep-kvs.toml";
+/// The scopes whose locations can be overridden.
+const SCOPES: [&str; 3] = ["user", "machine", "session"];
+
+/// Returns the base directory configured for `scope`, if any.
+///
+/// # Errors
+///
+/// Returns an error if a configuration file exists but cannot be read or
+/// is invalid.
+pub(crate) fn configured_path(scope: &str) -> Result<Option<PathBuf>, KvsError> {
+    let app = Path::new(env!("ZEP_KVS_APP_NAME")).join(FILE_NAME);
+    let mut files = Vec::new();
+    if scope != "machine"
+        && let Some(dir) = user_config_dir()
+    {
+        files.push(dir.join(&app));
+    }
+    files.push(Path::new("/etc").join(&app));
+    lookup(&files, scope)
+}
+
+/// Returns the user's configuration directory, following the XDG Base
+/// Directory Specification.
+fn user_config_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+}
+
+/// Returns the base directory for `scope` from the first of `files` that
+/// sets one, skipping files that don't exist.
+fn lookup(files: &[PathBuf], scope: &str) -> Result<Option<PathBuf>, KvsError> {
+    for file in files {
+        let text = match fs::read_to_string(file) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(KvsError::io_at(e, file)),
+        };
+        let path = parse(&text, scope).map_err(|reason| {
+            KvsError::SerializationError(format!("Invalid {}: {reason}", file.display()))
+        })?;
+        if let Some(path) = path {
+            debug!(
+                "{} moves the {scope} scope to {}",
+                file.display(),
+                path.display()
+            );
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the base directory set for `scope` in a configuration file.
+///
+/// The whole file is validated, so that a misspelled scope or setting is
+/// reported rather than silently ignored.
+fn parse(text: &str, scope: &str) -> Result<Option<PathBuf>, String> {
+    let table: toml::Table = text
+        .parse()
+        .map_err(|e: toml::de::Error| e.message().to_string())?;
+    let mut found = None;
+    for (name, section) in &table {
+        if !SCOPES.contains(&name.as_str()) {
+            return Err(format!("unknown scope `{name}`"));
+        }
+        let section = section
+            .as_table()
+            .ok_or_else(|| format!("`{name}` is not a table"))?;
+        for (setting, value) in section {
+            if setting != "path" {
+                return Err(format!("unknown setting `{name}.{setting}`"));
+            }
+            let path = value
+                .as_str()
+                .map(PathBuf::from)
+                .filter(|path| path.is_absolute())
+                .ok_or_else(|| format!("`{name}.path` is not an absolute path"))?;
+            if name == scope {
+                found = Some(path);
+            }
+        }
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = "[machine]\npath = \"/srv/data\"\n\n[user]\n";
+        assert_eq!(
+            parse(text, "machine").unwrap(),
+            Some(PathBuf::from("/srv/data"))
+        );
+        assert_eq!(parse(text, "user").unwrap(), None);
+        assert_eq!(parse("", "session").unwrap(), None);
+
+        assert!(parse("[machin]\npath = \"/srv\"", "user").is_err());
+        assert!(parse("[user]\nbackend = \"memory\"", "user").is_err());
+        assert!(parse("[user]\npath = \"data\"", "user").is_err());
+        assert!(parse("[user", "user").is_err());
+    }
+
+    #[test]
+    fn test_first_file_setting_a_scope_wins() {
+        let dir = env::temp_dir().join(format!("zep-kvs-config-{}", rand::random::<u64>()));
+        fs::create_dir_all(&dir).unwrap();
+        let user = dir.join("user.toml");
+        let system = dir.join("system.toml");
+        fs::write(&user, "[user]\npath = \"/home/data\"\n").unwrap();
+        fs::write(
+            &system,
+            "[user]\npath = \"/srv/user\"\n[session]\npath = \"/tmp\"\n",
+        )
+        .unwrap();
+        let files = [dir.join("missing.toml"), user, system];
+
+        let user = lookup(&files, "user").unwrap();
+        let session = lookup(&files, "session").unwrap();
+        let machine = lookup(&files, "machine").unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(user, Some(PathBuf::from("/home/data")));
+        assert_eq!(session, Some(PathBuf::from("/tmp")));
+        assert_eq!(machine, None);
+    }
+}
@@ -5,7 +5,17 @@
 //! for storage.
 
 use crate::error::KvsError;
+use crate::sensitive::wipe;
 use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::num::{
+    NonZeroI8, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI128, NonZeroIsize, NonZeroU8,
+    NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU128, NonZeroUsize, Saturating, Wrapping,
+};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
 /// Trait for types that can be converted to bytes for storage.
 ///
@@ -21,6 +31,34 @@ pub trait OutBytes {
     ///
     /// Returns an error if the value cannot be serialized.
     fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError>;
+
+    /// Returns the tag identifying this type in self-describing values.
+    ///
+    /// Tags are only written when type tags are enabled on a store (see
+    /// [`KeyValueStore::with_type_tags`](crate::api::KeyValueStore::with_type_tags)).
+    /// Types that share an encoding, such as `&str` and `String`, share a tag
+    /// so that values can be retrieved as any compatible type. Every type in
+    /// this crate has a fixed tag. Custom types stored with type tags must
+    /// override this too: the default, `std::any::type_name`, may change
+    /// between compiler versions, which would make stored values unreadable.
+    fn type_tag() -> &'static str
+    where
+        Self: Sized,
+    {
+        std::any::type_name::<Self>()
+    }
+
+    /// Returns whether values of this type are secrets.
+    ///
+    /// The store zeroes the buffers it uses to convert sensitive values
+    /// once it is done with them. Defaults to `false`; see
+    /// [`Sensitive`](crate::sensitive::Sensitive).
+    fn is_sensitive() -> bool
+    where
+        Self: Sized,
+    {
+        false
+    }
 }
 
 /// Trait for types that can be converted from bytes after retrieval.
@@ -41,6 +79,31 @@ pub trait InBytes {
     fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError>
     where
         Self: Sized;
+
+    /// Returns the tag identifying this type in self-describing values.
+    ///
+    /// Must match [`OutBytes::type_tag`] for every type whose stored bytes
+    /// this type can read. As there, custom types must override the
+    /// default, `std::any::type_name`, which may change between compiler
+    /// versions.
+    fn type_tag() -> &'static str
+    where
+        Self: Sized,
+    {
+        std::any::type_name::<Self>()
+    }
+
+    /// Returns whether values of this type are secrets.
+    ///
+    /// The store zeroes the buffers it uses to convert sensitive values
+    /// once it is done with them. Defaults to `false`; see
+    /// [`Sensitive`](crate::sensitive::Sensitive).
+    fn is_sensitive() -> bool
+    where
+        Self: Sized,
+    {
+        false
+    }
 }
 
 /// Implementation for string slices.
@@ -48,6 +111,10 @@ impl OutBytes for &str {
     fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
         Ok(Cow::Borrowed(self.as_bytes()))
     }
+
+    fn type_tag() -> &'static str {
+        "String"
+    }
 }
 
 /// Implementation for deserializing strings from UTF-8 bytes.
@@ -55,6 +122,10 @@ impl InBytes for String {
     fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
         Ok(String::from_utf8(Vec::from(bytes))?)
     }
+
+    fn type_tag() -> &'static str {
+        "String"
+    }
 }
 
 /// Implementation for byte slices.
@@ -62,6 +133,10 @@ impl OutBytes for &[u8] {
     fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
         Ok(Cow::Borrowed(self))
     }
+
+    fn type_tag() -> &'static str {
+        "Bytes"
+    }
 }
 
 /// Implementation for deserializing byte vectors.
@@ -69,6 +144,10 @@ impl InBytes for Vec<u8> {
     fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
         Ok(Vec::from(bytes))
     }
+
+    fn type_tag() -> &'static str {
+        "Bytes"
+    }
 }
 
 // Boolean implementations
@@ -76,6 +155,10 @@ impl OutBytes for bool {
     fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
         Ok(Cow::Owned(vec![if *self { 1 } else { 0 }]))
     }
+
+    fn type_tag() -> &'static str {
+        "bool"
+    }
 }
 
 impl InBytes for bool {
@@ -93,6 +176,10 @@ impl InBytes for bool {
             )),
         }
     }
+
+    fn type_tag() -> &'static str {
+        "bool"
+    }
 }
 
 // Character implementations
@@ -102,6 +189,10 @@ impl OutBytes for char {
         let s = self.encode_utf8(&mut buf);
         Ok(Cow::Owned(s.as_bytes().to_vec()))
     }
+
+    fn type_tag() -> &'static str {
+        "char"
+    }
 }
 
 impl InBytes for char {
@@ -119,6 +210,10 @@ impl InBytes for char {
         }
         Ok(ch)
     }
+
+    fn type_tag() -> &'static str {
+        "char"
+    }
 }
 
 // Signed integer implementations
@@ -126,6 +221,10 @@ impl OutBytes for i8 {
     fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
         Ok(Cow::Owned(self.to_be_bytes().to_vec()))
     }
+
+    fn type_tag() -> &'static str {
+        "i8"
+    }
 }
 
 impl InBytes for i8 {
@@ -137,12 +236,20 @@ impl InBytes for i8 {
         }
         Ok(i8::from_be_bytes([bytes[0]]))
     }
+
+    fn type_tag() -> &'static str {
+        "i8"
+    }
 }
 
 impl OutBytes for i16 {
     fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
         Ok(Cow::Owned(self.to_be_bytes().to_vec()))
     }
+
+    fn type_tag() -> &'static str {
+        "i16"
+    }
 }
 
 impl InBytes for i16 {
@@ -154,12 +261,20 @@ impl InBytes for i16 {
         }
         Ok(i16::from_be_bytes([bytes[0], bytes[1]]))
     }
+
+    fn type_tag() -> &'static str {
+        "i16"
+    }
 }
 
 impl OutBytes for i32 {
     fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
         Ok(Cow::Owned(self.to_be_bytes().to_vec()))
     }
+
+    fn type_tag() -> &'static str {
+        "i32"
+    }
 }
 
 impl InBytes for i32 {
@@ -173,12 +288,20 @@ impl InBytes for i32 {
         arr.copy_from_slice(bytes);
         Ok(i32::from_be_bytes(arr))
     }
+
+    fn type_tag() -> &'static str {
+        "i32"
+    }
 }
 
 impl OutBytes for i64 {
     fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
         Ok(Cow::Owned(self.to_be_bytes().to_vec()))
     }
+
+    fn type_tag() -> &'static str {
+        "i64"
+    }
 }
 
 impl InBytes for i64 {
@@ -192,12 +315,20 @@ impl InBytes for i64 {
         arr.copy_from_slice(bytes);
         Ok(i64::from_be_bytes(arr))
     }
+
+    fn type_tag() -> &'static str {
+        "i64"
+    }
 }
 
 impl OutBytes for i128 {
     fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
         Ok(Cow::Owned(self.to_be_bytes().to_vec()))
     }
+
+    fn type_tag() -> &'static str {
+        "i128"
+    }
 }
 
 impl InBytes for i128 {
@@ -211,12 +342,20 @@ impl InBytes for i128 {
         arr.copy_from_slice(bytes);
         Ok(i128::from_be_bytes(arr))
     }
+
+    fn type_tag() -> &'static str {
+        "i128"
+    }
 }
 
 impl OutBytes for isize {
     fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
         Ok(Cow::Owned(self.to_be_bytes().to_vec()))
     }
+
+    fn type_tag() -> &'static str {
+        "isize"
+    }
 }
 
 impl InBytes for isize {
@@ -230,6 +369,10 @@ impl InBytes for isize {
         arr.copy_from_slice(bytes);
         Ok(isize::from_be_bytes(arr))
     }
+
+    fn type_tag() -> &'static str {
+        "isize"
+    }
 }
 
 // Unsigned integer implementations
@@ -237,6 +380,10 @@ impl OutBytes for u8 {
     fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
         Ok(Cow::Owned(self.to_be_bytes().to_vec()))
     }
+
+    fn type_tag() -> &'static str {
+        "u8"
+    }
 }
 
 impl InBytes for u8 {
@@ -248,12 +395,20 @@ impl InBytes for u8 {
         }
         Ok(u8::from_be_bytes([bytes[0]]))
     }
+
+    fn type_tag() -> &'static str {
+        "u8"
+    }
 }
 
 impl OutBytes for u16 {
     fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
         Ok(Cow::Owned(self.to_be_bytes().to_vec()))
     }
+
+    fn type_tag() -> &'static str {
+        "u16"
+    }
 }
 
 impl InBytes for u16 {
@@ -265,12 +420,20 @@ impl InBytes for u16 {
         }
         Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
     }
+
+    fn type_tag() -> &'static str {
+        "u16"
+    }
 }
 
 impl OutBytes for u32 {
     fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
         Ok(Cow::Owned(self.to_be_bytes().to_vec()))
     }
+
+    fn type_tag() -> &'static str {
+        "u32"
+    }
 }
 
 impl InBytes for u32 {
@@ -284,12 +447,20 @@ impl InBytes for u32 {
         arr.copy_from_slice(bytes);
         Ok(u32::from_be_bytes(arr))
     }
+
+    fn type_tag() -> &'static str {
+        "u32"
+    }
 }
 
 impl OutBytes for u64 {
     fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
         Ok(Cow::Owned(self.to_be_bytes().to_vec()))
     }
+
+    fn type_tag() -> &'static str {
+        "u64"
+    }
 }
 
 impl InBytes for u64 {
@@ -303,12 +474,20 @@ impl InBytes for u64 {
         arr.copy_from_slice(bytes);
         Ok(u64::from_be_bytes(arr))
     }
+
+    fn type_tag() -> &'static str {
+        "u64"
+    }
 }
 
 impl OutBytes for u128 {
     fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
         Ok(Cow::Owned(self.to_be_bytes().to_vec()))
     }
+
+    fn type_tag() -> &'static str {
+        "u128"
+    }
 }
 
 impl InBytes for u128 {
@@ -322,12 +501,20 @@ impl InBytes for u128 {
         arr.copy_from_slice(bytes);
         Ok(u128::from_be_bytes(arr))
     }
+
+    fn type_tag() -> &'static str {
+        "u128"
+    }
 }
 
 impl OutBytes for usize {
     fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
         Ok(Cow::Owned(self.to_be_bytes().to_vec()))
     }
+
+    fn type_tag() -> &'static str {
+        "usize"
+    }
 }
 
 impl InBytes for usize {
@@ -341,6 +528,10 @@ impl InBytes for usize {
         arr.copy_from_slice(bytes);
         Ok(usize::from_be_bytes(arr))
     }
+
+    fn type_tag() -> &'static str {
+        "usize"
+    }
 }
 
 // Floating-point implementations
@@ -348,6 +539,10 @@ impl OutBytes for f32 {
     fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
         Ok(Cow::Owned(self.to_be_bytes().to_vec()))
     }
+
+    fn type_tag() -> &'static str {
+        "f32"
+    }
 }
 
 impl InBytes for f32 {
@@ -361,12 +556,20 @@ impl InBytes for f32 {
         arr.copy_from_slice(bytes);
         Ok(f32::from_be_bytes(arr))
     }
+
+    fn type_tag() -> &'static str {
+        "f32"
+    }
 }
 
 impl OutBytes for f64 {
     fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
         Ok(Cow::Owned(self.to_be_bytes().to_vec()))
     }
+
+    fn type_tag() -> &'static str {
+        "f64"
+    }
 }
 
 impl InBytes for f64 {
@@ -380,6 +583,89 @@ impl InBytes for f64 {
         arr.copy_from_slice(bytes);
         Ok(f64::from_be_bytes(arr))
     }
+
+    fn type_tag() -> &'static str {
+        "f64"
+    }
+}
+
+// Non-zero integer implementations using macro, sharing the layout of the underlying integer
+macro_rules! impl_non_zero {
+    ($($t:ty => $n:ty),*) => {
+        $(
+            impl OutBytes for $n {
+                fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+                    Ok(Cow::Owned(self.get().to_be_bytes().to_vec()))
+                }
+
+                fn type_tag() -> &'static str {
+                    <$t as OutBytes>::type_tag()
+                }
+            }
+
+            impl InBytes for $n {
+                fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+                    <$n>::new(<$t>::in_bytes(bytes)?).ok_or_else(|| {
+                        KvsError::SerializationError(
+                            concat!("Invalid zero value for ", stringify!($n)).to_string(),
+                        )
+                    })
+                }
+
+                fn type_tag() -> &'static str {
+                    <$t as InBytes>::type_tag()
+                }
+            }
+        )*
+    };
+}
+
+impl_non_zero!(
+    i8 => NonZeroI8, i16 => NonZeroI16, i32 => NonZeroI32, i64 => NonZeroI64,
+    i128 => NonZeroI128, isize => NonZeroIsize,
+    u8 => NonZeroU8, u16 => NonZeroU16, u32 => NonZeroU32, u64 => NonZeroU64,
+    u128 => NonZeroU128, usize => NonZeroUsize
+);
+
+// Integer wrapper implementations, sharing the layout of the wrapped value
+impl<T: OutBytes> OutBytes for Wrapping<T> {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        self.0.out_bytes()
+    }
+
+    fn type_tag() -> &'static str {
+        T::type_tag()
+    }
+}
+
+impl<T: InBytes> InBytes for Wrapping<T> {
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        Ok(Wrapping(T::in_bytes(bytes)?))
+    }
+
+    fn type_tag() -> &'static str {
+        T::type_tag()
+    }
+}
+
+impl<T: OutBytes> OutBytes for Saturating<T> {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        self.0.out_bytes()
+    }
+
+    fn type_tag() -> &'static str {
+        T::type_tag()
+    }
+}
+
+impl<T: InBytes> InBytes for Saturating<T> {
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        Ok(Saturating(T::in_bytes(bytes)?))
+    }
+
+    fn type_tag() -> &'static str {
+        T::type_tag()
+    }
 }
 
 // Fixed-size u8 array implementations using macro
@@ -391,6 +677,10 @@ macro_rules! impl_fixed_u8_array {
                     // Use borrowed data for efficiency - no need to allocate a new vector
                     Ok(Cow::Borrowed(self))
                 }
+
+                fn type_tag() -> &'static str {
+                    "Bytes"
+                }
             }
 
             impl InBytes for [u8; $n] {
@@ -406,6 +696,10 @@ macro_rules! impl_fixed_u8_array {
                     arr.copy_from_slice(bytes);
                     Ok(arr)
                 }
+
+                fn type_tag() -> &'static str {
+                    "Bytes"
+                }
             }
         )*
     };
@@ -418,121 +712,1312 @@ impl_fixed_u8_array!(
     51, 52, 53, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63, 64
 );
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_bool_conversion() {
-        let true_val = true;
-        let false_val = false;
-
-        let true_bytes = true_val.out_bytes().unwrap();
-        let false_bytes = false_val.out_bytes().unwrap();
-
-        assert_eq!(true_bytes.as_ref(), &[1]);
-        assert_eq!(false_bytes.as_ref(), &[0]);
+// Network address implementations
+//
+// Addresses are stored in network byte order: 4 bytes for IPv4 and 16 bytes
+// for IPv6, so `IpAddr` is distinguished by length. Socket addresses append
+// the port as a big-endian `u16`, and for IPv6 also the flow info and scope id
+// as big-endian `u32`s, giving 6 or 26 bytes.
 
-        assert_eq!(bool::in_bytes(&[1]).unwrap(), true);
-        assert_eq!(bool::in_bytes(&[0]).unwrap(), false);
+impl OutBytes for Ipv4Addr {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        Ok(Cow::Owned(self.octets().to_vec()))
     }
 
-    #[test]
-    fn test_integer_conversions() {
-        let i32_val = 42i32;
-        let u64_val = 1234567890u64;
+    fn type_tag() -> &'static str {
+        "IpAddr"
+    }
+}
 
-        let i32_bytes = i32_val.out_bytes().unwrap();
-        let u64_bytes = u64_val.out_bytes().unwrap();
+impl InBytes for Ipv4Addr {
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        let octets: [u8; 4] = bytes.try_into().map_err(|_| {
+            KvsError::SerializationError("Invalid Ipv4Addr byte length".to_string())
+        })?;
+        Ok(Ipv4Addr::from(octets))
+    }
 
-        assert_eq!(i32::in_bytes(&i32_bytes).unwrap(), 42i32);
-        assert_eq!(u64::in_bytes(&u64_bytes).unwrap(), 1234567890u64);
+    fn type_tag() -> &'static str {
+        "IpAddr"
     }
+}
 
-    #[test]
-    fn test_float_conversions() {
-        let f32_val = 3.14f32;
-        let f64_val = 2.718281828f64;
+impl OutBytes for Ipv6Addr {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        Ok(Cow::Owned(self.octets().to_vec()))
+    }
 
-        let f32_bytes = f32_val.out_bytes().unwrap();
-        let f64_bytes = f64_val.out_bytes().unwrap();
+    fn type_tag() -> &'static str {
+        "IpAddr"
+    }
+}
 
-        assert_eq!(f32::in_bytes(&f32_bytes).unwrap(), 3.14f32);
-        assert_eq!(f64::in_bytes(&f64_bytes).unwrap(), 2.718281828f64);
+impl InBytes for Ipv6Addr {
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        let octets: [u8; 16] = bytes.try_into().map_err(|_| {
+            KvsError::SerializationError("Invalid Ipv6Addr byte length".to_string())
+        })?;
+        Ok(Ipv6Addr::from(octets))
     }
 
-    #[test]
-    fn test_char_conversion() {
-        let char_val = '🚀';
-        let char_bytes = char_val.out_bytes().unwrap();
+    fn type_tag() -> &'static str {
+        "IpAddr"
+    }
+}
 
-        assert_eq!(char::in_bytes(&char_bytes).unwrap(), '🚀');
+impl OutBytes for IpAddr {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        match self {
+            IpAddr::V4(ip) => ip.out_bytes(),
+            IpAddr::V6(ip) => ip.out_bytes(),
+        }
     }
 
-    #[test]
-    fn test_fixed_array_conversions() {
-        // Test [u8; 1]
-        let arr1: [u8; 1] = [42];
-        let arr1_bytes = arr1.out_bytes().unwrap();
-        assert_eq!(<[u8; 1]>::in_bytes(&arr1_bytes).unwrap(), [42]);
+    fn type_tag() -> &'static str {
+        "IpAddr"
+    }
+}
 
-        // Test [u8; 4]
-        let arr4: [u8; 4] = [1, 2, 3, 4];
-        let arr4_bytes = arr4.out_bytes().unwrap();
-        assert_eq!(<[u8; 4]>::in_bytes(&arr4_bytes).unwrap(), [1, 2, 3, 4]);
+impl InBytes for IpAddr {
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        match bytes.len() {
+            4 => Ok(IpAddr::V4(Ipv4Addr::in_bytes(bytes)?)),
+            16 => Ok(IpAddr::V6(Ipv6Addr::in_bytes(bytes)?)),
+            _ => Err(KvsError::SerializationError(
+                "Invalid IpAddr byte length".to_string(),
+            )),
+        }
+    }
 
-        // Test [u8; 16]
-        let arr16: [u8; 16] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
-        let arr16_bytes = arr16.out_bytes().unwrap();
-        assert_eq!(<[u8; 16]>::in_bytes(&arr16_bytes).unwrap(), arr16);
+    fn type_tag() -> &'static str {
+        "IpAddr"
+    }
+}
 
-        // Test [u8; 32]
-        let arr32: [u8; 32] = [
-            1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24,
-            25, 26, 27, 28, 29, 30, 31, 32,
-        ];
-        let arr32_bytes = arr32.out_bytes().unwrap();
-        assert_eq!(<[u8; 32]>::in_bytes(&arr32_bytes).unwrap(), arr32);
+impl OutBytes for SocketAddr {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        let mut bytes = Vec::with_capacity(26);
+        match self {
+            SocketAddr::V4(addr) => {
+                bytes.extend_from_slice(&addr.ip().octets());
+                bytes.extend_from_slice(&addr.port().to_be_bytes());
+            }
+            SocketAddr::V6(addr) => {
+                bytes.extend_from_slice(&addr.ip().octets());
+                bytes.extend_from_slice(&addr.port().to_be_bytes());
+                bytes.extend_from_slice(&addr.flowinfo().to_be_bytes());
+                bytes.extend_from_slice(&addr.scope_id().to_be_bytes());
+            }
+        }
+        Ok(Cow::Owned(bytes))
+    }
 
-        // Test [u8; 64]
-        let arr64: [u8; 64] = [
-            1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24,
-            25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46,
-            47, 48, 49, 50, 51, 52, 53, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63, 64,
-        ];
-        let arr64_bytes = arr64.out_bytes().unwrap();
-        assert_eq!(<[u8; 64]>::in_bytes(&arr64_bytes).unwrap(), arr64);
+    fn type_tag() -> &'static str {
+        "SocketAddr"
     }
+}
 
-    #[test]
-    fn test_fixed_array_error_handling() {
-        // Test with incorrect byte length
-        let result = <[u8; 4]>::in_bytes(&[1, 2, 3]);
-        assert!(result.is_err());
-        if let Err(KvsError::SerializationError(msg)) = result {
-            assert!(msg.contains("Invalid [u8; 4] byte length"));
-            assert!(msg.contains("expected 4 bytes, got 3"));
-        } else {
-            panic!("Expected SerializationError");
+impl InBytes for SocketAddr {
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        match bytes.len() {
+            6 => {
+                let ip = Ipv4Addr::in_bytes(&bytes[..4])?;
+                let port = u16::in_bytes(&bytes[4..6])?;
+                Ok(SocketAddr::V4(SocketAddrV4::new(ip, port)))
+            }
+            26 => {
+                let ip = Ipv6Addr::in_bytes(&bytes[..16])?;
+                let port = u16::in_bytes(&bytes[16..18])?;
+                let flowinfo = u32::in_bytes(&bytes[18..22])?;
+                let scope_id = u32::in_bytes(&bytes[22..26])?;
+                Ok(SocketAddr::V6(SocketAddrV6::new(
+                    ip, port, flowinfo, scope_id,
+                )))
+            }
+            _ => Err(KvsError::SerializationError(
+                "Invalid SocketAddr byte length".to_string(),
+            )),
         }
+    }
 
-        // Test passing array directly
-        let src_arr = [1u8, 2, 3, 4];
-        let result = <[u8; 4]>::in_bytes(&src_arr);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), [1, 2, 3, 4]);
+    fn type_tag() -> &'static str {
+        "SocketAddr"
     }
+}
 
-    #[test]
-    fn test_cow_efficiency() {
-        use std::borrow::Cow;
+// Path and OS string implementations
+//
+// On Unix, OS strings are arbitrary byte sequences and are stored verbatim.
+// On Windows, OS strings are potentially ill-formed UTF-16 and are stored as
+// WTF-8, which is identical to UTF-8 for well-formed strings and encodes
+// unpaired surrogates as three byte sequences. Either way, paths that are
+// valid Unicode are stored as plain UTF-8.
+
+#[cfg(unix)]
+fn os_str_out_bytes(s: &OsStr) -> Result<Cow<'_, [u8]>, KvsError> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(Cow::Borrowed(s.as_bytes()))
+}
 
-        // String slice uses borrowed data - no allocation
-        let str_val = "hello world";
-        let str_bytes = str_val.out_bytes().unwrap();
-        assert!(matches!(str_bytes, Cow::Borrowed(_)));
+#[cfg(unix)]
+fn os_string_in_bytes(bytes: &[u8]) -> Result<OsString, KvsError> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(OsStr::from_bytes(bytes).to_os_string())
+}
 
-        // Byte slice uses borrowed data - no allocation
+#[cfg(windows)]
+fn os_str_out_bytes(s: &OsStr) -> Result<Cow<'_, [u8]>, KvsError> {
+    use std::os::windows::ffi::OsStrExt;
+    Ok(match s.to_str() {
+        Some(s) => Cow::Borrowed(s.as_bytes()),
+        None => Cow::Owned(wtf8_encode(s.encode_wide())),
+    })
+}
+
+#[cfg(windows)]
+fn os_string_in_bytes(bytes: &[u8]) -> Result<OsString, KvsError> {
+    use std::os::windows::ffi::OsStringExt;
+    Ok(OsString::from_wide(&wtf8_decode(bytes)?))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn os_str_out_bytes(s: &OsStr) -> Result<Cow<'_, [u8]>, KvsError> {
+    s.to_str()
+        .map(|s| Cow::Borrowed(s.as_bytes()))
+        .ok_or_else(|| KvsError::SerializationError("OS string is not valid UTF-8".to_string()))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn os_string_in_bytes(bytes: &[u8]) -> Result<OsString, KvsError> {
+    Ok(OsString::from(String::from_utf8(Vec::from(bytes))?))
+}
+
+/// Encodes potentially ill-formed UTF-16 as WTF-8.
+#[cfg(any(windows, test))]
+fn wtf8_encode(units: impl Iterator<Item = u16>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for unit in char::decode_utf16(units) {
+        match unit {
+            Ok(ch) => {
+                let mut buf = [0u8; 4];
+                bytes.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+            }
+            Err(e) => {
+                let u = e.unpaired_surrogate();
+                bytes.extend_from_slice(&[
+                    0xE0 | (u >> 12) as u8,
+                    0x80 | ((u >> 6) & 0x3F) as u8,
+                    0x80 | (u & 0x3F) as u8,
+                ]);
+            }
+        }
+    }
+    bytes
+}
+
+/// Decodes WTF-8 back into potentially ill-formed UTF-16.
+#[cfg(any(windows, test))]
+fn wtf8_decode(mut bytes: &[u8]) -> Result<Vec<u16>, KvsError> {
+    let mut units = Vec::with_capacity(bytes.len());
+    loop {
+        match std::str::from_utf8(bytes) {
+            Ok(s) => {
+                units.extend(s.encode_utf16());
+                return Ok(units);
+            }
+            Err(e) => {
+                let (valid, rest) = bytes.split_at(e.valid_up_to());
+                units.extend(
+                    std::str::from_utf8(valid)
+                        .unwrap_or_default()
+                        .encode_utf16(),
+                );
+                match rest {
+                    [0xED, b1 @ 0xA0..=0xBF, b2 @ 0x80..=0xBF, ..] => {
+                        units.push(0xD000 | ((*b1 as u16 & 0x3F) << 6) | (*b2 as u16 & 0x3F));
+                        bytes = &rest[3..];
+                    }
+                    _ => {
+                        return Err(KvsError::SerializationError(
+                            "Invalid WTF-8 for OS string".to_string(),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Implementation for OS string slices.
+impl OutBytes for &OsStr {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        os_str_out_bytes(self)
+    }
+
+    fn type_tag() -> &'static str {
+        "OsString"
+    }
+}
+
+impl OutBytes for OsString {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        os_str_out_bytes(self)
+    }
+
+    fn type_tag() -> &'static str {
+        "OsString"
+    }
+}
+
+impl InBytes for OsString {
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        os_string_in_bytes(bytes)
+    }
+
+    fn type_tag() -> &'static str {
+        "OsString"
+    }
+}
+
+/// Implementation for path slices.
+impl OutBytes for &Path {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        os_str_out_bytes(self.as_os_str())
+    }
+
+    fn type_tag() -> &'static str {
+        "PathBuf"
+    }
+}
+
+impl OutBytes for PathBuf {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        os_str_out_bytes(self.as_os_str())
+    }
+
+    fn type_tag() -> &'static str {
+        "PathBuf"
+    }
+}
+
+impl InBytes for PathBuf {
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        Ok(PathBuf::from(os_string_in_bytes(bytes)?))
+    }
+
+    fn type_tag() -> &'static str {
+        "PathBuf"
+    }
+}
+
+// Map implementations
+//
+// Small dictionaries are stored as an entry count followed by each entry's
+// key and value, all prefixed with their lengths as big-endian `u32`s.
+// Entries are written in key order, so equal maps give equal bytes.
+
+/// Frames map entries, which must be given in key order.
+fn map_out_bytes<'a>(
+    entries: impl ExactSizeIterator<Item = (&'a str, &'a [u8])>,
+) -> Result<Vec<u8>, KvsError> {
+    let too_long = || KvsError::SerializationError("Map too large to store".to_string());
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(
+        &u32::try_from(entries.len())
+            .map_err(|_| too_long())?
+            .to_be_bytes(),
+    );
+    for (key, value) in entries {
+        for part in [key.as_bytes(), value] {
+            bytes.extend_from_slice(
+                &u32::try_from(part.len())
+                    .map_err(|_| too_long())?
+                    .to_be_bytes(),
+            );
+            bytes.extend_from_slice(part);
+        }
+    }
+    Ok(bytes)
+}
+
+/// Parses map entries framed by [`map_out_bytes`].
+fn map_in_bytes(bytes: &[u8]) -> Result<Vec<(String, &[u8])>, KvsError> {
+    let malformed = || KvsError::SerializationError("Invalid map encoding".to_string());
+    let (count, mut rest) = bytes.split_first_chunk::<4>().ok_or_else(malformed)?;
+    let count = u32::from_be_bytes(*count) as usize;
+    // Each entry takes at least eight bytes, so a corrupt count can't
+    // cause a huge allocation
+    let mut entries = Vec::with_capacity(count.min(rest.len() / 8));
+    for _ in 0..count {
+        let key = split_part(&mut rest).ok_or_else(malformed)?;
+        let value = split_part(&mut rest).ok_or_else(malformed)?;
+        entries.push((String::from_utf8(key.to_vec())?, value));
+    }
+    if !rest.is_empty() {
+        return Err(malformed());
+    }
+    Ok(entries)
+}
+
+/// Splits one length-prefixed part off the front of `bytes`.
+fn split_part<'a>(bytes: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let (len, rest) = bytes.split_first_chunk::<4>()?;
+    let len = u32::from_be_bytes(*len) as usize;
+    if rest.len() < len {
+        return None;
+    }
+    let (part, rest) = rest.split_at(len);
+    *bytes = rest;
+    Some(part)
+}
+
+/// Implementation for string dictionaries, stored with length-prefixed
+/// framing in key order.
+impl OutBytes for HashMap<String, String> {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        let mut entries: Vec<(&str, &[u8])> = self
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_bytes()))
+            .collect();
+        entries.sort_unstable_by_key(|&(key, _)| key);
+        map_out_bytes(entries.into_iter()).map(Cow::Owned)
+    }
+
+    fn type_tag() -> &'static str {
+        "StringMap"
+    }
+}
+
+impl InBytes for HashMap<String, String> {
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        map_in_bytes(bytes)?
+            .into_iter()
+            .map(|(key, value)| Ok((key, String::from_utf8(value.to_vec())?)))
+            .collect()
+    }
+
+    fn type_tag() -> &'static str {
+        "StringMap"
+    }
+}
+
+/// Implementation for binary dictionaries, stored with length-prefixed
+/// framing in key order.
+impl OutBytes for BTreeMap<String, Vec<u8>> {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        map_out_bytes(
+            self.iter()
+                .map(|(key, value)| (key.as_str(), value.as_slice())),
+        )
+        .map(Cow::Owned)
+    }
+
+    fn type_tag() -> &'static str {
+        "BytesMap"
+    }
+}
+
+impl InBytes for BTreeMap<String, Vec<u8>> {
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        Ok(map_in_bytes(bytes)?
+            .into_iter()
+            .map(|(key, value)| (key, value.to_vec()))
+            .collect())
+    }
+
+    fn type_tag() -> &'static str {
+        "BytesMap"
+    }
+}
+
+// List implementations
+
+/// Wrapper for storing a list of any convertible element type.
+///
+/// Elements are stored as a count followed by each element's bytes, all
+/// prefixed with their lengths as big-endian `u32`s. `Vec<u8>` is already
+/// stored as raw bytes, so other lists go through this wrapper.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::convert::List;
+/// use zep_kvs::prelude::*;
+///
+/// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+/// store.store("recent", List(vec!["a.txt", "b.txt"]))?;
+/// let List(recent): List<String> = store.retrieve("recent")?.unwrap();
+/// assert_eq!(recent, ["a.txt", "b.txt"]);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct List<T>(pub Vec<T>);
+
+impl<T: OutBytes> OutBytes for List<T> {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        let too_long = || KvsError::SerializationError("List too large to store".to_string());
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(
+            &u32::try_from(self.0.len())
+                .map_err(|_| too_long())?
+                .to_be_bytes(),
+        );
+        for element in &self.0 {
+            let mut element = element.out_bytes()?;
+            bytes.extend_from_slice(
+                &u32::try_from(element.len())
+                    .map_err(|_| too_long())?
+                    .to_be_bytes(),
+            );
+            bytes.extend_from_slice(&element);
+            if let (Cow::Owned(element), true) = (&mut element, T::is_sensitive()) {
+                wipe(element);
+            }
+        }
+        Ok(Cow::Owned(bytes))
+    }
+
+    fn type_tag() -> &'static str {
+        "List"
+    }
+
+    fn is_sensitive() -> bool {
+        T::is_sensitive()
+    }
+}
+
+impl<T: InBytes> InBytes for List<T> {
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        let malformed = || KvsError::SerializationError("Invalid list encoding".to_string());
+        let (count, mut rest) = bytes.split_first_chunk::<4>().ok_or_else(malformed)?;
+        let count = u32::from_be_bytes(*count) as usize;
+        // Each element takes at least four bytes, so a corrupt count can't
+        // cause a huge allocation
+        let mut elements = Vec::with_capacity(count.min(rest.len() / 4));
+        for _ in 0..count {
+            let element = split_part(&mut rest).ok_or_else(malformed)?;
+            elements.push(T::in_bytes(element)?);
+        }
+        if !rest.is_empty() {
+            return Err(malformed());
+        }
+        Ok(List(elements))
+    }
+
+    fn type_tag() -> &'static str {
+        "List"
+    }
+
+    fn is_sensitive() -> bool {
+        T::is_sensitive()
+    }
+}
+
+// Date and time implementations
+//
+// `SystemTime`, `chrono::DateTime<Utc>` and `time::OffsetDateTime` share a
+// fixed 12 byte encoding: whole seconds since the Unix epoch as a big-endian
+// `i64`, followed by the sub-second nanoseconds as a big-endian `u32`. Values
+// written as one type can be read back as another, and the encoding does not
+// depend on either crate's version.
+
+fn timestamp_out_bytes(secs: i64, nanos: u32) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(12);
+    bytes.extend_from_slice(&secs.to_be_bytes());
+    bytes.extend_from_slice(&nanos.to_be_bytes());
+    bytes
+}
+
+fn timestamp_in_bytes(bytes: &[u8], type_name: &str) -> Result<(i64, u32), KvsError> {
+    if bytes.len() != 12 {
+        return Err(KvsError::SerializationError(format!(
+            "Invalid {type_name} byte length"
+        )));
+    }
+    let mut secs = [0u8; 8];
+    let mut nanos = [0u8; 4];
+    secs.copy_from_slice(&bytes[..8]);
+    nanos.copy_from_slice(&bytes[8..]);
+    Ok((i64::from_be_bytes(secs), u32::from_be_bytes(nanos)))
+}
+
+/// Implementation for system times, stored as Unix seconds and nanoseconds.
+///
+/// Times before the epoch are stored with negative seconds and positive
+/// nanoseconds, as the date-time crates do.
+impl OutBytes for SystemTime {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        let out_of_range = || KvsError::SerializationError("SystemTime out of range".to_string());
+        let (secs, nanos) = match self.duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(after) => (
+                i64::try_from(after.as_secs()).map_err(|_| out_of_range())?,
+                after.subsec_nanos(),
+            ),
+            Err(before) => {
+                let before = before.duration();
+                let secs = i64::try_from(before.as_secs()).map_err(|_| out_of_range())?;
+                match before.subsec_nanos() {
+                    0 => (-secs, 0),
+                    nanos => (-secs - 1, 1_000_000_000 - nanos),
+                }
+            }
+        };
+        Ok(Cow::Owned(timestamp_out_bytes(secs, nanos)))
+    }
+
+    fn type_tag() -> &'static str {
+        "Timestamp"
+    }
+}
+
+impl InBytes for SystemTime {
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        let (secs, nanos) = timestamp_in_bytes(bytes, "SystemTime")?;
+        if nanos >= 1_000_000_000 {
+            return Err(KvsError::SerializationError(
+                "Invalid SystemTime nanoseconds".to_string(),
+            ));
+        }
+        let whole = Duration::from_secs(secs.unsigned_abs());
+        let time = if secs >= 0 {
+            SystemTime::UNIX_EPOCH.checked_add(whole)
+        } else {
+            SystemTime::UNIX_EPOCH.checked_sub(whole)
+        };
+        time.and_then(|t| t.checked_add(Duration::from_nanos(nanos as u64)))
+            .ok_or_else(|| KvsError::SerializationError("SystemTime out of range".to_string()))
+    }
+
+    fn type_tag() -> &'static str {
+        "Timestamp"
+    }
+}
+
+/// Implementation for UTC timestamps, stored as Unix seconds and nanoseconds.
+#[cfg(feature = "chrono")]
+impl OutBytes for chrono::DateTime<chrono::Utc> {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        Ok(Cow::Owned(timestamp_out_bytes(
+            self.timestamp(),
+            self.timestamp_subsec_nanos(),
+        )))
+    }
+
+    fn type_tag() -> &'static str {
+        "Timestamp"
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl InBytes for chrono::DateTime<chrono::Utc> {
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        let (secs, nanos) = timestamp_in_bytes(bytes, "DateTime<Utc>")?;
+        chrono::DateTime::from_timestamp(secs, nanos)
+            .ok_or_else(|| KvsError::SerializationError("DateTime<Utc> out of range".to_string()))
+    }
+
+    fn type_tag() -> &'static str {
+        "Timestamp"
+    }
+}
+
+/// Implementation for offset date times, stored as Unix seconds and nanoseconds.
+///
+/// The UTC offset is not stored; retrieved values are always in UTC.
+#[cfg(feature = "time")]
+impl OutBytes for time::OffsetDateTime {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        Ok(Cow::Owned(timestamp_out_bytes(
+            self.unix_timestamp(),
+            self.nanosecond(),
+        )))
+    }
+
+    fn type_tag() -> &'static str {
+        "Timestamp"
+    }
+}
+
+#[cfg(feature = "time")]
+impl InBytes for time::OffsetDateTime {
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        let (secs, nanos) = timestamp_in_bytes(bytes, "OffsetDateTime")?;
+        if nanos >= 1_000_000_000 {
+            return Err(KvsError::SerializationError(
+                "Invalid OffsetDateTime nanoseconds".to_string(),
+            ));
+        }
+        time::OffsetDateTime::from_unix_timestamp_nanos(
+            secs as i128 * 1_000_000_000 + nanos as i128,
+        )
+        .map_err(|_| KvsError::SerializationError("OffsetDateTime out of range".to_string()))
+    }
+
+    fn type_tag() -> &'static str {
+        "Timestamp"
+    }
+}
+
+/// Implementation for UUIDs, stored as their 16 raw bytes.
+#[cfg(feature = "uuid")]
+impl OutBytes for uuid::Uuid {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        Ok(Cow::Borrowed(self.as_bytes()))
+    }
+
+    fn type_tag() -> &'static str {
+        "Uuid"
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl InBytes for uuid::Uuid {
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        uuid::Uuid::from_slice(bytes)
+            .map_err(|_| KvsError::SerializationError("Invalid Uuid byte length".to_string()))
+    }
+
+    fn type_tag() -> &'static str {
+        "Uuid"
+    }
+}
+
+/// Implementation for exact decimals, stored as the 16 bytes of
+/// `Decimal::serialize`: flags with the sign and scale, then the 96-bit
+/// mantissa, all little-endian.
+#[cfg(feature = "rust_decimal")]
+impl OutBytes for rust_decimal::Decimal {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        Ok(Cow::Owned(self.serialize().to_vec()))
+    }
+
+    fn type_tag() -> &'static str {
+        "Decimal"
+    }
+}
+
+#[cfg(feature = "rust_decimal")]
+impl InBytes for rust_decimal::Decimal {
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        let bytes: [u8; 16] = bytes
+            .try_into()
+            .map_err(|_| KvsError::SerializationError("Invalid Decimal byte length".to_string()))?;
+        let flags = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        if flags & 0x7F00_FFFF != 0 || (flags >> 16) & 0xFF > rust_decimal::Decimal::MAX_SCALE {
+            return Err(KvsError::SerializationError(
+                "Invalid Decimal flags".to_string(),
+            ));
+        }
+        Ok(rust_decimal::Decimal::deserialize(bytes))
+    }
+
+    fn type_tag() -> &'static str {
+        "Decimal"
+    }
+}
+
+/// Implementation for arbitrary-precision integers, stored as big-endian
+/// two's complement bytes.
+#[cfg(feature = "num-bigint")]
+impl OutBytes for num_bigint::BigInt {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        Ok(Cow::Owned(self.to_signed_bytes_be()))
+    }
+
+    fn type_tag() -> &'static str {
+        "BigInt"
+    }
+}
+
+#[cfg(feature = "num-bigint")]
+impl InBytes for num_bigint::BigInt {
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        Ok(num_bigint::BigInt::from_signed_bytes_be(bytes))
+    }
+
+    fn type_tag() -> &'static str {
+        "BigInt"
+    }
+}
+
+/// Implementation for semantic versions, stored as their text form.
+///
+/// Versions share the `String` type tag, so a version previously stored as
+/// a string, such as the last version of the application that ran, can be
+/// retrieved as a `semver::Version` directly.
+#[cfg(feature = "semver")]
+impl OutBytes for semver::Version {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        Ok(Cow::Owned(self.to_string().into_bytes()))
+    }
+
+    fn type_tag() -> &'static str {
+        "String"
+    }
+}
+
+#[cfg(feature = "semver")]
+impl InBytes for semver::Version {
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        std::str::from_utf8(bytes)
+            .map_err(|e| KvsError::SerializationError(e.to_string()))?
+            .parse()
+            .map_err(|e: semver::Error| KvsError::SerializationError(e.to_string()))
+    }
+
+    fn type_tag() -> &'static str {
+        "String"
+    }
+}
+
+/// Wrapper for storing a `bitflags` type as its underlying integer.
+///
+/// The value is stored exactly like its bits, and shares their type tag,
+/// so flags written with a manual cast can be read through the wrapper.
+/// Reading fails if any bit is set that the type doesn't define, so a set
+/// of flags written by a newer version isn't silently truncated.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::convert::Flags;
+/// use zep_kvs::prelude::*;
+///
+/// bitflags::bitflags! {
+///     #[derive(Debug, Clone, Copy, PartialEq)]
+///     struct Features: u32 {
+///         const BETA = 1;
+///         const TELEMETRY = 1 << 1;
+///     }
+/// }
+///
+/// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+/// store.store("features", Flags(Features::BETA | Features::TELEMETRY))?;
+/// let Flags(features): Flags<Features> = store.retrieve("features")?.unwrap();
+/// assert!(features.contains(Features::BETA));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[cfg(feature = "bitflags")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Flags<T>(pub T);
+
+#[cfg(feature = "bitflags")]
+impl<T> OutBytes for Flags<T>
+where
+    T: bitflags::Flags,
+    T::Bits: OutBytes,
+{
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        Ok(Cow::Owned(self.0.bits().out_bytes()?.into_owned()))
+    }
+
+    fn type_tag() -> &'static str {
+        <T::Bits as OutBytes>::type_tag()
+    }
+}
+
+#[cfg(feature = "bitflags")]
+impl<T> InBytes for Flags<T>
+where
+    T: bitflags::Flags,
+    T::Bits: InBytes,
+{
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        T::from_bits(T::Bits::in_bytes(bytes)?)
+            .map(Flags)
+            .ok_or_else(|| KvsError::SerializationError("Unknown flags are set".to_string()))
+    }
+
+    fn type_tag() -> &'static str {
+        <T::Bits as InBytes>::type_tag()
+    }
+}
+
+// JSON implementations
+
+/// Wrapper for storing any serde-compatible value as JSON.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::convert::Json;
+/// use zep_kvs::prelude::*;
+///
+/// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+/// store.store("window", Json((800u32, 600u32)))?;
+/// let Json(size): Json<(u32, u32)> = store.retrieve("window")?.unwrap();
+/// assert_eq!(size, (800, 600));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Json<T>(pub T);
+
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize> OutBytes for Json<T> {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        serde_json::to_vec(&self.0)
+            .map(Cow::Owned)
+            .map_err(|e| KvsError::SerializationError(e.to_string()))
+    }
+
+    fn type_tag() -> &'static str {
+        "Json"
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: serde::de::DeserializeOwned> InBytes for Json<T> {
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        serde_json::from_slice(bytes)
+            .map(Json)
+            .map_err(|e| KvsError::SerializationError(e.to_string()))
+    }
+
+    fn type_tag() -> &'static str {
+        "Json"
+    }
+}
+
+/// Implementation for semi-structured JSON values, stored as JSON text.
+#[cfg(feature = "serde")]
+impl OutBytes for serde_json::Value {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        serde_json::to_vec(self)
+            .map(Cow::Owned)
+            .map_err(|e| KvsError::SerializationError(e.to_string()))
+    }
+
+    fn type_tag() -> &'static str {
+        "Json"
+    }
+}
+
+#[cfg(feature = "serde")]
+impl InBytes for serde_json::Value {
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        Json::in_bytes(bytes).map(|Json(value)| value)
+    }
+
+    fn type_tag() -> &'static str {
+        "Json"
+    }
+}
+
+// MessagePack implementations
+
+/// Wrapper for storing any serde-compatible value as MessagePack.
+///
+/// MessagePack is more compact than JSON and faster to parse. Structs are
+/// encoded as maps keyed by field name, so fields can be added later the
+/// same way as with JSON. With type tags, the value's envelope records it
+/// as `MessagePack`, so retrieving it as `Json` fails with a type mismatch.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::convert::MessagePack;
+/// use zep_kvs::prelude::*;
+///
+/// let mut store = KeyValueStore::<scope::Ephemeral>::new()?.with_type_tags();
+/// store.store("window", MessagePack((800u32, 600u32)))?;
+/// let MessagePack(size): MessagePack<(u32, u32)> = store.retrieve("window")?.unwrap();
+/// assert_eq!(size, (800, 600));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[cfg(feature = "rmp-serde")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessagePack<T>(pub T);
+
+#[cfg(feature = "rmp-serde")]
+impl<T: serde::Serialize> OutBytes for MessagePack<T> {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        rmp_serde::to_vec_named(&self.0)
+            .map(Cow::Owned)
+            .map_err(|e| KvsError::SerializationError(e.to_string()))
+    }
+
+    fn type_tag() -> &'static str {
+        "MessagePack"
+    }
+}
+
+#[cfg(feature = "rmp-serde")]
+impl<T: serde::de::DeserializeOwned> InBytes for MessagePack<T> {
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        rmp_serde::from_slice(bytes)
+            .map(MessagePack)
+            .map_err(|e| KvsError::SerializationError(e.to_string()))
+    }
+
+    fn type_tag() -> &'static str {
+        "MessagePack"
+    }
+}
+
+// CBOR implementations
+
+/// Wrapper for storing any serde-compatible value as CBOR (RFC 8949).
+///
+/// CBOR is a standard binary encoding that needs no schema, so exported
+/// stores can be inspected with generic CBOR tools in other languages.
+/// With type tags, the value's envelope records it as `Cbor`.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::convert::Cbor;
+/// use zep_kvs::prelude::*;
+///
+/// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+/// store.store("window", Cbor((800u32, 600u32)))?;
+/// let Cbor(size): Cbor<(u32, u32)> = store.retrieve("window")?.unwrap();
+/// assert_eq!(size, (800, 600));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[cfg(feature = "ciborium")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cbor<T>(pub T);
+
+#[cfg(feature = "ciborium")]
+impl<T: serde::Serialize> OutBytes for Cbor<T> {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(&self.0, &mut bytes)
+            .map_err(|e| KvsError::SerializationError(e.to_string()))?;
+        Ok(Cow::Owned(bytes))
+    }
+
+    fn type_tag() -> &'static str {
+        "Cbor"
+    }
+}
+
+#[cfg(feature = "ciborium")]
+impl<T: serde::de::DeserializeOwned> InBytes for Cbor<T> {
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        ciborium::from_reader(bytes)
+            .map(Cbor)
+            .map_err(|e| KvsError::SerializationError(e.to_string()))
+    }
+
+    fn type_tag() -> &'static str {
+        "Cbor"
+    }
+}
+
+// Protobuf implementations
+
+/// Wrapper for storing any [`prost::Message`] in its protobuf wire format.
+///
+/// Records defined as protobufs shared with other services can be stored
+/// as they are sent, and remain readable after fields are added on either
+/// side. With type tags, the value's envelope records it as `Protobuf`.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::convert::Protobuf;
+/// use zep_kvs::prelude::*;
+///
+/// #[derive(Clone, PartialEq, prost::Message)]
+/// struct Session {
+///     #[prost(string, tag = "1")]
+///     user: String,
+///     #[prost(uint64, tag = "2")]
+///     expires: u64,
+/// }
+///
+/// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+/// let session = Session { user: "alice".into(), expires: 1_700_000_000 };
+/// store.store("session", Protobuf(session.clone()))?;
+/// let Protobuf(stored): Protobuf<Session> = store.retrieve("session")?.unwrap();
+/// assert_eq!(stored, session);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[cfg(feature = "prost")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Protobuf<T>(pub T);
+
+#[cfg(feature = "prost")]
+impl<T: prost::Message> OutBytes for Protobuf<T> {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        Ok(Cow::Owned(self.0.encode_to_vec()))
+    }
+
+    fn type_tag() -> &'static str {
+        "Protobuf"
+    }
+}
+
+#[cfg(feature = "prost")]
+impl<T: prost::Message + Default> InBytes for Protobuf<T> {
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        T::decode(bytes)
+            .map(Protobuf)
+            .map_err(|e| KvsError::SerializationError(e.to_string()))
+    }
+
+    fn type_tag() -> &'static str {
+        "Protobuf"
+    }
+}
+
+// rkyv implementations
+
+/// Validator used to check archived values before they are accessed.
+#[cfg(feature = "rkyv")]
+type RkyvValidator<'a> = rkyv::api::high::HighValidator<'a, rkyv::rancor::Error>;
+
+/// Wrapper for storing any [`rkyv::Archive`] type in rkyv's archived form.
+///
+/// Values stored this way can be read back either deserialized, as
+/// `Rkyv<T>`, or without deserialization, as an [`ArchivedValue<T>`].
+/// With type tags, the value's envelope records it as `Rkyv`.
+#[cfg(feature = "rkyv")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rkyv<T>(pub T);
+
+#[cfg(feature = "rkyv")]
+impl<T> OutBytes for Rkyv<T>
+where
+    T: for<'a> rkyv::Serialize<
+            rkyv::api::high::HighSerializer<
+                rkyv::util::AlignedVec,
+                rkyv::ser::allocator::ArenaHandle<'a>,
+                rkyv::rancor::Error,
+            >,
+        >,
+{
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        rkyv::to_bytes::<rkyv::rancor::Error>(&self.0)
+            .map(|bytes| Cow::Owned(bytes.into_vec()))
+            .map_err(|e| KvsError::SerializationError(e.to_string()))
+    }
+
+    fn type_tag() -> &'static str {
+        "Rkyv"
+    }
+}
+
+#[cfg(feature = "rkyv")]
+impl<T> InBytes for Rkyv<T>
+where
+    T: rkyv::Archive,
+    T::Archived: for<'a> rkyv::bytecheck::CheckBytes<RkyvValidator<'a>>
+        + rkyv::Deserialize<T, rkyv::api::high::HighDeserializer<rkyv::rancor::Error>>,
+{
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        let archived = ArchivedValue::<T>::in_bytes(bytes)?;
+        rkyv::deserialize::<T, rkyv::rancor::Error>(archived.get())
+            .map(Rkyv)
+            .map_err(|e| KvsError::SerializationError(e.to_string()))
+    }
+
+    fn type_tag() -> &'static str {
+        "Rkyv"
+    }
+}
+
+/// A validated, archived view of a value stored as [`Rkyv<T>`].
+///
+/// Retrieving an `ArchivedValue<T>` checks the stored bytes once and then
+/// gives access to the archived value in place, skipping deserialization.
+/// This suits hot-path reads of large structured values, where only a few
+/// fields are needed. The bytes are copied once into an aligned buffer.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::convert::{ArchivedValue, Rkyv};
+/// use zep_kvs::prelude::*;
+///
+/// #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+/// struct Catalog {
+///     names: Vec<String>,
+/// }
+///
+/// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+/// let catalog = Catalog { names: vec!["alpha".into(), "beta".into()] };
+/// store.store("catalog", Rkyv(catalog))?;
+///
+/// let view: ArchivedValue<Catalog> = store.retrieve("catalog")?.unwrap();
+/// assert_eq!(view.get().names[1], "beta");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[cfg(feature = "rkyv")]
+pub struct ArchivedValue<T: rkyv::Archive> {
+    /// Bytes that have been validated as an archived `T`.
+    bytes: rkyv::util::AlignedVec,
+    value: std::marker::PhantomData<T>,
+}
+
+#[cfg(feature = "rkyv")]
+impl<T: rkyv::Archive> ArchivedValue<T> {
+    /// Returns the archived value.
+    pub fn get(&self) -> &T::Archived {
+        // SAFETY: `bytes` was validated as an archived `T` when this value
+        // was created, and is never modified afterwards.
+        unsafe { rkyv::access_unchecked::<T::Archived>(&self.bytes) }
+    }
+}
+
+#[cfg(feature = "rkyv")]
+impl<T: rkyv::Archive> std::fmt::Debug for ArchivedValue<T>
+where
+    T::Archived: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ArchivedValue").field(self.get()).finish()
+    }
+}
+
+#[cfg(feature = "rkyv")]
+impl<T> InBytes for ArchivedValue<T>
+where
+    T: rkyv::Archive,
+    T::Archived: for<'a> rkyv::bytecheck::CheckBytes<RkyvValidator<'a>>,
+{
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        let mut aligned = rkyv::util::AlignedVec::with_capacity(bytes.len());
+        aligned.extend_from_slice(bytes);
+        rkyv::access::<T::Archived, rkyv::rancor::Error>(&aligned)
+            .map_err(|e| KvsError::SerializationError(e.to_string()))?;
+        Ok(Self {
+            bytes: aligned,
+            value: std::marker::PhantomData,
+        })
+    }
+
+    fn type_tag() -> &'static str {
+        "Rkyv"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bool_conversion() {
+        let true_val = true;
+        let false_val = false;
+
+        let true_bytes = true_val.out_bytes().unwrap();
+        let false_bytes = false_val.out_bytes().unwrap();
+
+        assert_eq!(true_bytes.as_ref(), &[1]);
+        assert_eq!(false_bytes.as_ref(), &[0]);
+
+        assert!(bool::in_bytes(&[1]).unwrap());
+        assert!(!bool::in_bytes(&[0]).unwrap());
+    }
+
+    #[test]
+    fn test_integer_conversions() {
+        let i32_val = 42i32;
+        let u64_val = 1234567890u64;
+
+        let i32_bytes = i32_val.out_bytes().unwrap();
+        let u64_bytes = u64_val.out_bytes().unwrap();
+
+        assert_eq!(i32::in_bytes(&i32_bytes).unwrap(), 42i32);
+        assert_eq!(u64::in_bytes(&u64_bytes).unwrap(), 1234567890u64);
+    }
+
+    #[test]
+    fn test_float_conversions() {
+        let f32_val = std::f32::consts::PI;
+        let f64_val = std::f64::consts::E;
+
+        let f32_bytes = f32_val.out_bytes().unwrap();
+        let f64_bytes = f64_val.out_bytes().unwrap();
+
+        assert_eq!(f32::in_bytes(&f32_bytes).unwrap(), std::f32::consts::PI);
+        assert_eq!(f64::in_bytes(&f64_bytes).unwrap(), std::f64::consts::E);
+    }
+
+    #[test]
+    fn test_char_conversion() {
+        let char_val = '🚀';
+        let char_bytes = char_val.out_bytes().unwrap();
+
+        assert_eq!(char::in_bytes(&char_bytes).unwrap(), '🚀');
+    }
+
+    #[test]
+    fn test_fixed_array_conversions() {
+        // Test [u8; 1]
+        let arr1: [u8; 1] = [42];
+        let arr1_bytes = arr1.out_bytes().unwrap();
+        assert_eq!(<[u8; 1]>::in_bytes(&arr1_bytes).unwrap(), [42]);
+
+        // Test [u8; 4]
+        let arr4: [u8; 4] = [1, 2, 3, 4];
+        let arr4_bytes = arr4.out_bytes().unwrap();
+        assert_eq!(<[u8; 4]>::in_bytes(&arr4_bytes).unwrap(), [1, 2, 3, 4]);
+
+        // Test [u8; 16]
+        let arr16: [u8; 16] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+        let arr16_bytes = arr16.out_bytes().unwrap();
+        assert_eq!(<[u8; 16]>::in_bytes(&arr16_bytes).unwrap(), arr16);
+
+        // Test [u8; 32]
+        let arr32: [u8; 32] = [
+            1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24,
+            25, 26, 27, 28, 29, 30, 31, 32,
+        ];
+        let arr32_bytes = arr32.out_bytes().unwrap();
+        assert_eq!(<[u8; 32]>::in_bytes(&arr32_bytes).unwrap(), arr32);
+
+        // Test [u8; 64]
+        let arr64: [u8; 64] = [
+            1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24,
+            25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46,
+            47, 48, 49, 50, 51, 52, 53, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63, 64,
+        ];
+        let arr64_bytes = arr64.out_bytes().unwrap();
+        assert_eq!(<[u8; 64]>::in_bytes(&arr64_bytes).unwrap(), arr64);
+    }
+
+    #[test]
+    fn test_fixed_array_error_handling() {
+        // Test with incorrect byte length
+        let result = <[u8; 4]>::in_bytes(&[1, 2, 3]);
+        assert!(result.is_err());
+        if let Err(KvsError::SerializationError(msg)) = result {
+            assert!(msg.contains("Invalid [u8; 4] byte length"));
+            assert!(msg.contains("expected 4 bytes, got 3"));
+        } else {
+            panic!("Expected SerializationError");
+        }
+
+        // Test passing array directly
+        let src_arr = [1u8, 2, 3, 4];
+        let result = <[u8; 4]>::in_bytes(&src_arr);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_primitive_type_tags_are_fixed() {
+        assert_eq!(<bool as OutBytes>::type_tag(), "bool");
+        assert_eq!(<char as InBytes>::type_tag(), "char");
+        assert_eq!(<i64 as OutBytes>::type_tag(), "i64");
+        assert_eq!(<usize as InBytes>::type_tag(), "usize");
+        assert_eq!(<f32 as OutBytes>::type_tag(), "f32");
+    }
+
+    #[test]
+    fn test_cow_efficiency() {
+        use std::borrow::Cow;
+
+        // String slice uses borrowed data - no allocation
+        let str_val = "hello world";
+        let str_bytes = str_val.out_bytes().unwrap();
+        assert!(matches!(str_bytes, Cow::Borrowed(_)));
+
+        // Byte slice uses borrowed data - no allocation
         let byte_val: &[u8] = b"hello world";
         let byte_bytes = byte_val.out_bytes().unwrap();
         assert!(matches!(byte_bytes, Cow::Borrowed(_)));
@@ -555,4 +2040,394 @@ mod tests {
         assert!(matches!(arr_bytes, Cow::Borrowed(_)));
         assert_eq!(arr_bytes.len(), 4);
     }
+
+    #[test]
+    fn test_system_time_conversion() {
+        let after = SystemTime::UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789);
+        let bytes = after.out_bytes().unwrap();
+        assert_eq!(bytes.len(), 12);
+        assert_eq!(SystemTime::in_bytes(&bytes).unwrap(), after);
+
+        // 41.999999993 seconds before the epoch is -42 seconds plus 7 nanoseconds
+        let before = SystemTime::UNIX_EPOCH - Duration::new(41, 999_999_993);
+        let bytes = before.out_bytes().unwrap();
+        assert_eq!(&bytes[..8], &(-42i64).to_be_bytes());
+        assert_eq!(&bytes[8..], &7u32.to_be_bytes());
+        assert_eq!(SystemTime::in_bytes(&bytes).unwrap(), before);
+
+        assert!(SystemTime::in_bytes(&[0u8; 8]).is_err());
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_chrono_conversion() {
+        let now = chrono::DateTime::from_timestamp(1_700_000_000, 123_456_789).unwrap();
+        let bytes = now.out_bytes().unwrap();
+        assert_eq!(bytes.len(), 12);
+        assert_eq!(
+            chrono::DateTime::<chrono::Utc>::in_bytes(&bytes).unwrap(),
+            now
+        );
+        assert!(chrono::DateTime::<chrono::Utc>::in_bytes(&[0u8; 8]).is_err());
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn test_time_conversion() {
+        let now =
+            time::OffsetDateTime::from_unix_timestamp_nanos(1_700_000_000_123_456_789).unwrap();
+        let bytes = now.out_bytes().unwrap();
+        assert_eq!(bytes.len(), 12);
+        assert_eq!(time::OffsetDateTime::in_bytes(&bytes).unwrap(), now);
+        assert!(time::OffsetDateTime::in_bytes(&[0u8; 8]).is_err());
+    }
+
+    #[cfg(all(feature = "chrono", feature = "time"))]
+    #[test]
+    fn test_chrono_and_time_share_encoding() {
+        let chrono_val = chrono::DateTime::from_timestamp(-42, 7).unwrap();
+        let bytes = chrono_val.out_bytes().unwrap();
+        let time_val = time::OffsetDateTime::in_bytes(&bytes).unwrap();
+        assert_eq!(time_val.unix_timestamp(), -42);
+        assert_eq!(time_val.nanosecond(), 7);
+    }
+
+    #[cfg(feature = "rust_decimal")]
+    #[test]
+    fn test_decimal_conversion() {
+        for text in [
+            "0",
+            "-1.50",
+            "79228162514264337593543950335",
+            "0.0000000000000000000000000001",
+        ] {
+            let value: rust_decimal::Decimal = text.parse().unwrap();
+            let bytes = value.out_bytes().unwrap();
+            assert_eq!(bytes.len(), 16);
+            let restored = rust_decimal::Decimal::in_bytes(&bytes).unwrap();
+            // Scale is kept, so trailing zeros survive the round trip
+            assert_eq!(restored.to_string(), text);
+        }
+        assert!(rust_decimal::Decimal::in_bytes(&[0u8; 15]).is_err());
+        let mut bad_scale = [0u8; 16];
+        bad_scale[2] = 29;
+        assert!(rust_decimal::Decimal::in_bytes(&bad_scale).is_err());
+    }
+
+    #[cfg(feature = "num-bigint")]
+    #[test]
+    fn test_big_int_conversion() {
+        let big: num_bigint::BigInt = "-123456789012345678901234567890".parse().unwrap();
+        let bytes = big.out_bytes().unwrap();
+        assert_eq!(num_bigint::BigInt::in_bytes(&bytes).unwrap(), big);
+        let one = num_bigint::BigInt::from(1);
+        assert_eq!(one.out_bytes().unwrap().as_ref(), [1]);
+        assert_eq!(
+            num_bigint::BigInt::from(-1).out_bytes().unwrap().as_ref(),
+            [0xff]
+        );
+    }
+
+    #[cfg(feature = "uuid")]
+    #[test]
+    fn test_uuid_conversion() {
+        let id = uuid::Uuid::from_u128(0x0123_4567_89ab_cdef_0123_4567_89ab_cdef);
+        let bytes = id.out_bytes().unwrap();
+        assert!(matches!(bytes, Cow::Borrowed(_)));
+        assert_eq!(bytes.len(), 16);
+        assert_eq!(uuid::Uuid::in_bytes(&bytes).unwrap(), id);
+        assert!(uuid::Uuid::in_bytes(&[0u8; 15]).is_err());
+    }
+
+    #[test]
+    fn test_map_conversions() {
+        let strings = HashMap::from([
+            ("theme".to_string(), "dark".to_string()),
+            ("lang".to_string(), "en".to_string()),
+        ]);
+        let bytes = strings.out_bytes().unwrap();
+        assert_eq!(
+            bytes.as_ref(),
+            b"\0\0\0\x02\0\0\0\x04lang\0\0\0\x02en\0\0\0\x05theme\0\0\0\x04dark"
+        );
+        assert_eq!(
+            HashMap::<String, String>::in_bytes(&bytes).unwrap(),
+            strings
+        );
+
+        let blobs = BTreeMap::from([
+            ("empty".to_string(), Vec::new()),
+            ("icon".to_string(), vec![0xFF, 0x00]),
+        ]);
+        let bytes = blobs.out_bytes().unwrap();
+        assert_eq!(
+            BTreeMap::<String, Vec<u8>>::in_bytes(&bytes).unwrap(),
+            blobs
+        );
+        assert!(
+            BTreeMap::<String, Vec<u8>>::in_bytes(&[0; 4])
+                .unwrap()
+                .is_empty()
+        );
+
+        assert!(BTreeMap::<String, Vec<u8>>::in_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(BTreeMap::<String, Vec<u8>>::in_bytes(&[bytes.as_ref(), &[0]].concat()).is_err());
+        assert!(BTreeMap::<String, Vec<u8>>::in_bytes(&[0xFF; 4]).is_err());
+        assert!(HashMap::<String, String>::in_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_list_conversion() {
+        let ids = List(vec![1u32, 2, 300]);
+        let bytes = ids.out_bytes().unwrap();
+        assert_eq!(
+            bytes.as_ref(),
+            [
+                0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0, 1, 0, 0, 0, 4, 0, 0, 0, 2, 0, 0, 0, 4, 0, 0, 1, 44
+            ]
+        );
+        assert_eq!(List::<u32>::in_bytes(&bytes).unwrap(), ids);
+
+        let names = List(vec!["", "alice"]);
+        let bytes = names.out_bytes().unwrap();
+        assert_eq!(List::<String>::in_bytes(&bytes).unwrap().0, ["", "alice"]);
+        assert!(List::<f64>::in_bytes(&[0; 4]).unwrap().0.is_empty());
+
+        assert!(List::<u32>::in_bytes(&bytes).is_err());
+        assert!(List::<String>::in_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(List::<String>::in_bytes(&[0xFF; 4]).is_err());
+    }
+
+    #[cfg(feature = "semver")]
+    #[test]
+    fn test_semver_conversion() {
+        let version = semver::Version::parse("1.2.3-beta.1+build.5").unwrap();
+        let bytes = version.out_bytes().unwrap();
+        assert_eq!(bytes.as_ref(), b"1.2.3-beta.1+build.5");
+        assert_eq!(semver::Version::in_bytes(&bytes).unwrap(), version);
+        assert!(semver::Version::in_bytes(b"1.2").is_err());
+        assert!(semver::Version::in_bytes(&[0xFF]).is_err());
+
+        // Versions stored as strings can be compared after an upgrade
+        let mut store = crate::api::KeyValueStore::<crate::api::scope::Ephemeral>::new()
+            .unwrap()
+            .with_type_tags();
+        store.store("last_run", "0.9.0").unwrap();
+        let last: semver::Version = store.retrieve("last_run").unwrap().unwrap();
+        assert!(last < version);
+    }
+
+    #[cfg(feature = "bitflags")]
+    #[test]
+    fn test_flags_conversion() {
+        bitflags::bitflags! {
+            #[derive(Debug, Clone, Copy, PartialEq)]
+            struct Mode: u8 {
+                const READ = 1;
+                const WRITE = 1 << 1;
+            }
+        }
+
+        let value = Flags(Mode::READ | Mode::WRITE);
+        let bytes = value.out_bytes().unwrap();
+        assert_eq!(bytes.as_ref(), [3]);
+        assert_eq!(Flags::<Mode>::in_bytes(&bytes).unwrap(), value);
+        assert_eq!(<Flags<Mode> as InBytes>::type_tag(), "u8");
+        assert!(Flags::<Mode>::in_bytes(&[4]).is_err());
+        assert!(Flags::<Mode>::in_bytes(&[1, 0]).is_err());
+    }
+
+    #[test]
+    fn test_path_conversion() {
+        let path = PathBuf::from("/home/alice/Documents/résumé.txt");
+        let bytes = path.out_bytes().unwrap();
+        assert_eq!(
+            bytes.as_ref(),
+            "/home/alice/Documents/résumé.txt".as_bytes()
+        );
+        assert_eq!(PathBuf::in_bytes(&bytes).unwrap(), path);
+        assert_eq!(path.as_path().out_bytes().unwrap(), bytes);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_os_string_conversion() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let name = OsString::from_vec(vec![b'f', 0xFF, b'o', 0x80]);
+        let bytes = name.out_bytes().unwrap();
+        assert_eq!(bytes.as_ref(), &[b'f', 0xFF, b'o', 0x80]);
+        assert_eq!(OsString::in_bytes(&bytes).unwrap(), name);
+    }
+
+    #[test]
+    fn test_wtf8_round_trip() {
+        // "a", an unpaired high surrogate, "😀" as a surrogate pair, an unpaired low surrogate
+        let units = [0x61, 0xD800, 0xD83D, 0xDE00, 0xDC00];
+        let bytes = wtf8_encode(units.iter().copied());
+        assert_eq!(
+            bytes,
+            [
+                0x61, 0xED, 0xA0, 0x80, 0xF0, 0x9F, 0x98, 0x80, 0xED, 0xB0, 0x80
+            ]
+        );
+        assert_eq!(wtf8_decode(&bytes).unwrap(), units);
+        assert!(wtf8_decode(&[0x61, 0xFF]).is_err());
+    }
+
+    #[test]
+    fn test_ip_addr_conversions() {
+        let v4: IpAddr = "192.168.1.20".parse().unwrap();
+        let v6: IpAddr = "fe80::1".parse().unwrap();
+
+        let v4_bytes = v4.out_bytes().unwrap();
+        let v6_bytes = v6.out_bytes().unwrap();
+        assert_eq!(v4_bytes.as_ref(), &[192, 168, 1, 20]);
+        assert_eq!(v6_bytes.len(), 16);
+
+        assert_eq!(IpAddr::in_bytes(&v4_bytes).unwrap(), v4);
+        assert_eq!(IpAddr::in_bytes(&v6_bytes).unwrap(), v6);
+        assert_eq!(
+            Ipv4Addr::in_bytes(&v4_bytes).unwrap(),
+            Ipv4Addr::new(192, 168, 1, 20)
+        );
+        assert!(Ipv6Addr::in_bytes(&v4_bytes).is_err());
+        assert!(IpAddr::in_bytes(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn test_socket_addr_conversions() {
+        let v4: SocketAddr = "10.0.0.1:8080".parse().unwrap();
+        let v6 = SocketAddr::V6(SocketAddrV6::new("fe80::1".parse().unwrap(), 443, 7, 3));
+
+        let v4_bytes = v4.out_bytes().unwrap();
+        let v6_bytes = v6.out_bytes().unwrap();
+        assert_eq!(v4_bytes.as_ref(), &[10, 0, 0, 1, 0x1F, 0x90]);
+        assert_eq!(v6_bytes.len(), 26);
+
+        assert_eq!(SocketAddr::in_bytes(&v4_bytes).unwrap(), v4);
+        assert_eq!(SocketAddr::in_bytes(&v6_bytes).unwrap(), v6);
+        assert!(SocketAddr::in_bytes(&[0u8; 18]).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_value_conversion() {
+        let value = serde_json::json!({
+            "name": "alice",
+            "tags": ["admin", "ops"],
+            "limits": { "daily": 10, "ratio": 0.5 },
+            "enabled": true,
+            "parent": null,
+        });
+        let bytes = value.out_bytes().unwrap();
+        assert_eq!(serde_json::Value::in_bytes(&bytes).unwrap(), value);
+        assert!(serde_json::Value::in_bytes(b"{not json").is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_wrapper_conversion() {
+        let value = Json(vec![(String::from("a"), 1u8), (String::from("b"), 2u8)]);
+        let bytes = value.out_bytes().unwrap();
+        assert_eq!(bytes.as_ref(), br#"[["a",1],["b",2]]"#);
+        assert_eq!(Json::in_bytes(&bytes).unwrap(), value);
+    }
+
+    #[cfg(feature = "rmp-serde")]
+    #[test]
+    fn test_message_pack_wrapper_conversion() {
+        let value = MessagePack(vec![(String::from("a"), 1u8), (String::from("b"), 2u8)]);
+        let bytes = value.out_bytes().unwrap();
+        assert_eq!(bytes.as_ref(), b"\x92\x92\xa1a\x01\x92\xa1b\x02");
+        assert_eq!(MessagePack::in_bytes(&bytes).unwrap(), value);
+        assert!(MessagePack::<String>::in_bytes(b"\xc1").is_err());
+    }
+
+    #[cfg(feature = "ciborium")]
+    #[test]
+    fn test_cbor_wrapper_conversion() {
+        let value = Cbor(vec![(String::from("a"), 1u8), (String::from("b"), 2u8)]);
+        let bytes = value.out_bytes().unwrap();
+        assert_eq!(bytes.as_ref(), b"\x82\x82\x61a\x01\x82\x61b\x02");
+        assert_eq!(Cbor::in_bytes(&bytes).unwrap(), value);
+        assert!(Cbor::<String>::in_bytes(b"\xff").is_err());
+    }
+
+    #[cfg(feature = "prost")]
+    #[test]
+    fn test_protobuf_wrapper_conversion() {
+        #[derive(Clone, PartialEq, prost::Message)]
+        struct Point {
+            #[prost(int32, tag = "1")]
+            x: i32,
+            #[prost(string, tag = "2")]
+            label: String,
+        }
+
+        let value = Protobuf(Point {
+            x: 150,
+            label: "a".to_string(),
+        });
+        let bytes = value.out_bytes().unwrap();
+        assert_eq!(bytes.as_ref(), b"\x08\x96\x01\x12\x01a");
+        assert_eq!(Protobuf::in_bytes(&bytes).unwrap(), value);
+        assert!(Protobuf::<Point>::in_bytes(b"\x08").is_err());
+    }
+
+    #[cfg(feature = "rkyv")]
+    #[test]
+    fn test_rkyv_wrapper_conversion() {
+        #[derive(Debug, PartialEq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+        struct Entry {
+            id: u32,
+            tags: Vec<String>,
+        }
+
+        let value = Rkyv(Entry {
+            id: 7,
+            tags: vec!["a".to_string(), "b".to_string()],
+        });
+        let bytes = value.out_bytes().unwrap();
+        assert_eq!(Rkyv::in_bytes(&bytes).unwrap(), value);
+
+        // Misaligned input is copied into an aligned buffer
+        let shifted = [&[0u8][..], &bytes].concat();
+        let view = ArchivedValue::<Entry>::in_bytes(&shifted[1..]).unwrap();
+        assert_eq!(view.get().id, 7);
+        assert_eq!(view.get().tags[1], "b");
+        assert!(ArchivedValue::<Entry>::in_bytes(&[0xff; 3]).is_err());
+    }
+
+    #[test]
+    fn test_non_zero_conversions() {
+        let val = NonZeroU32::new(42).unwrap();
+        let bytes = val.out_bytes().unwrap();
+        assert_eq!(bytes.as_ref(), 42u32.out_bytes().unwrap().as_ref());
+        assert_eq!(NonZeroU32::in_bytes(&bytes).unwrap(), val);
+        assert_eq!(
+            NonZeroI64::in_bytes(&(-7i64).out_bytes().unwrap()).unwrap(),
+            NonZeroI64::new(-7).unwrap()
+        );
+
+        let result = NonZeroU32::in_bytes(&0u32.out_bytes().unwrap());
+        if let Err(KvsError::SerializationError(msg)) = result {
+            assert_eq!(msg, "Invalid zero value for NonZeroU32");
+        } else {
+            panic!("Expected SerializationError");
+        }
+    }
+
+    #[test]
+    fn test_integer_wrapper_conversions() {
+        let wrapping = Wrapping(250u8) + Wrapping(10u8);
+        let bytes = wrapping.out_bytes().unwrap();
+        assert_eq!(bytes.as_ref(), &[4]);
+        assert_eq!(Wrapping::<u8>::in_bytes(&bytes).unwrap(), wrapping);
+
+        let saturating = Saturating(-100i16);
+        let bytes = saturating.out_bytes().unwrap();
+        assert_eq!(bytes.as_ref(), (-100i16).out_bytes().unwrap().as_ref());
+        assert_eq!(Saturating::<i16>::in_bytes(&bytes).unwrap(), saturating);
+    }
 }
@@ -0,0 +1,442 @@
+//! Passphrase-based authenticated encryption of byte streams.
+//!
+//! Keys are derived from a passphrase with Argon2id, and data is encrypted
+//! with ChaCha20-Poly1305 in the STREAM construction, in chunks of
+//! [`CHUNK`] bytes, so streams of any size are encrypted and decrypted in
+//! constant memory. Any modification, truncation or reordering of the
+//! ciphertext is detected.
+//!
+//! # Format
+//!
+//! All integers are big-endian.
+//!
+//! | Field   | Size     | Contents                                     |
+//! |---------|----------|----------------------------------------------|
+//! | Magic   | 7 bytes  | `ZEPKVSE`                                    |
+//! | Version | 1 byte   | Encryption format version, currently `1`     |
+//! | Memory  | 4 bytes  | Argon2id memory cost in KiB                  |
+//! | Passes  | 4 bytes  | Argon2id number of passes                    |
+//! | Lanes   | 4 bytes  | Argon2id degree of parallelism               |
+//! | Salt    | 16 bytes | Random Argon2id salt                         |
+//! | Nonce   | 7 bytes  | Random STREAM nonce prefix                   |
+//! | Chunks  | variable | Ciphertext chunks of `CHUNK` + 16 bytes, the |
+//! |         |          | last of which may be shorter                 |
+//!
+//! The header is authenticated as associated data of every chunk.
+
+use std::io::{self, Read, Write};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::random;
+#[cfg(windows)]
+use std::ptr;
+#[cfg(windows)]
+use windows_sys::Win32::Foundation::LocalFree;
+#[cfg(windows)]
+use windows_sys::Win32::Security::Cryptography::{
+    CRYPT_INTEGER_BLOB, CRYPTPROTECT_UI_FORBIDDEN, CryptProtectData, CryptUnprotectData,
+};
+
+use crate::error::KvsError;
+
+const MAGIC: &[u8; 7] = b"ZEPKVSE";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 8 + 12 + 16 + 7;
+/// Plaintext bytes per chunk.
+const CHUNK: usize = 64 * 1024;
+/// Bytes added to each chunk by the authentication tag.
+const TAG: usize = 16;
+/// The largest Argon2id memory cost accepted from a header: 1 GiB, about
+/// fifty times the default.
+const MAX_MEMORY: u32 = 1024 * 1024;
+/// The largest number of Argon2id passes accepted from a header.
+const MAX_PASSES: u32 = 16;
+/// The largest Argon2id degree of parallelism accepted from a header.
+const MAX_LANES: u32 = 16;
+
+/// Argon2id cost parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct KdfParams {
+    /// Memory cost in KiB.
+    pub(crate) memory: u32,
+    /// Number of passes.
+    pub(crate) passes: u32,
+    /// Degree of parallelism.
+    pub(crate) lanes: u32,
+}
+
+impl Default for KdfParams {
+    /// The parameters recommended by the argon2 crate: 19 MiB, 2 passes
+    /// and 1 lane.
+    fn default() -> Self {
+        Self {
+            memory: Params::DEFAULT_M_COST,
+            passes: Params::DEFAULT_T_COST,
+            lanes: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl KdfParams {
+    /// Encodes the parameters as three big-endian `u32`s.
+    pub(crate) fn encode(self) -> [u8; 12] {
+        let mut bytes = [0; 12];
+        for (field, value) in bytes
+            .chunks_exact_mut(4)
+            .zip([self.memory, self.passes, self.lanes])
+        {
+            field.copy_from_slice(&value.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Decodes parameters written by [`KdfParams::encode`].
+    ///
+    /// Stored parameters can only be authenticated after deriving a key
+    /// with them, so costs beyond the `MAX_` limits are rejected first
+    /// rather than letting a crafted header exhaust memory or CPU.
+    pub(crate) fn decode(bytes: &[u8; 12]) -> Result<Self, KvsError> {
+        let field = |i: usize| u32::from_be_bytes(bytes[4 * i..4 * i + 4].try_into().unwrap());
+        let params = Self {
+            memory: field(0),
+            passes: field(1),
+            lanes: field(2),
+        };
+        if params.memory > MAX_MEMORY || params.passes > MAX_PASSES || params.lanes > MAX_LANES {
+            return Err(KvsError::SerializationError(format!(
+                "Key derivation parameters out of range: {params:?}"
+            )));
+        }
+        Ok(params)
+    }
+}
+
+/// Derives a 256-bit key from a passphrase.
+pub(crate) fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+    params: KdfParams,
+) -> Result<Key, KvsError> {
+    let params = Params::new(params.memory, params.passes, params.lanes, Some(32))
+        .map_err(|e| KvsError::SerializationError(format!("Invalid key derivation: {e}")))?;
+    let mut key = Key::default();
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| KvsError::SerializationError(format!("Invalid key derivation: {e}")))?;
+    Ok(key)
+}
+
+/// Encrypts a single message, authenticating `aad` along with it.
+///
+/// The result is a random 12-byte nonce followed by the ciphertext.
+pub(crate) fn seal(cipher: &ChaCha20Poly1305, aad: &[u8], msg: &[u8]) -> Result<Vec<u8>, KvsError> {
+    let nonce: [u8; 12] = random();
+    let mut sealed = nonce.to_vec();
+    sealed.extend(
+        cipher
+            .encrypt(&Nonce::from(nonce), Payload { msg, aad })
+            .map_err(|_| failed())?,
+    );
+    Ok(sealed)
+}
+
+/// Decrypts a message encrypted by [`seal`] with the same `aad`.
+pub(crate) fn open(
+    cipher: &ChaCha20Poly1305,
+    aad: &[u8],
+    sealed: &[u8],
+) -> Result<Vec<u8>, KvsError> {
+    if sealed.len() < 12 {
+        return Err(failed());
+    }
+    let (nonce, msg) = sealed.split_at(12);
+    cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg, aad })
+        .map_err(|_| failed())
+}
+
+/// Encrypts data with DPAPI for the current Windows user.
+#[cfg(windows)]
+pub(crate) fn protect(data: &[u8]) -> Result<Vec<u8>, KvsError> {
+    dpapi(data, |input, output| {
+        // SAFETY: `input` and `output` are valid blobs, and the optional
+        // parameters may be null.
+        unsafe {
+            CryptProtectData(
+                input,
+                ptr::null(),
+                ptr::null(),
+                ptr::null(),
+                ptr::null(),
+                CRYPTPROTECT_UI_FORBIDDEN,
+                output,
+            )
+        }
+    })
+}
+
+/// Decrypts data encrypted by [`protect`].
+#[cfg(windows)]
+pub(crate) fn unprotect(data: &[u8]) -> Result<Vec<u8>, KvsError> {
+    dpapi(data, |input, output| {
+        // SAFETY: `input` and `output` are valid blobs, and the optional
+        // parameters may be null.
+        unsafe {
+            CryptUnprotectData(
+                input,
+                ptr::null_mut(),
+                ptr::null(),
+                ptr::null(),
+                ptr::null(),
+                CRYPTPROTECT_UI_FORBIDDEN,
+                output,
+            )
+        }
+    })
+    .map_err(|_| failed())
+}
+
+/// Runs a DPAPI function on `data`, returning its output.
+#[cfg(windows)]
+fn dpapi(
+    data: &[u8],
+    call: impl FnOnce(*const CRYPT_INTEGER_BLOB, *mut CRYPT_INTEGER_BLOB) -> i32,
+) -> Result<Vec<u8>, KvsError> {
+    let input = CRYPT_INTEGER_BLOB {
+        cbData: data.len() as u32,
+        pbData: data.as_ptr().cast_mut(),
+    };
+    let mut output = CRYPT_INTEGER_BLOB {
+        cbData: 0,
+        pbData: ptr::null_mut(),
+    };
+    if call(&input, &mut output) == 0 {
+        return Err(KvsError::io_at(
+            io::Error::last_os_error(),
+            std::path::Path::new("dpapi"),
+        ));
+    }
+    // SAFETY: On success `output` holds a buffer of `cbData` bytes
+    // allocated with LocalAlloc, which is freed after copying.
+    unsafe {
+        let result = std::slice::from_raw_parts(output.pbData, output.cbData as usize).to_vec();
+        LocalFree(output.pbData.cast());
+        Ok(result)
+    }
+}
+
+/// Encrypts everything read from `reader` to `writer` with a key derived
+/// from `passphrase`.
+pub(crate) fn encrypt(
+    mut reader: impl Read,
+    mut writer: impl Write,
+    passphrase: &str,
+    params: KdfParams,
+) -> Result<(), KvsError> {
+    let salt: [u8; 16] = random();
+    let nonce: [u8; 7] = random();
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(MAGIC);
+    header.push(VERSION);
+    header.extend_from_slice(&params.encode());
+    header.extend_from_slice(&salt);
+    header.extend_from_slice(&nonce);
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt, params)?);
+    let mut encryptor = EncryptorBE32::from_aead(cipher, nonce.as_ref().into());
+    writer.write_all(&header).map_err(stream_error)?;
+
+    let mut chunk = read_chunk(&mut reader, CHUNK)?;
+    loop {
+        let next = read_chunk(&mut reader, CHUNK)?;
+        let payload = Payload {
+            msg: &chunk,
+            aad: &header,
+        };
+        if next.is_empty() {
+            let sealed = encryptor.encrypt_last(payload).map_err(|_| failed())?;
+            writer.write_all(&sealed).map_err(stream_error)?;
+            break;
+        }
+        let sealed = encryptor.encrypt_next(payload).map_err(|_| failed())?;
+        writer.write_all(&sealed).map_err(stream_error)?;
+        chunk = next;
+    }
+    writer.flush().map_err(stream_error)
+}
+
+/// Decrypts a stream written by [`encrypt`] from `reader` to `writer`.
+///
+/// Chunks are written as they are authenticated, so on failure `writer`
+/// may have received part of the plaintext, which must be discarded.
+pub(crate) fn decrypt(
+    mut reader: impl Read,
+    mut writer: impl Write,
+    passphrase: &str,
+) -> Result<(), KvsError> {
+    let mut header = [0; HEADER_LEN];
+    reader.read_exact(&mut header).map_err(stream_error)?;
+    if &header[..7] != MAGIC {
+        return Err(KvsError::SerializationError(
+            "not an encrypted zep-kvs stream".to_string(),
+        ));
+    }
+    if header[7] > VERSION {
+        return Err(KvsError::UnsupportedFormat {
+            found: header[7].into(),
+            supported: VERSION.into(),
+        });
+    }
+    let params = KdfParams::decode(header[8..20].try_into().unwrap())?;
+    let (salt, nonce) = header[20..].split_at(16);
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, salt, params)?);
+    let mut decryptor = DecryptorBE32::from_aead(cipher, nonce.into());
+
+    let mut chunk = read_chunk(&mut reader, CHUNK + TAG)?;
+    loop {
+        let next = read_chunk(&mut reader, CHUNK + TAG)?;
+        let payload = Payload {
+            msg: &chunk,
+            aad: &header,
+        };
+        if next.is_empty() {
+            let opened = decryptor.decrypt_last(payload).map_err(|_| failed())?;
+            writer.write_all(&opened).map_err(stream_error)?;
+            break;
+        }
+        let opened = decryptor.decrypt_next(payload).map_err(|_| failed())?;
+        writer.write_all(&opened).map_err(stream_error)?;
+        chunk = next;
+    }
+    writer.flush().map_err(stream_error)
+}
+
+/// Reads up to `len` bytes, stopping early only at the end of the stream.
+fn read_chunk(reader: &mut impl Read, len: usize) -> Result<Vec<u8>, KvsError> {
+    let mut chunk = Vec::with_capacity(len);
+    reader
+        .take(len as u64)
+        .read_to_end(&mut chunk)
+        .map_err(stream_error)?;
+    Ok(chunk)
+}
+
+fn stream_error(e: io::Error) -> KvsError {
+    KvsError::io_at(e, std::path::Path::new("<stream>"))
+}
+
+fn failed() -> KvsError {
+    KvsError::DecryptionFailed
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Cheap parameters, so tests don't spend time deriving keys.
+    pub(crate) const FAST: KdfParams = KdfParams {
+        memory: 8,
+        passes: 1,
+        lanes: 1,
+    };
+
+    #[test]
+    fn test_round_trip() {
+        for len in [0, 1, CHUNK, CHUNK + 1, 3 * CHUNK - 1] {
+            let plain: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let mut sealed = Vec::new();
+            encrypt(plain.as_slice(), &mut sealed, "secret", FAST).unwrap();
+            assert_eq!(
+                sealed.len(),
+                HEADER_LEN + len + len.div_ceil(CHUNK).max(1) * TAG
+            );
+
+            let mut opened = Vec::new();
+            decrypt(sealed.as_slice(), &mut opened, "secret").unwrap();
+            assert_eq!(opened, plain);
+        }
+    }
+
+    #[test]
+    fn test_tampering_is_detected() {
+        let plain = vec![7; 2 * CHUNK];
+        let mut sealed = Vec::new();
+        encrypt(plain.as_slice(), &mut sealed, "secret", FAST).unwrap();
+
+        let wrong_passphrase = decrypt(sealed.as_slice(), io::sink(), "guess");
+        assert!(matches!(wrong_passphrase, Err(KvsError::DecryptionFailed)));
+
+        let truncated = &sealed[..HEADER_LEN + CHUNK + TAG];
+        assert!(matches!(
+            decrypt(truncated, io::sink(), "secret"),
+            Err(KvsError::DecryptionFailed)
+        ));
+
+        let mut flipped = sealed.clone();
+        flipped[HEADER_LEN + 100] ^= 1;
+        assert!(matches!(
+            decrypt(flipped.as_slice(), io::sink(), "secret"),
+            Err(KvsError::DecryptionFailed)
+        ));
+
+        // The KDF parameters are authenticated too
+        let mut weakened = sealed;
+        weakened[11] ^= 1;
+        assert!(decrypt(weakened.as_slice(), io::sink(), "secret").is_err());
+    }
+
+    #[test]
+    fn test_hostile_kdf_parameters_are_rejected() {
+        let mut sealed = Vec::new();
+        encrypt([1, 2, 3].as_slice(), &mut sealed, "secret", FAST).unwrap();
+        let hostile = [
+            KdfParams {
+                memory: u32::MAX,
+                ..FAST
+            },
+            KdfParams {
+                passes: u32::MAX,
+                ..FAST
+            },
+            KdfParams {
+                lanes: MAX_LANES + 1,
+                ..FAST
+            },
+        ];
+        for params in hostile {
+            let mut forged = sealed.clone();
+            forged[8..20].copy_from_slice(&params.encode());
+            assert!(matches!(
+                decrypt(forged.as_slice(), io::sink(), "secret"),
+                Err(KvsError::SerializationError(e)) if e.contains("out of range")
+            ));
+        }
+        let limits = KdfParams {
+            memory: MAX_MEMORY,
+            passes: MAX_PASSES,
+            lanes: MAX_LANES,
+        };
+        assert_eq!(KdfParams::decode(&limits.encode()).unwrap(), limits);
+    }
+
+    #[test]
+    fn test_memory_cost_over_the_cap_is_rejected_before_derivation() {
+        let mut sealed = Vec::new();
+        encrypt([1, 2, 3].as_slice(), &mut sealed, "secret", FAST).unwrap();
+        // Deriving with this would allocate over a gigabyte before the
+        // header could be authenticated
+        let params = KdfParams {
+            memory: MAX_MEMORY + 1,
+            ..FAST
+        };
+        sealed[8..20].copy_from_slice(&params.encode());
+        assert!(matches!(
+            decrypt(sealed.as_slice(), io::sink(), "secret"),
+            Err(KvsError::SerializationError(e)) if e.contains("out of range")
+        ));
+    }
+}
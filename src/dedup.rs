@@ -0,0 +1,558 @@
+//! Content-addressed deduplication of large values.
+//!
+//! The [`Deduplicated`] scope wraps another scope and stores every value at
+//! or above a size threshold once, as a blob addressed by its SHA-256
+//! digest, no matter how many keys hold it. Keys then hold a short
+//! reference to the blob, and each reference is recorded under a reserved
+//! key of its own so that the blob is removed along with its last
+//! reference. Processes sharing a store therefore never update a shared
+//! count. Smaller values are stored inline.
+//!
+//! Blobs and reference markers live under reserved keys of the wrapped
+//! store, so the layer works with any backend.
+//!
+//! # Format
+//!
+//! ```text
+//! [0][value]          value stored inline
+//! [1][SHA-256 digest] value stored in a blob
+//! ```
+//!
+//! A process interrupted between writing a blob and writing a key can leave
+//! a stale reference marker, which only keeps the blob alive. Maintenance
+//! rebuilds the markers from the keys and removes blobs nothing refers to.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::Path;
+use std::time::SystemTime;
+
+use sha2::{Digest, Sha256};
+
+use crate::api::{BackingStore, KeyValueStore, Scope, is_reserved};
+use crate::dynamic::DynBackingStore;
+use crate::error::KvsError;
+use crate::watch::ChangeListener;
+
+/// Reserved key marking a store as deduplicated.
+const HEADER_KEY: &str = "__zep_dedup";
+const HEADER_VERSION: u8 = 2;
+/// Prefix of reserved keys holding blobs, followed by the hex digest.
+const BLOB_PREFIX: &str = "__zep_blob_";
+/// Prefix of reserved keys marking references to blobs, followed by the
+/// hex digest, an underscore and a hash of the referring key. Version 1
+/// stores kept a reference count under the prefix and digest alone.
+const REFS_PREFIX: &str = "__zep_refs_";
+
+const INLINE: u8 = 0;
+const BLOB: u8 = 1;
+
+/// Values of at least this many bytes are deduplicated by default.
+pub const DEFAULT_THRESHOLD: usize = 4096;
+
+/// A scope whose large values are stored once however many keys hold them.
+///
+/// Opening a store with `KeyValueStore::new` deduplicates values of at
+/// least [`DEFAULT_THRESHOLD`] bytes; `KeyValueStore::with_dedup_threshold`
+/// chooses another threshold. The first open of a store that holds no data
+/// sets it up for deduplication.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::dedup::Deduplicated;
+/// use zep_kvs::prelude::*;
+///
+/// let mut cache = KeyValueStore::<Deduplicated<scope::Ephemeral>>::with_dedup_threshold(16)?;
+/// let artifact = vec![7u8; 1024];
+/// cache.store("build/1", artifact.as_slice())?;
+/// cache.store("build/2", artifact.as_slice())?;
+/// assert_eq!(cache.retrieve::<_, Vec<u8>>("build/2")?, Some(artifact));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct Deduplicated<S: Scope>(S);
+
+impl<S: Scope> Scope for Deduplicated<S> {
+    type Store = DedupStore<S::Store>;
+
+    fn new() -> Result<Self::Store, KvsError> {
+        DedupStore::open(S::new()?, DEFAULT_THRESHOLD)
+    }
+}
+
+impl<S: Scope> KeyValueStore<Deduplicated<S>> {
+    /// Opens a deduplicated store, deduplicating values of at least
+    /// `threshold` bytes.
+    ///
+    /// The threshold only affects values written from now on, so stores
+    /// can be reopened with a different one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store already holds data written without
+    /// deduplication, or if the underlying scope cannot be opened.
+    pub fn with_dedup_threshold(threshold: usize) -> Result<Self, KvsError> {
+        Self::with_backing_store(DedupStore::open(S::new()?, threshold)?)
+    }
+}
+
+/// Backing store of the [`Deduplicated`] scope.
+pub struct DedupStore<B: BackingStore> {
+    inner: B,
+    threshold: usize,
+}
+
+impl<B: BackingStore + fmt::Debug> fmt::Debug for DedupStore<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DedupStore")
+            .field("inner", &self.inner)
+            .field("threshold", &self.threshold)
+            .finish()
+    }
+}
+
+impl<B: BackingStore> DedupStore<B> {
+    /// Opens `inner`, deduplicating values of at least `threshold` bytes.
+    ///
+    /// If `inner` is not yet deduplicated, any existing store metadata is
+    /// rewritten in the deduplicated format.
+    pub(crate) fn open(mut inner: B, threshold: usize) -> Result<Self, KvsError> {
+        match inner.retrieve(HEADER_KEY)?.as_deref() {
+            Some([version, ..]) if *version > HEADER_VERSION => {
+                return Err(KvsError::UnsupportedFormat {
+                    found: (*version).into(),
+                    supported: HEADER_VERSION.into(),
+                });
+            }
+            // Reference counts are replaced with markers
+            Some([1]) => {
+                let mut store = Self { inner, threshold };
+                store.rebuild_refs()?;
+                store.inner.store(HEADER_KEY, &[HEADER_VERSION])?;
+                return Ok(store);
+            }
+            Some(_) => {}
+            None => {
+                let keys = inner.keys()?;
+                if keys.iter().any(|key| !is_reserved(key)) {
+                    return Err(KvsError::SerializationError(
+                        "Cannot deduplicate a store that already holds data".to_string(),
+                    ));
+                }
+                for key in keys {
+                    if let Some(value) = inner.retrieve(&key)? {
+                        inner.store(&key, &[&[INLINE], value.as_slice()].concat())?;
+                    }
+                }
+                inner.store(HEADER_KEY, &[HEADER_VERSION])?;
+            }
+        }
+        Ok(Self { inner, threshold })
+    }
+
+    /// Returns the hex digest of the blob `key` refers to, if any.
+    fn reference(&self, key: &str) -> Result<Option<String>, KvsError> {
+        Ok(match self.inner.retrieve(key)?.as_deref() {
+            Some([BLOB, digest @ ..]) => Some(hex(digest)),
+            _ => None,
+        })
+    }
+
+    /// Returns whether any key refers to the blob `digest`.
+    fn is_referenced(&self, digest: &str) -> Result<bool, KvsError> {
+        Ok(!self
+            .inner
+            .keys_with_prefix(&format!("{REFS_PREFIX}{digest}_"))?
+            .is_empty())
+    }
+
+    /// Adds the reference from `key` to the blob holding `value`, creating
+    /// the blob if needed.
+    fn add_ref(&mut self, digest: &str, key: &str, value: &[u8]) -> Result<(), KvsError> {
+        // Marking the reference first keeps a blob found here from being
+        // removed with what was its last reference
+        self.inner.store(&ref_key(digest, key), &[])?;
+        if !self.inner.contains(&blob_key(digest))? {
+            self.inner.store(&blob_key(digest), value)?;
+        }
+        Ok(())
+    }
+
+    /// Drops the reference from `key` to a blob, removing the blob with
+    /// `remove` along with its last reference.
+    fn release(
+        &mut self,
+        digest: &str,
+        key: &str,
+        remove: fn(&mut B, &str) -> Result<(), KvsError>,
+    ) -> Result<(), KvsError> {
+        self.inner.remove(&ref_key(digest, key))?;
+        if self.is_referenced(digest)? {
+            return Ok(());
+        }
+        // A reference added meanwhile may have found the blob still
+        // present, so the blob is put back if one appears
+        let blob = self.inner.retrieve(&blob_key(digest))?;
+        remove(&mut self.inner, &blob_key(digest))?;
+        if let Some(blob) = blob
+            && self.is_referenced(digest)?
+        {
+            self.inner.store(&blob_key(digest), &blob)?;
+        }
+        Ok(())
+    }
+
+    /// Rebuilds the reference markers from the keys, removing stale ones
+    /// and blobs nothing refers to.
+    fn rebuild_refs(&mut self) -> Result<(), KvsError> {
+        let keys = self.inner.keys()?;
+        let mut digests = BTreeSet::new();
+        let mut markers = BTreeSet::new();
+        for key in keys.iter().filter(|key| !is_internal(key)) {
+            if let Some(digest) = self.reference(key)? {
+                markers.insert(ref_key(&digest, key));
+                digests.insert(digest);
+            }
+        }
+        for key in &keys {
+            let stale = match key.strip_prefix(BLOB_PREFIX) {
+                Some(digest) => !digests.contains(digest),
+                None => key.starts_with(REFS_PREFIX) && !markers.remove(key),
+            };
+            if stale {
+                self.inner.remove(key)?;
+            }
+        }
+        for marker in markers {
+            self.inner.store(&marker, &[])?;
+        }
+        Ok(())
+    }
+
+    /// Returns the value `stored` under a key, reading its blob if it was
+    /// deduplicated.
+    fn resolve(&self, mut stored: Vec<u8>) -> Result<Vec<u8>, KvsError> {
+        let invalid = |message: &str| KvsError::SerializationError(message.to_string());
+        match stored.first() {
+            Some(&INLINE) => {
+                stored.remove(0);
+                Ok(stored)
+            }
+            Some(&BLOB) => {
+                let blob = self.inner.retrieve(&blob_key(&hex(&stored[1..])))?;
+                blob.ok_or_else(|| invalid("Missing deduplicated blob"))
+            }
+            _ => Err(invalid("Invalid deduplicated value")),
+        }
+    }
+
+    fn remove_with(
+        &mut self,
+        key: &str,
+        remove: fn(&mut B, &str) -> Result<(), KvsError>,
+    ) -> Result<(), KvsError> {
+        let old = self.reference(key)?;
+        remove(&mut self.inner, key)?;
+        match old {
+            Some(digest) => self.release(&digest, key, remove),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Returns whether `key` is used internally by the deduplication layer.
+fn is_internal(key: &str) -> bool {
+    key == HEADER_KEY || key.starts_with(BLOB_PREFIX) || key.starts_with(REFS_PREFIX)
+}
+
+fn blob_key(digest: &str) -> String {
+    format!("{BLOB_PREFIX}{digest}")
+}
+
+/// Returns the key marking the reference from `key` to the blob `digest`.
+fn ref_key(digest: &str, key: &str) -> String {
+    let hash = Sha256::digest(key.as_bytes());
+    format!("{REFS_PREFIX}{digest}_{}", hex(&hash[..8]))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+impl<B: BackingStore> BackingStore for DedupStore<B> {
+    fn keys(&self) -> Result<Vec<String>, KvsError> {
+        let mut keys = self.inner.keys()?;
+        keys.retain(|key| !is_internal(key));
+        Ok(keys)
+    }
+
+    fn store(&mut self, key: &str, value: &[u8]) -> Result<(), KvsError> {
+        let old = self.reference(key)?;
+        let mut new = None;
+        // Store metadata is always inline
+        if value.len() >= self.threshold && !is_reserved(key) {
+            let digest = Sha256::digest(value);
+            self.add_ref(&hex(&digest), key, value)?;
+            self.inner
+                .store(key, &[&[BLOB], digest.as_slice()].concat())?;
+            new = Some(hex(&digest));
+        } else {
+            self.inner.store(key, &[&[INLINE], value].concat())?;
+        }
+        match old {
+            Some(digest) if Some(&digest) != new.as_ref() => self.release(&digest, key, B::remove),
+            _ => Ok(()),
+        }
+    }
+
+    fn store_if_absent(&mut self, key: &str, value: &[u8]) -> Result<bool, KvsError> {
+        if value.len() < self.threshold || is_reserved(key) {
+            return self
+                .inner
+                .store_if_absent(key, &[&[INLINE], value].concat());
+        }
+        // The reference is taken first and dropped again if another writer
+        // got there first, so the blob is never missing
+        let digest = Sha256::digest(value);
+        let hexed = hex(&digest);
+        self.add_ref(&hexed, key, value)?;
+        let stored = self
+            .inner
+            .store_if_absent(key, &[&[BLOB], digest.as_slice()].concat())?;
+        // The existing value may refer to the same blob
+        if !stored && self.reference(key)?.as_ref() != Some(&hexed) {
+            self.release(&hexed, key, B::remove)?;
+        }
+        Ok(stored)
+    }
+
+    fn retrieve(&self, key: &str) -> Result<Option<Vec<u8>>, KvsError> {
+        if is_internal(key) {
+            return Ok(None);
+        }
+        self.inner
+            .retrieve(key)?
+            .map(|stored| self.resolve(stored))
+            .transpose()
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), KvsError> {
+        self.remove_with(key, B::remove)
+    }
+
+    fn remove_secure(&mut self, key: &str) -> Result<(), KvsError> {
+        self.remove_with(key, B::remove_secure)
+    }
+
+    fn remove_if(&mut self, key: &str, expected: &[u8]) -> Result<bool, KvsError> {
+        // The stored bytes are matched exactly, since the threshold may have
+        // changed since the value was written
+        let stored = match self.inner.retrieve(key)? {
+            Some(stored) if !is_internal(key) => stored,
+            _ => return Ok(false),
+        };
+        let old = match stored.as_slice() {
+            [BLOB, digest @ ..] => Some(hex(digest)),
+            _ => None,
+        };
+        if self.resolve(stored.clone())? != expected || !self.inner.remove_if(key, &stored)? {
+            return Ok(false);
+        }
+        if let Some(digest) = old {
+            self.release(&digest, key, B::remove)?;
+        }
+        Ok(true)
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> Result<bool, KvsError> {
+        // The reference is marked under its new key before the value moves,
+        // so the blob is never unreferenced
+        let moved = self.reference(from)?;
+        let replaced = self.reference(to)?;
+        if let Some(digest) = &moved {
+            self.inner.store(&ref_key(digest, to), &[])?;
+        }
+        if !self.inner.rename(from, to)? {
+            if let Some(digest) = moved.filter(|digest| replaced.as_ref() != Some(digest)) {
+                self.inner.remove(&ref_key(&digest, to))?;
+            }
+            return Ok(false);
+        }
+        if let Some(digest) = &moved {
+            self.release(digest, from, B::remove)?;
+        }
+        if let Some(digest) = replaced.filter(|digest| moved.as_ref() != Some(digest)) {
+            self.release(&digest, to, B::remove)?;
+        }
+        Ok(true)
+    }
+
+    fn modified(&self, key: &str) -> Result<Option<SystemTime>, KvsError> {
+        self.inner.modified(key)
+    }
+
+    fn touch(&mut self, key: &str, at: SystemTime) -> Result<bool, KvsError> {
+        self.inner.touch(key, at)
+    }
+
+    fn location(&self) -> Option<&Path> {
+        self.inner.location()
+    }
+
+    fn free_space(&self) -> Result<Option<u64>, KvsError> {
+        self.inner.free_space()
+    }
+
+    /// Rebuilds the reference markers, removing ones left behind by
+    /// interrupted writes and blobs nothing refers to.
+    fn maintain(&mut self) -> Result<(), KvsError> {
+        self.inner.maintain()?;
+        self.rebuild_refs()
+    }
+
+    fn listen(&self) -> Result<Option<Box<dyn ChangeListener>>, KvsError> {
+        self.inner.listen()
+    }
+
+    fn reopen(&self) -> Result<Option<DynBackingStore>, KvsError> {
+        self.inner.reopen()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Mock, MockStore};
+
+    fn open(mock: &MockStore) -> KeyValueStore<Deduplicated<Mock>> {
+        KeyValueStore::with_backing_store(DedupStore::open(mock.clone(), 8).unwrap()).unwrap()
+    }
+
+    fn blobs(mock: &MockStore) -> usize {
+        let keys = mock.keys().unwrap();
+        keys.iter().filter(|k| k.starts_with(BLOB_PREFIX)).count()
+    }
+
+    #[test]
+    fn test_identical_values_are_stored_once() {
+        let mock = MockStore::new();
+        let mut store = open(&mock);
+        let artifact = [1u8; 64];
+        store.store("a", artifact.as_slice()).unwrap();
+        store.store("b", artifact.as_slice()).unwrap();
+        store.store("small", "tiny").unwrap();
+        assert_eq!(blobs(&mock), 1);
+        let mut keys = store.keys().unwrap();
+        keys.sort();
+        assert_eq!(keys, ["a", "b", "small"]);
+        assert_eq!(
+            store.retrieve::<_, Vec<u8>>("b").unwrap().as_deref(),
+            Some(artifact.as_slice())
+        );
+        assert_eq!(
+            store.retrieve::<_, String>("small").unwrap().as_deref(),
+            Some("tiny")
+        );
+
+        store.remove("a").unwrap();
+        assert_eq!(blobs(&mock), 1);
+        store.store("b", [2u8; 64].as_slice()).unwrap();
+        assert_eq!(blobs(&mock), 1);
+        store.rename("b", "c").unwrap();
+        store.remove("c").unwrap();
+        assert_eq!(blobs(&mock), 0);
+        assert!(
+            mock.keys()
+                .unwrap()
+                .iter()
+                .all(|k| !k.starts_with(REFS_PREFIX))
+        );
+    }
+
+    #[test]
+    fn test_store_if_absent_releases_unused_blob() {
+        let mock = MockStore::new();
+        let mut store = open(&mock);
+        assert!(store.store_if_absent("a", [1u8; 64].as_slice()).unwrap());
+        assert!(!store.store_if_absent("a", [2u8; 64].as_slice()).unwrap());
+        assert_eq!(blobs(&mock), 1);
+        store.remove("a").unwrap();
+        assert_eq!(blobs(&mock), 0);
+    }
+
+    #[test]
+    fn test_maintenance_removes_orphaned_blobs() {
+        let mut mock = MockStore::new();
+        let mut store = open(&mock);
+        store.store("a", [1u8; 64].as_slice()).unwrap();
+        // Simulate a write interrupted after its blob was added
+        let digest = hex(&Sha256::digest([3u8; 64]));
+        mock.store(&blob_key(&digest), &[3u8; 64]).unwrap();
+        mock.store(&ref_key(&digest, "b"), &[]).unwrap();
+        assert_eq!(blobs(&mock), 2);
+
+        store.maintenance().run().unwrap();
+        assert_eq!(blobs(&mock), 1);
+        assert_eq!(
+            store.retrieve::<_, Vec<u8>>("a").unwrap(),
+            Some(vec![1u8; 64])
+        );
+    }
+
+    #[test]
+    fn test_stores_sharing_blobs_keep_them_referenced() {
+        let mock = MockStore::new();
+        let (mut first, mut second) = (open(&mock), open(&mock));
+        let artifact = [1u8; 64];
+        first.store("a", artifact.as_slice()).unwrap();
+        second.store("b", artifact.as_slice()).unwrap();
+        // Rewriting the same value keeps its reference
+        first.store("a", artifact.as_slice()).unwrap();
+        assert!(!first.store_if_absent("a", artifact.as_slice()).unwrap());
+        first.rename("a", "b").unwrap();
+        assert_eq!(blobs(&mock), 1);
+        assert_eq!(mock.keys_with_prefix(REFS_PREFIX).unwrap().len(), 1);
+        second.remove("b").unwrap();
+        assert_eq!(blobs(&mock), 0);
+        assert!(mock.keys_with_prefix(REFS_PREFIX).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_reference_counts_are_upgraded() {
+        let mut mock = MockStore::new();
+        let mut store = open(&mock);
+        store.store("a", [1u8; 64].as_slice()).unwrap();
+        store.store("b", [1u8; 64].as_slice()).unwrap();
+        let digest = hex(&Sha256::digest([1u8; 64]));
+        for marker in mock.keys_with_prefix(REFS_PREFIX).unwrap() {
+            mock.remove(&marker).unwrap();
+        }
+        mock.store(&format!("{REFS_PREFIX}{digest}"), &2u64.to_be_bytes())
+            .unwrap();
+        mock.store(HEADER_KEY, &[1]).unwrap();
+
+        let mut store = open(&mock);
+        assert_eq!(
+            mock.retrieve(HEADER_KEY).unwrap().unwrap(),
+            [HEADER_VERSION]
+        );
+        // One marker per reference replaces the count
+        assert_eq!(mock.keys_with_prefix(REFS_PREFIX).unwrap().len(), 2);
+        store.remove("a").unwrap();
+        assert_eq!(blobs(&mock), 1);
+        store.remove("b").unwrap();
+        assert_eq!(blobs(&mock), 0);
+    }
+
+    #[test]
+    fn test_first_open_of_existing_store() {
+        let mock = MockStore::new();
+        KeyValueStore::<Mock>::with_backing_store(mock.clone()).unwrap();
+        open(&mock).info().unwrap();
+        open(&mock);
+
+        let mut plain = MockStore::new();
+        plain.store("key", b"value").unwrap();
+        assert!(DedupStore::open(plain, 8).is_err());
+    }
+}
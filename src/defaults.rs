@@ -0,0 +1,244 @@
+//! Managed defaults: user settings falling back to machine-wide defaults.
+//!
+//! Administrators deploy defaults to the machine scope, and each user's
+//! changes are kept in their own scope. Reads see a user's value if they
+//! have one and the machine default otherwise, so a default changed later
+//! reaches every user who hasn't customized that setting.
+
+use std::collections::BTreeSet;
+
+use crate::api::scope::{Machine, User};
+use crate::api::{KeyValueStore, Scope};
+use crate::convert::{InBytes, OutBytes};
+use crate::error::KvsError;
+
+/// A user store whose missing keys are read from a store of defaults.
+///
+/// Writes only ever go to the user store; the defaults are read-only
+/// through this type, so they can be kept where users can't write.
+///
+/// Values copied by [`adopt`](Self::adopt) are copied as stored, so both
+/// stores must use the same value format, as they do when opened with the
+/// same type tag and codec settings.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::defaults::ManagedDefaults;
+/// use zep_kvs::prelude::*;
+///
+/// let mut machine = KeyValueStore::<scope::Ephemeral>::new()?;
+/// machine.store("update_channel", "stable")?;
+/// machine.store("proxy", "proxy.corp:8080")?;
+///
+/// let user = KeyValueStore::<scope::Ephemeral>::new()?;
+/// let mut settings = ManagedDefaults::new(user, machine);
+/// settings.store("update_channel", "beta")?;
+/// assert_eq!(settings.retrieve::<_, String>("update_channel")?.as_deref(), Some("beta"));
+/// assert_eq!(settings.retrieve::<_, String>("proxy")?.as_deref(), Some("proxy.corp:8080"));
+///
+/// // Going back to the managed value
+/// settings.reset("update_channel")?;
+/// assert_eq!(settings.retrieve::<_, String>("update_channel")?.as_deref(), Some("stable"));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct ManagedDefaults<U: Scope = User, D: Scope = Machine> {
+    user: KeyValueStore<U>,
+    /// The defaults, or `None` if there are none to read.
+    defaults: Option<KeyValueStore<D>>,
+}
+
+impl ManagedDefaults {
+    /// Opens the user scope, falling back to defaults in the machine scope.
+    ///
+    /// Users usually can't write to the machine scope, so one that can't be
+    /// opened, typically because no defaults were deployed and the user
+    /// can't create its directory, is treated as holding no defaults.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoUserScope` if the user scope can't be opened, or an error
+    /// if the machine scope exists but can't be read.
+    pub fn open() -> Result<Self, KvsError> {
+        let defaults = match KeyValueStore::new() {
+            Ok(defaults) => Some(defaults),
+            Err(KvsError::NoMachineScope { .. }) => None,
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            user: KeyValueStore::new()?,
+            defaults,
+        })
+    }
+}
+
+impl<U: Scope, D: Scope> ManagedDefaults<U, D> {
+    /// Layers `user` over `defaults`.
+    pub fn new(user: KeyValueStore<U>, defaults: KeyValueStore<D>) -> Self {
+        Self {
+            user,
+            defaults: Some(defaults),
+        }
+    }
+
+    /// Returns the keys of the user store and the defaults, sorted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if either store's keys cannot be listed.
+    pub fn keys(&self) -> Result<Vec<String>, KvsError> {
+        let mut keys: BTreeSet<String> = self.user.keys()?.into_iter().collect();
+        if let Some(defaults) = &self.defaults {
+            keys.extend(defaults.keys()?);
+        }
+        Ok(keys.into_iter().collect())
+    }
+
+    /// Returns whether `key` exists in the user store or the defaults.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if either store cannot be read.
+    pub fn contains_key<K: AsRef<str>>(&self, key: K) -> Result<bool, KvsError> {
+        Ok(self.user.contains_key(key.as_ref())?
+            || match &self.defaults {
+                Some(defaults) => defaults.contains_key(key)?,
+                None => false,
+            })
+    }
+
+    /// Returns whether the user has their own value for `key`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the user store cannot be read.
+    pub fn is_customized<K: AsRef<str>>(&self, key: K) -> Result<bool, KvsError> {
+        self.user.contains_key(key)
+    }
+
+    /// Retrieves the user's value for `key`, or else its default.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`KeyValueStore::retrieve`].
+    pub fn retrieve<K: AsRef<str>, V: InBytes>(&self, key: K) -> Result<Option<V>, KvsError> {
+        match (self.user.retrieve(key.as_ref())?, &self.defaults) {
+            (Some(value), _) => Ok(Some(value)),
+            (None, Some(defaults)) => defaults.retrieve(key),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Stores the user's own value for `key`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`KeyValueStore::store`].
+    pub fn store<K: AsRef<str>, V: OutBytes>(&mut self, key: K, value: V) -> Result<(), KvsError> {
+        self.user.store(key, value)
+    }
+
+    /// Removes the user's value for `key`, so its default applies again.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`KeyValueStore::remove`].
+    pub fn reset<K: AsRef<str>>(&mut self, key: K) -> Result<(), KvsError> {
+        self.user.remove(key)
+    }
+
+    /// Copies the default for `key` into the user store, where the user can
+    /// go on to customize it.
+    ///
+    /// The copy no longer follows changes to the default. Returns `false`,
+    /// copying nothing, if the user already has a value for `key` or there
+    /// is no default.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if either store cannot be accessed, or the same
+    /// errors as [`KeyValueStore::store`] for the copy.
+    pub fn adopt<K: AsRef<str>>(&mut self, key: K) -> Result<bool, KvsError> {
+        let key = key.as_ref();
+        if self.user.contains_key(key)? {
+            return Ok(false);
+        }
+        let Some(defaults) = &self.defaults else {
+            return Ok(false);
+        };
+        match defaults.retrieve_raw(key)? {
+            Some(bytes) => self.user.store_raw(key, &bytes).map(|()| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Returns the user store.
+    pub fn user(&self) -> &KeyValueStore<U> {
+        &self.user
+    }
+
+    /// Returns the user store, for operations this type doesn't offer.
+    pub fn user_mut(&mut self) -> &mut KeyValueStore<U> {
+        &mut self.user
+    }
+
+    /// Returns the store of defaults, or `None` if there are none to read.
+    pub fn defaults(&self) -> Option<&KeyValueStore<D>> {
+        self.defaults.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::scope::Ephemeral;
+
+    #[test]
+    fn test_adopt_copies_default() {
+        let mut machine = KeyValueStore::<Ephemeral>::new().unwrap().with_type_tags();
+        machine.store("retries", 3u32).unwrap();
+        machine.store("proxy", "proxy.corp").unwrap();
+        let user = KeyValueStore::<Ephemeral>::new().unwrap().with_type_tags();
+        let mut settings = ManagedDefaults::new(user, machine);
+        settings.store("theme", "dark").unwrap();
+
+        assert_eq!(settings.keys().unwrap(), ["proxy", "retries", "theme"]);
+        assert!(!settings.is_customized("retries").unwrap());
+        assert!(settings.adopt("retries").unwrap());
+        assert!(settings.is_customized("retries").unwrap());
+        assert_eq!(
+            settings.user().retrieve::<_, u32>("retries").unwrap(),
+            Some(3)
+        );
+        assert!(!settings.adopt("retries").unwrap());
+        assert!(!settings.adopt("missing").unwrap());
+
+        settings.store("retries", 5u32).unwrap();
+        assert_eq!(settings.retrieve::<_, u32>("retries").unwrap(), Some(5));
+        assert_eq!(
+            settings
+                .defaults()
+                .unwrap()
+                .retrieve::<_, u32>("retries")
+                .unwrap(),
+            Some(3)
+        );
+    }
+
+    #[test]
+    #[cfg(all(not(windows), not(feature = "ephemeral-scopes")))]
+    fn test_open_without_machine_scope() {
+        let guard = crate::testing::TestScopeGuard::new();
+        // A file where the machine scope's directory would be created
+        std::fs::create_dir_all(guard.path()).unwrap();
+        std::fs::write(guard.path().join("machine"), b"").unwrap();
+
+        let mut settings = ManagedDefaults::open().unwrap();
+        assert!(settings.defaults().is_none());
+        assert!(settings.keys().unwrap().is_empty());
+        assert_eq!(settings.retrieve::<_, String>("proxy").unwrap(), None);
+        assert!(!settings.adopt("proxy").unwrap());
+        settings.store("proxy", "proxy.home").unwrap();
+        assert!(settings.contains_key("proxy").unwrap());
+    }
+}
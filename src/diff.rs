@@ -0,0 +1,93 @@
+//! Comparing the contents of two stores.
+
+use std::collections::BTreeSet;
+
+use crate::api::{KeyValueStore, Scope};
+use crate::error::KvsError;
+
+/// The differences between two stores.
+///
+/// Each list is sorted. Values are compared by their stored bytes, so a
+/// value stored with type tags differs from the same value stored without.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StoreDiff {
+    /// Keys only present in the second store.
+    pub added: Vec<String>,
+    /// Keys only present in the first store.
+    pub removed: Vec<String>,
+    /// Keys present in both stores with different values.
+    pub changed: Vec<String>,
+}
+
+impl StoreDiff {
+    /// Returns whether the stores hold the same keys and values.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Compares the application keys and values of two stores.
+///
+/// The result describes how to get from `from` to `to`: keys added, keys
+/// removed, and keys whose values changed. The stores' internal metadata is
+/// not compared.
+///
+/// # Errors
+///
+/// Returns an error if either store cannot be read.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::diff::diff;
+/// use zep_kvs::prelude::*;
+///
+/// let mut before = KeyValueStore::<scope::Ephemeral>::new()?;
+/// before.store("theme", "light")?;
+/// before.store("volume", 5u8)?;
+///
+/// let mut after = KeyValueStore::<scope::Ephemeral>::new()?;
+/// after.store("theme", "dark")?;
+/// after.store("language", "en")?;
+///
+/// let changes = diff(&before, &after)?;
+/// assert_eq!(changes.added, ["language"]);
+/// assert_eq!(changes.removed, ["volume"]);
+/// assert_eq!(changes.changed, ["theme"]);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn diff<A: Scope, B: Scope>(
+    from: &KeyValueStore<A>,
+    to: &KeyValueStore<B>,
+) -> Result<StoreDiff, KvsError> {
+    let from_keys: BTreeSet<String> = from.keys()?.into_iter().collect();
+    let to_keys: BTreeSet<String> = to.keys()?.into_iter().collect();
+    let mut result = StoreDiff {
+        added: to_keys.difference(&from_keys).cloned().collect(),
+        removed: from_keys.difference(&to_keys).cloned().collect(),
+        changed: Vec::new(),
+    };
+    for key in from_keys.intersection(&to_keys) {
+        if from.retrieve_raw(key)? != to.retrieve_raw(key)? {
+            result.changed.push(key.clone());
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::scope::Ephemeral;
+
+    #[test]
+    fn test_identical_stores_have_empty_diff() {
+        let mut a = KeyValueStore::<Ephemeral>::new().unwrap();
+        let mut b = KeyValueStore::<Ephemeral>::new().unwrap();
+        a.store("key", "value").unwrap();
+        b.store("key", "value").unwrap();
+        assert!(diff(&a, &b).unwrap().is_empty());
+        b.store("key", "other").unwrap();
+        assert_eq!(diff(&a, &b).unwrap().changed, ["key"]);
+    }
+}
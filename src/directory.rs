@@ -4,18 +4,41 @@
 //! data to the file system. Each key-value pair is stored as a separate
 //! file within a dedicated directory structure.
 
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::BTreeSet;
+use std::fmt;
 use std::fs;
 use std::fs::File;
-use std::io::{ErrorKind, Write};
-use std::path::PathBuf;
-use std::time::Duration;
+use std::io::{self, ErrorKind, Write};
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
 
 use rand::random;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 
-use crate::api::BackingStore;
+use crate::api::{BackingStore, KeyValueStore, Scope};
+use crate::dynamic::DynBackingStore;
 use crate::error::KvsError;
+use crate::watch::ChangeListener;
 
 const TEMP_PREFIX: &str = ".tmp_";
+/// File marking a store as using the sharded layout.
+const SHARDED_MARKER: &str = ".sharded";
+/// Directory holding the sockets of watchers listening for changes.
+const WATCHERS_DIR: &str = ".watchers";
+/// Prefix of the files of keys whose names would clash with internal files.
+const ESCAPE_PREFIX: &str = ".key_";
+/// Number of keys above which `contains_many` lists the directory instead
+/// of checking each key.
+const SCAN_THRESHOLD: usize = 64;
 
 /// File system-based key-value store.
 ///
@@ -29,7 +52,28 @@ const TEMP_PREFIX: &str = ".tmp_";
 /// base_directory/
 /// ├── key1              # File containing value for "key1"
 /// ├── key2              # File containing value for "key2"
-/// └── .tmp_random_id    # Temporary files during atomic writes
+/// ├── .tmp_random_id    # Temporary files during atomic writes
+/// └── .watchers/        # Sockets of watchers listening for changes
+/// ```
+///
+/// Keys named like the internal files, such as `.sharded`, are stored in
+/// files prefixed with `.key_`, as are keys that start with `.key_`.
+///
+/// # Sharded Layout
+///
+/// Some file systems slow down with very large directories. Stores can be
+/// switched to a sharded layout, in which each key file lives two levels
+/// down in a directory chosen by a hash of the key:
+///
+/// ```text
+/// base_directory/
+/// ├── .sharded          # Marks the sharded layout
+/// ├── 3f/
+/// │   └── a2/
+/// │       └── key1
+/// └── 9c/
+///     └── 07/
+///         └── key2
 /// ```
 ///
 /// # Atomic Writes
@@ -42,6 +86,20 @@ pub struct DirectoryStore {
     path: PathBuf,
     /// File handle for the base directory, used for sync.
     dir: File,
+    /// User and group ids to act as, when writing on behalf of another
+    /// user.
+    owner: Option<(u32, u32)>,
+    /// Whether key files are stored in the sharded layout.
+    sharded: bool,
+}
+
+impl fmt::Debug for DirectoryStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DirectoryStore")
+            .field("path", &self.path)
+            .field("owner", &self.owner)
+            .finish_non_exhaustive()
+    }
 }
 
 impl DirectoryStore {
@@ -55,7 +113,7 @@ impl DirectoryStore {
     /// # Arguments
     ///
     /// * `path` - Base path where the store directory should be created.
-    ///           The actual storage directory will be `path/package_name/app_name`.
+    ///   The actual storage directory will be `path/package_name/app_name`.
     ///
     /// # Errors
     ///
@@ -64,74 +122,359 @@ impl DirectoryStore {
     /// - Directory cannot be opened
     /// - Cleanup of stale temporary files fails
     pub(crate) fn new(path: PathBuf) -> Result<Self, KvsError> {
+        Self::with_owner(path, None)
+    }
+
+    /// Creates a new directory store at the specified path, owned by the
+    /// given user and group ids.
+    ///
+    /// Every file system operation on the store is made with the calling
+    /// thread's file system credentials switched to `owner`, so that a
+    /// privileged process can write a store that its user can later modify.
+    /// Directories and files created are the user's, and the process can't
+    /// be led by links the user planted to files the user couldn't write
+    /// themselves. Switching credentials requires root privileges.
+    pub(crate) fn with_owner(path: PathBuf, owner: Option<(u32, u32)>) -> Result<Self, KvsError> {
         let path = path
             .join(env!("CARGO_PKG_NAME"))
             .join(env!("ZEP_KVS_APP_NAME"));
 
-        let remove_stale = || {
+        let _owner = Credentials::assume(owner).map_err(|e| KvsError::io_at(e, &path))?;
+        let open = || {
             fs::create_dir_all(&path)?; // Ensure directory exists
-            fs::read_dir(&path)?
-                .filter_map(|d| d.ok()) // Skip entries with errors
-                .filter(|d| {
-                    d.file_type().is_ok_and(|f| f.is_file())
-                        && d.file_name()
-                            .to_str()
-                            .is_some_and(|s| s.starts_with(TEMP_PREFIX))
-                }) // Only include temporary files
-                .filter(|d| {
-                    d.metadata().is_ok_and(|m| {
-                        m.modified().is_ok_and(|t| {
-                            t.elapsed().is_ok_and(|d| d > Duration::from_secs(86400))
-                        })
-                    })
-                }) // Only include files older than 24 hours
-                .for_each(|d| {
-                    let _ = fs::remove_file(d.path());
-                });
+            remove_stale_temp_files(&path)?;
             let dir = File::open(&path)?;
             dir.sync_all()?;
             Ok(dir)
         };
-        let dir = remove_stale().map_err(|e| KvsError::io_at(e, &path))?;
-        Ok(Self { path, dir })
+        let dir = open().map_err(|e| KvsError::io_at(e, &path))?;
+        let sharded = path.join(SHARDED_MARKER).exists();
+        let mut store = Self {
+            path,
+            dir,
+            owner,
+            sharded,
+        };
+        if sharded {
+            // Finish any migration that was interrupted
+            store.move_flat_files()?;
+        }
+        Ok(store)
+    }
+
+    /// Switches the store to the sharded layout, moving existing key files.
+    ///
+    /// Does nothing if the store is already sharded. The layout is recorded
+    /// before any file is moved, and opening a sharded store moves any key
+    /// files still in the flat layout, so an interrupted migration is
+    /// completed the next time the store is opened.
+    pub(crate) fn shard(&mut self) -> Result<(), KvsError> {
+        if self.sharded {
+            return Ok(());
+        }
+        let _owner = self.act_as_owner()?;
+        let marker = self.path.join(SHARDED_MARKER);
+        let result = || {
+            let file = File::create(&marker)?;
+            file.sync_all()?;
+            self.dir.sync_all()
+        };
+        result().map_err(|e| KvsError::io_at(e, &marker))?;
+        self.sharded = true;
+        self.move_flat_files()
+    }
+
+    /// Moves key files in the top-level directory to their shards.
+    fn move_flat_files(&mut self) -> Result<(), KvsError> {
+        for key in self.flat_keys()? {
+            let from = self.path.join(&*file_name(&key));
+            let to = self.key_path(&key);
+            let result = || {
+                self.create_shard(&to)?;
+                fs::rename(&from, &to)?;
+                self.sync_parent(&to)
+            };
+            result().map_err(|e| KvsError::io_at(e, &from))?;
+        }
+        self.dir
+            .sync_all()
+            .map_err(|e| KvsError::io_at(e, &self.path))
+    }
+
+    /// Switches the calling thread to the store owner's credentials until
+    /// the returned guard is dropped.
+    fn act_as_owner(&self) -> Result<Credentials, KvsError> {
+        Credentials::assume(self.owner).map_err(|e| KvsError::io_at(e, &self.path))
+    }
+
+    /// Lists the key files in the top-level directory.
+    fn flat_keys(&self) -> Result<Vec<String>, KvsError> {
+        list_files(&self.path).map_err(|e| KvsError::io_at(e, &self.path))
+    }
+
+    /// Returns the path of the file holding `key`.
+    fn key_path(&self, key: &str) -> PathBuf {
+        if !self.sharded {
+            return self.path.join(&*file_name(key));
+        }
+        // FNV-1a, which is stable across releases and platforms
+        let hash = key.bytes().fold(0x811c9dc5u32, |hash, byte| {
+            (hash ^ u32::from(byte)).wrapping_mul(0x01000193)
+        });
+        self.path
+            .join(format!("{:02x}", hash >> 24))
+            .join(format!("{:02x}", (hash >> 16) & 0xff))
+            .join(&*file_name(key))
+    }
+
+    /// Creates the shard directories for the key file at `path`.
+    fn create_shard(&self, path: &Path) -> std::io::Result<()> {
+        let Some(shard) = path.parent().filter(|p| *p != self.path) else {
+            return Ok(());
+        };
+        let missing: Vec<&Path> = shard.ancestors().take_while(|p| !p.exists()).collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        fs::create_dir_all(shard)?;
+        // Make the new directories persistent
+        for dir in &missing {
+            if let Some(parent) = dir.parent() {
+                File::open(parent)?.sync_all()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes `value` to the key file at `path` through a temporary file,
+    /// without syncing the directory containing it.
+    fn write_file(&self, path: &Path, value: &[u8]) -> std::io::Result<()> {
+        let tmp = self.write_temp(value)?;
+
+        // Atomically move temporary file to final location
+        self.create_shard(path)?;
+        fs::rename(tmp, path)
+    }
+
+    /// Writes `value` to a new file at `path`, failing with `AlreadyExists`
+    /// if there is one.
+    ///
+    /// The value is written to a temporary file first and then hard linked
+    /// into place, so the new file never appears partially written.
+    fn write_new_file(&self, path: &Path, value: &[u8]) -> std::io::Result<()> {
+        let tmp = self.write_temp(value)?;
+        self.create_shard(path)?;
+        let linked = fs::hard_link(&tmp, path);
+        // A leftover temporary file is cleaned up by maintenance
+        let _ = fs::remove_file(&tmp);
+        linked
+    }
+
+    /// Writes `value` to a new temporary file in the store, returning its
+    /// path.
+    fn write_temp(&self, value: &[u8]) -> std::io::Result<PathBuf> {
+        // Create temporary file with unique name
+        let tmp = self.path.join(format!("{TEMP_PREFIX}{}", random::<u128>()));
+        let mut file = File::create_new(&tmp)?;
+
+        // Write data and ensure it's flushed to disk
+        file.write_all(value)?;
+        file.sync_all()?;
+        Ok(tmp)
+    }
+
+    /// Signals every watcher listening for changes to this store.
+    ///
+    /// Sockets left behind by watchers that exited without removing them
+    /// are removed. Signals are best effort, since watchers also poll.
+    fn signal_watchers(&self) {
+        let Ok(entries) = fs::read_dir(self.path.join(WATCHERS_DIR)) else {
+            return; // Nobody has ever watched this store
+        };
+        let Ok(socket) = UnixDatagram::unbound() else {
+            return;
+        };
+        // Never block a write on a watcher that isn't reading its signals
+        if socket.set_nonblocking(true).is_err() {
+            return;
+        }
+        for entry in entries.filter_map(|e| e.ok()) {
+            if let Err(e) = socket.send_to(&[0], entry.path())
+                && e.kind() == ErrorKind::ConnectionRefused
+            {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+
+    /// Syncs the directory containing the key file at `path`.
+    fn sync_parent(&self, path: &Path) -> std::io::Result<()> {
+        match path.parent() {
+            Some(dir) if dir != self.path => File::open(dir)?.sync_all(),
+            _ => self.dir.sync_all(),
+        }
     }
 }
 
+impl<S: Scope<Store = DirectoryStore>> KeyValueStore<S> {
+    /// Switches the store to a sharded directory layout.
+    ///
+    /// By default every key is a file in a single directory, which makes
+    /// listing keys and creating files slow on some file systems once a
+    /// store holds many thousands of keys. The sharded layout spreads key
+    /// files over two levels of subdirectories chosen by a hash of the key.
+    ///
+    /// Existing key files are moved to the new layout. The layout is
+    /// recorded in the store, so later opens use it automatically, and a
+    /// migration that is interrupted is completed the next time the store
+    /// is opened. Versions of this library without sharding support do not
+    /// see keys in a sharded store.
+    ///
+    /// Does nothing if the store is already sharded. Only available on
+    /// Linux and macOS, where stores are directories.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the layout cannot be recorded or a key file
+    /// cannot be moved. Keys moved before the error remain accessible.
+    pub fn use_sharded_layout(&mut self) -> Result<(), KvsError> {
+        self.backing_store_mut().shard()
+    }
+}
+
+/// Lists the names of the key files directly in `path`.
+fn list_files(path: &Path) -> std::io::Result<Vec<String>> {
+    Ok(fs::read_dir(path)?
+        .filter_map(|d| d.ok()) // Skip entries with errors
+        .filter(|d| d.file_type().is_ok_and(|d| d.is_file())) // Only include files
+        .filter_map(|f| f.file_name().to_str().map(|f| f.to_owned())) // Convert to strings
+        .filter_map(|f| key_name(&f).map(|k| k.to_owned())) // Exclude internal files
+        .collect())
+}
+
+/// Returns the name of the file holding `key`.
+fn file_name(key: &str) -> Cow<'_, str> {
+    if key == SHARDED_MARKER
+        || key == WATCHERS_DIR
+        || key.starts_with(TEMP_PREFIX)
+        || key.starts_with(ESCAPE_PREFIX)
+    {
+        Cow::Owned(format!("{ESCAPE_PREFIX}{key}"))
+    } else {
+        Cow::Borrowed(key)
+    }
+}
+
+/// Returns the key held in the file `name`, or `None` for internal files.
+fn key_name(name: &str) -> Option<&str> {
+    if name == SHARDED_MARKER || name == WATCHERS_DIR || name.starts_with(TEMP_PREFIX) {
+        return None;
+    }
+    Some(name.strip_prefix(ESCAPE_PREFIX).unwrap_or(name))
+}
+
+/// Lists the shard subdirectories of `path`.
+fn subdirectories(path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    Ok(fs::read_dir(path)?
+        .filter_map(|d| d.ok()) // Skip entries with errors
+        .filter(|d| d.file_type().is_ok_and(|d| d.is_dir()))
+        .filter(|d| d.file_name() != WATCHERS_DIR)
+        .map(|d| d.path())
+        .collect())
+}
+
+/// Removes temporary files older than 24 hours left behind by interrupted
+/// writes.
+fn remove_stale_temp_files(path: &Path) -> std::io::Result<()> {
+    fs::read_dir(path)?
+        .filter_map(|d| d.ok()) // Skip entries with errors
+        .filter(|d| {
+            d.file_type().is_ok_and(|f| f.is_file())
+                && d.file_name()
+                    .to_str()
+                    .is_some_and(|s| s.starts_with(TEMP_PREFIX))
+        }) // Only include temporary files
+        .filter(|d| {
+            d.metadata().is_ok_and(|m| {
+                m.modified()
+                    .is_ok_and(|t| t.elapsed().is_ok_and(|d| d > Duration::from_secs(86400)))
+            })
+        }) // Only include files older than 24 hours
+        .for_each(|d| match fs::remove_file(d.path()) {
+            Ok(()) => debug!("removed stale temporary file {}", d.path().display()),
+            Err(e) => warn!(
+                "failed to remove stale temporary file {}: {e}",
+                d.path().display()
+            ),
+        });
+    Ok(())
+}
+
 impl BackingStore for DirectoryStore {
     fn keys(&self) -> Result<Vec<String>, KvsError> {
-        // Read directory entries and filter for regular files
-        Ok(fs::read_dir(&self.path)
-            .map_err(|e| KvsError::io_at(e, &self.path))?
-            .filter_map(|d| d.ok()) // Skip entries with errors
-            .filter(|d| d.file_type().is_ok_and(|d| d.is_file())) // Only include files
-            .filter_map(|f| f.file_name().to_str().map(|f| f.to_owned())) // Convert to strings
-            .filter(|k| !k.starts_with(TEMP_PREFIX)) // Exclude temporary files
-            .collect())
+        let _owner = self.act_as_owner()?;
+        let mut keys = self.flat_keys()?;
+        if self.sharded {
+            let mut result = || {
+                for level in subdirectories(&self.path)? {
+                    for shard in subdirectories(&level)? {
+                        keys.extend(list_files(&shard)?);
+                    }
+                }
+                Ok(())
+            };
+            result().map_err(|e| KvsError::io_at(e, &self.path))?;
+        }
+        Ok(keys)
     }
 
     fn store(&mut self, key: &str, value: &[u8]) -> Result<(), KvsError> {
-        let path = self.path.join(key);
+        let _owner = self.act_as_owner()?;
+        let path = self.key_path(key);
         let result = || {
-            // Create temporary file with unique name
-            let tmp = self.path.join(format!("{TEMP_PREFIX}{}", random::<u128>()));
-            let mut file = File::create_new(&tmp)?;
-
-            // Write data and ensure it's flushed to disk
-            file.write_all(value)?;
-            file.sync_all()?;
-
-            // Atomically move temporary file to final location
-            fs::rename(tmp, &path)?;
+            self.write_file(&path, value)?;
 
             // Sync directory to ensure rename is persistent
-            self.dir.sync_all()
+            self.sync_parent(&path)
         };
-        result().map_err(|e| KvsError::io_at(e, &path))
+        result().map_err(|e| KvsError::io_at(e, &path))?;
+        self.signal_watchers();
+        Ok(())
+    }
+
+    #[cfg(feature = "rayon")]
+    fn store_many(&mut self, entries: &[(&str, &[u8])]) -> Result<(), KvsError> {
+        let _owner = self.act_as_owner()?;
+        entries.par_iter().try_for_each(|(key, value)| {
+            // Credentials are per thread, so each worker switches its own
+            let _owner = self.act_as_owner()?;
+            let path = self.key_path(key);
+            self.write_file(&path, value)
+                .map_err(|e| KvsError::io_at(e, &path))
+        })?;
+
+        // Sync each directory written to once, rather than after every file
+        let paths: BTreeSet<PathBuf> = entries.iter().map(|(key, _)| self.key_path(key)).collect();
+        let mut synced = BTreeSet::new();
+        for path in &paths {
+            if synced.insert(path.parent()) {
+                self.sync_parent(path)
+                    .map_err(|e| KvsError::io_at(e, path))?;
+            }
+        }
+        self.signal_watchers();
+        Ok(())
+    }
+
+    #[cfg(feature = "rayon")]
+    fn retrieve_many(&self, keys: &[&str]) -> Result<Vec<Option<Vec<u8>>>, KvsError> {
+        keys.par_iter().map(|key| self.retrieve(key)).collect()
     }
 
     fn retrieve(&self, key: &str) -> Result<Option<Vec<u8>>, crate::error::KvsError> {
+        let _owner = self.act_as_owner()?;
         // Attempt to read the file for this key
-        match fs::read(self.path.join(key)) {
+        match fs::read(self.key_path(key)) {
             Ok(value) => Ok(Some(value)),
             Err(e) if e.kind() == ErrorKind::NotFound => Ok(None), // Key doesn't exist
             Err(e) => Err(KvsError::io_at(e, &self.path)),
@@ -139,13 +482,377 @@ impl BackingStore for DirectoryStore {
     }
 
     fn remove(&mut self, key: &str) -> Result<(), crate::error::KvsError> {
-        let path = self.path.join(key);
+        let _owner = self.act_as_owner()?;
+        let path = self.key_path(key);
         let result = || {
             // Remove the file for this key
             fs::remove_file(&path)?;
             // Sync directory to ensure removal is persistent
-            self.dir.sync_all()
+            self.sync_parent(&path)
         };
-        result().map_err(|e| KvsError::io_at(e, &path))
+        result().map_err(|e| KvsError::io_at(e, &path))?;
+        self.signal_watchers();
+        Ok(())
+    }
+
+    fn remove_secure(&mut self, key: &str) -> Result<(), KvsError> {
+        let _owner = self.act_as_owner()?;
+        let path = self.key_path(key);
+        let overwrite = || {
+            // Opening for writing without truncating keeps the file's blocks
+            let mut file = match File::options().write(true).open(&path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            let zeros = [0u8; 64 * 1024];
+            let mut remaining = file.metadata()?.len();
+            while remaining > 0 {
+                let chunk = remaining.min(zeros.len() as u64) as usize;
+                file.write_all(&zeros[..chunk])?;
+                remaining -= chunk as u64;
+            }
+            file.sync_all()
+        };
+        overwrite().map_err(|e| KvsError::io_at(e, &path))?;
+        self.remove(key)
+    }
+
+    fn maintain(&mut self) -> Result<(), KvsError> {
+        let _owner = self.act_as_owner()?;
+        remove_stale_temp_files(&self.path).map_err(|e| KvsError::io_at(e, &self.path))
+    }
+
+    fn location(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+
+    fn free_space(&self) -> Result<Option<u64>, KvsError> {
+        let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+        // SAFETY: `dir` is an open file descriptor and `stat` is valid for
+        // writes of a `statvfs`.
+        if unsafe { libc::fstatvfs(self.dir.as_raw_fd(), stat.as_mut_ptr()) } != 0 {
+            return Err(KvsError::io_at(io::Error::last_os_error(), &self.path));
+        }
+        // SAFETY: `fstatvfs` succeeded, so it initialized `stat`.
+        let stat = unsafe { stat.assume_init() };
+        // The field types differ between platforms
+        let free = u128::from(stat.f_bavail) * u128::from(stat.f_frsize);
+        Ok(Some(u64::try_from(free).unwrap_or(u64::MAX)))
+    }
+
+    fn contains(&self, key: &str) -> Result<bool, KvsError> {
+        let _owner = self.act_as_owner()?;
+        let path = self.key_path(key);
+        path.try_exists().map_err(|e| KvsError::io_at(e, &path))
+    }
+
+    fn store_if_absent(&mut self, key: &str, value: &[u8]) -> Result<bool, KvsError> {
+        let _owner = self.act_as_owner()?;
+        let path = self.key_path(key);
+        let result = || match self.write_new_file(&path, value) {
+            Ok(()) => self.sync_parent(&path).map(|()| true),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(false),
+            Err(e) => Err(e),
+        };
+        let stored = result().map_err(|e| KvsError::io_at(e, &path))?;
+        if stored {
+            self.signal_watchers();
+        }
+        Ok(stored)
+    }
+
+    fn remove_if(&mut self, key: &str, expected: &[u8]) -> Result<bool, KvsError> {
+        let _owner = self.act_as_owner()?;
+        let path = self.key_path(key);
+        let tmp = self.path.join(format!("{TEMP_PREFIX}{}", random::<u128>()));
+        let result = || {
+            // Linking the file aside claims the value compared without
+            // removing the key, which stays readable, and intact after a
+            // crash, unless it matches
+            match fs::hard_link(&path, &tmp) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+                Err(e) => return Err(e),
+            }
+            let removed = fs::read(&tmp).and_then(|current| {
+                if current != expected {
+                    return Ok(false);
+                }
+                // Only the claimed file is removed, so a value written
+                // meanwhile is kept. One written between this check and
+                // the removal is still lost, as no lock is taken.
+                let claimed = fs::metadata(&tmp)?;
+                match fs::symlink_metadata(&path) {
+                    Ok(m) if (m.dev(), m.ino()) == (claimed.dev(), claimed.ino()) => {}
+                    Ok(_) => return Ok(false),
+                    Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+                    Err(e) => return Err(e),
+                }
+                fs::remove_file(&path)?;
+                self.sync_parent(&path).map(|()| true)
+            });
+            fs::remove_file(&tmp)?;
+            removed
+        };
+        let removed = result().map_err(|e| KvsError::io_at(e, &path))?;
+        if removed {
+            self.signal_watchers();
+        }
+        Ok(removed)
+    }
+
+    fn contains_many(&self, keys: &[&str]) -> Result<Vec<bool>, KvsError> {
+        // Listing the directory once beats a lookup per key in large batches
+        if keys.len() < SCAN_THRESHOLD {
+            return keys.iter().map(|key| self.contains(key)).collect();
+        }
+        let existing: BTreeSet<String> = self.keys()?.into_iter().collect();
+        Ok(keys.iter().map(|key| existing.contains(*key)).collect())
+    }
+
+    fn modified(&self, key: &str) -> Result<Option<SystemTime>, KvsError> {
+        let _owner = self.act_as_owner()?;
+        let path = self.key_path(key);
+        match fs::metadata(&path).and_then(|m| m.modified()) {
+            Ok(modified) => Ok(Some(modified)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None), // Key doesn't exist
+            Err(e) => Err(KvsError::io_at(e, &path)),
+        }
+    }
+
+    fn touch(&mut self, key: &str, at: SystemTime) -> Result<bool, KvsError> {
+        let _owner = self.act_as_owner()?;
+        let path = self.key_path(key);
+        // Opening for writing without truncating leaves the contents intact
+        match File::options().write(true).open(&path) {
+            Ok(file) => file
+                .set_modified(at)
+                .map(|_| true)
+                .map_err(|e| KvsError::io_at(e, &path)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(KvsError::io_at(e, &path)),
+        }
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> Result<bool, KvsError> {
+        let _owner = self.act_as_owner()?;
+        let path = self.key_path(from);
+        let target = self.key_path(to);
+        let result = || {
+            if !path.exists() {
+                return Ok(false);
+            }
+            // Atomically replace any existing file for the new key
+            self.create_shard(&target)?;
+            match fs::rename(&path, &target) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+                Err(e) => return Err(e),
+            }
+            // Sync directories to ensure rename is persistent
+            self.sync_parent(&path)?;
+            self.sync_parent(&target)?;
+            Ok(true)
+        };
+        let renamed = result().map_err(|e| KvsError::io_at(e, &path))?;
+        if renamed {
+            self.signal_watchers();
+        }
+        Ok(renamed)
+    }
+
+    fn listen(&self) -> Result<Option<Box<dyn ChangeListener>>, KvsError> {
+        let _owner = self.act_as_owner()?;
+        let dir = self.path.join(WATCHERS_DIR);
+        let path = dir.join(format!("{:016x}", random::<u64>()));
+        let result = || {
+            fs::create_dir_all(&dir)?;
+            UnixDatagram::bind(&path)
+        };
+        match result() {
+            Ok(socket) => Ok(Some(Box::new(SocketListener {
+                socket,
+                path,
+                owner: self.owner,
+            }))),
+            Err(e) => {
+                // Socket paths are limited to about 100 bytes, so watchers of
+                // deeply nested stores fall back to polling
+                debug!("not listening for changes at {}: {e}", path.display());
+                Ok(None)
+            }
+        }
+    }
+
+    fn reopen(&self) -> Result<Option<DynBackingStore>, KvsError> {
+        let dir = self
+            .dir
+            .try_clone()
+            .map_err(|e| KvsError::io_at(e, &self.path))?;
+        Ok(Some(Box::new(Self {
+            path: self.path.clone(),
+            dir,
+            owner: self.owner,
+            sharded: self.sharded,
+        })))
     }
 }
+
+/// Receives change signals on a datagram socket in the watchers directory.
+struct SocketListener {
+    socket: UnixDatagram,
+    path: PathBuf,
+    /// The store's owner, to act as when removing the socket.
+    owner: Option<(u32, u32)>,
+}
+
+impl ChangeListener for SocketListener {
+    fn wait(&mut self, timeout: Duration) -> bool {
+        let mut signal = [0u8; 1];
+        // A zero timeout is rejected, so wait at least a millisecond
+        if let Err(e) = self
+            .socket
+            .set_read_timeout(Some(timeout.max(Duration::from_millis(1))))
+        {
+            debug!("failed to wait for change signals: {e}");
+            thread::sleep(timeout);
+            return false;
+        }
+        if self.socket.recv(&mut signal).is_err() {
+            return false;
+        }
+        // Drain signals sent by a burst of writes, so they cause one poll
+        if self.socket.set_nonblocking(true).is_ok() {
+            while self.socket.recv(&mut signal).is_ok() {}
+            let _ = self.socket.set_nonblocking(false);
+        }
+        true
+    }
+}
+
+impl Drop for SocketListener {
+    fn drop(&mut self) {
+        if let Ok(_owner) = Credentials::assume(self.owner) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+thread_local! {
+    /// Whether the current thread has switched to another user's
+    /// credentials.
+    static ACTING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// The calling thread's file system credentials, switched to another
+/// user's until dropped.
+///
+/// Only the calling thread is affected, so other threads of the process
+/// keep its own credentials. Switching while already switched does
+/// nothing, so operations can be nested.
+struct Credentials {
+    switched: bool,
+    /// Credentials belong to the thread that switched them.
+    _thread: PhantomData<*const ()>,
+}
+
+impl Credentials {
+    /// Switches to the user and group ids of `owner`, if given and
+    /// different from the process's own.
+    fn assume(owner: Option<(u32, u32)>) -> io::Result<Self> {
+        let mut credentials = Self {
+            switched: false,
+            _thread: PhantomData,
+        };
+        let Some((uid, gid)) = owner else {
+            return Ok(credentials);
+        };
+        // SAFETY: geteuid and getegid have no preconditions and cannot fail.
+        let own = unsafe { (libc::geteuid(), libc::getegid()) };
+        if own == (uid, gid) || ACTING.get() {
+            return Ok(credentials);
+        }
+        switch_credentials(uid, gid)?;
+        ACTING.set(true);
+        credentials.switched = true;
+        Ok(credentials)
+    }
+}
+
+impl Drop for Credentials {
+    fn drop(&mut self) {
+        if self.switched {
+            restore_credentials();
+            ACTING.set(false);
+        }
+    }
+}
+
+/// Switches the calling thread's file system user and group ids.
+#[cfg(target_os = "linux")]
+fn switch_credentials(uid: u32, gid: u32) -> io::Result<()> {
+    // setfsuid and setfsgid return the previous id rather than reporting
+    // failure, so each change is confirmed by making it again. The group is
+    // changed first, while the thread still has the privilege to.
+    // SAFETY: These calls have no preconditions and only affect the
+    // calling thread.
+    let switched = unsafe {
+        libc::setfsgid(gid);
+        libc::setfsgid(gid) as u32 == gid && {
+            libc::setfsuid(uid);
+            libc::setfsuid(uid) as u32 == uid
+        }
+    };
+    if !switched {
+        restore_credentials();
+        return Err(io::Error::from(ErrorKind::PermissionDenied));
+    }
+    Ok(())
+}
+
+/// Restores the calling thread's file system user and group ids to the
+/// process's effective ids.
+#[cfg(target_os = "linux")]
+fn restore_credentials() {
+    // SAFETY: These calls have no preconditions and only affect the
+    // calling thread.
+    unsafe {
+        libc::setfsuid(libc::geteuid());
+        libc::setfsgid(libc::getegid());
+    }
+}
+
+#[cfg(target_os = "macos")]
+unsafe extern "C" {
+    fn pthread_setugid_np(uid: libc::uid_t, gid: libc::gid_t) -> libc::c_int;
+}
+
+/// Passed to `pthread_setugid_np` to revert to the process's credentials.
+#[cfg(target_os = "macos")]
+const KAUTH_ID_NONE: u32 = u32::MAX - 100;
+
+/// Switches the calling thread's user and group ids.
+#[cfg(target_os = "macos")]
+fn switch_credentials(uid: u32, gid: u32) -> io::Result<()> {
+    // SAFETY: pthread_setugid_np only affects the calling thread.
+    if unsafe { pthread_setugid_np(uid, gid) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Restores the calling thread's user and group ids to the process's.
+#[cfg(target_os = "macos")]
+fn restore_credentials() {
+    // SAFETY: pthread_setugid_np only affects the calling thread.
+    unsafe { pthread_setugid_np(KAUTH_ID_NONE, KAUTH_ID_NONE) };
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn switch_credentials(_uid: u32, _gid: u32) -> io::Result<()> {
+    Err(io::Error::from(ErrorKind::Unsupported))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn restore_credentials() {}
@@ -0,0 +1,204 @@
+//! Stores whose scope is chosen at runtime.
+//!
+//! `KeyValueStore` is generic over its scope, which suits code that always
+//! uses the same one. Applications that pick the scope from configuration
+//! or command-line flags can hold a [`DynKeyValueStore`] instead, whose
+//! backing store is boxed, so the choice doesn't spread type parameters
+//! through every struct that keeps a store.
+
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::api::{BackingStore, KeyValueStore, Scope};
+use crate::error::KvsError;
+use crate::watch::ChangeListener;
+
+/// A boxed backing store of any scope.
+pub type DynBackingStore = Box<dyn BackingStore + Send + Sync>;
+
+/// A key-value store whose scope was chosen at runtime.
+pub type DynKeyValueStore = KeyValueStore<Dynamic>;
+
+/// A scope standing for any other, chosen when the store is opened.
+///
+/// Stores in this scope are opened with [`KeyValueStore::open_scope`],
+/// converted from a typed store with [`KeyValueStore::into_dyn`], or built
+/// over any backing store with `KeyValueStore::with_backing_store`; opening
+/// them with `KeyValueStore::new` fails, since there is no default scope.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::dynamic::DynKeyValueStore;
+/// use zep_kvs::prelude::*;
+///
+/// struct Settings {
+///     store: DynKeyValueStore,
+/// }
+///
+/// let portable = true;
+/// let store = match portable {
+///     true => DynKeyValueStore::open_scope::<scope::Ephemeral>()?,
+///     false => DynKeyValueStore::open_scope::<scope::User>()?,
+/// };
+/// let mut settings = Settings { store };
+/// settings.store.store("theme", "dark")?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct Dynamic(());
+
+impl Scope for Dynamic {
+    type Store = DynBackingStore;
+
+    fn new() -> Result<Self::Store, KvsError> {
+        Err(KvsError::SerializationError(
+            "Dynamic stores must be opened with open_scope or into_dyn".to_string(),
+        ))
+    }
+}
+
+impl KeyValueStore<Dynamic> {
+    /// Opens a store of scope `S`, erasing the scope from its type.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`KeyValueStore::new`] for scope `S`.
+    pub fn open_scope<S>() -> Result<Self, KvsError>
+    where
+        S: Scope,
+        S::Store: Send + Sync + 'static,
+    {
+        Self::with_backing_store(Box::new(S::new()?))
+    }
+}
+
+impl<S: Scope> KeyValueStore<S>
+where
+    S::Store: Send + Sync + 'static,
+{
+    /// Erases the scope from the store's type, keeping its configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store's quota usage cannot be measured
+    /// again.
+    pub fn into_dyn(self) -> Result<DynKeyValueStore, KvsError> {
+        self.map_backing_store(|inner| Ok(Box::new(inner) as DynBackingStore))
+    }
+}
+
+impl BackingStore for DynBackingStore {
+    fn keys(&self) -> Result<Vec<String>, KvsError> {
+        (**self).keys()
+    }
+
+    fn store(&mut self, key: &str, value: &[u8]) -> Result<(), KvsError> {
+        (**self).store(key, value)
+    }
+
+    fn retrieve(&self, key: &str) -> Result<Option<Vec<u8>>, KvsError> {
+        (**self).retrieve(key)
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), KvsError> {
+        (**self).remove(key)
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> Result<bool, KvsError> {
+        (**self).rename(from, to)
+    }
+
+    fn store_many(&mut self, entries: &[(&str, &[u8])]) -> Result<(), KvsError> {
+        (**self).store_many(entries)
+    }
+
+    fn retrieve_many(&self, keys: &[&str]) -> Result<Vec<Option<Vec<u8>>>, KvsError> {
+        (**self).retrieve_many(keys)
+    }
+
+    fn contains(&self, key: &str) -> Result<bool, KvsError> {
+        (**self).contains(key)
+    }
+
+    fn store_if_absent(&mut self, key: &str, value: &[u8]) -> Result<bool, KvsError> {
+        (**self).store_if_absent(key, value)
+    }
+
+    fn remove_if(&mut self, key: &str, expected: &[u8]) -> Result<bool, KvsError> {
+        (**self).remove_if(key, expected)
+    }
+
+    fn contains_many(&self, keys: &[&str]) -> Result<Vec<bool>, KvsError> {
+        (**self).contains_many(keys)
+    }
+
+    fn keys_with_prefix(&self, prefix: &str) -> Result<Vec<String>, KvsError> {
+        (**self).keys_with_prefix(prefix)
+    }
+
+    fn remove_secure(&mut self, key: &str) -> Result<(), KvsError> {
+        (**self).remove_secure(key)
+    }
+
+    fn listen(&self) -> Result<Option<Box<dyn ChangeListener>>, KvsError> {
+        (**self).listen()
+    }
+
+    fn reopen(&self) -> Result<Option<DynBackingStore>, KvsError> {
+        (**self).reopen()
+    }
+
+    fn modified(&self, key: &str) -> Result<Option<SystemTime>, KvsError> {
+        (**self).modified(key)
+    }
+
+    fn touch(&mut self, key: &str, at: SystemTime) -> Result<bool, KvsError> {
+        (**self).touch(key, at)
+    }
+
+    fn location(&self) -> Option<&Path> {
+        (**self).location()
+    }
+
+    fn free_space(&self) -> Result<Option<u64>, KvsError> {
+        (**self).free_space()
+    }
+
+    fn maintain(&mut self) -> Result<(), KvsError> {
+        (**self).maintain()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::scope::Ephemeral;
+    use crate::ephemeral::EphemeralStore;
+
+    #[test]
+    fn test_dynamic_store_round_trip() {
+        let mut store = DynKeyValueStore::open_scope::<Ephemeral>().unwrap();
+        store.store("theme", "dark").unwrap();
+        assert!(store.rename("theme", "colors").unwrap());
+        assert_eq!(store.keys().unwrap(), ["colors"]);
+
+        let mut custom =
+            DynKeyValueStore::with_backing_store(Box::new(EphemeralStore::new())).unwrap();
+        custom.store("a", 1u32).unwrap();
+        assert_eq!(custom.retrieve::<_, u32>("a").unwrap(), Some(1));
+
+        assert!(DynKeyValueStore::new().is_err());
+    }
+
+    #[test]
+    fn test_into_dyn_keeps_configuration() {
+        let mut store = KeyValueStore::<Ephemeral>::new().unwrap().with_type_tags();
+        store.store("count", 7u32).unwrap();
+        let store = store.into_dyn().unwrap();
+        assert_eq!(store.retrieve::<_, u32>("count").unwrap(), Some(7));
+        assert!(matches!(
+            store.retrieve::<_, String>("count"),
+            Err(KvsError::TypeMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,963 @@
+//! Password-protected stores.
+//!
+//! The [`Encrypted`] scope wraps another scope and encrypts every value
+//! with ChaCha20-Poly1305 before it reaches storage. Values are encrypted
+//! with a random data key, which is itself stored encrypted with a key
+//! derived from the user's passphrase with Argon2id. Changing the passphrase
+//! therefore only re-encrypts the data key.
+//!
+//! On Windows, the data key can instead be protected with DPAPI, which ties
+//! it to the user's Windows logon, so no passphrase is needed.
+//!
+//! The [`PartlyEncrypted`] scope encrypts only chosen keys in the same way,
+//! for settings stores that hold a few secrets among values that should
+//! stay readable.
+//!
+//! Key names, value sizes and modification times are not hidden. Each value
+//! is bound to its key, so values cannot be swapped between keys without
+//! detection.
+
+use std::fmt;
+use std::path::Path;
+use std::time::SystemTime;
+
+use chacha20poly1305::aead::KeyInit;
+use chacha20poly1305::{ChaCha20Poly1305, Key};
+use rand::random;
+
+use crate::api::{BackingStore, Scope, is_reserved};
+use crate::crypto::{self, KdfParams};
+use crate::dynamic::DynBackingStore;
+use crate::error::KvsError;
+use crate::watch::ChangeListener;
+
+/// Reserved key holding the key derivation parameters and the encrypted
+/// data key.
+const HEADER_KEY: &str = "__zep_encryption";
+const HEADER_VERSION: u8 = 1;
+/// Prefix of reserved keys holding metadata encrypted by the first unlock
+/// of a store until the header is written.
+const SEALING_PREFIX: &str = "__zep_encryption_sealing:";
+/// Associated data authenticated with the encrypted data key.
+const DATA_KEY_AAD: &[u8] = b"zep-kvs data key";
+/// Reserved key holding the encryption header of a partly encrypted store.
+const PARTIAL_HEADER_KEY: &str = "__zep_partial_encryption";
+/// Reserved key holding the [`EncryptedKeys`] a partly encrypted store was
+/// last unlocked with, encrypted.
+const ENCRYPTED_KEYS_KEY: &str = "__zep_partial_encrypted_keys";
+/// Prefix of reserved keys holding values converted by the first unlock of
+/// a partly encrypted store until the header is written.
+const CONVERTING_PREFIX: &str = "__zep_partial_converting:";
+
+/// Marks a value of a partly encrypted store stored as is.
+const PLAIN: u8 = 0;
+/// Marks a value of a partly encrypted store stored encrypted.
+const SEALED: u8 = 1;
+
+/// A scope whose values are encrypted with a key derived from a passphrase.
+///
+/// Stores in this scope are opened with `KeyValueStore::unlock`; opening
+/// them with `KeyValueStore::new` fails with `KvsError::Locked`. The first
+/// unlock of an empty store sets its passphrase.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::encrypted::Encrypted;
+/// use zep_kvs::prelude::*;
+///
+/// let mut vault = KeyValueStore::<Encrypted<scope::Ephemeral>>::unlock("passphrase")?;
+/// vault.store("api_token", "s3cr3t")?;
+/// vault.change_password("new passphrase")?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct Encrypted<S: Scope>(S);
+
+impl<S: Scope> Scope for Encrypted<S> {
+    type Store = EncryptedStore<S::Store>;
+
+    fn new() -> Result<Self::Store, KvsError> {
+        Err(KvsError::Locked)
+    }
+}
+
+/// How the data key of an encrypted store is protected.
+pub(crate) enum Protection<'a> {
+    /// Encrypted with a key derived from a passphrase.
+    Passphrase(&'a str, KdfParams),
+    /// Encrypted with DPAPI for the current Windows user.
+    #[cfg(windows)]
+    Platform,
+}
+
+/// Header byte identifying passphrase protection.
+const PASSPHRASE: u8 = 0;
+/// Header byte identifying platform key protection.
+const PLATFORM: u8 = 1;
+
+/// Backing store of the [`Encrypted`] scope.
+pub struct EncryptedStore<B: BackingStore> {
+    inner: B,
+    data_key: Key,
+    cipher: ChaCha20Poly1305,
+    /// Key derivation parameters for new passphrases.
+    params: KdfParams,
+}
+
+/// Shows the underlying store, never the key.
+impl<B: BackingStore + fmt::Debug> fmt::Debug for EncryptedStore<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedStore")
+            .field("inner", &self.inner)
+            .field("params", &self.params)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<B: BackingStore> EncryptedStore<B> {
+    /// Unlocks `inner` with the data key protected by `protection`.
+    ///
+    /// If `inner` has no encryption header yet, a new data key is created
+    /// and protected with `protection`, and any existing store metadata is
+    /// encrypted.
+    pub(crate) fn unlock(inner: B, protection: Protection) -> Result<Self, KvsError> {
+        let (data_key, params) = match inner.retrieve(HEADER_KEY)? {
+            Some(header) => open_header(&header, protection)?,
+            None => return Self::initialize(inner, protection),
+        };
+        let mut store = Self {
+            inner,
+            cipher: ChaCha20Poly1305::new(&data_key),
+            data_key,
+            params,
+        };
+        store.finish_sealing()?;
+        Ok(store)
+    }
+
+    /// Encrypts the existing metadata of `inner` and writes its header.
+    ///
+    /// The encrypted metadata is staged under other keys until the header
+    /// is written, so a store is never left with a header it can't be
+    /// opened with.
+    fn initialize(mut inner: B, protection: Protection) -> Result<Self, KvsError> {
+        let mut keys = inner.keys()?;
+        if keys.iter().any(|key| !is_reserved(key)) {
+            return Err(KvsError::SerializationError(
+                "Cannot encrypt a store that already holds unencrypted data".to_string(),
+            ));
+        }
+        // Discard values staged by an interrupted first unlock
+        for key in keys.iter().filter(|k| k.starts_with(SEALING_PREFIX)) {
+            inner.remove(key)?;
+        }
+        keys.retain(|k| !k.starts_with(SEALING_PREFIX));
+        let (data_key, params, header) = create_data_key(protection)?;
+        let mut store = Self {
+            inner,
+            cipher: ChaCha20Poly1305::new(&data_key),
+            data_key,
+            params,
+        };
+        for key in keys {
+            if let Some(value) = store.inner.retrieve(&key)? {
+                let sealed = crypto::seal(&store.cipher, key.as_bytes(), &value)?;
+                store
+                    .inner
+                    .store(&format!("{SEALING_PREFIX}{key}"), &sealed)?;
+            }
+        }
+        store.inner.store(HEADER_KEY, &header)?;
+        store.finish_sealing()?;
+        Ok(store)
+    }
+
+    /// Moves the metadata encrypted by the first unlock over the originals.
+    fn finish_sealing(&mut self) -> Result<(), KvsError> {
+        for staged in self.inner.keys()? {
+            let Some(key) = staged.strip_prefix(SEALING_PREFIX) else {
+                continue;
+            };
+            if let Some(sealed) = self.inner.retrieve(&staged)? {
+                self.inner.store(key, &sealed)?;
+            }
+            self.inner.remove(&staged)?;
+        }
+        Ok(())
+    }
+
+    /// Protects the data key with a new passphrase.
+    pub(crate) fn change_password(&mut self, passphrase: &str) -> Result<(), KvsError> {
+        let header = header(
+            &self.data_key,
+            Protection::Passphrase(passphrase, self.params),
+        )?;
+        self.inner.store(HEADER_KEY, &header)
+    }
+}
+
+/// Creates a data key, returning it along with the key derivation
+/// parameters for new passphrases and the encryption header protecting it.
+fn create_data_key(protection: Protection) -> Result<(Key, KdfParams, Vec<u8>), KvsError> {
+    let data_key = Key::from(random::<[u8; 32]>());
+    let params = match protection {
+        Protection::Passphrase(_, params) => params,
+        #[cfg(windows)]
+        Protection::Platform => KdfParams::default(),
+    };
+    let header = header(&data_key, protection)?;
+    Ok((data_key, params, header))
+}
+
+/// Builds an encryption header protecting `data_key`.
+fn header(data_key: &Key, protection: Protection) -> Result<Vec<u8>, KvsError> {
+    let mut header = vec![HEADER_VERSION];
+    match protection {
+        Protection::Passphrase(passphrase, params) => {
+            header.push(PASSPHRASE);
+            header.extend_from_slice(&params.encode());
+            let salt: [u8; 16] = random();
+            header.extend_from_slice(&salt);
+            let key_cipher = ChaCha20Poly1305::new(&crypto::derive_key(passphrase, &salt, params)?);
+            header.extend(crypto::seal(&key_cipher, DATA_KEY_AAD, data_key)?);
+        }
+        #[cfg(windows)]
+        Protection::Platform => {
+            header.push(PLATFORM);
+            header.extend(crypto::protect(data_key)?);
+        }
+    }
+    Ok(header)
+}
+
+/// Recovers the data key from an encryption header, along with the key
+/// derivation parameters to use for new passphrases.
+fn open_header(header: &[u8], protection: Protection) -> Result<(Key, KdfParams), KvsError> {
+    let invalid = || KvsError::SerializationError("Invalid encryption header".to_string());
+    let [version, protector, rest @ ..] = header else {
+        return Err(invalid());
+    };
+    if *version > HEADER_VERSION {
+        return Err(KvsError::UnsupportedFormat {
+            found: (*version).into(),
+            supported: HEADER_VERSION.into(),
+        });
+    }
+    let (data_key, params) = match (*protector, protection) {
+        (PASSPHRASE, Protection::Passphrase(passphrase, _)) => {
+            if rest.len() < 28 {
+                return Err(invalid());
+            }
+            let params = KdfParams::decode(rest[..12].try_into().unwrap())?;
+            let (salt, wrapped) = rest[12..].split_at(16);
+            let key_cipher = ChaCha20Poly1305::new(&crypto::derive_key(passphrase, salt, params)?);
+            (crypto::open(&key_cipher, DATA_KEY_AAD, wrapped)?, params)
+        }
+        #[cfg(windows)]
+        (PLATFORM, Protection::Platform) => (crypto::unprotect(rest)?, KdfParams::default()),
+        // Protected some other way
+        (protector, _) if protector <= PLATFORM => return Err(KvsError::DecryptionFailed),
+        _ => return Err(invalid()),
+    };
+    if data_key.len() != 32 {
+        return Err(invalid());
+    }
+    Ok((*Key::from_slice(&data_key), params))
+}
+
+impl<B: BackingStore> BackingStore for EncryptedStore<B> {
+    fn keys(&self) -> Result<Vec<String>, KvsError> {
+        self.inner.keys()
+    }
+
+    fn store(&mut self, key: &str, value: &[u8]) -> Result<(), KvsError> {
+        let sealed = crypto::seal(&self.cipher, key.as_bytes(), value)?;
+        self.inner.store(key, &sealed)
+    }
+
+    fn store_if_absent(&mut self, key: &str, value: &[u8]) -> Result<bool, KvsError> {
+        let sealed = crypto::seal(&self.cipher, key.as_bytes(), value)?;
+        self.inner.store_if_absent(key, &sealed)
+    }
+
+    fn retrieve(&self, key: &str) -> Result<Option<Vec<u8>>, KvsError> {
+        match self.inner.retrieve(key)? {
+            Some(sealed) if key == HEADER_KEY => Ok(Some(sealed)),
+            Some(sealed) => crypto::open(&self.cipher, key.as_bytes(), &sealed).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), KvsError> {
+        self.inner.remove(key)
+    }
+
+    fn remove_secure(&mut self, key: &str) -> Result<(), KvsError> {
+        self.inner.remove_secure(key)
+    }
+
+    fn remove_if(&mut self, key: &str, expected: &[u8]) -> Result<bool, KvsError> {
+        // Sealing is randomized, so the stored bytes are compared by their
+        // plaintext and then matched exactly
+        match self.inner.retrieve(key)? {
+            Some(sealed)
+                if key != HEADER_KEY
+                    && crypto::open(&self.cipher, key.as_bytes(), &sealed)? == expected =>
+            {
+                self.inner.remove_if(key, &sealed)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn modified(&self, key: &str) -> Result<Option<SystemTime>, KvsError> {
+        self.inner.modified(key)
+    }
+
+    fn touch(&mut self, key: &str, at: SystemTime) -> Result<bool, KvsError> {
+        self.inner.touch(key, at)
+    }
+
+    fn location(&self) -> Option<&Path> {
+        self.inner.location()
+    }
+
+    fn free_space(&self) -> Result<Option<u64>, KvsError> {
+        self.inner.free_space()
+    }
+
+    fn maintain(&mut self) -> Result<(), KvsError> {
+        self.inner.maintain()
+    }
+
+    fn listen(&self) -> Result<Option<Box<dyn ChangeListener>>, KvsError> {
+        self.inner.listen()
+    }
+
+    fn reopen(&self) -> Result<Option<DynBackingStore>, KvsError> {
+        self.inner.reopen()
+    }
+}
+
+/// A scope in which only chosen keys are encrypted.
+///
+/// Stores in this scope are opened with `KeyValueStore::unlock`, passing
+/// the passphrase and the [`EncryptedKeys`] to encrypt; opening them with
+/// `KeyValueStore::new` fails with `KvsError::Locked`. Values of other keys
+/// are stored as they are, apart from a one-byte marker.
+///
+/// Unlike [`Encrypted`], the first unlock can convert a store that already
+/// holds data, encrypting the values of the chosen keys. If more keys are
+/// chosen later, their values are encrypted by the next unlock; values of
+/// keys no longer chosen stay encrypted until next written. A value of a
+/// chosen key found unencrypted otherwise has been tampered with, and
+/// reading it fails with `KvsError::DecryptionFailed`.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::encrypted::{EncryptedKeys, PartlyEncrypted};
+/// use zep_kvs::prelude::*;
+///
+/// let secrets = EncryptedKeys {
+///     keys: vec!["api_token".to_string()],
+///     prefixes: vec!["accounts/".to_string()],
+/// };
+/// let mut settings = KeyValueStore::<PartlyEncrypted<scope::Ephemeral>>::unlock("passphrase", secrets)?;
+/// settings.store("theme", "dark")?;
+/// settings.store("api_token", "s3cr3t")?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct PartlyEncrypted<S: Scope>(S);
+
+impl<S: Scope> Scope for PartlyEncrypted<S> {
+    type Store = PartlyEncryptedStore<S::Store>;
+
+    fn new() -> Result<Self::Store, KvsError> {
+        Err(KvsError::Locked)
+    }
+}
+
+/// The keys whose values a [`PartlyEncrypted`] store encrypts.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EncryptedKeys {
+    /// Individual keys to encrypt.
+    pub keys: Vec<String>,
+    /// Prefixes of keys to encrypt, such as `"secrets/"`.
+    pub prefixes: Vec<String>,
+}
+
+impl EncryptedKeys {
+    /// Returns whether values stored under `key` are encrypted.
+    pub fn contains(&self, key: &str) -> bool {
+        !is_reserved(key)
+            && (self.keys.iter().any(|k| k == key)
+                || self.prefixes.iter().any(|p| key.starts_with(p.as_str())))
+    }
+
+    /// Encodes the keys and prefixes, each as a tag byte and a
+    /// length-prefixed name.
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for (tag, names) in [(0, &self.keys), (1, &self.prefixes)] {
+            for name in names {
+                bytes.push(tag);
+                bytes.extend_from_slice(&(name.len() as u32).to_be_bytes());
+                bytes.extend_from_slice(name.as_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// Decodes keys and prefixes written by [`encode`](Self::encode).
+    fn decode(mut bytes: &[u8]) -> Result<Self, KvsError> {
+        let invalid = || KvsError::SerializationError("Invalid encrypted key list".to_string());
+        let mut encrypted = Self::default();
+        while let [tag, rest @ ..] = bytes {
+            let (len, rest) = rest.split_at_checked(4).ok_or_else(invalid)?;
+            let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+            let (name, rest) = rest.split_at_checked(len).ok_or_else(invalid)?;
+            let name = String::from_utf8(name.to_vec()).map_err(|_| invalid())?;
+            match tag {
+                0 => encrypted.keys.push(name),
+                1 => encrypted.prefixes.push(name),
+                _ => return Err(invalid()),
+            }
+            bytes = rest;
+        }
+        Ok(encrypted)
+    }
+}
+
+/// Backing store of the [`PartlyEncrypted`] scope.
+pub struct PartlyEncryptedStore<B: BackingStore> {
+    inner: B,
+    data_key: Key,
+    cipher: ChaCha20Poly1305,
+    /// Key derivation parameters for new passphrases.
+    params: KdfParams,
+    encrypted: EncryptedKeys,
+}
+
+/// Shows the underlying store and the keys encrypted, never the key.
+impl<B: BackingStore + fmt::Debug> fmt::Debug for PartlyEncryptedStore<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PartlyEncryptedStore")
+            .field("inner", &self.inner)
+            .field("params", &self.params)
+            .field("encrypted", &self.encrypted)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<B: BackingStore> PartlyEncryptedStore<B> {
+    /// Unlocks `inner` with the data key protected by `protection`,
+    /// encrypting the values of `encrypted` keys.
+    ///
+    /// If `inner` has no encryption header yet, a new data key is created
+    /// and protected with `protection`, and every existing value is
+    /// rewritten, encrypted if its key is chosen. The converted values are
+    /// staged under reserved keys and the header is written last, so an
+    /// interrupted conversion is discarded by the next unlock if the header
+    /// was not written, and completed if it was.
+    pub(crate) fn unlock(
+        mut inner: B,
+        protection: Protection,
+        encrypted: EncryptedKeys,
+    ) -> Result<Self, KvsError> {
+        if inner.retrieve(HEADER_KEY)?.is_some() {
+            return Err(KvsError::SerializationError(
+                "Store is already fully encrypted".to_string(),
+            ));
+        }
+        if let Some(header) = inner.retrieve(PARTIAL_HEADER_KEY)? {
+            let (data_key, params) = open_header(&header, protection)?;
+            let mut store = Self {
+                inner,
+                cipher: ChaCha20Poly1305::new(&data_key),
+                data_key,
+                params,
+                encrypted,
+            };
+            store.finish_conversion()?;
+            store.seal_chosen_keys()?;
+            return Ok(store);
+        }
+
+        let mut keys = inner.keys()?;
+        // Discard values staged by an interrupted first unlock
+        for key in keys.iter().filter(|k| k.starts_with(CONVERTING_PREFIX)) {
+            inner.remove(key)?;
+        }
+        keys.retain(|k| !k.starts_with(CONVERTING_PREFIX) && k != ENCRYPTED_KEYS_KEY);
+        let (data_key, params, header) = create_data_key(protection)?;
+        let mut store = Self {
+            inner,
+            cipher: ChaCha20Poly1305::new(&data_key),
+            data_key,
+            params,
+            encrypted,
+        };
+        for key in keys {
+            if let Some(value) = store.inner.retrieve(&key)? {
+                let marked = store.mark(&key, &value)?;
+                store
+                    .inner
+                    .store(&format!("{CONVERTING_PREFIX}{key}"), &marked)?;
+            }
+        }
+        store.inner.store(PARTIAL_HEADER_KEY, &header)?;
+        store.finish_conversion()?;
+        store.seal_chosen_keys()?;
+        Ok(store)
+    }
+
+    /// Encrypts the values of keys chosen since the last unlock, then
+    /// records the keys chosen.
+    ///
+    /// Only newly chosen keys are encrypted, so an unencrypted value
+    /// written over the value of a key that was already chosen is still
+    /// rejected.
+    fn seal_chosen_keys(&mut self) -> Result<(), KvsError> {
+        let previous = match self.inner.retrieve(ENCRYPTED_KEYS_KEY)? {
+            Some(marked) => EncryptedKeys::decode(&self.unmark(ENCRYPTED_KEYS_KEY, &marked)?)?,
+            None => EncryptedKeys::default(),
+        };
+        if previous == self.encrypted {
+            return Ok(());
+        }
+        for key in self.inner.keys()? {
+            if !self.encrypted.contains(&key) || previous.contains(&key) {
+                continue;
+            }
+            if let Some(marked) = self.inner.retrieve(&key)?
+                && let Some((&PLAIN, value)) = marked.split_first()
+            {
+                let sealed = self.mark(&key, value)?;
+                self.inner.store(&key, &sealed)?;
+            }
+        }
+        let sealed = crypto::seal(
+            &self.cipher,
+            ENCRYPTED_KEYS_KEY.as_bytes(),
+            &self.encrypted.encode(),
+        )?;
+        self.inner
+            .store(ENCRYPTED_KEYS_KEY, &[&[SEALED], sealed.as_slice()].concat())
+    }
+
+    /// Moves the values converted by the first unlock over the originals.
+    fn finish_conversion(&mut self) -> Result<(), KvsError> {
+        for staged in self.inner.keys()? {
+            let Some(key) = staged.strip_prefix(CONVERTING_PREFIX) else {
+                continue;
+            };
+            if let Some(marked) = self.inner.retrieve(&staged)? {
+                self.inner.store(key, &marked)?;
+            }
+            self.inner.remove(&staged)?;
+        }
+        Ok(())
+    }
+
+    /// Protects the data key with a new passphrase.
+    pub(crate) fn change_password(&mut self, passphrase: &str) -> Result<(), KvsError> {
+        let header = header(
+            &self.data_key,
+            Protection::Passphrase(passphrase, self.params),
+        )?;
+        self.inner.store(PARTIAL_HEADER_KEY, &header)
+    }
+
+    /// Returns the bytes stored for `value` under `key`: marked, and
+    /// encrypted if the key is chosen.
+    fn mark(&self, key: &str, value: &[u8]) -> Result<Vec<u8>, KvsError> {
+        Ok(match self.encrypted.contains(key) {
+            true => [
+                &[SEALED],
+                crypto::seal(&self.cipher, key.as_bytes(), value)?.as_slice(),
+            ]
+            .concat(),
+            false => [&[PLAIN], value].concat(),
+        })
+    }
+
+    /// Returns the value in bytes stored by [`mark`](Self::mark).
+    fn unmark(&self, key: &str, marked: &[u8]) -> Result<Vec<u8>, KvsError> {
+        match marked.split_first() {
+            // An encrypted value replaced with an unencrypted one
+            Some((&PLAIN, _)) if self.encrypted.contains(key) => Err(KvsError::DecryptionFailed),
+            Some((&PLAIN, value)) => Ok(value.to_vec()),
+            Some((&SEALED, sealed)) => crypto::open(&self.cipher, key.as_bytes(), sealed),
+            _ => Err(KvsError::SerializationError(
+                "Invalid partly encrypted value".to_string(),
+            )),
+        }
+    }
+}
+
+impl<B: BackingStore> BackingStore for PartlyEncryptedStore<B> {
+    fn keys(&self) -> Result<Vec<String>, KvsError> {
+        self.inner.keys()
+    }
+
+    fn store(&mut self, key: &str, value: &[u8]) -> Result<(), KvsError> {
+        let marked = self.mark(key, value)?;
+        self.inner.store(key, &marked)
+    }
+
+    fn store_if_absent(&mut self, key: &str, value: &[u8]) -> Result<bool, KvsError> {
+        let marked = self.mark(key, value)?;
+        self.inner.store_if_absent(key, &marked)
+    }
+
+    fn retrieve(&self, key: &str) -> Result<Option<Vec<u8>>, KvsError> {
+        match self.inner.retrieve(key)? {
+            Some(header) if key == PARTIAL_HEADER_KEY => Ok(Some(header)),
+            Some(marked) => self.unmark(key, &marked).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), KvsError> {
+        self.inner.remove(key)
+    }
+
+    fn remove_secure(&mut self, key: &str) -> Result<(), KvsError> {
+        self.inner.remove_secure(key)
+    }
+
+    fn remove_if(&mut self, key: &str, expected: &[u8]) -> Result<bool, KvsError> {
+        // Sealing is randomized, so the stored bytes are compared by their
+        // value and then matched exactly
+        match self.inner.retrieve(key)? {
+            Some(marked) if key != PARTIAL_HEADER_KEY && self.unmark(key, &marked)? == expected => {
+                self.inner.remove_if(key, &marked)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn modified(&self, key: &str) -> Result<Option<SystemTime>, KvsError> {
+        self.inner.modified(key)
+    }
+
+    fn touch(&mut self, key: &str, at: SystemTime) -> Result<bool, KvsError> {
+        self.inner.touch(key, at)
+    }
+
+    fn location(&self) -> Option<&Path> {
+        self.inner.location()
+    }
+
+    fn free_space(&self) -> Result<Option<u64>, KvsError> {
+        self.inner.free_space()
+    }
+
+    fn maintain(&mut self) -> Result<(), KvsError> {
+        self.inner.maintain()
+    }
+
+    fn listen(&self) -> Result<Option<Box<dyn ChangeListener>>, KvsError> {
+        self.inner.listen()
+    }
+
+    fn reopen(&self) -> Result<Option<DynBackingStore>, KvsError> {
+        self.inner.reopen()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::KeyValueStore;
+    use crate::crypto::tests::FAST;
+    use crate::testing::{Mock, MockStore, Operation};
+
+    fn unlock(
+        mock: &MockStore,
+        passphrase: &str,
+    ) -> Result<KeyValueStore<Encrypted<Mock>>, KvsError> {
+        KeyValueStore::with_backing_store(EncryptedStore::unlock(
+            mock.clone(),
+            Protection::Passphrase(passphrase, FAST),
+        )?)
+    }
+
+    #[test]
+    fn test_values_are_encrypted() {
+        let mock = MockStore::new();
+        let mut store = unlock(&mock, "passphrase").unwrap();
+        store.store("token", "s3cr3t").unwrap();
+
+        let sealed = mock.retrieve("token").unwrap().unwrap();
+        assert!(!sealed.windows(6).any(|w| w == b"s3cr3t"));
+        assert_eq!(store.keys().unwrap(), ["token"]);
+
+        let reopened = unlock(&mock, "passphrase").unwrap();
+        assert_eq!(
+            reopened.retrieve::<_, String>("token").unwrap().as_deref(),
+            Some("s3cr3t")
+        );
+    }
+
+    #[test]
+    fn test_wrong_passphrase_is_rejected() {
+        let mock = MockStore::new();
+        unlock(&mock, "passphrase").unwrap();
+        assert!(matches!(
+            unlock(&mock, "guess"),
+            Err(KvsError::DecryptionFailed)
+        ));
+    }
+
+    #[test]
+    fn test_hostile_kdf_parameters_are_rejected() {
+        let mut mock = MockStore::new();
+        unlock(&mock, "passphrase").unwrap();
+        let mut header = mock.retrieve(HEADER_KEY).unwrap().unwrap();
+        header[2..6].copy_from_slice(&u32::MAX.to_be_bytes());
+        mock.store(HEADER_KEY, &header).unwrap();
+        assert!(matches!(
+            unlock(&mock, "passphrase"),
+            Err(KvsError::SerializationError(e)) if e.contains("out of range")
+        ));
+    }
+
+    #[test]
+    fn test_change_password() {
+        let mock = MockStore::new();
+        let mut store = unlock(&mock, "old").unwrap();
+        store.store("key", 1u8).unwrap();
+        store.change_password("new").unwrap();
+
+        assert!(matches!(
+            unlock(&mock, "old"),
+            Err(KvsError::DecryptionFailed)
+        ));
+        let store = unlock(&mock, "new").unwrap();
+        assert_eq!(store.retrieve::<_, u8>("key").unwrap(), Some(1));
+    }
+
+    #[test]
+    fn test_swapped_values_are_detected() {
+        let mut mock = MockStore::new();
+        let mut store = unlock(&mock, "passphrase").unwrap();
+        store.store("a", "1").unwrap();
+        let sealed = mock.retrieve("a").unwrap().unwrap();
+        mock.store("b", &sealed).unwrap();
+        assert!(matches!(
+            store.retrieve::<_, String>("b"),
+            Err(KvsError::DecryptionFailed)
+        ));
+    }
+
+    #[test]
+    fn test_remove_if_compares_plaintext() {
+        let mock = MockStore::new();
+        let mut store = unlock(&mock, "passphrase").unwrap();
+        store.store("token", "refreshed").unwrap();
+        assert!(!store.remove_if("token", "expired").unwrap());
+        assert!(store.remove_if("token", "refreshed").unwrap());
+        assert_eq!(mock.retrieve("token").unwrap(), None);
+    }
+
+    fn unlock_partly(
+        mock: &MockStore,
+        passphrase: &str,
+    ) -> Result<KeyValueStore<PartlyEncrypted<Mock>>, KvsError> {
+        let encrypted = EncryptedKeys {
+            keys: vec!["token".to_string()],
+            prefixes: vec!["secret/".to_string()],
+        };
+        KeyValueStore::with_backing_store(PartlyEncryptedStore::unlock(
+            mock.clone(),
+            Protection::Passphrase(passphrase, FAST),
+            encrypted,
+        )?)
+    }
+
+    #[test]
+    fn test_only_chosen_keys_are_encrypted() {
+        let mock = MockStore::new();
+        let mut store = unlock_partly(&mock, "passphrase").unwrap();
+        store.store("theme", "dark").unwrap();
+        store.store("token", "s3cr3t").unwrap();
+        store.store("secret/pin", "1234").unwrap();
+
+        assert_eq!(mock.retrieve("theme").unwrap().unwrap(), b"\0dark");
+        for (key, value) in [("token", b"s3cr3t".as_slice()), ("secret/pin", b"1234")] {
+            let sealed = mock.retrieve(key).unwrap().unwrap();
+            assert!(!sealed.windows(value.len()).any(|w| w == value));
+        }
+
+        // Renamed values are encrypted according to their new key
+        store.rename("token", "old_token").unwrap();
+        assert_eq!(mock.retrieve("old_token").unwrap().unwrap(), b"\0s3cr3t");
+
+        let reopened = unlock_partly(&mock, "passphrase").unwrap();
+        assert_eq!(
+            reopened
+                .retrieve::<_, String>("secret/pin")
+                .unwrap()
+                .as_deref(),
+            Some("1234")
+        );
+        assert!(matches!(
+            unlock_partly(&mock, "guess"),
+            Err(KvsError::DecryptionFailed)
+        ));
+    }
+
+    #[test]
+    fn test_first_partial_unlock_converts_existing_data() {
+        let mut mock = MockStore::new();
+        let mut plain = KeyValueStore::<Mock>::with_backing_store(mock.clone()).unwrap();
+        plain.store("theme", "dark").unwrap();
+        plain.store("token", "s3cr3t").unwrap();
+
+        let store = unlock_partly(&mock, "passphrase").unwrap();
+        assert!(
+            !mock
+                .retrieve("token")
+                .unwrap()
+                .unwrap()
+                .ends_with(b"s3cr3t")
+        );
+        assert_eq!(
+            store.retrieve::<_, String>("token").unwrap().as_deref(),
+            Some("s3cr3t")
+        );
+        store.info().unwrap();
+
+        let full = MockStore::new();
+        unlock(&full, "passphrase").unwrap();
+        assert!(unlock_partly(&full, "passphrase").is_err());
+        mock.store("other", b"value").unwrap();
+        assert!(unlock(&mock, "passphrase").is_err());
+    }
+
+    #[test]
+    fn test_interrupted_partial_conversion() {
+        let mut mock = MockStore::new();
+        mock.store("token", b"s3cr3t").unwrap();
+        mock.store("theme", b"dark").unwrap();
+
+        // Staged values without a header are discarded
+        mock.store(&format!("{CONVERTING_PREFIX}token"), b"\x01stale")
+            .unwrap();
+        let store = unlock_partly(&mock, "passphrase").unwrap();
+        let mut keys = store.keys().unwrap();
+        keys.sort();
+        assert_eq!(keys, ["theme", "token"]);
+        assert_eq!(
+            store.retrieve::<_, String>("token").unwrap().as_deref(),
+            Some("s3cr3t")
+        );
+
+        // Staged values with a header are moved into place
+        let converted = mock.retrieve("theme").unwrap().unwrap();
+        mock.store("theme", b"dark").unwrap();
+        mock.store(&format!("{CONVERTING_PREFIX}theme"), &converted)
+            .unwrap();
+        let store = unlock_partly(&mock, "passphrase").unwrap();
+        assert_eq!(mock.retrieve("theme").unwrap().unwrap(), b"\0dark");
+        assert!(
+            !mock
+                .keys()
+                .unwrap()
+                .iter()
+                .any(|k| k.starts_with(CONVERTING_PREFIX))
+        );
+        assert_eq!(
+            store.retrieve::<_, String>("theme").unwrap().as_deref(),
+            Some("dark")
+        );
+    }
+
+    #[test]
+    fn test_unencrypted_values_of_chosen_keys() {
+        let mut mock = MockStore::new();
+        let mut store = unlock_partly(&mock, "passphrase").unwrap();
+        store.store("token", "s3cr3t").unwrap();
+        store.store("pin", "1234").unwrap();
+
+        // A downgraded value of a chosen key is rejected
+        mock.store("token", b"\0forged").unwrap();
+        assert!(matches!(
+            store.retrieve::<_, String>("token"),
+            Err(KvsError::DecryptionFailed)
+        ));
+        assert!(matches!(
+            unlock_partly(&mock, "passphrase")
+                .unwrap()
+                .retrieve::<_, String>("token"),
+            Err(KvsError::DecryptionFailed)
+        ));
+
+        // Values of newly chosen keys are encrypted on unlock
+        let encrypted = EncryptedKeys {
+            keys: vec!["token".to_string(), "pin".to_string()],
+            prefixes: Vec::new(),
+        };
+        let store = KeyValueStore::<PartlyEncrypted<Mock>>::with_backing_store(
+            PartlyEncryptedStore::unlock(
+                mock.clone(),
+                Protection::Passphrase("passphrase", FAST),
+                encrypted,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert!(!mock.retrieve("pin").unwrap().unwrap().ends_with(b"1234"));
+        assert_eq!(
+            store.retrieve::<_, String>("pin").unwrap().as_deref(),
+            Some("1234")
+        );
+        assert!(store.retrieve::<_, String>("token").is_err());
+    }
+
+    /// Returns a store holding only metadata, and the number of its keys.
+    fn metadata_only() -> (MockStore, usize) {
+        let mock = MockStore::new();
+        let mut plain = KeyValueStore::<Mock>::with_backing_store(mock.clone()).unwrap();
+        plain.store("key", "value").unwrap();
+        plain.remove("key").unwrap();
+        let count = mock.keys().unwrap().len();
+        (mock, count)
+    }
+
+    #[test]
+    fn test_interrupted_first_unlock() {
+        // Interrupted before the header is written, and after
+        for interrupted in [1, 2] {
+            let (mock, count) = metadata_only();
+            let info = KeyValueStore::<Mock>::with_backing_store(mock.clone())
+                .unwrap()
+                .info()
+                .unwrap();
+            mock.script_reserved_keys().fail_nth(
+                Operation::Store,
+                count + interrupted,
+                std::io::ErrorKind::PermissionDenied,
+            );
+            assert!(unlock(&mock, "passphrase").is_err());
+
+            let store = unlock(&mock, "passphrase").unwrap();
+            assert_eq!(store.info().unwrap(), info);
+            assert_eq!(mock.keys().unwrap().len(), count + 1);
+        }
+    }
+
+    #[test]
+    fn test_first_unlock_of_existing_store() {
+        let (mock, _) = metadata_only();
+        // Existing metadata is encrypted on first unlock
+        unlock(&mock, "passphrase").unwrap();
+        unlock(&mock, "passphrase").unwrap();
+
+        let mut plain = MockStore::new();
+        plain.store("key", b"value").unwrap();
+        assert!(unlock(&plain, "passphrase").is_err());
+    }
+}
@@ -0,0 +1,96 @@
+//! Self-describing value envelopes.
+//!
+//! When type tags are enabled on a store, every value is wrapped in an
+//! envelope recording the type it was stored as, so that retrieving it as an
+//! incompatible type fails with a clear type mismatch instead of a confusing
+//! byte length error.
+//!
+//! # Format
+//!
+//! ```text
+//! [tag length: u16][tag: UTF-8][value length: u32][value]
+//! ```
+//!
+//! Both lengths are big-endian.
+
+use crate::error::KvsError;
+
+/// Wraps value bytes in an envelope carrying the given type tag.
+///
+/// # Errors
+///
+/// Returns `SerializationError` if the tag or value is too long to be
+/// described by the envelope header.
+pub(crate) fn wrap(tag: &str, value: &[u8]) -> Result<Vec<u8>, KvsError> {
+    let tag_len = u16::try_from(tag.len())
+        .map_err(|_| KvsError::SerializationError("Type tag too long".to_string()))?;
+    let value_len = u32::try_from(value.len())
+        .map_err(|_| KvsError::SerializationError("Value too long for envelope".to_string()))?;
+    let mut bytes = Vec::with_capacity(6 + tag.len() + value.len());
+    bytes.extend_from_slice(&tag_len.to_be_bytes());
+    bytes.extend_from_slice(tag.as_bytes());
+    bytes.extend_from_slice(&value_len.to_be_bytes());
+    bytes.extend_from_slice(value);
+    Ok(bytes)
+}
+
+/// Unwraps an envelope, checking that it carries the expected type tag.
+///
+/// # Errors
+///
+/// Returns `TypeMismatch` if the stored tag differs from `expected`, or
+/// `SerializationError` if the bytes are not a well-formed envelope.
+pub(crate) fn unwrap<'a>(expected: &str, bytes: &'a [u8]) -> Result<&'a [u8], KvsError> {
+    let malformed = || KvsError::SerializationError("Invalid value envelope".to_string());
+    let (tag_len, rest) = bytes.split_first_chunk::<2>().ok_or_else(malformed)?;
+    let tag_len = u16::from_be_bytes(*tag_len) as usize;
+    if rest.len() < tag_len {
+        return Err(malformed());
+    }
+    let (tag, rest) = rest.split_at(tag_len);
+    let tag = std::str::from_utf8(tag).map_err(|_| malformed())?;
+    let (value_len, value) = rest.split_first_chunk::<4>().ok_or_else(malformed)?;
+    if u32::from_be_bytes(*value_len) as usize != value.len() {
+        return Err(malformed());
+    }
+    if tag != expected {
+        return Err(KvsError::TypeMismatch {
+            expected: expected.to_string(),
+            found: tag.to_string(),
+        });
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope_round_trip() {
+        let bytes = wrap("u32", &[0, 0, 0, 42]).unwrap();
+        assert_eq!(bytes, [0, 3, b'u', b'3', b'2', 0, 0, 0, 4, 0, 0, 0, 42]);
+        assert_eq!(unwrap("u32", &bytes).unwrap(), &[0, 0, 0, 42]);
+    }
+
+    #[test]
+    fn test_envelope_type_mismatch() {
+        let bytes = wrap("String", b"hello").unwrap();
+        match unwrap("u32", &bytes) {
+            Err(KvsError::TypeMismatch { expected, found }) => {
+                assert_eq!(expected, "u32");
+                assert_eq!(found, "String");
+            }
+            _ => panic!("Expected TypeMismatch"),
+        }
+    }
+
+    #[test]
+    fn test_envelope_malformed() {
+        let bytes = wrap("String", b"hello").unwrap();
+        assert!(unwrap("String", &bytes[..bytes.len() - 1]).is_err());
+        assert!(unwrap("String", &[0]).is_err());
+        assert!(unwrap("String", &[0, 200, b'a']).is_err());
+        assert!(unwrap("String", b"hello").is_err());
+    }
+}
@@ -1,13 +1,29 @@
 //! In-memory storage implementation for ephemeral data.
 //!
-//! This module provides a HashMap-based storage backend that keeps
-//! data in memory only. Data is lost when the store is dropped,
+//! This module provides a BTreeMap-based storage backend that keeps
+//! data in memory only. Keys are kept in sorted order, so `keys()` output
+//! is deterministic and ranges of keys can be queried efficiently. Data is lost when the store is dropped,
 //! making it ideal for testing and temporary storage needs.
+//!
+//! With the `ephemeral-scopes` feature enabled, the `User`, `Machine` and
+//! `Session` scopes are backed by this store too, so application test suites never
+//! touch persistent storage.
 
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
 
-use crate::api::{BackingStore, Scope, scope::Ephemeral};
+#[cfg(feature = "ephemeral-scopes")]
+use crate::api::scope::{Machine, Session, User};
+use crate::api::{BackingStore, Scope, is_reserved, scope::Ephemeral};
+use crate::dynamic::DynBackingStore;
 use crate::error::KvsError;
+use crate::sensitive::wipe;
 
 impl Scope for Ephemeral {
     type Store = EphemeralStore;
@@ -17,11 +33,42 @@ impl Scope for Ephemeral {
     }
 }
 
-/// In-memory key-value store using a HashMap.
+#[cfg(feature = "ephemeral-scopes")]
+impl Scope for User {
+    type Store = EphemeralStore;
+
+    fn new() -> Result<Self::Store, KvsError> {
+        Ok(EphemeralStore::new())
+    }
+}
+
+#[cfg(feature = "ephemeral-scopes")]
+impl Scope for Machine {
+    type Store = EphemeralStore;
+
+    fn new() -> Result<Self::Store, KvsError> {
+        Ok(EphemeralStore::new())
+    }
+}
+
+#[cfg(feature = "ephemeral-scopes")]
+impl Scope for Session {
+    type Store = EphemeralStore;
+
+    fn new() -> Result<Self::Store, KvsError> {
+        Ok(EphemeralStore::new())
+    }
+}
+
+/// In-memory key-value store using a BTreeMap.
 ///
 /// This store keeps all data in memory and provides fast access
 /// to stored values. Data is not persisted and will be lost when
-/// the store is dropped.
+/// the store is dropped. Keys are always listed in sorted order.
+///
+/// A store created with [`EphemeralStore::with_capacity`] is bounded and
+/// evicts its least recently used entries to make room for new ones, so it
+/// can serve as an in-process cache.
 ///
 /// # Examples
 ///
@@ -34,35 +81,399 @@ impl Scope for Ephemeral {
 /// assert_eq!(value, "value");
 /// # Ok::<(), Box<dyn std::error::Error>>(())
 /// ```
+#[derive(Default)]
 pub struct EphemeralStore {
-    store: HashMap<String, Vec<u8>>,
+    store: BTreeMap<String, Entry>,
+    capacity: Option<Capacity>,
+    /// Total size of all keys and values outside the reserved namespace, in
+    /// bytes.
+    bytes: usize,
+    /// Number of entries in the reserved namespace, which the capacity
+    /// doesn't count.
+    reserved: usize,
+    /// Source of recency stamps for entries.
+    clock: AtomicU64,
+}
+
+/// Shows the size of the store, never its keys or values.
+impl fmt::Debug for EphemeralStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EphemeralStore")
+            .field("entries", &self.store.len())
+            .field("bytes", &self.bytes)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A stored value with the time it was last used.
+///
+/// Values are shared between forks of a store and never modified in place.
+struct Entry {
+    value: Arc<[u8]>,
+    used: AtomicU64,
+    modified: SystemTime,
+}
+
+impl Clone for Entry {
+    fn clone(&self) -> Self {
+        Self {
+            value: Arc::clone(&self.value),
+            used: AtomicU64::new(self.used.load(Ordering::Relaxed)),
+            modified: self.modified,
+        }
+    }
+}
+
+/// Limits of a bounded store.
+#[derive(Clone, Copy)]
+struct Capacity {
+    entries: usize,
+    bytes: usize,
 }
 
 impl EphemeralStore {
     /// Creates a new empty ephemeral store.
-    fn new() -> Self {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty store holding at most `max_entries` entries and
+    /// `max_bytes` bytes of keys and values.
+    ///
+    /// When a write would exceed either limit, the least recently stored or
+    /// retrieved entries are evicted until it fits. An entry that could never
+    /// fit is rejected with `KvsError::ValueTooLarge`. Eviction scans every
+    /// entry, so this is intended for modestly sized caches. The store's
+    /// metadata, kept under reserved keys, is neither counted nor evicted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::ephemeral::EphemeralStore;
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let cache = EphemeralStore::with_capacity(100, 64 * 1024);
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::with_backing_store(cache)?;
+    /// store.store("thumbnail", [0u8; 1024].as_slice())?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn with_capacity(max_entries: usize, max_bytes: usize) -> Self {
+        Self {
+            capacity: Some(Capacity {
+                entries: max_entries,
+                bytes: max_bytes,
+            }),
+            ..Self::default()
+        }
+    }
+
+    /// Returns an independent copy of this store.
+    ///
+    /// Values are shared with the original rather than copied, so forking
+    /// is cheap even for large values, and changes to either store are not
+    /// seen by the other. This lets tests build a fixture once and branch
+    /// it for every case.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::api::BackingStore;
+    /// use zep_kvs::ephemeral::EphemeralStore;
+    ///
+    /// let mut fixture = EphemeralStore::new();
+    /// fixture.store("user", b"alice")?;
+    ///
+    /// let mut case = fixture.fork();
+    /// case.remove("user")?;
+    /// assert_eq!(fixture.retrieve("user")?, Some(b"alice".to_vec()));
+    /// assert_eq!(case.retrieve("user")?, None);
+    /// # Ok::<(), zep_kvs::error::KvsError>(())
+    /// ```
+    pub fn fork(&self) -> Self {
         Self {
-            store: HashMap::new(),
+            store: self.store.clone(),
+            capacity: self.capacity,
+            bytes: self.bytes,
+            reserved: self.reserved,
+            clock: AtomicU64::new(self.clock.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Returns a new recency stamp, later than all previous ones.
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Evicts least recently used entries other than `keep` until the store
+    /// is within its capacity.
+    fn evict(&mut self, keep: &str) {
+        let Some(capacity) = self.capacity else {
+            return;
+        };
+        while self.store.len() - self.reserved > capacity.entries || self.bytes > capacity.bytes {
+            let oldest = self
+                .store
+                .iter()
+                .filter(|(key, _)| key.as_str() != keep && !is_reserved(key))
+                .min_by_key(|(_, entry)| entry.used.load(Ordering::Relaxed))
+                .map(|(key, _)| key.clone());
+            match oldest {
+                Some(key) => {
+                    self.remove_entry(&key);
+                }
+                None => break,
+            }
         }
     }
+
+    fn remove_entry(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.store.remove(key)?;
+        match is_reserved(key) {
+            true => self.reserved -= 1,
+            false => self.bytes -= key.len() + entry.value.len(),
+        }
+        Some(entry)
+    }
+
+    /// Returns the entries whose keys fall within `range`, in key order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::api::BackingStore;
+    /// use zep_kvs::ephemeral::EphemeralStore;
+    ///
+    /// let mut store = EphemeralStore::new();
+    /// for key in ["apple", "banana", "cherry"] {
+    ///     store.store(key, key.as_bytes())?;
+    /// }
+    /// let keys: Vec<&str> = store.range("b".."c").map(|(key, _)| key).collect();
+    /// assert_eq!(keys, ["banana"]);
+    /// # Ok::<(), zep_kvs::error::KvsError>(())
+    /// ```
+    pub fn range<'a, R>(&self, range: R) -> impl Iterator<Item = (&str, &[u8])>
+    where
+        R: RangeBounds<&'a str>,
+    {
+        let bounds = (
+            range.start_bound().map(|key| *key),
+            range.end_bound().map(|key| *key),
+        );
+        self.store
+            .range::<str, _>(bounds)
+            .map(|(key, entry)| (key.as_str(), &*entry.value))
+    }
 }
 
 impl BackingStore for EphemeralStore {
     fn keys(&self) -> Result<Vec<String>, KvsError> {
-        Ok(self.store.keys().map(|k| k.clone()).collect())
+        Ok(self.store.keys().cloned().collect())
     }
 
     fn store(&mut self, key: &str, value: &[u8]) -> Result<(), KvsError> {
-        self.store.insert(String::from(key), Vec::from(value));
+        let size = key.len() + value.len();
+        if let Some(capacity) = self.capacity.as_ref().filter(|_| !is_reserved(key)) {
+            if size > capacity.bytes {
+                return Err(KvsError::ValueTooLarge {
+                    size,
+                    limit: capacity.bytes,
+                });
+            }
+            if capacity.entries == 0 {
+                return Err(KvsError::io_at(
+                    io::Error::from(io::ErrorKind::StorageFull),
+                    Path::new(key),
+                ));
+            }
+        }
+        self.remove_entry(key);
+        let used = AtomicU64::new(self.tick());
+        self.store.insert(
+            String::from(key),
+            Entry {
+                value: Arc::from(value),
+                used,
+                modified: SystemTime::now(),
+            },
+        );
+        match is_reserved(key) {
+            true => self.reserved += 1,
+            false => self.bytes += size,
+        }
+        self.evict(key);
         Ok(())
     }
 
     fn retrieve(&self, key: &str) -> Result<Option<Vec<u8>>, KvsError> {
-        Ok(self.store.get(key).map(|value| value.clone()))
+        Ok(self.store.get(key).map(|entry| {
+            entry.used.store(self.tick(), Ordering::Relaxed);
+            entry.value.to_vec()
+        }))
     }
 
     fn remove(&mut self, key: &str) -> Result<(), KvsError> {
-        self.store.remove(key);
+        self.remove_entry(key);
+        Ok(())
+    }
+
+    fn remove_secure(&mut self, key: &str) -> Result<(), KvsError> {
+        // Values still shared with a fork or snapshot are left intact
+        if let Some(value) = self
+            .remove_entry(key)
+            .as_mut()
+            .and_then(|entry| Arc::get_mut(&mut entry.value))
+        {
+            wipe(value);
+        }
         Ok(())
     }
+
+    fn contains(&self, key: &str) -> Result<bool, KvsError> {
+        Ok(self.store.contains_key(key))
+    }
+
+    fn contains_many(&self, keys: &[&str]) -> Result<Vec<bool>, KvsError> {
+        Ok(keys
+            .iter()
+            .map(|key| self.store.contains_key(*key))
+            .collect())
+    }
+
+    fn keys_with_prefix(&self, prefix: &str) -> Result<Vec<String>, KvsError> {
+        Ok(self
+            .store
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(prefix))
+            .cloned()
+            .collect())
+    }
+
+    fn modified(&self, key: &str) -> Result<Option<SystemTime>, KvsError> {
+        Ok(self.store.get(key).map(|entry| entry.modified))
+    }
+
+    fn touch(&mut self, key: &str, at: SystemTime) -> Result<bool, KvsError> {
+        Ok(match self.store.get_mut(key) {
+            Some(entry) => {
+                entry.modified = at;
+                true
+            }
+            None => false,
+        })
+    }
+
+    /// Returns a new, empty store, as no other instance can see this one.
+    fn reopen(&self) -> Result<Option<DynBackingStore>, KvsError> {
+        Ok(Some(Box::new(EphemeralStore::new())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_are_sorted() {
+        let mut store = EphemeralStore::new();
+        for key in ["pear", "apple", "fig", "banana"] {
+            store.store(key, b"").unwrap();
+        }
+        assert_eq!(store.keys().unwrap(), ["apple", "banana", "fig", "pear"]);
+    }
+
+    #[test]
+    fn test_range() {
+        let mut store = EphemeralStore::new();
+        for (i, key) in ["a1", "a2", "b1", "b2", "c1"].iter().enumerate() {
+            store.store(key, &[i as u8]).unwrap();
+        }
+        let found: Vec<_> = store.range("b"..).collect();
+        assert_eq!(
+            found,
+            [("b1", &[2u8][..]), ("b2", &[3][..]), ("c1", &[4][..])]
+        );
+        assert_eq!(store.range("a2"..="b1").count(), 2);
+        assert_eq!(store.range("d"..).count(), 0);
+    }
+
+    #[test]
+    fn test_capacity_evicts_least_recently_used() {
+        let mut store = EphemeralStore::with_capacity(2, 1024);
+        store.store("a", b"1").unwrap();
+        store.store("b", b"2").unwrap();
+        // Using "a" makes "b" the least recently used entry
+        store.retrieve("a").unwrap();
+        store.store("c", b"3").unwrap();
+        assert_eq!(store.keys().unwrap(), ["a", "c"]);
+    }
+
+    #[test]
+    fn test_capacity_limits_bytes() {
+        let mut store = EphemeralStore::with_capacity(10, 8);
+        store.store("a", b"123").unwrap();
+        store.store("b", b"123").unwrap();
+        // Overwriting frees the old value's bytes first
+        store.store("b", b"12").unwrap();
+        assert_eq!(store.keys().unwrap(), ["a", "b"]);
+        store.store("c", b"1234").unwrap();
+        assert_eq!(store.keys().unwrap(), ["b", "c"]);
+        store.remove("c").unwrap();
+        store.store("d", b"1234567").unwrap();
+        assert_eq!(store.keys().unwrap(), ["d"]);
+    }
+
+    #[test]
+    fn test_capacity_ignores_reserved_keys() {
+        let mut store = EphemeralStore::with_capacity(1, 8);
+        store.store("__zep_version", b"12345678").unwrap();
+        store.store("a", b"1").unwrap();
+        store.retrieve("__zep_version").unwrap();
+        store.store("b", b"1234567").unwrap();
+        assert_eq!(store.keys().unwrap(), ["__zep_version", "b"]);
+        store.remove("__zep_version").unwrap();
+        assert_eq!(store.reserved, 0);
+        assert_eq!(store.bytes, 8);
+    }
+
+    #[test]
+    fn test_capacity_rejects_oversized_value() {
+        let mut store = EphemeralStore::with_capacity(10, 8);
+        store.store("a", b"1").unwrap();
+        assert!(matches!(
+            store.store("b", &[0; 8]),
+            Err(KvsError::ValueTooLarge { size: 9, limit: 8 })
+        ));
+        assert_eq!(store.keys().unwrap(), ["a"]);
+    }
+
+    #[test]
+    fn test_fork_is_independent() {
+        let mut fixture = EphemeralStore::with_capacity(2, 1024);
+        fixture.store("a", b"1").unwrap();
+        fixture.store("b", b"2").unwrap();
+
+        let mut fork = fixture.fork();
+        fork.store("a", b"changed").unwrap();
+        fork.store("c", b"3").unwrap();
+        fixture.remove("b").unwrap();
+
+        assert_eq!(fixture.keys().unwrap(), ["a"]);
+        assert_eq!(fixture.retrieve("a").unwrap(), Some(b"1".to_vec()));
+        // The fork keeps the capacity, evicting "b" to make room for "c"
+        assert_eq!(fork.keys().unwrap(), ["a", "c"]);
+        assert_eq!(fork.retrieve("a").unwrap(), Some(b"changed".to_vec()));
+    }
+
+    #[test]
+    fn test_remove_secure_keeps_shared_values() {
+        let mut fixture = EphemeralStore::new();
+        fixture.store("token", b"s3cr3t").unwrap();
+        let fork = fixture.fork();
+
+        fixture.remove_secure("token").unwrap();
+        fixture.remove_secure("missing").unwrap();
+        assert_eq!(fixture.retrieve("token").unwrap(), None);
+        assert_eq!(fork.retrieve("token").unwrap(), Some(b"s3cr3t".to_vec()));
+    }
 }
@@ -4,6 +4,8 @@
 //! the key-value store, including I/O errors, scope access issues,
 //! and data conversion problems.
 
+use std::fmt;
+use std::io;
 use std::path::{Path, PathBuf};
 
 use thiserror::Error;
@@ -13,6 +15,7 @@ use thiserror::Error;
 /// This enum covers all possible failure modes, from file system
 /// access issues to data serialization problems.
 #[derive(Error, Debug)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Error), uniffi(flat_error))]
 pub enum KvsError {
     /// Error when stored data cannot be decoded as valid UTF-8.
     ///
@@ -28,17 +31,65 @@ pub enum KvsError {
     #[error("Serialization error: {0}")]
     SerializationError(String),
 
+    /// A tagged value was retrieved as an incompatible type.
+    ///
+    /// This only occurs on stores with type tags enabled, when the type
+    /// tag recorded with the stored value differs from the requested type.
+    #[error("Type mismatch: stored as {found}, requested as {expected}")]
+    TypeMismatch {
+        /// The type tag of the requested type.
+        expected: String,
+        /// The type tag the value was stored with.
+        found: String,
+    },
+
+    /// The store was written with a newer, unsupported format version.
+    ///
+    /// This occurs when a store written by a newer version of the
+    /// application is opened by an older one.
+    #[error("Unsupported store format version {found}, newest supported is {supported}")]
+    UnsupportedFormat {
+        /// The format version recorded in the store.
+        found: u32,
+        /// The newest format version supported by this library.
+        supported: u32,
+    },
+
     /// I/O error that occurred while accessing storage.
     ///
     /// This includes file system errors, permission issues,
     /// and other low-level storage problems. The `path` field
-    /// indicates where the error occurred.
-    #[error("{source}: {path}")]
+    /// indicates where the error occurred, and `operation` and `key`
+    /// what the store was doing at the time.
+    #[error("{source}: {path}{}", describe_operation(*.operation, .key.as_deref()))]
     IoError {
         /// The file system path where the error occurred.
         path: PathBuf,
         /// The underlying I/O error.
         source: std::io::Error,
+        /// The store operation that failed, if known.
+        operation: Option<Operation>,
+        /// The key the operation was applied to, if any.
+        key: Option<String>,
+    },
+
+    /// The file system holding the store has run out of space.
+    ///
+    /// Either a write failed because the disk was full, or the space check
+    /// enabled with `KeyValueStore::with_space_check` found too little room
+    /// for a value, in which case nothing was written.
+    #[error("Storage full: {path}{}", describe_operation(*.operation, .key.as_deref()))]
+    StorageFull {
+        /// The location that is out of space.
+        path: PathBuf,
+        /// The I/O error reporting the full disk, or `None` if the space
+        /// check failed.
+        #[source]
+        source: Option<io::Error>,
+        /// The store operation that failed, if known.
+        operation: Option<Operation>,
+        /// The key the operation was applied to, if any.
+        key: Option<String>,
     },
 
     /// Machine-wide storage scope is not available.
@@ -46,16 +97,90 @@ pub enum KvsError {
     /// This typically occurs when the application lacks the necessary
     /// permissions to access system-wide storage locations, or when
     /// the required directories cannot be created.
-    #[error("No machine scope. {0}")]
-    NoMachineScope(String),
+    #[error("No machine scope. {reason}")]
+    NoMachineScope {
+        /// Why the scope could not be opened.
+        reason: String,
+        /// The I/O error that prevented opening the scope, if any.
+        #[source]
+        source: Option<io::Error>,
+    },
 
     /// User-specific storage scope is not available.
     ///
     /// This can happen when the user's home directory is not accessible,
     /// when environment variables are missing, or when user directories
     /// cannot be created due to permission issues.
-    #[error("No user scope. {0}")]
-    NoUserScope(String),
+    #[error("No user scope. {reason}")]
+    NoUserScope {
+        /// Why the scope could not be opened.
+        reason: String,
+        /// The I/O error that prevented opening the scope, if any.
+        #[source]
+        source: Option<io::Error>,
+    },
+
+    /// A write would take the store over its configured quota.
+    ///
+    /// The write is rejected before anything is stored. The message
+    /// describes which limit would have been exceeded.
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(String),
+
+    /// A key in the namespace reserved for internal use was written.
+    ///
+    /// Keys starting with `api::RESERVED_PREFIX` hold the store's own
+    /// bookkeeping and cannot be stored or removed by applications.
+    #[error("Key {0} is reserved for internal use")]
+    ReservedKey(String),
+
+    /// A value is larger than the store accepts.
+    ///
+    /// The write is rejected before anything is stored.
+    #[error("Value of {size} bytes exceeds the limit of {limit} bytes")]
+    ValueTooLarge {
+        /// The size of the value in bytes.
+        size: usize,
+        /// The maximum value size in bytes.
+        limit: usize,
+    },
+
+    /// Encrypted data could not be decrypted.
+    ///
+    /// Either the passphrase or key is wrong, or the data has been
+    /// corrupted or tampered with. The two cannot be told apart.
+    #[error("Decryption failed: wrong passphrase or corrupted data")]
+    DecryptionFailed,
+
+    /// A protected store was opened without its passphrase or key.
+    ///
+    /// Encrypted stores must be opened with `KeyValueStore::unlock`, and
+    /// authenticated stores with `KeyValueStore::with_integrity_key`.
+    #[error("Store is protected and must be opened with its passphrase or key")]
+    Locked,
+
+    /// Data in an authenticated store failed verification.
+    ///
+    /// Either the integrity key is wrong, or the store has been modified
+    /// outside the application. The two cannot be told apart.
+    #[error("Integrity check failed for {}", describe_subject(.key.as_deref()))]
+    IntegrityViolation {
+        /// The key whose value failed verification, or `None` if the set
+        /// of keys did.
+        key: Option<String>,
+    },
+
+    /// A machine-bound value was stored on another machine.
+    ///
+    /// The store, or the value, has been copied from another computer, or
+    /// this machine's identifier has changed, for example by reinstalling
+    /// the operating system.
+    #[error("{} was stored on another machine", describe_value(.key.as_deref()))]
+    ForeignMachine {
+        /// The key whose value is foreign, or `None` if the value was read
+        /// through a `Bound` wrapper, which doesn't know its key.
+        key: Option<String>,
+    },
 }
 
 impl KvsError {
@@ -73,6 +198,233 @@ impl KvsError {
         KvsError::IoError {
             source: io,
             path: at.to_path_buf(),
+            operation: None,
+            key: None,
         }
     }
+
+    /// Returns the kind of the underlying I/O error, if any.
+    ///
+    /// This lets callers tell, for example, a full disk from missing
+    /// permissions without parsing error messages.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::io::ErrorKind;
+    /// use zep_kvs::prelude::*;
+    ///
+    /// match KeyValueStore::<scope::Machine>::new() {
+    ///     Err(e) if e.io_error_kind() == Some(ErrorKind::PermissionDenied) => {
+    ///         eprintln!("run as administrator to change machine settings");
+    ///     }
+    ///     result => drop(result?),
+    /// }
+    /// # Ok::<(), zep_kvs::error::KvsError>(())
+    /// ```
+    pub fn io_error_kind(&self) -> Option<io::ErrorKind> {
+        self.io_source().map(io::Error::kind)
+    }
+
+    /// Returns the operating system error code of the underlying I/O error,
+    /// if any.
+    pub fn raw_os_error(&self) -> Option<i32> {
+        self.io_source().and_then(io::Error::raw_os_error)
+    }
+
+    fn io_source(&self) -> Option<&io::Error> {
+        match self {
+            KvsError::IoError { source, .. } => Some(source),
+            KvsError::StorageFull { source, .. }
+            | KvsError::NoMachineScope { source, .. }
+            | KvsError::NoUserScope { source, .. } => source.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Converts an error opening the machine scope's storage into
+    /// `NoMachineScope`, keeping any underlying I/O error.
+    #[cfg_attr(feature = "ephemeral-scopes", allow(dead_code))]
+    pub(crate) fn no_machine_scope(cause: KvsError) -> KvsError {
+        let (reason, source) = cause.into_reason();
+        KvsError::NoMachineScope { reason, source }
+    }
+
+    /// Converts an error opening the user scope's storage into
+    /// `NoUserScope`, keeping any underlying I/O error.
+    #[cfg_attr(feature = "ephemeral-scopes", allow(dead_code))]
+    pub(crate) fn no_user_scope(cause: KvsError) -> KvsError {
+        let (reason, source) = cause.into_reason();
+        KvsError::NoUserScope { reason, source }
+    }
+
+    fn into_reason(self) -> (String, Option<io::Error>) {
+        let reason = self.to_string();
+        match self {
+            KvsError::IoError { source, .. } => (reason, Some(source)),
+            KvsError::StorageFull { source, .. } => (reason, source),
+            _ => (reason, None),
+        }
+    }
+
+    /// Records the operation and key during which an I/O error occurred,
+    /// turning I/O errors reporting a full disk into `StorageFull`.
+    ///
+    /// Context already recorded by the backing store is kept, and errors
+    /// other than `IoError` and `StorageFull` are returned unchanged.
+    pub(crate) fn during(mut self, op: Operation, at_key: Option<&str>) -> KvsError {
+        if let KvsError::IoError { operation, key, .. }
+        | KvsError::StorageFull { operation, key, .. } = &mut self
+            && operation.is_none()
+        {
+            *operation = Some(op);
+            *key = at_key.map(String::from);
+        }
+        match self {
+            KvsError::IoError {
+                path,
+                source,
+                operation,
+                key,
+            } if source.kind() == io::ErrorKind::StorageFull => KvsError::StorageFull {
+                path,
+                source: Some(source),
+                operation,
+                key,
+            },
+            error => error,
+        }
+    }
+}
+
+/// A store operation, reported with I/O errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Listing keys.
+    Keys,
+    /// Storing a value.
+    Store,
+    /// Retrieving a value.
+    Retrieve,
+    /// Removing a value.
+    Remove,
+    /// Renaming a key.
+    Rename,
+    /// Reading a modification time.
+    Modified,
+    /// Updating a modification time.
+    Touch,
+    /// Running maintenance.
+    Maintain,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Operation::Keys => "listing keys",
+            Operation::Store => "storing",
+            Operation::Retrieve => "retrieving",
+            Operation::Remove => "removing",
+            Operation::Rename => "renaming",
+            Operation::Modified => "reading modification time of",
+            Operation::Touch => "touching",
+            Operation::Maintain => "maintenance",
+        })
+    }
+}
+
+/// Names the key an integrity error is about, or the set of keys.
+fn describe_subject(key: Option<&str>) -> String {
+    match key {
+        Some(key) => format!("key {key:?}"),
+        None => "the set of keys".to_string(),
+    }
+}
+
+fn describe_value(key: Option<&str>) -> String {
+    match key {
+        Some(key) => format!("Value of key {key:?}"),
+        None => "Value".to_string(),
+    }
+}
+
+/// Formats the operation context of an I/O error, such as
+/// ` (while storing key "theme")`.
+fn describe_operation(operation: Option<Operation>, key: Option<&str>) -> String {
+    match (operation, key) {
+        (Some(operation), Some(key)) => format!(" (while {operation} key {key:?})"),
+        (Some(operation), None) => format!(" (while {operation})"),
+        (None, _) => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_error_context() {
+        let io = || std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        let error = KvsError::io_at(io(), Path::new("/var/lib/app"));
+        assert_eq!(error.to_string(), "permission denied: /var/lib/app");
+
+        let error = error.during(Operation::Store, Some("theme"));
+        assert_eq!(
+            error.to_string(),
+            "permission denied: /var/lib/app (while storing key \"theme\")"
+        );
+        // The innermost context wins
+        let error = error.during(Operation::Rename, Some("other"));
+        assert!(matches!(
+            error,
+            KvsError::IoError {
+                operation: Some(Operation::Store),
+                ..
+            }
+        ));
+
+        let error = KvsError::io_at(io(), Path::new("/var/lib/app")).during(Operation::Keys, None);
+        assert!(error.to_string().ends_with("(while listing keys)"));
+    }
+
+    #[test]
+    fn test_full_disk_is_storage_full() {
+        let error = KvsError::io_at(
+            std::io::Error::from(std::io::ErrorKind::StorageFull),
+            Path::new("/var/lib/app/theme"),
+        )
+        .during(Operation::Store, Some("theme"));
+        assert!(matches!(
+            error,
+            KvsError::StorageFull {
+                source: Some(_),
+                operation: Some(Operation::Store),
+                ..
+            }
+        ));
+        assert_eq!(error.io_error_kind(), Some(std::io::ErrorKind::StorageFull));
+        assert_eq!(
+            error.to_string(),
+            "Storage full: /var/lib/app/theme (while storing key \"theme\")"
+        );
+    }
+
+    #[test]
+    fn test_scope_errors_keep_io_error() {
+        let cause = KvsError::io_at(
+            std::io::Error::from_raw_os_error(28),
+            Path::new("/var/lib/app"),
+        );
+        let error = KvsError::no_machine_scope(cause);
+        assert!(error.to_string().starts_with("No machine scope. "));
+        assert_eq!(error.raw_os_error(), Some(28));
+        assert_eq!(error.io_error_kind(), Some(std::io::ErrorKind::StorageFull));
+        assert!(std::error::Error::source(&error).is_some());
+
+        let error = KvsError::NoUserScope {
+            reason: "no user directory found".to_string(),
+            source: None,
+        };
+        assert_eq!(error.io_error_kind(), None);
+    }
 }
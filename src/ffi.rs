@@ -0,0 +1,411 @@
+//! C foreign function interface.
+//!
+//! This module exports `extern "C"` functions that allow non-Rust components
+//! to share the same stores as the Rust application. Stores are accessed
+//! through an opaque `ZepKvsStore` handle, values are passed as raw byte
+//! buffers, and every function returns one of the `ZEP_KVS_*` status codes.
+//! A description of the most recent error on the calling thread is available
+//! from `zep_kvs_last_error`.
+//!
+//! The C header `include/zep_kvs.h` is generated from this module by the
+//! build script when the `ffi` feature is enabled. The crate is not built as
+//! a shared library by default; build one with
+//! `cargo rustc --lib --release --features ffi --crate-type cdylib`.
+//!
+//! # Memory Ownership
+//!
+//! Buffers and key arrays returned by the library are owned by the caller
+//! and must be released with `zep_kvs_free_buffer` and `zep_kvs_free_keys`
+//! respectively. Store handles must be released with `zep_kvs_close`.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString, c_char, c_int};
+use std::ptr;
+
+use crate::any::AnyStore;
+use crate::api::KeyValueStore;
+use crate::error::KvsError;
+
+/// Scope identifier for user-specific storage.
+pub const ZEP_KVS_SCOPE_USER: c_int = 0;
+/// Scope identifier for system-wide storage.
+pub const ZEP_KVS_SCOPE_MACHINE: c_int = 1;
+/// Scope identifier for in-memory storage.
+pub const ZEP_KVS_SCOPE_EPHEMERAL: c_int = 2;
+
+/// The operation completed successfully.
+pub const ZEP_KVS_OK: c_int = 0;
+/// The requested key does not exist.
+pub const ZEP_KVS_NOT_FOUND: c_int = 1;
+/// A null pointer, unknown scope, or non UTF-8 key was passed.
+pub const ZEP_KVS_ERR_INVALID_ARGUMENT: c_int = -1;
+/// The requested storage scope is not available.
+pub const ZEP_KVS_ERR_NO_SCOPE: c_int = -2;
+/// The storage backend failed to read or write data.
+pub const ZEP_KVS_ERR_IO: c_int = -3;
+/// Stored data could not be converted.
+pub const ZEP_KVS_ERR_SERIALIZATION: c_int = -4;
+/// The store was written with a newer, unsupported format version.
+pub const ZEP_KVS_ERR_UNSUPPORTED_FORMAT: c_int = -5;
+/// The write would take the store over its quota.
+pub const ZEP_KVS_ERR_QUOTA_EXCEEDED: c_int = -6;
+/// The value is larger than the store accepts.
+pub const ZEP_KVS_ERR_VALUE_TOO_LARGE: c_int = -7;
+/// Encrypted data could not be decrypted with the given passphrase or key,
+/// or an encrypted store was opened without one.
+pub const ZEP_KVS_ERR_DECRYPTION_FAILED: c_int = -8;
+/// Data in an authenticated store failed verification, or a machine-bound
+/// value was stored on another machine.
+pub const ZEP_KVS_ERR_INTEGRITY: c_int = -9;
+/// The disk holding the store is full.
+pub const ZEP_KVS_ERR_STORAGE_FULL: c_int = -10;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Opaque handle to an open key-value store.
+pub struct ZepKvsStore(AnyStore);
+
+/// Records an error message for `zep_kvs_last_error` and returns its status code.
+fn fail(status: c_int, message: impl Into<Vec<u8>>) -> c_int {
+    let message = CString::new(message).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+    status
+}
+
+/// Maps a library error to its status code, recording its message.
+fn fail_with(error: KvsError) -> c_int {
+    let status = match error {
+        KvsError::NoUserScope { .. } | KvsError::NoMachineScope { .. } => ZEP_KVS_ERR_NO_SCOPE,
+        KvsError::IoError { .. } => ZEP_KVS_ERR_IO,
+        KvsError::StorageFull { .. } => ZEP_KVS_ERR_STORAGE_FULL,
+        KvsError::StringDecodeError(_)
+        | KvsError::SerializationError(_)
+        | KvsError::TypeMismatch { .. } => ZEP_KVS_ERR_SERIALIZATION,
+        KvsError::UnsupportedFormat { .. } => ZEP_KVS_ERR_UNSUPPORTED_FORMAT,
+        KvsError::ReservedKey(_) => ZEP_KVS_ERR_INVALID_ARGUMENT,
+        KvsError::QuotaExceeded(_) => ZEP_KVS_ERR_QUOTA_EXCEEDED,
+        KvsError::ValueTooLarge { .. } => ZEP_KVS_ERR_VALUE_TOO_LARGE,
+        KvsError::DecryptionFailed | KvsError::Locked => ZEP_KVS_ERR_DECRYPTION_FAILED,
+        KvsError::IntegrityViolation { .. } | KvsError::ForeignMachine { .. } => {
+            ZEP_KVS_ERR_INTEGRITY
+        }
+    };
+    fail(status, error.to_string())
+}
+
+/// Converts a C string key into a string slice.
+///
+/// # Safety
+///
+/// `key` must be null or point to a valid NUL-terminated string.
+unsafe fn key_arg<'a>(key: *const c_char) -> Result<&'a str, c_int> {
+    if key.is_null() {
+        return Err(fail(ZEP_KVS_ERR_INVALID_ARGUMENT, "key is null"));
+    }
+    // SAFETY: the caller guarantees `key` is a valid NUL-terminated string.
+    unsafe { CStr::from_ptr(key) }
+        .to_str()
+        .map_err(|_| fail(ZEP_KVS_ERR_INVALID_ARGUMENT, "key is not valid UTF-8"))
+}
+
+/// Returns a description of the most recent error on the calling thread.
+///
+/// Returns null if no error has occurred. The returned string remains valid
+/// until the next failing call on the same thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn zep_kvs_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+/// Opens the store for the given scope.
+///
+/// On success, `*out_store` receives a handle that must be released with
+/// `zep_kvs_close`.
+///
+/// # Safety
+///
+/// `out_store` must be a valid pointer to writable memory for a handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn zep_kvs_open(scope: c_int, out_store: *mut *mut ZepKvsStore) -> c_int {
+    if out_store.is_null() {
+        return fail(ZEP_KVS_ERR_INVALID_ARGUMENT, "out_store is null");
+    }
+    let handle = match scope {
+        ZEP_KVS_SCOPE_USER => KeyValueStore::new().map(AnyStore::User),
+        ZEP_KVS_SCOPE_MACHINE => KeyValueStore::new().map(AnyStore::Machine),
+        ZEP_KVS_SCOPE_EPHEMERAL => KeyValueStore::new().map(AnyStore::Ephemeral),
+        _ => return fail(ZEP_KVS_ERR_INVALID_ARGUMENT, "unknown scope"),
+    };
+    match handle {
+        Ok(handle) => {
+            // SAFETY: checked non-null above; the caller guarantees validity.
+            unsafe { *out_store = Box::into_raw(Box::new(ZepKvsStore(handle))) };
+            ZEP_KVS_OK
+        }
+        Err(e) => fail_with(e),
+    }
+}
+
+/// Closes a store handle. Passing null is a no-op.
+///
+/// # Safety
+///
+/// `store` must be null or a handle returned by `zep_kvs_open` that has not
+/// already been closed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn zep_kvs_close(store: *mut ZepKvsStore) {
+    if !store.is_null() {
+        // SAFETY: the caller guarantees `store` came from `zep_kvs_open`.
+        drop(unsafe { Box::from_raw(store) });
+    }
+}
+
+/// Stores `value_len` bytes from `value` under `key`.
+///
+/// # Safety
+///
+/// `store` must be a valid open handle, `key` a valid NUL-terminated string,
+/// and `value` must point to at least `value_len` readable bytes (it may be
+/// null only if `value_len` is zero).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn zep_kvs_store(
+    store: *mut ZepKvsStore,
+    key: *const c_char,
+    value: *const u8,
+    value_len: usize,
+) -> c_int {
+    // SAFETY: the caller guarantees `store` is null or a valid handle.
+    let Some(store) = (unsafe { store.as_mut() }) else {
+        return fail(ZEP_KVS_ERR_INVALID_ARGUMENT, "store is null");
+    };
+    // SAFETY: the caller guarantees `key` is a valid string.
+    let key = match unsafe { key_arg(key) } {
+        Ok(key) => key,
+        Err(status) => return status,
+    };
+    let value = match (value.is_null(), value_len) {
+        (true, 0) => &[][..],
+        (true, _) => return fail(ZEP_KVS_ERR_INVALID_ARGUMENT, "value is null"),
+        // SAFETY: the caller guarantees `value_len` readable bytes.
+        (false, _) => unsafe { std::slice::from_raw_parts(value, value_len) },
+    };
+    match store.0.store(key, value) {
+        Ok(()) => ZEP_KVS_OK,
+        Err(e) => fail_with(e),
+    }
+}
+
+/// Retrieves the value stored under `key`.
+///
+/// On success, `*out_value` and `*out_len` receive a buffer that must be
+/// released with `zep_kvs_free_buffer`. Returns `ZEP_KVS_NOT_FOUND` and
+/// leaves the outputs untouched if the key does not exist.
+///
+/// # Safety
+///
+/// `store` must be a valid open handle, `key` a valid NUL-terminated string,
+/// and `out_value` and `out_len` valid pointers to writable memory.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn zep_kvs_retrieve(
+    store: *const ZepKvsStore,
+    key: *const c_char,
+    out_value: *mut *mut u8,
+    out_len: *mut usize,
+) -> c_int {
+    // SAFETY: the caller guarantees `store` is null or a valid handle.
+    let Some(store) = (unsafe { store.as_ref() }) else {
+        return fail(ZEP_KVS_ERR_INVALID_ARGUMENT, "store is null");
+    };
+    if out_value.is_null() || out_len.is_null() {
+        return fail(ZEP_KVS_ERR_INVALID_ARGUMENT, "output pointer is null");
+    }
+    // SAFETY: the caller guarantees `key` is a valid string.
+    let key = match unsafe { key_arg(key) } {
+        Ok(key) => key,
+        Err(status) => return status,
+    };
+    match store.0.retrieve(key) {
+        Ok(Some(value)) => {
+            let value = value.into_boxed_slice();
+            // SAFETY: checked non-null above; the caller guarantees validity.
+            unsafe {
+                *out_len = value.len();
+                *out_value = Box::into_raw(value).cast::<u8>();
+            }
+            ZEP_KVS_OK
+        }
+        Ok(None) => ZEP_KVS_NOT_FOUND,
+        Err(e) => fail_with(e),
+    }
+}
+
+/// Removes `key` and its value.
+///
+/// # Safety
+///
+/// `store` must be a valid open handle and `key` a valid NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn zep_kvs_remove(store: *mut ZepKvsStore, key: *const c_char) -> c_int {
+    // SAFETY: the caller guarantees `store` is null or a valid handle.
+    let Some(store) = (unsafe { store.as_mut() }) else {
+        return fail(ZEP_KVS_ERR_INVALID_ARGUMENT, "store is null");
+    };
+    // SAFETY: the caller guarantees `key` is a valid string.
+    let key = match unsafe { key_arg(key) } {
+        Ok(key) => key,
+        Err(status) => return status,
+    };
+    match store.0.remove(key) {
+        Ok(()) => ZEP_KVS_OK,
+        Err(e) => fail_with(e),
+    }
+}
+
+/// Lists all keys in the store.
+///
+/// On success, `*out_keys` receives an array of `*out_count` NUL-terminated
+/// strings that must be released with `zep_kvs_free_keys`.
+///
+/// # Safety
+///
+/// `store` must be a valid open handle and `out_keys` and `out_count` valid
+/// pointers to writable memory.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn zep_kvs_keys(
+    store: *const ZepKvsStore,
+    out_keys: *mut *mut *mut c_char,
+    out_count: *mut usize,
+) -> c_int {
+    // SAFETY: the caller guarantees `store` is null or a valid handle.
+    let Some(store) = (unsafe { store.as_ref() }) else {
+        return fail(ZEP_KVS_ERR_INVALID_ARGUMENT, "store is null");
+    };
+    if out_keys.is_null() || out_count.is_null() {
+        return fail(ZEP_KVS_ERR_INVALID_ARGUMENT, "output pointer is null");
+    }
+    match store.0.keys() {
+        Ok(keys) => {
+            let keys: Box<[*mut c_char]> = keys
+                .into_iter()
+                .filter_map(|k| CString::new(k).ok())
+                .map(CString::into_raw)
+                .collect();
+            // SAFETY: checked non-null above; the caller guarantees validity.
+            unsafe {
+                *out_count = keys.len();
+                *out_keys = Box::into_raw(keys).cast::<*mut c_char>();
+            }
+            ZEP_KVS_OK
+        }
+        Err(e) => fail_with(e),
+    }
+}
+
+/// Releases a buffer returned by `zep_kvs_retrieve`. Passing null is a no-op.
+///
+/// # Safety
+///
+/// `value` and `len` must be exactly as returned by `zep_kvs_retrieve`, and
+/// the buffer must not already have been released.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn zep_kvs_free_buffer(value: *mut u8, len: usize) {
+    if !value.is_null() {
+        // SAFETY: the caller guarantees the buffer came from `zep_kvs_retrieve`.
+        drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(value, len)) });
+    }
+}
+
+/// Releases a key array returned by `zep_kvs_keys`. Passing null is a no-op.
+///
+/// # Safety
+///
+/// `keys` and `count` must be exactly as returned by `zep_kvs_keys`, and the
+/// array must not already have been released.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn zep_kvs_free_keys(keys: *mut *mut c_char, count: usize) {
+    if !keys.is_null() {
+        // SAFETY: the caller guarantees the array came from `zep_kvs_keys`.
+        let keys = unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(keys, count)) };
+        for key in keys.iter() {
+            // SAFETY: every entry was produced by `CString::into_raw`.
+            drop(unsafe { CString::from_raw(*key) });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ffi_round_trip() {
+        unsafe {
+            let mut store = ptr::null_mut();
+            assert_eq!(
+                zep_kvs_open(ZEP_KVS_SCOPE_EPHEMERAL, &mut store),
+                ZEP_KVS_OK
+            );
+
+            let value = [1u8, 2, 3];
+            assert_eq!(
+                zep_kvs_store(store, c"key".as_ptr(), value.as_ptr(), value.len()),
+                ZEP_KVS_OK
+            );
+
+            let mut out = ptr::null_mut();
+            let mut len = 0;
+            assert_eq!(
+                zep_kvs_retrieve(store, c"key".as_ptr(), &mut out, &mut len),
+                ZEP_KVS_OK
+            );
+            assert_eq!(std::slice::from_raw_parts(out, len), &value);
+            zep_kvs_free_buffer(out, len);
+
+            let mut keys = ptr::null_mut();
+            let mut count = 0;
+            assert_eq!(zep_kvs_keys(store, &mut keys, &mut count), ZEP_KVS_OK);
+            assert_eq!(count, 1);
+            assert_eq!(CStr::from_ptr(*keys), c"key");
+            zep_kvs_free_keys(keys, count);
+
+            assert_eq!(zep_kvs_remove(store, c"key".as_ptr()), ZEP_KVS_OK);
+            assert_eq!(
+                zep_kvs_retrieve(store, c"key".as_ptr(), &mut out, &mut len),
+                ZEP_KVS_NOT_FOUND
+            );
+            zep_kvs_close(store);
+        }
+    }
+
+    #[test]
+    fn test_ffi_invalid_arguments() {
+        unsafe {
+            let mut store = ptr::null_mut();
+            assert_eq!(zep_kvs_open(42, &mut store), ZEP_KVS_ERR_INVALID_ARGUMENT);
+            assert_eq!(
+                CStr::from_ptr(zep_kvs_last_error()).to_str().unwrap(),
+                "unknown scope"
+            );
+
+            assert_eq!(
+                zep_kvs_open(ZEP_KVS_SCOPE_EPHEMERAL, &mut store),
+                ZEP_KVS_OK
+            );
+            assert_eq!(
+                zep_kvs_store(store, ptr::null(), ptr::null(), 0),
+                ZEP_KVS_ERR_INVALID_ARGUMENT
+            );
+            assert_eq!(
+                zep_kvs_store(store, c"key".as_ptr(), ptr::null(), 4),
+                ZEP_KVS_ERR_INVALID_ARGUMENT
+            );
+            assert_eq!(
+                zep_kvs_store(store, c"empty".as_ptr(), ptr::null(), 0),
+                ZEP_KVS_OK
+            );
+            zep_kvs_close(store);
+        }
+    }
+}
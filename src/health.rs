@@ -0,0 +1,136 @@
+//! Checks that a store is usable, for status endpoints and diagnostics.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use rand::random;
+
+use crate::api::{BackingStore, KeyValueStore, RESERVED_PREFIX, Scope};
+use crate::error::KvsError;
+
+/// The outcome of [`KeyValueStore::health_check`].
+#[derive(Debug)]
+pub struct HealthReport {
+    /// The directory holding the store, for stores kept in files.
+    pub location: Option<PathBuf>,
+    /// Whether the location exists. Always `true` for stores not kept in
+    /// files.
+    pub exists: bool,
+    /// Whether the store's keys could be listed.
+    pub readable: bool,
+    /// Whether a probe value could be written, read back and removed.
+    pub writable: bool,
+    /// How long writing, reading back and removing the probe value took,
+    /// if it succeeded.
+    pub round_trip: Option<Duration>,
+    /// The number of bytes available to the store, where known.
+    pub free_space: Option<u64>,
+    /// The errors met by failed checks, in the order they ran.
+    pub problems: Vec<KvsError>,
+}
+
+impl HealthReport {
+    /// Returns whether every check passed.
+    pub fn is_healthy(&self) -> bool {
+        self.exists && self.readable && self.writable && self.problems.is_empty()
+    }
+}
+
+impl<S: Scope> KeyValueStore<S> {
+    /// Checks that the store can be read and written, and reports on its
+    /// location.
+    ///
+    /// The write check stores a probe value under a reserved key, reads it
+    /// back and removes it, so it never shows up among the store's keys and
+    /// doesn't count against a quota. Checks that fail are recorded in the
+    /// report rather than returned as errors.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+    /// let report = store.health_check();
+    /// assert!(report.is_healthy());
+    /// assert!(report.round_trip.is_some());
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn health_check(&mut self) -> HealthReport {
+        let inner = self.backing_store_mut();
+        let mut problems = Vec::new();
+        let location = inner.location().map(Path::to_path_buf);
+        let exists = match &location {
+            Some(path) => path.try_exists().unwrap_or_else(|e| {
+                problems.push(KvsError::io_at(e, path));
+                false
+            }),
+            None => true,
+        };
+        let readable = inner.keys().map_err(|e| problems.push(e)).is_ok();
+        let round_trip = probe(inner).map_err(|e| problems.push(e)).ok();
+        let free_space = inner.free_space().unwrap_or_else(|e| {
+            problems.push(e);
+            None
+        });
+        HealthReport {
+            location,
+            exists,
+            readable,
+            writable: round_trip.is_some(),
+            round_trip,
+            free_space,
+            problems,
+        }
+    }
+}
+
+/// Writes, reads back and removes a probe value, returning how long it
+/// took.
+fn probe<B: BackingStore>(inner: &mut B) -> Result<Duration, KvsError> {
+    // A key per process keeps concurrent checks from reading each other's
+    // probes
+    let key = format!("{RESERVED_PREFIX}health_{}", std::process::id());
+    let value = random::<[u8; 16]>();
+    let start = Instant::now();
+    inner.store(&key, &value)?;
+    let read = inner.retrieve(&key);
+    inner.remove(&key)?;
+    if read?.as_deref() != Some(value.as_slice()) {
+        return Err(KvsError::SerializationError(
+            "Probe value read back differs from the one written".to_string(),
+        ));
+    }
+    Ok(start.elapsed())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+    use crate::api::scope::Ephemeral;
+    use crate::testing::{Mock, MockStore, Operation};
+
+    #[test]
+    fn test_healthy_store() {
+        let mut store = KeyValueStore::<Ephemeral>::new().unwrap();
+        let report = store.health_check();
+        assert!(report.is_healthy(), "{report:?}");
+        assert_eq!(report.location, None);
+        let keys = store.backing_store().keys().unwrap();
+        assert!(!keys.iter().any(|key| key.contains("health")));
+    }
+
+    #[test]
+    fn test_failed_checks_are_reported() {
+        let mock = MockStore::new();
+        let mut store = KeyValueStore::<Mock>::with_backing_store(mock.clone()).unwrap();
+        mock.fail_always(Operation::Keys, io::ErrorKind::PermissionDenied);
+        let report = store.health_check();
+        assert!(!report.readable);
+        assert!(report.writable);
+        assert_eq!(report.problems.len(), 1);
+        assert!(!report.is_healthy());
+    }
+}
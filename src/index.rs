@@ -0,0 +1,383 @@
+//! Persistent key index for large stores.
+//!
+//! Listing the keys of a directory or registry store scans the whole
+//! directory or registry key, which becomes slow with hundreds of thousands
+//! of entries. The [`Indexed`] scope wraps another scope and keeps its keys
+//! in a sorted in-memory index, so listing keys, checking for a key and
+//! prefix queries never touch the backend.
+//!
+//! The index is saved in the store when it is dropped or maintained, and
+//! loaded instead of scanning the next time the store is opened. The first
+//! write after the index is loaded or saved marks the saved copy stale, so
+//! an index left behind by a process that did not exit cleanly is rebuilt
+//! rather than trusted.
+//!
+//! Keys written by processes that open the store without this scope are
+//! not seen until the index is rebuilt with
+//! `KeyValueStore::rebuild_index`.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::ops::Bound;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::api::{BackingStore, KeyValueStore, Scope};
+use crate::dynamic::DynBackingStore;
+use crate::error::KvsError;
+use crate::watch::ChangeListener;
+
+/// Reserved key holding the saved index.
+const INDEX_KEY: &str = "__zep_index";
+const INDEX_VERSION: u8 = 1;
+/// Reserved key counting how many times the index has been saved, so an
+/// instance can tell whether another one saved it after it was marked stale.
+const GENERATION_KEY: &str = "__zep_index_generation";
+
+const STALE: u8 = 0;
+const CURRENT: u8 = 1;
+
+/// A scope whose keys are indexed for fast listing.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::index::Indexed;
+/// use zep_kvs::prelude::*;
+///
+/// let mut store = KeyValueStore::<Indexed<scope::Ephemeral>>::new()?;
+/// store.store("user/1", "alice")?;
+/// store.store("user/2", "bob")?;
+/// assert!(store.contains_key("user/2")?);
+/// assert_eq!(store.keys_with_prefix("user/")?, ["user/1", "user/2"]);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct Indexed<S: Scope>(S);
+
+impl<S: Scope> Scope for Indexed<S> {
+    type Store = IndexStore<S::Store>;
+
+    fn new() -> Result<Self::Store, KvsError> {
+        IndexStore::open(S::new()?)
+    }
+}
+
+impl<S: Scope> KeyValueStore<Indexed<S>> {
+    /// Rebuilds the key index by listing every key in the backend.
+    ///
+    /// Only needed after other processes have changed the store without
+    /// the [`Indexed`] scope.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage backend cannot be accessed.
+    pub fn rebuild_index(&mut self) -> Result<(), KvsError> {
+        self.backing_store_mut().rebuild()
+    }
+}
+
+/// Backing store of the [`Indexed`] scope.
+pub struct IndexStore<B: BackingStore> {
+    inner: B,
+    keys: BTreeSet<String>,
+    /// Whether the saved index is marked stale and needs saving.
+    dirty: bool,
+    /// The save generation seen when this instance marked the index stale.
+    marked: Option<Vec<u8>>,
+}
+
+impl<B: BackingStore + fmt::Debug> fmt::Debug for IndexStore<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IndexStore")
+            .field("inner", &self.inner)
+            .field("keys", &self.keys.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<B: BackingStore> IndexStore<B> {
+    /// Opens `inner`, loading its saved index if it is current, or
+    /// rebuilding it otherwise.
+    pub(crate) fn open(inner: B) -> Result<Self, KvsError> {
+        let mut store = Self {
+            keys: BTreeSet::new(),
+            dirty: false,
+            marked: None,
+            inner,
+        };
+        match store.inner.retrieve(INDEX_KEY)? {
+            Some(saved) if saved.first() > Some(&INDEX_VERSION) => {
+                return Err(KvsError::UnsupportedFormat {
+                    found: saved[0].into(),
+                    supported: INDEX_VERSION.into(),
+                });
+            }
+            Some(saved) => match parse(&saved) {
+                Some(keys) => store.keys = keys,
+                None => store.rebuild()?,
+            },
+            None => store.rebuild()?,
+        }
+        Ok(store)
+    }
+
+    /// Replaces the index with the backend's current keys.
+    pub(crate) fn rebuild(&mut self) -> Result<(), KvsError> {
+        debug!("rebuilding key index");
+        self.keys = self.inner.keys()?.into_iter().collect();
+        self.keys.remove(INDEX_KEY);
+        self.keys.remove(GENERATION_KEY);
+        self.dirty = true;
+        self.save()
+    }
+
+    /// Marks the saved index stale before the key set changes.
+    ///
+    /// The mark is written again if another instance has saved the index
+    /// since, as its saved copy lacks the keys this instance adds.
+    fn invalidate(&mut self) -> Result<(), KvsError> {
+        let generation = self.inner.retrieve(GENERATION_KEY)?;
+        if !self.dirty || generation != self.marked {
+            self.inner.store(INDEX_KEY, &[INDEX_VERSION, STALE])?;
+            self.dirty = true;
+            self.marked = generation;
+        }
+        Ok(())
+    }
+
+    /// Saves the index if it has changed since it was last saved.
+    fn save(&mut self) -> Result<(), KvsError> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let mut saved = vec![INDEX_VERSION, CURRENT];
+        for key in &self.keys {
+            saved.extend_from_slice(&(key.len() as u32).to_be_bytes());
+            saved.extend_from_slice(key.as_bytes());
+        }
+        let generation = self
+            .inner
+            .retrieve(GENERATION_KEY)?
+            .and_then(|saved| saved.try_into().ok())
+            .map_or(0, u64::from_be_bytes);
+        self.inner
+            .store(GENERATION_KEY, &generation.wrapping_add(1).to_be_bytes())?;
+        self.inner.store(INDEX_KEY, &saved)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+/// Parses a saved index, returning `None` if it is stale or malformed.
+fn parse(saved: &[u8]) -> Option<BTreeSet<String>> {
+    let [INDEX_VERSION, CURRENT, rest @ ..] = saved else {
+        return None;
+    };
+    let mut rest = rest;
+    let mut keys = BTreeSet::new();
+    while let Some((len, tail)) = rest.split_first_chunk::<4>() {
+        let len = u32::from_be_bytes(*len) as usize;
+        let key = tail.get(..len)?;
+        keys.insert(String::from_utf8(key.to_vec()).ok()?);
+        rest = &tail[len..];
+    }
+    rest.is_empty().then_some(keys)
+}
+
+impl<B: BackingStore> Drop for IndexStore<B> {
+    fn drop(&mut self) {
+        if let Err(e) = self.save() {
+            warn!("failed to save key index: {e}");
+        }
+    }
+}
+
+impl<B: BackingStore> BackingStore for IndexStore<B> {
+    fn keys(&self) -> Result<Vec<String>, KvsError> {
+        Ok(self.keys.iter().cloned().collect())
+    }
+
+    fn store(&mut self, key: &str, value: &[u8]) -> Result<(), KvsError> {
+        if self.keys.contains(key) {
+            return self.inner.store(key, value);
+        }
+        self.invalidate()?;
+        self.inner.store(key, value)?;
+        self.keys.insert(key.to_string());
+        Ok(())
+    }
+
+    fn store_if_absent(&mut self, key: &str, value: &[u8]) -> Result<bool, KvsError> {
+        if !self.keys.contains(key) {
+            self.invalidate()?;
+        }
+        let stored = self.inner.store_if_absent(key, value)?;
+        self.keys.insert(key.to_string());
+        Ok(stored)
+    }
+
+    fn retrieve(&self, key: &str) -> Result<Option<Vec<u8>>, KvsError> {
+        if key == INDEX_KEY || key == GENERATION_KEY {
+            return Ok(None);
+        }
+        self.inner.retrieve(key)
+    }
+
+    fn store_many(&mut self, entries: &[(&str, &[u8])]) -> Result<(), KvsError> {
+        if entries.iter().any(|(key, _)| !self.keys.contains(*key)) {
+            self.invalidate()?;
+        }
+        self.inner.store_many(entries)?;
+        self.keys
+            .extend(entries.iter().map(|(key, _)| key.to_string()));
+        Ok(())
+    }
+
+    fn retrieve_many(&self, keys: &[&str]) -> Result<Vec<Option<Vec<u8>>>, KvsError> {
+        let mut values = self.inner.retrieve_many(keys)?;
+        for (key, value) in keys.iter().zip(&mut values) {
+            if *key == INDEX_KEY || *key == GENERATION_KEY {
+                *value = None;
+            }
+        }
+        Ok(values)
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), KvsError> {
+        self.invalidate()?;
+        self.inner.remove(key)?;
+        self.keys.remove(key);
+        Ok(())
+    }
+
+    fn remove_secure(&mut self, key: &str) -> Result<(), KvsError> {
+        self.invalidate()?;
+        self.inner.remove_secure(key)?;
+        self.keys.remove(key);
+        Ok(())
+    }
+
+    fn remove_if(&mut self, key: &str, expected: &[u8]) -> Result<bool, KvsError> {
+        self.invalidate()?;
+        let removed = self.inner.remove_if(key, expected)?;
+        if removed {
+            self.keys.remove(key);
+        }
+        Ok(removed)
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> Result<bool, KvsError> {
+        self.invalidate()?;
+        if !self.inner.rename(from, to)? {
+            return Ok(false);
+        }
+        self.keys.remove(from);
+        self.keys.insert(to.to_string());
+        Ok(true)
+    }
+
+    fn contains(&self, key: &str) -> Result<bool, KvsError> {
+        Ok(self.keys.contains(key))
+    }
+
+    fn contains_many(&self, keys: &[&str]) -> Result<Vec<bool>, KvsError> {
+        Ok(keys.iter().map(|key| self.keys.contains(*key)).collect())
+    }
+
+    fn keys_with_prefix(&self, prefix: &str) -> Result<Vec<String>, KvsError> {
+        Ok(self
+            .keys
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|key| key.starts_with(prefix))
+            .cloned()
+            .collect())
+    }
+
+    fn modified(&self, key: &str) -> Result<Option<SystemTime>, KvsError> {
+        self.inner.modified(key)
+    }
+
+    fn touch(&mut self, key: &str, at: SystemTime) -> Result<bool, KvsError> {
+        self.inner.touch(key, at)
+    }
+
+    fn location(&self) -> Option<&Path> {
+        self.inner.location()
+    }
+
+    fn free_space(&self) -> Result<Option<u64>, KvsError> {
+        self.inner.free_space()
+    }
+
+    fn maintain(&mut self) -> Result<(), KvsError> {
+        self.inner.maintain()?;
+        self.save()
+    }
+
+    fn listen(&self) -> Result<Option<Box<dyn ChangeListener>>, KvsError> {
+        self.inner.listen()
+    }
+
+    fn reopen(&self) -> Result<Option<DynBackingStore>, KvsError> {
+        self.inner.reopen()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Mock, MockStore};
+
+    fn open(mock: &MockStore) -> KeyValueStore<Indexed<Mock>> {
+        KeyValueStore::with_backing_store(IndexStore::open(mock.clone()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_index_is_saved_and_loaded() {
+        let mut mock = MockStore::new();
+        {
+            let mut store = open(&mock);
+            store.store("b", 2u8).unwrap();
+            store.store("a", 1u8).unwrap();
+            store.rename("b", "c").unwrap();
+        }
+        // Keys the index doesn't know about are not listed until rebuilt
+        mock.store("outside", b"x").unwrap();
+
+        let mut store = open(&mock);
+        assert_eq!(store.keys().unwrap(), ["a", "c"]);
+        assert!(store.contains_key("c").unwrap());
+        assert!(!store.contains_key("b").unwrap());
+        store.rebuild_index().unwrap();
+        assert_eq!(store.keys().unwrap(), ["a", "c", "outside"]);
+    }
+
+    #[test]
+    fn test_stale_index_is_rebuilt() {
+        let mut mock = MockStore::new();
+        let mut store = open(&mock);
+        store.store("a", 1u8).unwrap();
+        // A process exiting without saving leaves the index marked stale
+        std::mem::forget(store);
+        mock.store("b", b"2").unwrap();
+
+        let store = open(&mock);
+        assert_eq!(store.keys_with_prefix("").unwrap(), ["a", "b"]);
+    }
+
+    #[test]
+    fn test_index_saved_by_another_instance_is_marked_stale_again() {
+        let mock = MockStore::new();
+        let mut first = open(&mock);
+        first.store("a", 1u8).unwrap();
+        // Another instance rebuilds and saves a current index meanwhile
+        drop(open(&mock));
+
+        first.store("b", 2u8).unwrap();
+        std::mem::forget(first);
+
+        let store = open(&mock);
+        assert_eq!(store.keys().unwrap(), ["a", "b"]);
+        assert!(store.contains_key("b").unwrap());
+    }
+}
@@ -0,0 +1,434 @@
+//! Tamper-evident stores.
+//!
+//! The [`Authenticated`] scope wraps another scope and appends an
+//! HMAC-SHA256 tag, computed with an application-provided key, to every
+//! value. Values modified outside the application are rejected when read,
+//! and a value cannot be moved to another key without detection. With
+//! [`Coverage::ValuesAndKeys`], the set of keys is authenticated too, so
+//! keys added or removed outside the application are also detected.
+//!
+//! Values are not hidden; see the `encrypted` module for confidentiality.
+//! Modification times are not authenticated.
+
+use std::fmt;
+use std::path::Path;
+use std::time::SystemTime;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::api::{BackingStore, Scope, is_reserved};
+use crate::dynamic::DynBackingStore;
+use crate::error::KvsError;
+use crate::watch::ChangeListener;
+
+/// Reserved key holding the integrity header.
+const HEADER_KEY: &str = "__zep_integrity";
+const HEADER_VERSION: u8 = 1;
+/// Length of an HMAC-SHA256 tag.
+const TAG_LEN: usize = 32;
+
+/// A scope whose values are authenticated with an application key.
+///
+/// Stores in this scope are opened with
+/// `KeyValueStore::with_integrity_key`; opening them with
+/// `KeyValueStore::new` fails with `KvsError::Locked`. The first open of an
+/// empty store protects it with the given key.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::integrity::{Authenticated, Coverage};
+/// use zep_kvs::prelude::*;
+///
+/// let key = b"application integrity key";
+/// let mut store =
+///     KeyValueStore::<Authenticated<scope::Ephemeral>>::with_integrity_key(key, Coverage::Values)?;
+/// store.store("policy", "enforce")?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct Authenticated<S: Scope>(S);
+
+impl<S: Scope> Scope for Authenticated<S> {
+    type Store = IntegrityStore<S::Store>;
+
+    fn new() -> Result<Self::Store, KvsError> {
+        Err(KvsError::Locked)
+    }
+}
+
+/// What an [`Authenticated`] store protects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coverage {
+    /// Each value is authenticated, but keys can be added or removed
+    /// without detection.
+    Values,
+    /// Each value and the set of keys are authenticated.
+    ///
+    /// Writes that add or remove keys also rewrite the key set's tag, so
+    /// they list every key in the store. A process that is interrupted
+    /// between the two writes leaves a store that fails verification.
+    ValuesAndKeys,
+}
+
+impl Coverage {
+    fn to_byte(self) -> u8 {
+        match self {
+            Coverage::Values => 0,
+            Coverage::ValuesAndKeys => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Coverage::Values),
+            1 => Some(Coverage::ValuesAndKeys),
+            _ => None,
+        }
+    }
+}
+
+/// Backing store of the [`Authenticated`] scope.
+pub struct IntegrityStore<B: BackingStore> {
+    inner: B,
+    mac: Hmac<Sha256>,
+    coverage: Coverage,
+}
+
+/// Shows the underlying store, never the key.
+impl<B: BackingStore + fmt::Debug> fmt::Debug for IntegrityStore<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IntegrityStore")
+            .field("inner", &self.inner)
+            .field("coverage", &self.coverage)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<B: BackingStore> IntegrityStore<B> {
+    /// Opens `inner`, verifying it with `key`.
+    ///
+    /// If `inner` has no integrity header yet, any existing store metadata
+    /// is authenticated and a header is written. If the store was protected
+    /// with a different coverage, the header is rewritten for `coverage`.
+    pub(crate) fn open(inner: B, key: &[u8], coverage: Coverage) -> Result<Self, KvsError> {
+        let mac = Hmac::new_from_slice(key).expect("HMAC accepts keys of any length");
+        let mut store = Self {
+            inner,
+            mac,
+            coverage,
+        };
+        let Some(header) = store.inner.retrieve(HEADER_KEY)? else {
+            store.initialize()?;
+            return Ok(store);
+        };
+        let invalid = || KvsError::SerializationError("Invalid integrity header".to_string());
+        let [version, protected, tag @ ..] = header.as_slice() else {
+            return Err(invalid());
+        };
+        if *version > HEADER_VERSION {
+            return Err(KvsError::UnsupportedFormat {
+                found: (*version).into(),
+                supported: HEADER_VERSION.into(),
+            });
+        }
+        let protected = Coverage::from_byte(*protected).ok_or_else(invalid)?;
+        store.verify_header(protected, tag, &store.inner.keys()?)?;
+        if protected != coverage {
+            store.write_header(&store.inner.keys()?)?;
+        }
+        Ok(store)
+    }
+
+    fn initialize(&mut self) -> Result<(), KvsError> {
+        let keys = self.inner.keys()?;
+        if keys.iter().any(|key| !is_reserved(key)) {
+            return Err(KvsError::SerializationError(
+                "Cannot protect a store that already holds unauthenticated data".to_string(),
+            ));
+        }
+        for key in &keys {
+            if let Some(value) = self.inner.retrieve(key)? {
+                let tagged = self.tagged(key, &value);
+                self.inner.store(key, &tagged)?;
+            }
+        }
+        self.write_header(&keys)
+    }
+
+    /// Returns a MAC over `value` bound to `key`.
+    fn value_mac(&self, key: &str, value: &[u8]) -> Hmac<Sha256> {
+        let mut mac = self.mac.clone();
+        mac.update(b"value");
+        update_with_key(&mut mac, key);
+        mac.update(value);
+        mac
+    }
+
+    /// Returns `value` followed by its tag.
+    fn tagged(&self, key: &str, value: &[u8]) -> Vec<u8> {
+        let mut tagged = value.to_vec();
+        tagged.extend(self.value_mac(key, value).finalize().into_bytes());
+        tagged
+    }
+
+    /// Returns a MAC over `coverage` and, if covered, the set of `keys`.
+    fn header_mac(&self, coverage: Coverage, keys: &[String]) -> Hmac<Sha256> {
+        let mut mac = self.mac.clone();
+        mac.update(b"header");
+        mac.update(&[coverage.to_byte()]);
+        if coverage == Coverage::ValuesAndKeys {
+            let mut keys: Vec<&str> = keys
+                .iter()
+                .map(String::as_str)
+                .filter(|&key| key != HEADER_KEY)
+                .collect();
+            keys.sort_unstable();
+            for key in keys {
+                update_with_key(&mut mac, key);
+            }
+        }
+        mac
+    }
+
+    fn verify_header(
+        &self,
+        coverage: Coverage,
+        tag: &[u8],
+        keys: &[String],
+    ) -> Result<(), KvsError> {
+        self.header_mac(coverage, keys)
+            .verify_slice(tag)
+            .map_err(|_| KvsError::IntegrityViolation { key: None })
+    }
+
+    fn write_header(&mut self, keys: &[String]) -> Result<(), KvsError> {
+        let mut header = vec![HEADER_VERSION, self.coverage.to_byte()];
+        header.extend(self.header_mac(self.coverage, keys).finalize().into_bytes());
+        self.inner.store(HEADER_KEY, &header)
+    }
+
+    /// Removes `key` from `inner` with `remove`, updating the key set's tag
+    /// if it is covered.
+    fn remove_with(
+        &mut self,
+        key: &str,
+        remove: fn(&mut B, &str) -> Result<(), KvsError>,
+    ) -> Result<(), KvsError> {
+        if self.coverage == Coverage::Values {
+            return remove(&mut self.inner, key);
+        }
+        let mut keys = self.verified_keys()?;
+        remove(&mut self.inner, key)?;
+        keys.retain(|k| k != key);
+        self.write_header(&keys)
+    }
+
+    /// Lists the keys, verifying them if the key set is covered.
+    fn verified_keys(&self) -> Result<Vec<String>, KvsError> {
+        let keys = self.inner.keys()?;
+        if self.coverage == Coverage::ValuesAndKeys {
+            let header = self.inner.retrieve(HEADER_KEY)?.unwrap_or_default();
+            let tag = header.get(2..).unwrap_or_default();
+            self.verify_header(self.coverage, tag, &keys)?;
+        }
+        Ok(keys)
+    }
+}
+
+/// Feeds a length-prefixed key to `mac`.
+fn update_with_key(mac: &mut Hmac<Sha256>, key: &str) {
+    mac.update(&(key.len() as u32).to_be_bytes());
+    mac.update(key.as_bytes());
+}
+
+impl<B: BackingStore> BackingStore for IntegrityStore<B> {
+    fn keys(&self) -> Result<Vec<String>, KvsError> {
+        self.verified_keys()
+    }
+
+    fn store(&mut self, key: &str, value: &[u8]) -> Result<(), KvsError> {
+        let tagged = self.tagged(key, value);
+        if self.coverage == Coverage::Values {
+            return self.inner.store(key, &tagged);
+        }
+        let mut keys = self.verified_keys()?;
+        self.inner.store(key, &tagged)?;
+        if !keys.iter().any(|k| k == key) {
+            keys.push(key.to_string());
+            self.write_header(&keys)?;
+        }
+        Ok(())
+    }
+
+    fn store_if_absent(&mut self, key: &str, value: &[u8]) -> Result<bool, KvsError> {
+        let tagged = self.tagged(key, value);
+        if self.coverage == Coverage::Values {
+            return self.inner.store_if_absent(key, &tagged);
+        }
+        let mut keys = self.verified_keys()?;
+        let stored = self.inner.store_if_absent(key, &tagged)?;
+        if stored && !keys.iter().any(|k| k == key) {
+            keys.push(key.to_string());
+            self.write_header(&keys)?;
+        }
+        Ok(stored)
+    }
+
+    fn retrieve(&self, key: &str) -> Result<Option<Vec<u8>>, KvsError> {
+        match self.inner.retrieve(key)? {
+            Some(header) if key == HEADER_KEY => Ok(Some(header)),
+            Some(mut tagged) => {
+                let violation = || KvsError::IntegrityViolation {
+                    key: Some(key.to_string()),
+                };
+                let split = tagged.len().checked_sub(TAG_LEN).ok_or_else(violation)?;
+                let tag = tagged.split_off(split);
+                self.value_mac(key, &tagged)
+                    .verify_slice(&tag)
+                    .map_err(|_| violation())?;
+                Ok(Some(tagged))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), KvsError> {
+        self.remove_with(key, B::remove)
+    }
+
+    fn remove_secure(&mut self, key: &str) -> Result<(), KvsError> {
+        self.remove_with(key, B::remove_secure)
+    }
+
+    fn remove_if(&mut self, key: &str, expected: &[u8]) -> Result<bool, KvsError> {
+        let tagged = self.tagged(key, expected);
+        if self.coverage == Coverage::Values {
+            return self.inner.remove_if(key, &tagged);
+        }
+        let mut keys = self.verified_keys()?;
+        let removed = self.inner.remove_if(key, &tagged)?;
+        if removed {
+            keys.retain(|k| k != key);
+            self.write_header(&keys)?;
+        }
+        Ok(removed)
+    }
+
+    fn modified(&self, key: &str) -> Result<Option<SystemTime>, KvsError> {
+        self.inner.modified(key)
+    }
+
+    fn touch(&mut self, key: &str, at: SystemTime) -> Result<bool, KvsError> {
+        self.inner.touch(key, at)
+    }
+
+    fn location(&self) -> Option<&Path> {
+        self.inner.location()
+    }
+
+    fn free_space(&self) -> Result<Option<u64>, KvsError> {
+        self.inner.free_space()
+    }
+
+    fn maintain(&mut self) -> Result<(), KvsError> {
+        self.inner.maintain()
+    }
+
+    fn listen(&self) -> Result<Option<Box<dyn ChangeListener>>, KvsError> {
+        self.inner.listen()
+    }
+
+    fn reopen(&self) -> Result<Option<DynBackingStore>, KvsError> {
+        self.inner.reopen()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::KeyValueStore;
+    use crate::testing::{Mock, MockStore};
+
+    fn open(
+        mock: &MockStore,
+        key: &[u8],
+        coverage: Coverage,
+    ) -> Result<KeyValueStore<Authenticated<Mock>>, KvsError> {
+        KeyValueStore::with_backing_store(IntegrityStore::open(mock.clone(), key, coverage)?)
+    }
+
+    #[test]
+    fn test_modified_values_are_detected() {
+        let mut mock = MockStore::new();
+        let mut store = open(&mock, b"key", Coverage::Values).unwrap();
+        store.store("policy", "enforce").unwrap();
+        store.store("other", "value").unwrap();
+
+        let reopened = open(&mock, b"key", Coverage::Values).unwrap();
+        assert_eq!(
+            reopened.retrieve::<_, String>("policy").unwrap().as_deref(),
+            Some("enforce")
+        );
+
+        let mut tagged = mock.retrieve("policy").unwrap().unwrap();
+        tagged[0] ^= 1;
+        mock.store("policy", &tagged).unwrap();
+        assert!(matches!(
+            store.retrieve::<_, String>("policy"),
+            Err(KvsError::IntegrityViolation { key: Some(k) }) if k == "policy"
+        ));
+
+        // Values are bound to their keys
+        let tagged = mock.retrieve("other").unwrap().unwrap();
+        mock.store("moved", &tagged).unwrap();
+        assert!(store.retrieve::<_, String>("moved").is_err());
+    }
+
+    #[test]
+    fn test_wrong_key_is_rejected() {
+        let mock = MockStore::new();
+        open(&mock, b"key", Coverage::Values).unwrap();
+        assert!(matches!(
+            open(&mock, b"other key", Coverage::Values),
+            Err(KvsError::IntegrityViolation { key: None })
+        ));
+    }
+
+    #[test]
+    fn test_key_set_changes_are_detected() {
+        let mut mock = MockStore::new();
+        let mut store = open(&mock, b"key", Coverage::ValuesAndKeys).unwrap();
+        store.store("a", 1u8).unwrap();
+        store.store("b", 2u8).unwrap();
+        store.remove("b").unwrap();
+        assert_eq!(store.keys().unwrap(), ["a"]);
+        open(&mock, b"key", Coverage::ValuesAndKeys).unwrap();
+
+        let tagged = mock.retrieve("a").unwrap().unwrap();
+        mock.remove("a").unwrap();
+        assert!(matches!(
+            store.keys(),
+            Err(KvsError::IntegrityViolation { key: None })
+        ));
+        assert!(open(&mock, b"key", Coverage::ValuesAndKeys).is_err());
+        // Writes don't legitimize the change
+        assert!(store.store("c", 3u8).is_err());
+
+        mock.store("a", &tagged).unwrap();
+        assert_eq!(store.keys().unwrap(), ["a"]);
+    }
+
+    #[test]
+    fn test_first_open_of_existing_store() {
+        let mock = MockStore::new();
+        KeyValueStore::<Mock>::with_backing_store(mock.clone()).unwrap();
+        // Existing metadata is authenticated on first open
+        open(&mock, b"key", Coverage::ValuesAndKeys).unwrap();
+        open(&mock, b"key", Coverage::ValuesAndKeys).unwrap();
+
+        let mut plain = MockStore::new();
+        plain.store("key", b"value").unwrap();
+        assert!(open(&plain, b"key", Coverage::Values).is_err());
+    }
+}
@@ -0,0 +1,1240 @@
+//! Journaling operations for replay onto other stores.
+//!
+//! The [`Journaled`] scope wraps another scope and records every store,
+//! removal and rename in a journal before applying it. The journal can be
+//! exported with [`KeyValueStore::export_journal`] and replayed onto any
+//! store with [`KeyValueStore::apply_journal`], to rebuild a store as it was
+//! at a point in time or to keep a replica up to date.
+//! [`KeyValueStore::as_of`] does the same in memory, to look at the store as
+//! it was at an earlier time.
+//!
+//! Entries live under reserved keys of the wrapped store, so the journal
+//! works with any backend and is encrypted along with the store when the
+//! wrapped scope is. Entries are kept until removed with
+//! [`KeyValueStore::truncate_journal`], or collected according to a
+//! [`Retention`] policy, so journaled stores otherwise grow with every
+//! write. Processes sharing a store can write at once, as each entry claims
+//! its sequence number atomically.
+//!
+//! # Format
+//!
+//! All integers are big-endian. An exported journal is:
+//!
+//! | Field   | Size     | Contents                                  |
+//! |---------|----------|-------------------------------------------|
+//! | Magic   | 6 bytes  | `ZEPJNL`                                  |
+//! | Version | 2 bytes  | Journal format version, currently `1`     |
+//! | Entries | variable | Zero or more entries, described below     |
+//! | End     | 1 byte   | `0xFF`                                    |
+//!
+//! Each entry is:
+//!
+//! | Field     | Size         | Contents                                    |
+//! |-----------|--------------|---------------------------------------------|
+//! | Operation | 1 byte       | `1` store, `2` remove, `3` rename           |
+//! | Sequence  | 8 bytes      | Position of the entry in the journal        |
+//! | Time      | 8 bytes      | Milliseconds since the Unix epoch           |
+//! | Key       | 4 + n bytes  | Length and UTF-8 bytes of the key           |
+//! | Value     | 8 + n bytes  | Stores only: length and bytes of the value  |
+//! | New key   | 4 + n bytes  | Renames only: length and bytes of the key   |
+//!
+//! Values are recorded as stored, including type tags if the store uses
+//! them. Changes to the store's internal metadata are not recorded.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use crate::api::scope::Ephemeral;
+use crate::api::{BackingStore, KeyValueStore, Scope, is_reserved};
+use crate::dynamic::DynBackingStore;
+use crate::ephemeral::EphemeralStore;
+use crate::error::KvsError;
+use crate::snapshot::Snapshot;
+use crate::stream::{read_exact, stream_error};
+use crate::watch::ChangeListener;
+
+/// Reserved key holding the journal's version and next sequence number.
+const HEADER_KEY: &str = "__zep_journal";
+const HEADER_VERSION: u8 = 2;
+/// Prefix of reserved keys holding entries, followed by the hex sequence
+/// number.
+const ENTRY_PREFIX: &str = "__zep_journal_";
+/// Prefix of reserved keys holding the copy of the store's contents staged
+/// by a collection, followed by the hex sequence number it moves to.
+const STAGED_PREFIX: &str = "__zep_journal_staged_";
+
+/// Identifies a journal written by [`KeyValueStore::export_journal`].
+const MAGIC: &[u8; 6] = b"ZEPJNL";
+/// The current journal format version.
+const VERSION: u16 = 1;
+
+const STORE: u8 = 1;
+const REMOVE: u8 = 2;
+const RENAME: u8 = 3;
+/// Operation marking the end of an exported journal.
+const END: u8 = 0xFF;
+
+/// A scope whose changes are recorded in a journal.
+///
+/// The first open of a store starts its journal with the store's existing
+/// contents, so replaying the journal from the start rebuilds the whole
+/// store. Changes made through another scope are not recorded.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::journal::Journaled;
+/// use zep_kvs::prelude::*;
+///
+/// let mut primary = KeyValueStore::<Journaled<scope::Ephemeral>>::new()?;
+/// primary.store("theme", "dark")?;
+///
+/// let mut journal = Vec::new();
+/// let position = primary.export_journal(0, &mut journal)?;
+///
+/// let mut replica = KeyValueStore::<scope::Ephemeral>::new()?;
+/// replica.apply_journal(journal.as_slice())?;
+/// assert_eq!(replica.retrieve::<_, String>("theme")?.as_deref(), Some("dark"));
+///
+/// // Only ship what the replica hasn't seen next time
+/// primary.store("theme", "light")?;
+/// let mut update = Vec::new();
+/// primary.export_journal(position, &mut update)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct Journaled<S: Scope>(S);
+
+impl<S: Scope> Scope for Journaled<S> {
+    type Store = JournalStore<S::Store>;
+
+    fn new() -> Result<Self::Store, KvsError> {
+        JournalStore::open(S::new()?)
+    }
+}
+
+/// How much of a journal's history to keep.
+///
+/// Changes beyond any of the limits are collected by
+/// [`KeyValueStore::gc`], and by maintenance, oldest first. Collected
+/// changes are folded into a copy of the store's contents as of the oldest
+/// change kept, which the limits don't count, so the journal can still
+/// rebuild the store from that point on.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use zep_kvs::journal::Retention;
+///
+/// let retention = Retention {
+///     max_age: Some(Duration::from_secs(30 * 24 * 60 * 60)),
+///     max_bytes: Some(10 * 1024 * 1024),
+///     ..Retention::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Retention {
+    /// The maximum number of changes to keep, or `None` for no limit.
+    pub max_entries: Option<usize>,
+    /// The maximum age of changes to keep, or `None` for no limit.
+    pub max_age: Option<Duration>,
+    /// The maximum total size of the changes kept in bytes, or `None` for
+    /// no limit.
+    pub max_bytes: Option<u64>,
+}
+
+/// Backing store of the [`Journaled`] scope.
+pub struct JournalStore<B: BackingStore> {
+    inner: B,
+    retention: Retention,
+}
+
+impl<B: BackingStore + fmt::Debug> fmt::Debug for JournalStore<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JournalStore")
+            .field("inner", &self.inner)
+            .field("retention", &self.retention)
+            .finish()
+    }
+}
+
+/// The journal's bookkeeping, stored under [`HEADER_KEY`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Header {
+    /// Sequence number of the next entry.
+    next: u64,
+    /// Sequence number of the first entry of the history, which starts
+    /// with a copy of the store's contents.
+    start: u64,
+    /// Sequence number of the first change after that copy.
+    changes: u64,
+    /// Milliseconds since the Unix epoch of the oldest state the history
+    /// can rebuild, or 0 if it reaches back to the journal's start.
+    since: u64,
+}
+
+impl Header {
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![HEADER_VERSION];
+        for field in [self.next, self.start, self.changes, self.since] {
+            bytes.extend_from_slice(&field.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Decodes a header, upgrading one from before retention was supported.
+    fn decode(bytes: &[u8]) -> Result<Self, KvsError> {
+        let fields: Vec<u64> = match bytes {
+            [HEADER_VERSION, rest @ ..] if rest.len() == 32 => rest
+                .chunks_exact(8)
+                .map(|field| u64::from_be_bytes(field.try_into().expect("8 bytes")))
+                .collect(),
+            // Version 1 only held the position. Its history is all changes,
+            // starting with the copy recorded by the first open.
+            [1, next @ ..] if next.len() == 8 => {
+                return Ok(Self {
+                    next: u64::from_be_bytes(next.try_into().expect("8 bytes")),
+                    ..Self::default()
+                });
+            }
+            _ => return Err(invalid("Invalid journal header")),
+        };
+        Ok(Self {
+            next: fields[0],
+            start: fields[1],
+            changes: fields[2],
+            since: fields[3],
+        })
+    }
+}
+
+/// An entry read back from a journal.
+struct Record {
+    op: u8,
+    seq: u64,
+    time: SystemTime,
+    key: String,
+    value: Option<Vec<u8>>,
+    to: Option<String>,
+}
+
+impl Record {
+    /// Reads the rest of an entry whose operation `op` was already read.
+    fn read(reader: &mut impl Read, op: u8) -> Result<Self, KvsError> {
+        let fields = read_exact(reader, 16)?;
+        let seq = u64::from_be_bytes(fields[..8].try_into().expect("8 bytes"));
+        let millis = u64::from_be_bytes(fields[8..].try_into().expect("8 bytes"));
+        let key = read_string(reader)?;
+        let (value, to) = match op {
+            STORE => (Some(read_value(reader)?), None),
+            REMOVE => (None, None),
+            RENAME => (None, Some(read_string(reader)?)),
+            _ => return Err(invalid("Invalid journal entry")),
+        };
+        Ok(Self {
+            op,
+            seq,
+            time: SystemTime::UNIX_EPOCH + Duration::from_millis(millis),
+            key,
+            value,
+            to,
+        })
+    }
+
+    fn decode(mut bytes: &[u8]) -> Result<Self, KvsError> {
+        let op = read_exact(&mut bytes, 1)?[0];
+        Self::read(&mut bytes, op)
+    }
+}
+
+/// An operation recorded in a journal.
+enum Entry<'a> {
+    Store(&'a str, &'a [u8]),
+    Remove(&'a str),
+    Rename(&'a str, &'a str),
+}
+
+impl<B: BackingStore> JournalStore<B> {
+    /// Opens `inner`, starting a journal if it has none.
+    pub(crate) fn open(mut inner: B) -> Result<Self, KvsError> {
+        match inner.retrieve(HEADER_KEY)?.as_deref() {
+            Some([version, ..]) if *version > HEADER_VERSION => {
+                return Err(KvsError::UnsupportedFormat {
+                    found: (*version).into(),
+                    supported: HEADER_VERSION.into(),
+                });
+            }
+            Some(_) => {
+                let mut journal = Self {
+                    inner,
+                    retention: Retention::default(),
+                };
+                journal.finish_collection()?;
+                return Ok(journal);
+            }
+            None => {
+                // Another process opening the store at once starts the
+                // journal instead
+                if !inner.store_if_absent(HEADER_KEY, &Header::default().encode())? {
+                    return Ok(Self {
+                        inner,
+                        retention: Retention::default(),
+                    });
+                }
+            }
+        }
+        let mut journal = Self {
+            inner,
+            retention: Retention::default(),
+        };
+        for key in journal.keys()? {
+            if let Some(value) = journal.inner.retrieve(&key)? {
+                journal.append(&[Entry::Store(&key, &value)])?;
+            }
+        }
+        let header = journal.header()?;
+        journal.set_header(Header {
+            changes: header.next,
+            ..header
+        })?;
+        Ok(journal)
+    }
+
+    fn header(&self) -> Result<Header, KvsError> {
+        match self.inner.retrieve(HEADER_KEY)? {
+            Some(bytes) => Header::decode(&bytes),
+            None => Err(invalid("Missing journal header")),
+        }
+    }
+
+    fn set_header(&mut self, header: Header) -> Result<(), KvsError> {
+        self.inner.store(HEADER_KEY, &header.encode())
+    }
+
+    /// Returns the sequence number of the next entry.
+    fn position(&self) -> Result<u64, KvsError> {
+        Ok(self.header()?.next)
+    }
+
+    /// Returns whether the journal still holds the whole history it
+    /// describes, so that replaying it rebuilds the store.
+    fn is_complete(&self) -> Result<bool, KvsError> {
+        let header = self.header()?;
+        Ok(match self.entries()?.first() {
+            Some(&first) => first == header.start,
+            None => header.next == header.start,
+        })
+    }
+
+    /// Returns the sequence numbers of the entries in the journal, in order.
+    fn entries(&self) -> Result<Vec<u64>, KvsError> {
+        self.sequence_numbers(ENTRY_PREFIX)
+    }
+
+    /// Returns the sequence numbers of keys starting with `prefix`, in
+    /// order.
+    fn sequence_numbers(&self, prefix: &str) -> Result<Vec<u64>, KvsError> {
+        let mut entries: Vec<u64> = self
+            .inner
+            .keys_with_prefix(prefix)?
+            .iter()
+            .filter_map(|key| u64::from_str_radix(key.strip_prefix(prefix)?, 16).ok())
+            .collect();
+        entries.sort_unstable();
+        Ok(entries)
+    }
+
+    /// Completes a collection that was interrupted.
+    ///
+    /// A collection stages its copy of the store's contents, switches the
+    /// header to it, then replaces the entries before the oldest change
+    /// kept with the copy. A copy staged before the switch is discarded;
+    /// after it, the replacement is finished.
+    fn finish_collection(&mut self) -> Result<(), KvsError> {
+        let staged = self.sequence_numbers(STAGED_PREFIX)?;
+        let (Some(&start), Some(&end)) = (staged.first(), staged.last()) else {
+            return Ok(());
+        };
+        if end >= self.header()?.changes {
+            for seq in staged {
+                self.inner.remove(&staged_key(seq))?;
+            }
+            return Ok(());
+        }
+        for seq in self.entries()?.into_iter().filter(|&seq| seq < start) {
+            self.inner.remove(&entry_key(seq))?;
+        }
+        // Moving the newest first keeps the start of the copy staged until
+        // the end, so an interrupted move is resumed from the same start
+        for seq in staged.into_iter().rev() {
+            if let Some(entry) = self.inner.retrieve(&staged_key(seq))? {
+                self.inner.store(&entry_key(seq), &entry)?;
+            }
+            self.inner.remove(&staged_key(seq))?;
+        }
+        Ok(())
+    }
+
+    /// Records `entries` in the journal, unless they only touch store
+    /// metadata.
+    fn append(&mut self, entries: &[Entry<'_>]) -> Result<(), KvsError> {
+        let entries: Vec<&Entry<'_>> = entries.iter().filter(|e| !e.is_internal()).collect();
+        if entries.is_empty() {
+            return Ok(());
+        }
+        // Sequence numbers are claimed with store_if_absent, so processes
+        // appending at once never share one. The header's position is a
+        // hint, passed over where another process has claimed it.
+        let mut next = self.header()?.next;
+        let time = SystemTime::now();
+        for entry in entries {
+            while !self
+                .inner
+                .store_if_absent(&entry_key(next), &entry.encode(next, time))?
+            {
+                next += 1;
+            }
+            next += 1;
+        }
+        // Never move the position back behind entries appended meanwhile
+        let header = self.header()?;
+        self.set_header(Header {
+            next: next.max(header.next),
+            ..header
+        })
+    }
+
+    /// Collects the changes beyond the retention limits, returning how many
+    /// were collected.
+    ///
+    /// Collected changes are folded into the copy of the store's contents
+    /// at the start of the history, which is rewritten just before the
+    /// oldest change kept. The copy is staged first, so the journal is
+    /// never left with a partial one.
+    fn collect(&mut self) -> Result<usize, KvsError> {
+        self.finish_collection()?;
+        let header = self.header()?;
+        let complete = self.is_complete()?;
+        let mut history = Vec::new();
+        let mut changes = Vec::new();
+        for seq in self.entries()? {
+            let Some(bytes) = self.inner.retrieve(&entry_key(seq))? else {
+                continue;
+            };
+            match seq < header.changes {
+                true => history.push(seq),
+                false => changes.push((bytes.len() as u64, Record::decode(&bytes)?)),
+            }
+        }
+
+        // Keep the newest changes within every limit
+        let Retention {
+            max_entries,
+            max_age,
+            max_bytes,
+        } = self.retention;
+        let mut cut = max_entries.map_or(0, |max| changes.len().saturating_sub(max));
+        if let Some(cutoff) = max_age.and_then(|age| SystemTime::now().checked_sub(age)) {
+            cut = cut.max(changes.partition_point(|(_, record)| record.time < cutoff));
+        }
+        if let Some(max_bytes) = max_bytes {
+            let mut kept = 0;
+            let within = changes
+                .iter()
+                .rev()
+                .take_while(|(size, _)| {
+                    kept += size;
+                    kept <= max_bytes
+                })
+                .count();
+            cut = cut.max(changes.len() - within);
+        }
+        if cut == 0 {
+            return Ok(0);
+        }
+
+        let first_kept = changes.get(cut).map_or(header.next, |(_, r)| r.seq);
+        let last_collected = changes[cut - 1].1.time;
+        let mut contents = BTreeMap::new();
+        if complete {
+            for seq in &history {
+                if let Some(bytes) = self.inner.retrieve(&entry_key(*seq))? {
+                    Record::decode(&bytes)?.apply_to(&mut contents);
+                }
+            }
+            for (_, record) in &changes[..cut] {
+                record.apply_to(&mut contents);
+            }
+        }
+        let start = first_kept
+            .checked_sub(contents.len() as u64)
+            .ok_or_else(|| invalid("Invalid journal sequence"))?;
+        for (seq, (key, value)) in (start..).zip(&contents) {
+            let entry = Entry::Store(key, value).encode(seq, last_collected);
+            self.inner.store(&staged_key(seq), &entry)?;
+        }
+        let millis = last_collected
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        self.set_header(Header {
+            start: if complete { start } else { header.start },
+            changes: first_kept,
+            since: millis,
+            ..header
+        })?;
+        let obsolete = history
+            .iter()
+            .copied()
+            .chain(changes[..cut].iter().map(|(_, r)| r.seq));
+        for seq in obsolete.filter(|&seq| seq < start) {
+            self.inner.remove(&entry_key(seq))?;
+        }
+        self.finish_collection()?;
+        Ok(cut)
+    }
+}
+
+impl Record {
+    /// Applies the change to a map of the store's contents.
+    fn apply_to(&self, contents: &mut BTreeMap<String, Vec<u8>>) {
+        match (&self.value, &self.to) {
+            (Some(value), _) => {
+                contents.insert(self.key.clone(), value.clone());
+            }
+            (None, Some(to)) => {
+                if let Some(value) = contents.remove(&self.key) {
+                    contents.insert(to.clone(), value);
+                }
+            }
+            (None, None) => {
+                contents.remove(&self.key);
+            }
+        }
+    }
+}
+
+impl Entry<'_> {
+    /// Returns whether the entry only changes store metadata.
+    fn is_internal(&self) -> bool {
+        match self {
+            Entry::Store(key, _) | Entry::Remove(key) => is_reserved(key),
+            Entry::Rename(from, to) => is_reserved(from) && is_reserved(to),
+        }
+    }
+
+    fn encode(&self, seq: u64, time: SystemTime) -> Vec<u8> {
+        let millis = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let (op, key) = match self {
+            Entry::Store(key, _) => (STORE, key),
+            Entry::Remove(key) => (REMOVE, key),
+            Entry::Rename(from, _) => (RENAME, from),
+        };
+        let mut bytes = vec![op];
+        bytes.extend_from_slice(&seq.to_be_bytes());
+        bytes.extend_from_slice(&millis.to_be_bytes());
+        bytes.extend_from_slice(&(key.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(key.as_bytes());
+        match self {
+            Entry::Store(_, value) => {
+                bytes.extend_from_slice(&(value.len() as u64).to_be_bytes());
+                bytes.extend_from_slice(value);
+            }
+            Entry::Remove(_) => {}
+            Entry::Rename(_, to) => {
+                bytes.extend_from_slice(&(to.len() as u32).to_be_bytes());
+                bytes.extend_from_slice(to.as_bytes());
+            }
+        }
+        bytes
+    }
+}
+
+fn entry_key(seq: u64) -> String {
+    format!("{ENTRY_PREFIX}{seq:016x}")
+}
+
+fn staged_key(seq: u64) -> String {
+    format!("{STAGED_PREFIX}{seq:016x}")
+}
+
+/// Returns whether `key` is used internally by the journal.
+fn is_internal(key: &str) -> bool {
+    key == HEADER_KEY || key.starts_with(ENTRY_PREFIX)
+}
+
+fn invalid(message: &str) -> KvsError {
+    KvsError::SerializationError(message.to_string())
+}
+
+impl<B: BackingStore> BackingStore for JournalStore<B> {
+    fn keys(&self) -> Result<Vec<String>, KvsError> {
+        let mut keys = self.inner.keys()?;
+        keys.retain(|key| !is_internal(key));
+        Ok(keys)
+    }
+
+    fn store(&mut self, key: &str, value: &[u8]) -> Result<(), KvsError> {
+        self.append(&[Entry::Store(key, value)])?;
+        self.inner.store(key, value)
+    }
+
+    fn store_if_absent(&mut self, key: &str, value: &[u8]) -> Result<bool, KvsError> {
+        // Only a value that was stored belongs in the journal
+        let stored = self.inner.store_if_absent(key, value)?;
+        if stored {
+            self.append(&[Entry::Store(key, value)])?;
+        }
+        Ok(stored)
+    }
+
+    fn retrieve(&self, key: &str) -> Result<Option<Vec<u8>>, KvsError> {
+        if is_internal(key) {
+            return Ok(None);
+        }
+        self.inner.retrieve(key)
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), KvsError> {
+        self.append(&[Entry::Remove(key)])?;
+        self.inner.remove(key)
+    }
+
+    fn remove_secure(&mut self, key: &str) -> Result<(), KvsError> {
+        self.append(&[Entry::Remove(key)])?;
+        self.inner.remove_secure(key)
+    }
+
+    fn remove_if(&mut self, key: &str, expected: &[u8]) -> Result<bool, KvsError> {
+        // Only a key that was removed belongs in the journal
+        let removed = self.inner.remove_if(key, expected)?;
+        if removed {
+            self.append(&[Entry::Remove(key)])?;
+        }
+        Ok(removed)
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> Result<bool, KvsError> {
+        self.append(&[Entry::Rename(from, to)])?;
+        self.inner.rename(from, to)
+    }
+
+    fn store_many(&mut self, entries: &[(&str, &[u8])]) -> Result<(), KvsError> {
+        let journal: Vec<Entry<'_>> = entries
+            .iter()
+            .map(|&(key, value)| Entry::Store(key, value))
+            .collect();
+        self.append(&journal)?;
+        self.inner.store_many(entries)
+    }
+
+    fn contains(&self, key: &str) -> Result<bool, KvsError> {
+        Ok(!is_internal(key) && self.inner.contains(key)?)
+    }
+
+    fn contains_many(&self, keys: &[&str]) -> Result<Vec<bool>, KvsError> {
+        let mut found = self.inner.contains_many(keys)?;
+        for (key, found) in keys.iter().zip(&mut found) {
+            *found &= !is_internal(key);
+        }
+        Ok(found)
+    }
+
+    fn modified(&self, key: &str) -> Result<Option<SystemTime>, KvsError> {
+        self.inner.modified(key)
+    }
+
+    fn touch(&mut self, key: &str, at: SystemTime) -> Result<bool, KvsError> {
+        self.inner.touch(key, at)
+    }
+
+    fn location(&self) -> Option<&Path> {
+        self.inner.location()
+    }
+
+    fn free_space(&self) -> Result<Option<u64>, KvsError> {
+        self.inner.free_space()
+    }
+
+    /// Collects history beyond the retention limits, if any are set.
+    fn maintain(&mut self) -> Result<(), KvsError> {
+        self.inner.maintain()?;
+        if self.retention != Retention::default() {
+            self.collect()?;
+        }
+        Ok(())
+    }
+
+    fn listen(&self) -> Result<Option<Box<dyn ChangeListener>>, KvsError> {
+        self.inner.listen()
+    }
+
+    fn reopen(&self) -> Result<Option<DynBackingStore>, KvsError> {
+        self.inner.reopen()
+    }
+}
+
+impl<S: Scope> KeyValueStore<Journaled<S>> {
+    /// Returns the sequence number the next journal entry will have.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the journal cannot be read.
+    pub fn journal_position(&self) -> Result<u64, KvsError> {
+        self.backing_store().position()
+    }
+
+    /// Sets how much of the journal's history to keep.
+    ///
+    /// Nothing is collected until [`gc`](Self::gc) or maintenance runs.
+    pub fn with_journal_retention(mut self, retention: Retention) -> Self {
+        self.backing_store_mut().retention = retention;
+        self
+    }
+
+    /// Collects the journal's changes beyond its [`Retention`] limits.
+    ///
+    /// The oldest changes are collected first, and folded into a copy of
+    /// the store's contents kept at the start of the journal, so the
+    /// journal can still rebuild the store as of any change it keeps.
+    /// Replicas that haven't applied the collected changes yet must be
+    /// rebuilt from the whole journal. Maintenance does the same when a
+    /// retention policy is set.
+    ///
+    /// Returns the number of changes collected.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the journal cannot be read or rewritten.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::journal::{Journaled, Retention};
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let retention = Retention {
+    ///     max_entries: Some(1),
+    ///     ..Retention::default()
+    /// };
+    /// let mut store =
+    ///     KeyValueStore::<Journaled<scope::Ephemeral>>::new()?.with_journal_retention(retention);
+    /// store.store("theme", "dark")?;
+    /// store.store("theme", "light")?;
+    /// assert_eq!(store.gc()?, 1);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn gc(&mut self) -> Result<usize, KvsError> {
+        self.backing_store_mut().collect()
+    }
+
+    /// Writes the journal entries from sequence number `since` onwards to
+    /// `writer`, in the [journal format](self).
+    ///
+    /// Returns the position to export from next time, so that each entry
+    /// is exported once.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the journal cannot be read or the writer fails.
+    pub fn export_journal<W: Write>(&self, since: u64, mut writer: W) -> Result<u64, KvsError> {
+        let journal = self.backing_store();
+        writer.write_all(MAGIC).map_err(stream_error)?;
+        writer
+            .write_all(&VERSION.to_be_bytes())
+            .map_err(stream_error)?;
+        let mut next = since;
+        for seq in journal.entries()?.into_iter().filter(|&seq| seq >= since) {
+            // Entries truncated while exporting are skipped
+            let Some(entry) = journal.inner.retrieve(&entry_key(seq))? else {
+                continue;
+            };
+            writer.write_all(&entry).map_err(stream_error)?;
+            next = seq + 1;
+        }
+        writer.write_all(&[END]).map_err(stream_error)?;
+        writer.flush().map_err(stream_error)?;
+        Ok(next)
+    }
+
+    /// Removes the journal entries before sequence number `before`.
+    ///
+    /// Returns the number of entries removed. The store's history can't be
+    /// rebuilt with [`as_of`](Self::as_of) once its first entries are gone.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the journal cannot be read or an entry cannot be
+    /// removed.
+    pub fn truncate_journal(&mut self, before: u64) -> Result<usize, KvsError> {
+        let journal = self.backing_store_mut();
+        let mut removed = 0;
+        for seq in journal.entries()?.into_iter().filter(|&seq| seq < before) {
+            journal.inner.remove(&entry_key(seq))?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    /// Returns a read-only view of the store as it was at `time`.
+    ///
+    /// The view is rebuilt in memory by replaying the journal up to `time`,
+    /// which takes time and memory in proportion to the journal. Times
+    /// before the journal was started show an empty store.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the journal cannot be read, if its first
+    /// entries were removed by [`truncate_journal`](Self::truncate_journal),
+    /// leaving the history incomplete, or if the changes at `time` were
+    /// collected by [`gc`](Self::gc).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::SystemTime;
+    ///
+    /// use zep_kvs::journal::Journaled;
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<Journaled<scope::Ephemeral>>::new()?;
+    /// store.store("theme", "dark")?;
+    /// let reported = SystemTime::now();
+    /// # std::thread::sleep(std::time::Duration::from_millis(2));
+    /// store.store("theme", "light")?;
+    ///
+    /// let past = store.as_of(reported)?;
+    /// assert_eq!(past.retrieve::<_, String>("theme")?.as_deref(), Some("dark"));
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn as_of(&self, time: SystemTime) -> Result<Snapshot, KvsError> {
+        let journal = self.backing_store();
+        if !journal.is_complete()? {
+            return Err(invalid(
+                "The journal was truncated, so its history is incomplete",
+            ));
+        }
+        let since = SystemTime::UNIX_EPOCH + Duration::from_millis(journal.header()?.since);
+        if time < since {
+            return Err(invalid("The history at that time was collected"));
+        }
+        let mut exported = Vec::new();
+        self.export_journal(0, &mut exported)?;
+        let mut past = KeyValueStore::<Ephemeral>::with_backing_store(EphemeralStore::new())?;
+        past.apply_journal_until(exported.as_slice(), time)?;
+        Ok(Snapshot::from_store(past.with_value_format_of(self)))
+    }
+}
+
+impl<S: Scope> KeyValueStore<S> {
+    /// Replays a journal written by
+    /// [`export_journal`](KeyValueStore::export_journal) onto this store.
+    ///
+    /// Entries are applied in order, subject to this store's limits.
+    /// Removing a key that doesn't exist does nothing, so a journal can be
+    /// replayed onto a store that has already seen some of its entries.
+    ///
+    /// Returns the number of entries applied.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the journal is malformed or truncated, was
+    /// written by a newer version, or if a change is rejected by this
+    /// store. Entries applied before the error remain.
+    pub fn apply_journal<R: Read>(&mut self, reader: R) -> Result<usize, KvsError> {
+        self.replay(reader, None)
+    }
+
+    /// Replays the entries of a journal recorded up to and including
+    /// `until`, rebuilding the store as it was at that time.
+    ///
+    /// Like [`apply_journal`](Self::apply_journal), but stops at the first
+    /// entry recorded after `until`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`apply_journal`](Self::apply_journal).
+    pub fn apply_journal_until<R: Read>(
+        &mut self,
+        reader: R,
+        until: SystemTime,
+    ) -> Result<usize, KvsError> {
+        self.replay(reader, Some(until))
+    }
+
+    fn replay<R: Read>(
+        &mut self,
+        mut reader: R,
+        until: Option<SystemTime>,
+    ) -> Result<usize, KvsError> {
+        let header = read_exact(&mut reader, 8)?;
+        if &header[..6] != MAGIC {
+            return Err(invalid("not a zep-kvs journal"));
+        }
+        let version = u16::from_be_bytes([header[6], header[7]]);
+        if version > VERSION {
+            return Err(KvsError::UnsupportedFormat {
+                found: version.into(),
+                supported: VERSION.into(),
+            });
+        }
+
+        let mut applied = 0;
+        loop {
+            let op = read_exact(&mut reader, 1)?[0];
+            if op == END {
+                break;
+            }
+            let record = Record::read(&mut reader, op)?;
+            if until.is_some_and(|until| record.time > until) {
+                break;
+            }
+            match (record.op, &record.value, &record.to) {
+                (STORE, Some(value), _) => self.store_raw(&record.key, value)?,
+                (RENAME, _, Some(to)) => {
+                    self.rename(&record.key, to)?;
+                }
+                _ => {
+                    if self.contains_key(&record.key)? {
+                        self.remove(&record.key)?;
+                    }
+                }
+            }
+            applied += 1;
+        }
+        Ok(applied)
+    }
+}
+
+fn read_string(reader: &mut impl Read) -> Result<String, KvsError> {
+    let len = read_exact(reader, 4)?;
+    let len = u32::from_be_bytes(len.try_into().expect("4 bytes"));
+    Ok(String::from_utf8(read_exact(reader, len.into())?)?)
+}
+
+fn read_value(reader: &mut impl Read) -> Result<Vec<u8>, KvsError> {
+    let len = read_exact(reader, 8)?;
+    read_exact(reader, u64::from_be_bytes(len.try_into().expect("8 bytes")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::scope::Ephemeral;
+    use crate::testing::{Mock, MockStore};
+
+    fn open(mock: &MockStore) -> KeyValueStore<Journaled<Mock>> {
+        KeyValueStore::with_backing_store(JournalStore::open(mock.clone()).unwrap()).unwrap()
+    }
+
+    fn export(store: &KeyValueStore<Journaled<Mock>>, since: u64) -> (Vec<u8>, u64) {
+        let mut journal = Vec::new();
+        let next = store.export_journal(since, &mut journal).unwrap();
+        (journal, next)
+    }
+
+    #[test]
+    fn test_replay_reproduces_changes() {
+        let mock = MockStore::new();
+        let mut store = open(&mock).with_type_tags();
+        store.store("a", 1u32).unwrap();
+        store.store("b", "two").unwrap();
+        store.rename("b", "c").unwrap();
+        store.store("d", "gone").unwrap();
+        store.remove("d").unwrap();
+        assert_eq!(store.journal_position().unwrap(), 5);
+        assert!(store.keys().unwrap().iter().all(|key| !is_internal(key)));
+
+        let (journal, next) = export(&store, 0);
+        assert_eq!(next, 5);
+        let mut replica = KeyValueStore::<Ephemeral>::new().unwrap().with_type_tags();
+        assert_eq!(replica.apply_journal(journal.as_slice()).unwrap(), 5);
+        assert_eq!(replica.keys().unwrap(), ["a", "c"]);
+        assert_eq!(replica.retrieve::<_, u32>("a").unwrap(), Some(1));
+        assert_eq!(
+            replica.retrieve::<_, String>("c").unwrap().as_deref(),
+            Some("two")
+        );
+
+        // Replaying again converges on the same contents
+        replica.apply_journal(journal.as_slice()).unwrap();
+        assert_eq!(replica.keys().unwrap(), ["a", "c"]);
+    }
+
+    #[test]
+    fn test_incremental_export_and_truncation() {
+        let mock = MockStore::new();
+        let mut store = open(&mock);
+        store.store("a", "1").unwrap();
+        let (_, next) = export(&store, 0);
+        store.store("b", "2").unwrap();
+
+        let (journal, after) = export(&store, next);
+        assert_eq!(after, 2);
+        let mut replica = KeyValueStore::<Ephemeral>::new().unwrap();
+        assert_eq!(replica.apply_journal(journal.as_slice()).unwrap(), 1);
+        assert_eq!(replica.keys().unwrap(), ["b"]);
+
+        assert_eq!(store.truncate_journal(after).unwrap(), 2);
+        let (journal, _) = export(&store, 0);
+        assert_eq!(journal, b"ZEPJNL\0\x01\xff");
+        // Reopening keeps the position
+        assert_eq!(open(&mock).journal_position().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_concurrent_appends_claim_distinct_positions() {
+        let mock = MockStore::new();
+        let mut first = open(&mock);
+        let mut second = open(&mock);
+        first.store("a", "1").unwrap();
+        // An append that read the position before the first one
+        let header = second.backing_store().header().unwrap();
+        second.store("b", "2").unwrap();
+        second
+            .backing_store_mut()
+            .set_header(Header { next: 0, ..header })
+            .unwrap();
+        second.store("c", "3").unwrap();
+
+        assert_eq!(first.journal_position().unwrap(), 3);
+        let (journal, _) = export(&first, 0);
+        let mut replica = KeyValueStore::<Ephemeral>::new().unwrap();
+        assert_eq!(replica.apply_journal(journal.as_slice()).unwrap(), 3);
+        assert_eq!(replica.keys().unwrap(), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_version_1_header_is_upgraded() {
+        let mut mock = MockStore::new();
+        let mut store = open(&mock);
+        store.store("a", "1").unwrap();
+        store.store("a", "2").unwrap();
+        mock.store(HEADER_KEY, &[&[1], 2u64.to_be_bytes().as_slice()].concat())
+            .unwrap();
+
+        let mut store = open(&mock).with_journal_retention(Retention {
+            max_entries: Some(1),
+            ..Retention::default()
+        });
+        assert_eq!(store.journal_position().unwrap(), 2);
+        store.store("b", "3").unwrap();
+        assert_eq!(mock.retrieve(HEADER_KEY).unwrap().unwrap()[0], 2);
+        assert_eq!(store.gc().unwrap(), 2);
+        let (journal, _) = export(&store, 0);
+        let mut replica = KeyValueStore::<Ephemeral>::new().unwrap();
+        replica.apply_journal(journal.as_slice()).unwrap();
+        assert_eq!(
+            replica.retrieve::<_, String>("a").unwrap().as_deref(),
+            Some("2")
+        );
+        assert_eq!(replica.keys().unwrap(), ["a", "b"]);
+    }
+
+    #[test]
+    fn test_apply_until_stops_at_later_entries() {
+        let mock = MockStore::new();
+        let mut store = open(&mock);
+        store.store("a", "1").unwrap();
+        let (journal, _) = export(&store, 0);
+
+        let mut replica = KeyValueStore::<Ephemeral>::new().unwrap();
+        let before = SystemTime::UNIX_EPOCH;
+        assert_eq!(
+            replica
+                .apply_journal_until(journal.as_slice(), before)
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            replica
+                .apply_journal_until(journal.as_slice(), SystemTime::now())
+                .unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_first_open_records_existing_contents() {
+        let mut mock = MockStore::new();
+        mock.store("existing", b"value").unwrap();
+        let store = open(&mock);
+        assert_eq!(store.journal_position().unwrap(), 1);
+        assert_eq!(open(&mock).journal_position().unwrap(), 1);
+
+        let (journal, _) = export(&store, 0);
+        let mut replica = KeyValueStore::<Ephemeral>::new().unwrap();
+        replica.apply_journal(journal.as_slice()).unwrap();
+        assert_eq!(replica.keys().unwrap(), ["existing"]);
+    }
+
+    #[test]
+    fn test_as_of_rebuilds_earlier_states() {
+        let mock = MockStore::new();
+        let mut store = open(&mock).with_type_tags();
+        let start = SystemTime::now() - Duration::from_secs(1);
+        store.store("a", 1u32).unwrap();
+        store.store("b", "two").unwrap();
+        let middle = SystemTime::now();
+        std::thread::sleep(Duration::from_millis(5));
+        store.remove("a").unwrap();
+
+        assert!(store.as_of(start).unwrap().keys().unwrap().is_empty());
+        let past = store.as_of(middle).unwrap();
+        let mut keys = past.keys().unwrap();
+        keys.sort();
+        assert_eq!(keys, ["a", "b"]);
+        assert_eq!(past.retrieve::<_, u32>("a").unwrap(), Some(1));
+        assert_eq!(
+            store.as_of(SystemTime::now()).unwrap().keys().unwrap(),
+            ["b"]
+        );
+
+        store.truncate_journal(1).unwrap();
+        assert!(store.as_of(middle).is_err());
+    }
+
+    fn contents(journal: &[u8]) -> Vec<(String, String)> {
+        let mut replica = KeyValueStore::<Ephemeral>::new().unwrap();
+        replica.apply_journal(journal).unwrap();
+        let mut keys = replica.keys().unwrap();
+        keys.sort();
+        keys.into_iter()
+            .map(|key| {
+                let value = replica.retrieve(&key).unwrap().unwrap();
+                (key, value)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_gc_keeps_the_store_rebuildable() {
+        let mut mock = MockStore::new();
+        mock.store("existing", b"0").unwrap();
+        let retention = Retention {
+            max_entries: Some(2),
+            ..Retention::default()
+        };
+        let mut store = open(&mock).with_journal_retention(retention);
+        store.store("a", "1").unwrap();
+        store.rename("existing", "b").unwrap();
+        store.remove("a").unwrap();
+        let collected_at = SystemTime::now();
+        std::thread::sleep(Duration::from_millis(5));
+        store.store("c", "3").unwrap();
+        store.store("b", "2").unwrap();
+        let before = export(&store, 0).0;
+
+        assert_eq!(store.gc().unwrap(), 3);
+        assert_eq!(store.gc().unwrap(), 0);
+        assert_eq!(store.journal_position().unwrap(), 6);
+        // One copied key and the two changes kept
+        assert_eq!(mock.keys_with_prefix(ENTRY_PREFIX).unwrap().len(), 3);
+        let after = export(&store, 0).0;
+        assert_eq!(contents(&after), contents(&before));
+
+        let past = store.as_of(collected_at).unwrap();
+        assert_eq!(past.keys().unwrap(), ["b"]);
+        assert_eq!(
+            past.retrieve::<_, String>("b").unwrap().as_deref(),
+            Some("0")
+        );
+        assert!(store.as_of(SystemTime::UNIX_EPOCH).is_err());
+    }
+
+    #[test]
+    fn test_interrupted_gc_is_discarded_or_finished() {
+        let mut mock = MockStore::new();
+        let retention = Retention {
+            max_entries: Some(1),
+            ..Retention::default()
+        };
+        let mut store = open(&mock);
+        store.store("a", "1").unwrap();
+        store.store("a", "2").unwrap();
+        store.store("b", "3").unwrap();
+        let before = contents(&export(&store, 0).0);
+        let obsolete = mock.retrieve(&entry_key(0)).unwrap().unwrap();
+
+        // A copy staged before the header was switched is discarded
+        mock.store(&staged_key(2), b"partial").unwrap();
+        let mut store = open(&mock).with_journal_retention(retention);
+        assert!(mock.keys_with_prefix(STAGED_PREFIX).unwrap().is_empty());
+        assert_eq!(contents(&export(&store, 0).0), before);
+
+        // After the switch, the copy replaces the collected entries
+        assert_eq!(store.gc().unwrap(), 2);
+        let copy = mock.retrieve(&entry_key(1)).unwrap().unwrap();
+        mock.store(&staged_key(1), &copy).unwrap();
+        mock.store(
+            &entry_key(1),
+            &Entry::Remove("a").encode(1, SystemTime::now()),
+        )
+        .unwrap();
+        mock.store(&entry_key(0), &obsolete).unwrap();
+        let store = open(&mock);
+        assert!(mock.keys_with_prefix(STAGED_PREFIX).unwrap().is_empty());
+        assert_eq!(store.backing_store().entries().unwrap(), [1, 2]);
+        assert_eq!(contents(&export(&store, 0).0), before);
+        assert!(store.as_of(SystemTime::now()).is_ok());
+    }
+
+    #[test]
+    fn test_maintenance_applies_size_and_age_limits() {
+        let mock = MockStore::new();
+        let retention = Retention {
+            max_bytes: Some(1),
+            ..Retention::default()
+        };
+        let mut store = open(&mock).with_journal_retention(retention);
+        store.store("a", "1").unwrap();
+        store.store("a", "2").unwrap();
+        store.maintenance().run().unwrap();
+        assert_eq!(mock.keys_with_prefix(ENTRY_PREFIX).unwrap().len(), 1);
+
+        let retention = Retention {
+            max_age: Some(Duration::ZERO),
+            ..Retention::default()
+        };
+        let mut store = open(&mock).with_journal_retention(retention);
+        store.store("b", "3").unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+        assert_eq!(store.gc().unwrap(), 1);
+        let journal = export(&store, 0).0;
+        assert_eq!(
+            contents(&journal),
+            [("a".into(), "2".into()), ("b".into(), "3".into())]
+        );
+    }
+
+    #[test]
+    fn test_journal_of_namespaced_store() {
+        use crate::namespace::{NamespacedStore, Tenant};
+
+        let mock = MockStore::new();
+        let journal = JournalStore::open(NamespacedStore::new(mock.clone(), "customer")).unwrap();
+        let mut store =
+            KeyValueStore::<Journaled<Tenant<Mock>>>::with_backing_store(journal).unwrap();
+        store.store("a", "1").unwrap();
+        let mut exported = Vec::new();
+        assert_eq!(store.export_journal(0, &mut exported).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_apply_rejects_bad_journals() {
+        let mut store = KeyValueStore::<Ephemeral>::new().unwrap();
+        assert!(store.apply_journal(b"ZEPKVS\0\x01\xff".as_slice()).is_err());
+        assert!(matches!(
+            store.apply_journal(b"ZEPJNL\0\x02\xff".as_slice()),
+            Err(KvsError::UnsupportedFormat { found: 2, .. })
+        ));
+        assert!(store.apply_journal(b"ZEPJNL\0\x01\x01".as_slice()).is_err());
+    }
+}
@@ -63,7 +63,11 @@
 //!
 //! - [`api::scope::User`] - User-specific data that persists between runs
 //! - [`api::scope::Machine`] - System-wide data (requires elevated privileges)
-//! - [`api::scope::Ephemeral`] - In-memory data for testing (not persistent)
+//! - [`api::scope::Ephemeral`] - In-memory data for testing (not persistent), with keys
+//!   listed in sorted order
+//!
+//! Small applications can use [`user_store`] to share a single user store
+//! across the whole process instead of opening it where needed.
 //!
 //! ## Data Types
 //!
@@ -94,26 +98,97 @@
 //! # }
 //! ```
 
+#[macro_use]
+mod logging;
+
 pub mod api;
+pub mod clock;
+pub mod codec;
 pub mod convert;
+pub mod defaults;
+pub mod diff;
+pub mod dynamic;
+pub mod ephemeral;
 pub mod error;
+pub mod health;
+pub mod index;
+pub mod journal;
+pub mod maintenance;
+pub mod manifest;
+pub mod merge;
+pub mod migrate;
+pub mod namespace;
+pub mod overlay;
+pub mod retry;
+pub mod sensitive;
+pub mod slow;
+pub mod snapshot;
+pub mod stream;
+pub mod watch;
+
+#[cfg(feature = "archive")]
+pub mod archive;
+
+#[cfg(feature = "dedup")]
+pub mod dedup;
+
+#[cfg(feature = "encryption")]
+pub mod encrypted;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+#[cfg(feature = "integrity")]
+pub mod integrity;
 
-mod ephemeral;
+#[cfg(feature = "machine-binding")]
+pub mod machine;
 
-#[cfg(not(target_os = "windows"))]
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+#[cfg(feature = "uniffi")]
+pub mod bindings;
+
+#[cfg(any(feature = "ffi", feature = "uniffi"))]
+mod any;
+#[cfg(feature = "encryption")]
+mod crypto;
+mod envelope;
+mod shared;
+
+pub use shared::user_store;
+
+#[cfg(all(not(target_os = "windows"), not(feature = "ephemeral-scopes")))]
 mod directory;
 
-#[cfg(target_os = "linux")]
+#[cfg(all(target_os = "linux", not(feature = "ephemeral-scopes")))]
 mod linux;
 
-#[cfg(target_os = "macos")]
+#[cfg(all(target_os = "macos", not(feature = "ephemeral-scopes")))]
 mod macos;
 
-#[cfg(target_os = "windows")]
+#[cfg(all(target_os = "windows", not(feature = "ephemeral-scopes")))]
 mod windows;
 
+#[cfg(all(
+    any(target_os = "linux", target_os = "macos"),
+    not(feature = "ephemeral-scopes")
+))]
+mod unix;
+
+#[cfg(all(
+    any(target_os = "linux", target_os = "macos"),
+    feature = "config-file",
+    not(feature = "ephemeral-scopes")
+))]
+mod config;
+
 mod tests;
 
+#[cfg(feature = "uniffi")]
+uniffi::setup_scaffolding!();
+
 /// Re-exports of commonly used types and traits.
 ///
 /// This module provides convenient access to the main API components
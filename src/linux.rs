@@ -5,10 +5,10 @@
 //! for system-wide machine data.
 
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use crate::api::Scope;
-use crate::api::scope::{Machine, User};
+use crate::api::scope::{Machine, Session, User};
 use crate::directory::DirectoryStore;
 use crate::error::KvsError;
 
@@ -32,8 +32,17 @@ impl Scope for Machine {
     /// - The file system is read-only
     /// - Directory creation fails for other I/O reasons
     fn new() -> Result<Self::Store, KvsError> {
-        DirectoryStore::new(PathBuf::from("/var/lib"))
-            .map_err(|e| KvsError::NoMachineScope(e.to_string()))
+        #[cfg(any(test, feature = "testing"))]
+        if let Some(path) = crate::testing::redirected("machine") {
+            return DirectoryStore::new(path).map_err(KvsError::no_machine_scope);
+        }
+        #[cfg(feature = "config-file")]
+        if let Some(path) =
+            crate::config::configured_path("machine").map_err(KvsError::no_machine_scope)?
+        {
+            return DirectoryStore::new(path).map_err(KvsError::no_machine_scope);
+        }
+        DirectoryStore::new(PathBuf::from("/var/lib")).map_err(KvsError::no_machine_scope)
     }
 }
 
@@ -64,14 +73,82 @@ impl Scope for User {
     /// - The user lacks permissions to create directories in the target location
     /// - Directory creation fails for other I/O reasons
     fn new() -> Result<Self::Store, KvsError> {
-        let path = env::var_os("XDG_DATA_HOME")
-            .map(|d| PathBuf::from(d))
-            .or(env::var_os("HOME").map(|d| PathBuf::from(d).join(".local/share")));
+        #[cfg(any(test, feature = "testing"))]
+        if let Some(path) = crate::testing::redirected("user") {
+            return DirectoryStore::new(path).map_err(KvsError::no_user_scope);
+        }
+        #[cfg(feature = "config-file")]
+        if let Some(path) =
+            crate::config::configured_path("user").map_err(KvsError::no_user_scope)?
+        {
+            return DirectoryStore::new(path).map_err(KvsError::no_user_scope);
+        }
+        let path = env::var_os("XDG_DATA_HOME").map(PathBuf::from).or_else(|| {
+            debug!("XDG_DATA_HOME is not set, falling back to $HOME/.local/share");
+            env::var_os("HOME").map(|d| user_data_dir(Path::new(&d)))
+        });
         match path {
-            Some(path) => {
-                DirectoryStore::new(path).map_err(|e| KvsError::NoUserScope(e.to_string()))
-            }
-            None => Err(KvsError::NoUserScope("no user directory found".to_string())),
+            Some(path) => DirectoryStore::new(path).map_err(KvsError::no_user_scope),
+            None => Err(KvsError::NoUserScope {
+                reason: "no user directory found".to_string(),
+                source: None,
+            }),
         }
     }
 }
+
+impl Scope for Session {
+    type Store = DirectoryStore;
+
+    /// Creates a login session storage scope for Linux.
+    ///
+    /// Uses the user's runtime directory, which systemd-logind mounts as a
+    /// tmpfs and removes when the user's last session ends, so nothing
+    /// stored survives a logout or reboot:
+    /// 1. First tries `$XDG_RUNTIME_DIR` if set
+    /// 2. Falls back to `/run/user/{uid}` if it exists
+    ///
+    /// # Storage Location
+    ///
+    /// Data is stored in `$XDG_RUNTIME_DIR/{package_name}/{app_name}/`
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoUserScope` if:
+    /// - `XDG_RUNTIME_DIR` is not set and `/run/user/{uid}` doesn't exist,
+    ///   for example outside a login session
+    /// - Directory creation fails for other I/O reasons
+    fn new() -> Result<Self::Store, KvsError> {
+        #[cfg(any(test, feature = "testing"))]
+        if let Some(path) = crate::testing::redirected("session") {
+            return DirectoryStore::new(path).map_err(KvsError::no_user_scope);
+        }
+        #[cfg(feature = "config-file")]
+        if let Some(path) =
+            crate::config::configured_path("session").map_err(KvsError::no_user_scope)?
+        {
+            return DirectoryStore::new(path).map_err(KvsError::no_user_scope);
+        }
+        let path = env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .or_else(|| {
+                debug!("XDG_RUNTIME_DIR is not set, falling back to /run/user");
+                // SAFETY: getuid has no preconditions and cannot fail.
+                let dir = PathBuf::from(format!("/run/user/{}", unsafe { libc::getuid() }));
+                dir.is_dir().then_some(dir)
+            });
+        match path {
+            Some(path) => DirectoryStore::new(path).map_err(KvsError::no_user_scope),
+            None => Err(KvsError::NoUserScope {
+                reason: "no runtime directory found".to_string(),
+                source: None,
+            }),
+        }
+    }
+}
+
+/// Returns the default user data directory for a home directory, used when
+/// `XDG_DATA_HOME` is not set.
+pub(crate) fn user_data_dir(home: &Path) -> PathBuf {
+    home.join(".local/share")
+}
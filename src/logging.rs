@@ -0,0 +1,23 @@
+//! Internal logging macros.
+//!
+//! With the `log` feature enabled, these forward to the `log` crate under the
+//! `zep_kvs` target. Without it they compile to nothing, but still type-check
+//! their arguments so logging code cannot rot.
+
+macro_rules! debug {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        ::log::debug!(target: "zep_kvs", $($arg)+);
+        #[cfg(not(feature = "log"))]
+        let _ = format_args!($($arg)+);
+    }};
+}
+
+macro_rules! warn {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        ::log::warn!(target: "zep_kvs", $($arg)+);
+        #[cfg(not(feature = "log"))]
+        let _ = format_args!($($arg)+);
+    }};
+}
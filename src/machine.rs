@@ -0,0 +1,519 @@
+//! Binding stored values to the machine that wrote them.
+//!
+//! Licensing and device-pairing data must not keep working when a user
+//! profile or backup is copied to another computer. The [`MachineBound`]
+//! scope wraps another scope and prefixes every value with a tag derived
+//! from the machine's identifier, and values read on a machine with a
+//! different identifier fail with `KvsError::ForeignMachine`. To bind only
+//! specific keys of an ordinary store, wrap their values in [`Bound`]
+//! instead.
+//!
+//! The identifier is `/etc/machine-id` on Linux, the `IOPlatformUUID` on
+//! macOS and the `MachineGuid` on Windows. Only a hash of it is stored.
+//!
+//! Binding detects copies, not tampering: anyone who can write the store
+//! can also write a tag for their machine. Combine it with the `integrity`
+//! feature's `Authenticated` scope where that matters.
+//!
+//! # Format
+//!
+//! ```text
+//! [1][first 16 bytes of SHA-256(domain, machine id)][value]
+//! ```
+
+use std::borrow::Cow;
+use std::fmt;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+use sha2::{Digest, Sha256};
+
+use crate::api::{BackingStore, KeyValueStore, Scope, is_reserved};
+use crate::convert::{InBytes, OutBytes};
+use crate::dynamic::DynBackingStore;
+use crate::error::KvsError;
+use crate::sensitive::wipe;
+use crate::watch::ChangeListener;
+
+/// Reserved key marking a store as machine bound.
+const HEADER_KEY: &str = "__zep_machine";
+const VERSION: u8 = 1;
+/// Length of a machine tag.
+const TAG_LEN: usize = 16;
+/// Hashed with the machine identifier, so tags can't be matched against
+/// identifiers hashed for other purposes.
+const DOMAIN: &[u8] = b"zep-kvs machine binding\0";
+
+type Tag = [u8; TAG_LEN];
+
+/// Returns the identifier of this machine.
+///
+/// This is the contents of `/etc/machine-id` (or
+/// `/var/lib/dbus/machine-id`) on Linux, the `IOPlatformUUID` on macOS and
+/// the `MachineGuid` value of `HKEY_LOCAL_MACHINE\SOFTWARE\Microsoft\Cryptography`
+/// on Windows. It survives reboots, but reinstalling the operating system
+/// usually changes it.
+///
+/// # Errors
+///
+/// Returns an error if the identifier cannot be read, for example in a
+/// container without a `/etc/machine-id`.
+pub fn machine_id() -> Result<String, KvsError> {
+    platform::machine_id()
+}
+
+/// Returns the tag identifying this machine, computing it on first use.
+fn machine_tag() -> Result<Tag, KvsError> {
+    static TAG: OnceLock<Tag> = OnceLock::new();
+    if let Some(tag) = TAG.get() {
+        return Ok(*tag);
+    }
+    let digest = Sha256::new()
+        .chain_update(DOMAIN)
+        .chain_update(machine_id()?)
+        .finalize();
+    let tag = digest[..TAG_LEN]
+        .try_into()
+        .expect("digest is longer than a tag");
+    Ok(*TAG.get_or_init(|| tag))
+}
+
+/// Prefixes `value` with the binding to the machine with `tag`.
+fn bind(tag: &Tag, value: &[u8]) -> Vec<u8> {
+    [&[VERSION], tag.as_slice(), value].concat()
+}
+
+/// Returns the value in `bound`, failing with `ForeignMachine` if it was
+/// bound to a machine other than the one with `tag`.
+fn unbind<'a>(tag: &Tag, bound: &'a [u8], key: Option<&str>) -> Result<&'a [u8], KvsError> {
+    match bound {
+        [VERSION, rest @ ..] if rest.len() >= TAG_LEN => {
+            let (found, value) = rest.split_at(TAG_LEN);
+            if found != tag {
+                return Err(KvsError::ForeignMachine {
+                    key: key.map(str::to_string),
+                });
+            }
+            Ok(value)
+        }
+        [version, ..] if *version > VERSION => Err(KvsError::UnsupportedFormat {
+            found: (*version).into(),
+            supported: VERSION.into(),
+        }),
+        _ => Err(KvsError::SerializationError(
+            "Invalid machine-bound value".to_string(),
+        )),
+    }
+}
+
+/// A scope whose values can only be read on the machine that wrote them.
+///
+/// The first open of a store that holds no data sets it up for binding.
+/// Store metadata is not bound, so [`KeyValueStore::info`] still works on
+/// a copied store.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::machine::MachineBound;
+/// use zep_kvs::prelude::*;
+///
+/// let mut store = KeyValueStore::<MachineBound<scope::Ephemeral>>::new()?;
+/// store.store("license", "XXXX-YYYY")?;
+/// assert_eq!(store.retrieve::<_, String>("license")?.as_deref(), Some("XXXX-YYYY"));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct MachineBound<S: Scope>(S);
+
+impl<S: Scope> Scope for MachineBound<S> {
+    type Store = BoundStore<S::Store>;
+
+    fn new() -> Result<Self::Store, KvsError> {
+        BoundStore::open(S::new()?)
+    }
+}
+
+/// Backing store of the [`MachineBound`] scope.
+pub struct BoundStore<B: BackingStore> {
+    inner: B,
+    tag: Tag,
+}
+
+/// Shows the underlying store, never the machine tag.
+impl<B: BackingStore + fmt::Debug> fmt::Debug for BoundStore<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BoundStore")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<B: BackingStore> BoundStore<B> {
+    /// Opens `inner`, binding values written to it to this machine.
+    ///
+    /// Fails if `inner` already holds data written without binding, which
+    /// could not be told apart from bound values.
+    pub(crate) fn open(mut inner: B) -> Result<Self, KvsError> {
+        let tag = machine_tag()?;
+        match inner.retrieve(HEADER_KEY)?.as_deref() {
+            Some([version, ..]) if *version > VERSION => {
+                return Err(KvsError::UnsupportedFormat {
+                    found: (*version).into(),
+                    supported: VERSION.into(),
+                });
+            }
+            Some(_) => {}
+            None => {
+                if inner.keys()?.iter().any(|key| !is_reserved(key)) {
+                    return Err(KvsError::SerializationError(
+                        "Cannot bind a store that already holds data".to_string(),
+                    ));
+                }
+                inner.store(HEADER_KEY, &[VERSION])?;
+            }
+        }
+        Ok(Self { inner, tag })
+    }
+}
+
+impl<B: BackingStore> BackingStore for BoundStore<B> {
+    fn keys(&self) -> Result<Vec<String>, KvsError> {
+        let mut keys = self.inner.keys()?;
+        keys.retain(|key| key != HEADER_KEY);
+        Ok(keys)
+    }
+
+    fn store(&mut self, key: &str, value: &[u8]) -> Result<(), KvsError> {
+        if is_reserved(key) {
+            return self.inner.store(key, value);
+        }
+        self.inner.store(key, &bind(&self.tag, value))
+    }
+
+    fn store_if_absent(&mut self, key: &str, value: &[u8]) -> Result<bool, KvsError> {
+        if is_reserved(key) {
+            return self.inner.store_if_absent(key, value);
+        }
+        self.inner.store_if_absent(key, &bind(&self.tag, value))
+    }
+
+    fn retrieve(&self, key: &str) -> Result<Option<Vec<u8>>, KvsError> {
+        match self.inner.retrieve(key)? {
+            Some(_) if key == HEADER_KEY => Ok(None),
+            Some(value) if is_reserved(key) => Ok(Some(value)),
+            Some(bound) => unbind(&self.tag, &bound, Some(key)).map(|value| Some(value.to_vec())),
+            None => Ok(None),
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), KvsError> {
+        self.inner.remove(key)
+    }
+
+    fn remove_secure(&mut self, key: &str) -> Result<(), KvsError> {
+        self.inner.remove_secure(key)
+    }
+
+    fn remove_if(&mut self, key: &str, expected: &[u8]) -> Result<bool, KvsError> {
+        if is_reserved(key) {
+            return self.inner.remove_if(key, expected);
+        }
+        self.inner.remove_if(key, &bind(&self.tag, expected))
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> Result<bool, KvsError> {
+        // Tags don't depend on the key, so values move as they are
+        self.inner.rename(from, to)
+    }
+
+    fn contains(&self, key: &str) -> Result<bool, KvsError> {
+        Ok(key != HEADER_KEY && self.inner.contains(key)?)
+    }
+
+    fn contains_many(&self, keys: &[&str]) -> Result<Vec<bool>, KvsError> {
+        let mut found = self.inner.contains_many(keys)?;
+        for (key, found) in keys.iter().zip(&mut found) {
+            *found &= *key != HEADER_KEY;
+        }
+        Ok(found)
+    }
+
+    fn modified(&self, key: &str) -> Result<Option<SystemTime>, KvsError> {
+        self.inner.modified(key)
+    }
+
+    fn touch(&mut self, key: &str, at: SystemTime) -> Result<bool, KvsError> {
+        self.inner.touch(key, at)
+    }
+
+    fn location(&self) -> Option<&Path> {
+        self.inner.location()
+    }
+
+    fn free_space(&self) -> Result<Option<u64>, KvsError> {
+        self.inner.free_space()
+    }
+
+    fn maintain(&mut self) -> Result<(), KvsError> {
+        self.inner.maintain()
+    }
+
+    fn listen(&self) -> Result<Option<Box<dyn ChangeListener>>, KvsError> {
+        self.inner.listen()
+    }
+
+    fn reopen(&self) -> Result<Option<DynBackingStore>, KvsError> {
+        self.inner.reopen()
+    }
+}
+
+impl<S: Scope> KeyValueStore<MachineBound<S>> {
+    /// Returns whether the value of `key` was stored on this machine.
+    ///
+    /// Returns `false` if the key doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value cannot be read or is not a valid
+    /// machine-bound value.
+    pub fn is_local(&self, key: &str) -> Result<bool, KvsError> {
+        match self.retrieve::<_, Vec<u8>>(key) {
+            Ok(value) => Ok(value.is_some()),
+            Err(KvsError::ForeignMachine { .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// A value that can only be retrieved on the machine that stored it.
+///
+/// Binds individual keys of any store, where [`MachineBound`] binds all of
+/// them. Values must be both stored and retrieved wrapped; retrieving a
+/// value stored on another machine fails with `KvsError::ForeignMachine`.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::machine::Bound;
+/// use zep_kvs::prelude::*;
+///
+/// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+/// store.store("device_token", Bound("paired"))?;
+/// let Bound(token): Bound<String> = store.retrieve("device_token")?.unwrap();
+/// assert_eq!(token, "paired");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bound<T>(pub T);
+
+impl<T: OutBytes> OutBytes for Bound<T> {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        let mut value = self.0.out_bytes()?;
+        let bound = bind(&machine_tag()?, &value);
+        if let (Cow::Owned(value), true) = (&mut value, T::is_sensitive()) {
+            wipe(value);
+        }
+        Ok(Cow::Owned(bound))
+    }
+
+    fn type_tag() -> &'static str {
+        "Bound"
+    }
+
+    fn is_sensitive() -> bool {
+        T::is_sensitive()
+    }
+}
+
+impl<T: InBytes> InBytes for Bound<T> {
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        T::in_bytes(unbind(&machine_tag()?, bytes, None)?).map(Bound)
+    }
+
+    fn type_tag() -> &'static str {
+        "Bound"
+    }
+
+    fn is_sensitive() -> bool {
+        T::is_sensitive()
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::error::KvsError;
+
+    pub(super) fn machine_id() -> Result<String, KvsError> {
+        // Older systems only have the D-Bus copy
+        let path = Path::new("/etc/machine-id");
+        let id = fs::read_to_string(path)
+            .or_else(|_| fs::read_to_string("/var/lib/dbus/machine-id"))
+            .map_err(|e| KvsError::io_at(e, path))?;
+        Ok(id.trim().to_string())
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::io;
+    use std::path::Path;
+
+    use crate::error::KvsError;
+
+    pub(super) fn machine_id() -> Result<String, KvsError> {
+        let mut uuid = [0u8; 16];
+        let timeout = libc::timespec {
+            tv_sec: 5,
+            tv_nsec: 0,
+        };
+        // SAFETY: `uuid` is the 16-byte buffer gethostuuid requires.
+        if unsafe { libc::gethostuuid(uuid.as_mut_ptr(), &timeout) } != 0 {
+            return Err(KvsError::io_at(
+                io::Error::last_os_error(),
+                Path::new("IOPlatformUUID"),
+            ));
+        }
+        // Formatted like `ioreg` shows the IOPlatformUUID
+        let hex: String = uuid.iter().map(|b| format!("{b:02X}")).collect();
+        Ok(format!(
+            "{}-{}-{}-{}-{}",
+            &hex[..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..]
+        ))
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use std::path::Path;
+
+    use winreg::RegKey;
+    use winreg::enums::{HKEY_LOCAL_MACHINE, KEY_READ, KEY_WOW64_64KEY};
+
+    use crate::error::KvsError;
+
+    const CRYPTOGRAPHY_KEY: &str = r"SOFTWARE\Microsoft\Cryptography";
+
+    pub(super) fn machine_id() -> Result<String, KvsError> {
+        // 32-bit processes would otherwise read the redirected WOW64 key,
+        // which has no MachineGuid
+        RegKey::predef(HKEY_LOCAL_MACHINE)
+            .open_subkey_with_flags(CRYPTOGRAPHY_KEY, KEY_READ | KEY_WOW64_64KEY)
+            .and_then(|key| key.get_value("MachineGuid"))
+            .map_err(|e| {
+                KvsError::io_at(
+                    e,
+                    Path::new(r"winreg:HKEY_LOCAL_MACHINE\SOFTWARE\Microsoft\Cryptography"),
+                )
+            })
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+mod platform {
+    use std::io;
+    use std::path::Path;
+
+    use crate::error::KvsError;
+
+    pub(super) fn machine_id() -> Result<String, KvsError> {
+        Err(KvsError::io_at(
+            io::Error::new(io::ErrorKind::Unsupported, "no machine identifier"),
+            Path::new("machine-id"),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Mock, MockStore};
+
+    fn open(mock: &MockStore) -> KeyValueStore<MachineBound<Mock>> {
+        KeyValueStore::with_backing_store(BoundStore::open(mock.clone()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_values_from_other_machines_are_detected() {
+        let mut mock = MockStore::new();
+        let mut store = open(&mock);
+        store.store("license", "XXXX-YYYY").unwrap();
+        assert_eq!(
+            store.retrieve::<_, String>("license").unwrap().as_deref(),
+            Some("XXXX-YYYY")
+        );
+        assert!(store.is_local("license").unwrap());
+        assert!(!store.is_local("missing").unwrap());
+
+        // Simulate a copy from a machine with another identifier
+        let mut bound = mock.retrieve("license").unwrap().unwrap();
+        bound[1] ^= 1;
+        mock.store("license", &bound).unwrap();
+        assert!(matches!(
+            store.retrieve::<_, String>("license"),
+            Err(KvsError::ForeignMachine { key: Some(k) }) if k == "license"
+        ));
+        assert!(!store.is_local("license").unwrap());
+
+        // Metadata is readable wherever the store is
+        assert!(store.info().unwrap().created.is_some());
+        assert_eq!(store.keys().unwrap(), ["license"]);
+    }
+
+    #[test]
+    fn test_existing_data_is_not_bound() {
+        let mut mock = MockStore::new();
+        mock.store("plain", b"value").unwrap();
+        assert!(matches!(
+            BoundStore::open(mock),
+            Err(KvsError::SerializationError(_))
+        ));
+    }
+
+    #[test]
+    fn test_bound_values_in_an_ordinary_store() {
+        let mut mock = MockStore::new();
+        let mut store = KeyValueStore::<Mock>::with_backing_store(mock.clone()).unwrap();
+        store.store("token", Bound(42u32)).unwrap();
+        assert_eq!(store.retrieve("token").unwrap(), Some(Bound(42u32)));
+
+        let mut bound = mock.retrieve("token").unwrap().unwrap();
+        bound[TAG_LEN] ^= 1;
+        mock.store("token", &bound).unwrap();
+        assert!(matches!(
+            store.retrieve::<_, Bound<u32>>("token"),
+            Err(KvsError::ForeignMachine { key: None })
+        ));
+
+        store.store("plain", "").unwrap();
+        assert!(matches!(
+            store.retrieve::<_, Bound<String>>("plain"),
+            Err(KvsError::SerializationError(_))
+        ));
+    }
+
+    #[test]
+    fn test_bound_values_have_their_own_type_tag() {
+        let mut store = KeyValueStore::<Mock>::with_backing_store(MockStore::new())
+            .unwrap()
+            .with_type_tags();
+        store.store("token", Bound(42u32)).unwrap();
+        store.store("plain", 42u32).unwrap();
+        assert_eq!(store.retrieve("token").unwrap(), Some(Bound(42u32)));
+        assert!(matches!(
+            store.retrieve::<_, u32>("token"),
+            Err(KvsError::TypeMismatch { .. })
+        ));
+        assert!(matches!(
+            store.retrieve::<_, Bound<u32>>("plain"),
+            Err(KvsError::TypeMismatch { .. })
+        ));
+    }
+}
@@ -5,10 +5,14 @@
 //! the Library/Application Support hierarchy.
 
 use std::env;
-use std::path::PathBuf;
+use std::ffi::c_void;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::ptr;
 
 use crate::api::Scope;
-use crate::api::scope::{Machine, User};
+use crate::api::scope::{Machine, Session, User};
 use crate::directory::DirectoryStore;
 use crate::error::KvsError;
 
@@ -38,9 +42,19 @@ impl Scope for Machine {
     /// - The file system is read-only
     /// - Directory creation fails for other I/O reasons
     fn new() -> Result<Self::Store, KvsError> {
+        #[cfg(any(test, feature = "testing"))]
+        if let Some(path) = crate::testing::redirected("machine") {
+            return DirectoryStore::new(path).map_err(KvsError::no_machine_scope);
+        }
+        #[cfg(feature = "config-file")]
+        if let Some(path) =
+            crate::config::configured_path("machine").map_err(KvsError::no_machine_scope)?
+        {
+            return DirectoryStore::new(path).map_err(KvsError::no_machine_scope);
+        }
         // Use /Library/Application Support for system-wide storage on macOS
         DirectoryStore::new(PathBuf::from("/Library/Application Support"))
-            .map_err(|e| KvsError::NoMachineScope(e.to_string()))
+            .map_err(KvsError::no_machine_scope)
     }
 }
 
@@ -76,18 +90,93 @@ impl Scope for User {
     /// - The user lacks permissions to create directories in `~/Library/Application Support`
     /// - Directory creation fails for other I/O reasons
     fn new() -> Result<Self::Store, KvsError> {
+        #[cfg(any(test, feature = "testing"))]
+        if let Some(path) = crate::testing::redirected("user") {
+            return DirectoryStore::new(path).map_err(KvsError::no_user_scope);
+        }
+        #[cfg(feature = "config-file")]
+        if let Some(path) =
+            crate::config::configured_path("user").map_err(KvsError::no_user_scope)?
+        {
+            return DirectoryStore::new(path).map_err(KvsError::no_user_scope);
+        }
         // Use ~/Library/Application Support for user-specific storage on macOS
-        let path = env::var_os("HOME").map(|home| {
-            PathBuf::from(home)
-                .join("Library")
-                .join("Application Support")
-        });
+        let path = env::var_os("HOME").map(|home| user_data_dir(Path::new(&home)));
 
         match path {
-            Some(path) => {
-                DirectoryStore::new(path).map_err(|e| KvsError::NoUserScope(e.to_string()))
-            }
-            None => Err(KvsError::NoUserScope("no user directory found".to_string())),
+            Some(path) => DirectoryStore::new(path).map_err(KvsError::no_user_scope),
+            None => Err(KvsError::NoUserScope {
+                reason: "no user directory found".to_string(),
+                source: None,
+            }),
+        }
+    }
+}
+
+impl Scope for Session {
+    type Store = DirectoryStore;
+
+    /// Creates a login session storage scope for macOS.
+    ///
+    /// Uses a directory under the user's temporary directory, `$TMPDIR`,
+    /// named after the time the system booted. macOS empties the temporary
+    /// directory when it restarts, and a store left from an earlier boot is
+    /// never opened again even if it survives.
+    ///
+    /// # Storage Location
+    ///
+    /// Data is stored in `$TMPDIR/session-{boot_time}/{package_name}/{app_name}/`
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoUserScope` if:
+    /// - The boot time can't be determined
+    /// - Directory creation fails for other I/O reasons
+    fn new() -> Result<Self::Store, KvsError> {
+        #[cfg(any(test, feature = "testing"))]
+        if let Some(path) = crate::testing::redirected("session") {
+            return DirectoryStore::new(path).map_err(KvsError::no_user_scope);
         }
+        #[cfg(feature = "config-file")]
+        if let Some(path) =
+            crate::config::configured_path("session").map_err(KvsError::no_user_scope)?
+        {
+            return DirectoryStore::new(path).map_err(KvsError::no_user_scope);
+        }
+        let boot = boot_time().map_err(|e| KvsError::NoUserScope {
+            reason: "failed to determine the boot time".to_string(),
+            source: Some(e),
+        })?;
+        DirectoryStore::new(env::temp_dir().join(format!("session-{boot}")))
+            .map_err(KvsError::no_user_scope)
+    }
+}
+
+/// Returns the time the system booted, in seconds since the Unix epoch.
+fn boot_time() -> io::Result<i64> {
+    let mut mib = [libc::CTL_KERN, libc::KERN_BOOTTIME];
+    // SAFETY: `timeval` is plain data, for which all zeroes is valid.
+    let mut boot: libc::timeval = unsafe { mem::zeroed() };
+    let mut size = mem::size_of::<libc::timeval>();
+    // SAFETY: `mib` names a `timeval` value, and `boot` and `size` describe
+    // a buffer large enough to hold it.
+    let result = unsafe {
+        libc::sysctl(
+            mib.as_mut_ptr(),
+            mib.len() as u32,
+            (&raw mut boot).cast::<c_void>(),
+            &mut size,
+            ptr::null_mut(),
+            0,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
     }
+    Ok(boot.tv_sec)
+}
+
+/// Returns the user data directory for a home directory.
+pub(crate) fn user_data_dir(home: &Path) -> PathBuf {
+    home.join("Library").join("Application Support")
 }
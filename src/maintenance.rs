@@ -0,0 +1,107 @@
+//! Housekeeping for long-lived stores.
+
+use std::time::SystemTime;
+
+use crate::api::{KeyValueStore, Scope};
+use crate::error::KvsError;
+
+/// A set of housekeeping tasks to run on a store in one pass.
+///
+/// Created by `KeyValueStore::maintenance`. Running it always performs the
+/// backing store's own housekeeping, such as removing temporary files left
+/// behind by interrupted writes; further tasks are opted into with the
+/// builder methods.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+/// use zep_kvs::prelude::*;
+///
+/// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+/// store.store("fresh", "value")?;
+///
+/// let month_ago = SystemTime::now() - Duration::from_secs(30 * 24 * 60 * 60);
+/// let report = store
+///     .maintenance()
+///     .remove_not_modified_since(month_ago)
+///     .run()?;
+/// assert!(report.removed.is_empty());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct Maintenance<'a, S: Scope> {
+    store: &'a mut KeyValueStore<S>,
+    stale_before: Option<SystemTime>,
+}
+
+/// The outcome of a maintenance run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MaintenanceReport {
+    /// Keys removed because they were stale.
+    pub removed: Vec<String>,
+}
+
+impl<'a, S: Scope> Maintenance<'a, S> {
+    pub(crate) fn new(store: &'a mut KeyValueStore<S>) -> Self {
+        Self {
+            store,
+            stale_before: None,
+        }
+    }
+
+    /// Also removes keys whose values have not been modified since `since`.
+    ///
+    /// Keys whose backend does not track modification times are kept.
+    pub fn remove_not_modified_since(mut self, since: SystemTime) -> Self {
+        self.stale_before = Some(since);
+        self
+    }
+
+    /// Runs the selected tasks.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error from any task. Tasks completed before the
+    /// error keep their effect.
+    pub fn run(self) -> Result<MaintenanceReport, KvsError> {
+        let mut report = MaintenanceReport::default();
+        self.store.maintain_backing_store()?;
+        if let Some(since) = self.stale_before {
+            for key in self.store.keys_not_modified_since(since)? {
+                self.store.remove(&key)?;
+                report.removed.push(key);
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::scope::Ephemeral;
+    use crate::testing::MockClock;
+    use std::time::Duration;
+
+    #[test]
+    fn test_maintenance_removes_stale_keys() {
+        let clock = MockClock::new(SystemTime::UNIX_EPOCH);
+        let mut store = KeyValueStore::<Ephemeral>::new()
+            .unwrap()
+            .with_clock(clock.clone());
+        store.store("stale", "1").unwrap();
+        store.store("fresh", "2").unwrap();
+        store.touch("stale").unwrap();
+        clock.advance(Duration::from_secs(60));
+        store.touch("fresh").unwrap();
+
+        let report = store
+            .maintenance()
+            .remove_not_modified_since(SystemTime::UNIX_EPOCH + Duration::from_secs(30))
+            .run()
+            .unwrap();
+        assert_eq!(report.removed, ["stale"]);
+        assert_eq!(store.keys().unwrap(), ["fresh"]);
+        assert!(store.maintenance().run().unwrap().removed.is_empty());
+    }
+}
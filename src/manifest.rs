@@ -0,0 +1,142 @@
+//! Detection of keys left behind by older versions of an application.
+//!
+//! Long-lived installs accumulate keys that earlier versions wrote and the
+//! current one no longer reads. An application declares the keys it uses in
+//! a [`KeyManifest`] and attaches it with `KeyValueStore::with_manifest`;
+//! [`KeyValueStore::orphans`] then lists the keys outside it, and
+//! [`KeyValueStore::prune_orphans`] removes them.
+
+use std::collections::BTreeSet;
+
+use crate::api::{KeyValueStore, Scope};
+use crate::error::KvsError;
+
+/// The keys an application uses.
+///
+/// Keys are declared by name, or by prefix for families of keys whose names
+/// are only known at runtime, such as `recent:` for `recent:1`, `recent:2`
+/// and so on.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::manifest::KeyManifest;
+/// use zep_kvs::prelude::*;
+///
+/// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+/// store.store("theme", "dark")?;
+/// store.store("recent:1", "notes.txt")?;
+/// store.store("legacy_colour", "blue")?;
+///
+/// let manifest = KeyManifest::new().key("theme").prefix("recent:");
+/// let mut store = store.with_manifest(manifest);
+/// assert_eq!(store.orphans()?, ["legacy_colour"]);
+/// store.prune_orphans()?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyManifest {
+    keys: BTreeSet<String>,
+    prefixes: BTreeSet<String>,
+}
+
+impl KeyManifest {
+    /// Creates a manifest declaring no keys.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declares `key`.
+    pub fn key(mut self, key: impl Into<String>) -> Self {
+        self.keys.insert(key.into());
+        self
+    }
+
+    /// Declares each of `keys`.
+    pub fn keys<I>(mut self, keys: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.keys.extend(keys.into_iter().map(Into::into));
+        self
+    }
+
+    /// Declares every key starting with `prefix`.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefixes.insert(prefix.into());
+        self
+    }
+
+    /// Returns whether `key` is declared, by name or by prefix.
+    pub fn contains(&self, key: &str) -> bool {
+        self.keys.contains(key) || self.prefixes.iter().any(|prefix| key.starts_with(prefix))
+    }
+}
+
+impl<S: Scope> KeyValueStore<S> {
+    /// Returns the keys not declared in the store's manifest.
+    ///
+    /// A store without a manifest has no orphans, so this returns an empty
+    /// list rather than every key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the keys cannot be listed.
+    pub fn orphans(&self) -> Result<Vec<String>, KvsError> {
+        let Some(manifest) = self.manifest() else {
+            return Ok(Vec::new());
+        };
+        let mut orphans = self.keys()?;
+        orphans.retain(|key| !manifest.contains(key));
+        Ok(orphans)
+    }
+
+    /// Removes the keys not declared in the store's manifest, returning
+    /// them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the keys cannot be listed or removed. Keys
+    /// removed before the error stay removed.
+    pub fn prune_orphans(&mut self) -> Result<Vec<String>, KvsError> {
+        let orphans = self.orphans()?;
+        for key in &orphans {
+            self.remove(key)?;
+        }
+        Ok(orphans)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::scope::Ephemeral;
+
+    #[test]
+    fn test_prune_orphans_keeps_declared_keys() {
+        let mut store = KeyValueStore::<Ephemeral>::new().unwrap();
+        for key in [
+            "theme",
+            "window",
+            "recent:1",
+            "recent:2",
+            "old_theme",
+            "recent",
+        ] {
+            store.store(key, "value").unwrap();
+        }
+        assert!(store.orphans().unwrap().is_empty());
+
+        let manifest = KeyManifest::new()
+            .keys(["theme", "window"])
+            .prefix("recent:");
+        let mut store = store.with_manifest(manifest);
+        assert_eq!(store.prune_orphans().unwrap(), ["old_theme", "recent"]);
+        assert_eq!(
+            store.keys().unwrap(),
+            ["recent:1", "recent:2", "theme", "window"]
+        );
+        assert!(store.orphans().unwrap().is_empty());
+    }
+}
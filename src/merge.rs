@@ -0,0 +1,130 @@
+//! Merging the contents of one store into another.
+
+use crate::api::{KeyValueStore, Scope};
+use crate::error::KvsError;
+
+/// A function choosing the value to keep for a key present in both stores.
+///
+/// It is called with the key, our value and their value, as stored bytes,
+/// and returns the bytes to keep.
+pub type Resolver<'a> = Box<dyn FnMut(&str, &[u8], &[u8]) -> Vec<u8> + 'a>;
+
+/// How to resolve keys present with different values in both stores.
+pub enum MergeStrategy<'a> {
+    /// Keep the value already in this store.
+    KeepOurs,
+    /// Replace the value with the one from the other store.
+    KeepTheirs,
+    /// Store the value returned by a function.
+    Resolve(Resolver<'a>),
+}
+
+impl<S: Scope> KeyValueStore<S> {
+    /// Copies the entries of another store into this one.
+    ///
+    /// Keys only present in `other` are copied, keys only present in this
+    /// store are kept, and keys present in both with different values are
+    /// resolved by `strategy`. Values are copied as stored, so both stores
+    /// should use type tags consistently. Internal metadata is not merged.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if either store cannot be accessed, or if a write
+    /// is rejected by this store's limits. Entries merged before the error
+    /// remain.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::merge::MergeStrategy;
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut old_install = KeyValueStore::<scope::Ephemeral>::new()?;
+    /// old_install.store("theme", "dark")?;
+    /// old_install.store("volume", 5u8)?;
+    ///
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+    /// store.store("theme", "light")?;
+    /// store.merge_from(&old_install, MergeStrategy::KeepOurs)?;
+    ///
+    /// assert_eq!(store.retrieve("theme")?, Some(String::from("light")));
+    /// assert_eq!(store.retrieve("volume")?, Some(5u8));
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn merge_from<T: Scope>(
+        &mut self,
+        other: &KeyValueStore<T>,
+        mut strategy: MergeStrategy,
+    ) -> Result<(), KvsError> {
+        for key in other.keys()? {
+            let Some(theirs) = other.retrieve_raw(&key)? else {
+                continue;
+            };
+            let value = match self.retrieve_raw(&key)? {
+                None => theirs,
+                Some(ours) if ours == theirs => continue,
+                Some(ours) => match &mut strategy {
+                    MergeStrategy::KeepOurs => continue,
+                    MergeStrategy::KeepTheirs => theirs,
+                    MergeStrategy::Resolve(resolve) => {
+                        let value = resolve(&key, &ours, &theirs);
+                        if value == ours {
+                            continue;
+                        }
+                        value
+                    }
+                },
+            };
+            self.store_raw(&key, &value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::scope::Ephemeral;
+
+    fn stores() -> (KeyValueStore<Ephemeral>, KeyValueStore<Ephemeral>) {
+        let mut ours = KeyValueStore::<Ephemeral>::new().unwrap();
+        ours.store("shared", "ours").unwrap();
+        ours.store("mine", "ours").unwrap();
+        let mut theirs = KeyValueStore::<Ephemeral>::new().unwrap();
+        theirs.store("shared", "theirs").unwrap();
+        theirs.store("yours", "theirs").unwrap();
+        (ours, theirs)
+    }
+
+    #[test]
+    fn test_merge_keep_theirs() {
+        let (mut ours, theirs) = stores();
+        ours.merge_from(&theirs, MergeStrategy::KeepTheirs).unwrap();
+        assert_eq!(
+            ours.retrieve("shared").unwrap(),
+            Some(String::from("theirs"))
+        );
+        assert_eq!(ours.retrieve("mine").unwrap(), Some(String::from("ours")));
+        assert_eq!(
+            ours.retrieve("yours").unwrap(),
+            Some(String::from("theirs"))
+        );
+    }
+
+    #[test]
+    fn test_merge_with_resolver() {
+        let (mut ours, theirs) = stores();
+        let mut conflicts = Vec::new();
+        let resolve = |key: &str, ours: &[u8], theirs: &[u8]| {
+            conflicts.push(key.to_string());
+            [ours, b"+", theirs].concat()
+        };
+        ours.merge_from(&theirs, MergeStrategy::Resolve(Box::new(resolve)))
+            .unwrap();
+        assert_eq!(conflicts, ["shared"]);
+        assert_eq!(
+            ours.retrieve("shared").unwrap(),
+            Some(String::from("ours+theirs"))
+        );
+    }
+}
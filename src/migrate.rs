@@ -0,0 +1,275 @@
+//! Application data migrations.
+//!
+//! Applications that change how they lay out their data register a
+//! [`Migrations`] set, with one closure per data version, and call
+//! `KeyValueStore::migrate_to` at startup. Pending migrations run in version
+//! order, and the version each one brings the store to is recorded in the
+//! store, so every migration runs at most once per store.
+//!
+//! The data version is the application's own and is unrelated to the store
+//! format version in `api::FORMAT_VERSION`.
+//!
+//! Moving data to a different location is handled by the [`Migrating`]
+//! scope, which writes to both an old and a new store until
+//! `KeyValueStore::finalize_migration` retires the old one.
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::api::{BackingStore, KeyValueStore, Scope};
+use crate::dynamic::DynBackingStore;
+use crate::error::KvsError;
+use crate::watch::ChangeListener;
+
+/// A migration bringing a store to a new data version.
+type Migration<S> = Box<dyn Fn(&mut KeyValueStore<S>) -> Result<(), KvsError>>;
+
+/// An ordered set of application data migrations.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::migrate::Migrations;
+/// use zep_kvs::prelude::*;
+///
+/// let migrations = Migrations::new()
+///     .add(1, |store| store.store("theme", "light"))
+///     .add(2, |store| {
+///         // Version 2 renamed "theme" to "ui.theme"
+///         if let Some(theme) = store.retrieve::<_, String>("theme")? {
+///             store.store("ui.theme", theme.as_str())?;
+///             store.remove("theme")?;
+///         }
+///         Ok(())
+///     });
+///
+/// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+/// assert_eq!(store.migrate_to(2, &migrations)?, 2);
+/// assert_eq!(store.retrieve("ui.theme")?, Some(String::from("light")));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct Migrations<S: Scope> {
+    steps: BTreeMap<u32, Migration<S>>,
+}
+
+impl<S: Scope> Migrations<S> {
+    /// Creates an empty set of migrations.
+    pub fn new() -> Self {
+        Self {
+            steps: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the migration that brings a store to data version `version`.
+    ///
+    /// Versions start at 1; a store that has never been migrated is at data
+    /// version 0. Adding a second migration for the same version replaces
+    /// the first.
+    pub fn add<F>(mut self, version: u32, migration: F) -> Self
+    where
+        F: Fn(&mut KeyValueStore<S>) -> Result<(), KvsError> + 'static,
+    {
+        self.steps.insert(version, Box::new(migration));
+        self
+    }
+
+    /// Returns the migrations after `from` up to and including `to`, in
+    /// version order.
+    pub(crate) fn pending(&self, from: u32, to: u32) -> impl Iterator<Item = (u32, &Migration<S>)> {
+        self.steps
+            .range((Bound::Excluded(from), Bound::Included(to.max(from))))
+            .map(|(version, migration)| (*version, migration))
+    }
+}
+
+impl<S: Scope> Default for Migrations<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Scope that gradually moves data from the `Old` scope to the `New` one.
+///
+/// While migrating, writes and removals go to both stores, and reads prefer
+/// the new store, falling back to the old one for keys not yet copied. This
+/// keeps both locations usable, so an application version that only knows
+/// the old location still sees current data. Once every deployed version
+/// reads the new location, `KeyValueStore::finalize_migration` copies any
+/// remaining keys and empties the old store.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::migrate::Migrating;
+/// use zep_kvs::prelude::*;
+///
+/// let mut store = KeyValueStore::<Migrating<scope::Ephemeral, scope::Ephemeral>>::new()?;
+/// store.store("key", "value")?;
+/// store.finalize_migration()?;
+/// assert_eq!(store.retrieve("key")?, Some(String::from("value")));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct Migrating<Old: Scope, New: Scope>(Old, New);
+
+impl<Old: Scope, New: Scope> Scope for Migrating<Old, New> {
+    type Store = MigratingStore<Old::Store, New::Store>;
+
+    fn new() -> Result<Self::Store, KvsError> {
+        Ok(MigratingStore {
+            old: Old::new()?,
+            new: New::new()?,
+        })
+    }
+}
+
+/// Backing store of the [`Migrating`] scope.
+#[derive(Debug)]
+pub struct MigratingStore<Old: BackingStore, New: BackingStore> {
+    old: Old,
+    new: New,
+}
+
+impl<Old: BackingStore, New: BackingStore> MigratingStore<Old, New> {
+    /// Copies keys only present in the old store to the new one, then
+    /// removes every key from the old store.
+    pub(crate) fn finalize(&mut self) -> Result<(), KvsError> {
+        for key in self.old.keys()? {
+            if self.new.retrieve(&key)?.is_none()
+                && let Some(value) = self.old.retrieve(&key)?
+            {
+                self.new.store(&key, &value)?;
+            }
+        }
+        for key in self.old.keys()? {
+            self.old.remove(&key)?;
+        }
+        Ok(())
+    }
+}
+
+impl<Old: BackingStore, New: BackingStore> BackingStore for MigratingStore<Old, New> {
+    fn keys(&self) -> Result<Vec<String>, KvsError> {
+        let mut keys = self.new.keys()?;
+        let new_keys: Vec<_> = keys.clone();
+        keys.extend(
+            self.old
+                .keys()?
+                .into_iter()
+                .filter(|key| !new_keys.contains(key)),
+        );
+        Ok(keys)
+    }
+
+    fn store(&mut self, key: &str, value: &[u8]) -> Result<(), KvsError> {
+        self.new.store(key, value)?;
+        self.old.store(key, value)
+    }
+
+    fn retrieve(&self, key: &str) -> Result<Option<Vec<u8>>, KvsError> {
+        match self.new.retrieve(key)? {
+            Some(value) => Ok(Some(value)),
+            None => self.old.retrieve(key),
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), KvsError> {
+        self.new.remove(key)?;
+        self.old.remove(key)
+    }
+
+    fn remove_secure(&mut self, key: &str) -> Result<(), KvsError> {
+        self.new.remove_secure(key)?;
+        self.old.remove_secure(key)
+    }
+
+    fn modified(&self, key: &str) -> Result<Option<SystemTime>, KvsError> {
+        match self.new.retrieve(key)? {
+            Some(_) => self.new.modified(key),
+            None => self.old.modified(key),
+        }
+    }
+
+    fn touch(&mut self, key: &str, at: SystemTime) -> Result<bool, KvsError> {
+        let new = self.new.touch(key, at)?;
+        Ok(self.old.touch(key, at)? || new)
+    }
+
+    fn location(&self) -> Option<&Path> {
+        self.new.location()
+    }
+
+    fn free_space(&self) -> Result<Option<u64>, KvsError> {
+        self.new.free_space()
+    }
+
+    fn maintain(&mut self) -> Result<(), KvsError> {
+        self.new.maintain()?;
+        self.old.maintain()
+    }
+
+    fn listen(&self) -> Result<Option<Box<dyn ChangeListener>>, KvsError> {
+        self.new.listen()
+    }
+
+    fn reopen(&self) -> Result<Option<DynBackingStore>, KvsError> {
+        let (Some(old), Some(new)) = (self.old.reopen()?, self.new.reopen()?) else {
+            return Ok(None);
+        };
+        Ok(Some(Box::new(MigratingStore { old, new })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ephemeral::EphemeralStore;
+    use crate::testing::{Mock, MockStore};
+
+    #[test]
+    fn test_migrating_store_dual_writes_and_finalizes() {
+        let old = MockStore::new();
+        let new = MockStore::new();
+        let mut legacy = KeyValueStore::<Mock>::with_backing_store(old.clone()).unwrap();
+        legacy.store("kept", "old").unwrap();
+        legacy.store("replaced", "old").unwrap();
+
+        let mut store =
+            KeyValueStore::<Migrating<Mock, Mock>>::with_backing_store(MigratingStore {
+                old: old.clone(),
+                new: new.clone(),
+            })
+            .unwrap();
+        assert_eq!(store.retrieve("kept").unwrap(), Some(String::from("old")));
+        store.store("replaced", "new").unwrap();
+        store.store("added", "new").unwrap();
+        assert_eq!(legacy.retrieve("added").unwrap(), Some(String::from("new")));
+        let mut keys = store.keys().unwrap();
+        keys.sort();
+        assert_eq!(keys, ["added", "kept", "replaced"]);
+
+        store.finalize_migration().unwrap();
+        assert!(old.keys().unwrap().is_empty());
+        let mut keys = new.keys().unwrap();
+        keys.retain(|key| !crate::api::is_reserved(key));
+        keys.sort();
+        assert_eq!(keys, ["added", "kept", "replaced"]);
+        assert_eq!(
+            store.retrieve("replaced").unwrap(),
+            Some(String::from("new"))
+        );
+    }
+
+    #[test]
+    fn test_migrating_store_removes_from_both() {
+        let mut inner = MigratingStore {
+            old: EphemeralStore::new(),
+            new: EphemeralStore::new(),
+        };
+        inner.old.store("key", b"value").unwrap();
+        inner.store("key", b"value").unwrap();
+        inner.remove("key").unwrap();
+        assert_eq!(inner.retrieve("key").unwrap(), None);
+    }
+}
@@ -0,0 +1,717 @@
+//! Partitioning a store by hostname or tenant.
+//!
+//! Home directories shared between computers, over a network mount or
+//! with a dotfile sync tool, share their `User` scope too. Settings that
+//! only make sense on one machine, such as window layouts or local paths,
+//! then leak between machines. The [`PerHost`] scope wraps another scope
+//! and keeps a separate set of keys for each host in the same store.
+//!
+//! Keys are stored with the short hostname as a prefix, `laptop:theme`,
+//! and store metadata such as the data version is kept per host too, so
+//! each host migrates its own data.
+//!
+//! The [`Tenant`] scope partitions a store the same way by an id chosen by
+//! the application, for software that keeps data for several customers or
+//! accounts on one machine.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use crate::api::{BackingStore, KeyValueStore, Scope, is_reserved};
+use crate::convert::{InBytes, OutBytes};
+use crate::dynamic::DynBackingStore;
+use crate::error::KvsError;
+use crate::watch::ChangeListener;
+
+/// Separates the hostname from the key.
+const SEPARATOR: char = ':';
+/// Prefix of the keys holding trashed namespaces.
+const TRASH_PREFIX: &str = "__zep_trash:";
+/// The length of the deletion time at the start of a trashed namespace.
+const TIME_LEN: usize = 12;
+
+/// Returns the name of this host, as used to partition keys.
+///
+/// This is the first label of the hostname, in lowercase, so that
+/// `Laptop.local` and `laptop.example.com` are the same host. On macOS the
+/// hostname can follow the network, so set a fixed name with
+/// `scutil --set HostName` if it changes.
+///
+/// # Errors
+///
+/// Returns an error if the hostname cannot be determined.
+pub fn hostname() -> Result<String, KvsError> {
+    let name = platform::hostname()?;
+    let short = name.split('.').next().unwrap_or_default().to_lowercase();
+    if short.is_empty() {
+        return Err(KvsError::SerializationError(format!(
+            "Invalid hostname {name:?}"
+        )));
+    }
+    Ok(short)
+}
+
+/// A scope whose keys are private to the current host.
+///
+/// Keys stored on other hosts are neither listed nor retrievable, even
+/// when they have the same names. Existing keys stored without a host are
+/// not visible either.
+///
+/// An already configured store can be switched to its host's keys with
+/// [`KeyValueStore::namespace_hostname`].
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::namespace::PerHost;
+/// use zep_kvs::prelude::*;
+///
+/// let mut store = KeyValueStore::<PerHost<scope::Ephemeral>>::new()?;
+/// store.store("window", "100,200")?;
+/// assert_eq!(store.keys()?, ["window"]);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct PerHost<S: Scope>(S);
+
+impl<S: Scope> Scope for PerHost<S> {
+    type Store = NamespacedStore<S::Store>;
+
+    fn new() -> Result<Self::Store, KvsError> {
+        Ok(NamespacedStore::new(S::new()?, &hostname()?))
+    }
+}
+
+/// Backing store of the [`PerHost`] scope.
+pub struct NamespacedStore<B: BackingStore> {
+    inner: B,
+    namespace: String,
+}
+
+impl<B: BackingStore + fmt::Debug> fmt::Debug for NamespacedStore<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NamespacedStore")
+            .field("inner", &self.inner)
+            .field("namespace", &self.namespace)
+            .finish()
+    }
+}
+
+impl<B: BackingStore> NamespacedStore<B> {
+    /// Wraps `inner`, keeping the keys of the namespace `namespace`.
+    pub(crate) fn new(inner: B, namespace: &str) -> Self {
+        Self {
+            inner,
+            namespace: namespace.to_string(),
+        }
+    }
+
+    /// Returns the key `key` is stored under.
+    ///
+    /// Reserved keys keep their prefix, so they stay hidden from listings
+    /// of the whole store.
+    fn qualify(&self, key: &str) -> String {
+        match is_reserved(key) {
+            true => format!("{key}{SEPARATOR}{}", self.namespace),
+            false => format!("{}{SEPARATOR}{key}", self.namespace),
+        }
+    }
+
+    /// Returns the key stored as `qualified`, if it is in this namespace.
+    fn unqualify<'a>(&self, qualified: &'a str) -> Option<&'a str> {
+        if is_reserved(qualified) {
+            qualified
+                .strip_suffix(self.namespace.as_str())?
+                .strip_suffix(SEPARATOR)
+        } else {
+            qualified
+                .strip_prefix(self.namespace.as_str())?
+                .strip_prefix(SEPARATOR)
+        }
+    }
+
+    fn unqualify_all(&self, qualified: Vec<String>) -> Vec<String> {
+        qualified
+            .iter()
+            .filter_map(|key| self.unqualify(key))
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Returns the key holding the namespace's trashed keys.
+    ///
+    /// It ends with the separator, as no qualified key does, so it belongs
+    /// to no namespace.
+    fn trash_key(&self) -> String {
+        format!("{TRASH_PREFIX}{}{SEPARATOR}", self.namespace)
+    }
+
+    /// Moves every key of the namespace, with its metadata, into the trash
+    /// at `now`, replacing anything trashed before.
+    fn trash(&mut self, now: SystemTime) -> Result<(), KvsError> {
+        let mut entries = BTreeMap::new();
+        for key in self.keys()? {
+            if let Some(value) = self.retrieve(&key)? {
+                entries.insert(key, value);
+            }
+        }
+        let trashed = [now.out_bytes()?, entries.out_bytes()?].concat();
+        self.inner.store(&self.trash_key(), &trashed)?;
+        for key in entries.keys() {
+            self.remove(key)?;
+        }
+        Ok(())
+    }
+
+    /// Moves the namespace's trashed keys back, returning whether there
+    /// were any.
+    fn restore(&mut self) -> Result<bool, KvsError> {
+        let Some(trashed) = self.inner.retrieve(&self.trash_key())? else {
+            return Ok(false);
+        };
+        if self.keys()?.iter().any(|key| !is_reserved(key)) {
+            return Err(KvsError::SerializationError(format!(
+                "Cannot restore {:?} over its new keys",
+                self.namespace
+            )));
+        }
+        let (_, entries) = parse_trash(&trashed)?;
+        for (key, value) in &entries {
+            self.store(key, value)?;
+        }
+        self.inner.remove(&self.trash_key())?;
+        Ok(true)
+    }
+}
+
+/// Parses a trashed namespace into its deletion time and keys.
+fn parse_trash(trashed: &[u8]) -> Result<(SystemTime, BTreeMap<String, Vec<u8>>), KvsError> {
+    let (time, entries) = trashed
+        .split_at_checked(TIME_LEN)
+        .ok_or_else(|| KvsError::SerializationError("Invalid trashed tenant".to_string()))?;
+    Ok((SystemTime::in_bytes(time)?, BTreeMap::in_bytes(entries)?))
+}
+
+impl<B: BackingStore> BackingStore for NamespacedStore<B> {
+    fn keys(&self) -> Result<Vec<String>, KvsError> {
+        Ok(self.unqualify_all(self.inner.keys()?))
+    }
+
+    fn store(&mut self, key: &str, value: &[u8]) -> Result<(), KvsError> {
+        self.inner.store(&self.qualify(key), value)
+    }
+
+    fn retrieve(&self, key: &str) -> Result<Option<Vec<u8>>, KvsError> {
+        self.inner.retrieve(&self.qualify(key))
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), KvsError> {
+        self.inner.remove(&self.qualify(key))
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> Result<bool, KvsError> {
+        self.inner.rename(&self.qualify(from), &self.qualify(to))
+    }
+
+    fn store_many(&mut self, entries: &[(&str, &[u8])]) -> Result<(), KvsError> {
+        let keys: Vec<String> = entries.iter().map(|(key, _)| self.qualify(key)).collect();
+        let entries: Vec<(&str, &[u8])> = keys
+            .iter()
+            .zip(entries)
+            .map(|(key, (_, value))| (key.as_str(), *value))
+            .collect();
+        self.inner.store_many(&entries)
+    }
+
+    fn retrieve_many(&self, keys: &[&str]) -> Result<Vec<Option<Vec<u8>>>, KvsError> {
+        let keys: Vec<String> = keys.iter().map(|key| self.qualify(key)).collect();
+        self.inner
+            .retrieve_many(&keys.iter().map(String::as_str).collect::<Vec<_>>())
+    }
+
+    fn contains(&self, key: &str) -> Result<bool, KvsError> {
+        self.inner.contains(&self.qualify(key))
+    }
+
+    fn store_if_absent(&mut self, key: &str, value: &[u8]) -> Result<bool, KvsError> {
+        self.inner.store_if_absent(&self.qualify(key), value)
+    }
+
+    fn remove_if(&mut self, key: &str, expected: &[u8]) -> Result<bool, KvsError> {
+        self.inner.remove_if(&self.qualify(key), expected)
+    }
+
+    fn contains_many(&self, keys: &[&str]) -> Result<Vec<bool>, KvsError> {
+        let keys: Vec<String> = keys.iter().map(|key| self.qualify(key)).collect();
+        self.inner
+            .contains_many(&keys.iter().map(String::as_str).collect::<Vec<_>>())
+    }
+
+    fn keys_with_prefix(&self, prefix: &str) -> Result<Vec<String>, KvsError> {
+        // Reserved keys end with the namespace, so can't be found by prefix
+        if is_reserved(prefix) {
+            let mut keys = self.keys()?;
+            keys.retain(|key| key.starts_with(prefix));
+            return Ok(keys);
+        }
+        let keys = self.inner.keys_with_prefix(&self.qualify(prefix))?;
+        Ok(self.unqualify_all(keys))
+    }
+
+    fn remove_secure(&mut self, key: &str) -> Result<(), KvsError> {
+        self.inner.remove_secure(&self.qualify(key))
+    }
+
+    fn modified(&self, key: &str) -> Result<Option<SystemTime>, KvsError> {
+        self.inner.modified(&self.qualify(key))
+    }
+
+    fn touch(&mut self, key: &str, at: SystemTime) -> Result<bool, KvsError> {
+        self.inner.touch(&self.qualify(key), at)
+    }
+
+    fn location(&self) -> Option<&Path> {
+        self.inner.location()
+    }
+
+    fn free_space(&self) -> Result<Option<u64>, KvsError> {
+        self.inner.free_space()
+    }
+
+    fn maintain(&mut self) -> Result<(), KvsError> {
+        self.inner.maintain()
+    }
+
+    fn listen(&self) -> Result<Option<Box<dyn ChangeListener>>, KvsError> {
+        self.inner.listen()
+    }
+
+    fn reopen(&self) -> Result<Option<DynBackingStore>, KvsError> {
+        Ok(self
+            .inner
+            .reopen()?
+            .map(|inner| Box::new(NamespacedStore::new(inner, &self.namespace)) as DynBackingStore))
+    }
+}
+
+/// A scope whose keys belong to one tenant of the application.
+///
+/// Stores in this scope are opened with [`KeyValueStore::for_tenant`];
+/// opening them with `KeyValueStore::new` fails. Each tenant has its own
+/// keys and metadata, and can be exported, cleared or destroyed without
+/// touching the others.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::namespace::Tenant;
+/// use zep_kvs::prelude::*;
+///
+/// let mut store = KeyValueStore::<Tenant<scope::Ephemeral>>::for_tenant("customer-42")?;
+/// store.store("endpoint", "https://example.com")?;
+/// store.destroy()?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct Tenant<S: Scope>(S);
+
+impl<S: Scope> Scope for Tenant<S> {
+    type Store = NamespacedStore<S::Store>;
+
+    fn new() -> Result<Self::Store, KvsError> {
+        Err(KvsError::SerializationError(
+            "Tenant stores are opened with KeyValueStore::for_tenant".to_string(),
+        ))
+    }
+}
+
+/// Fails unless `tenant` can be used as a namespace.
+///
+/// The separator is rejected so that no tenant's keys can be mistaken for
+/// another's, and path separators because keys may become file names.
+fn check_tenant(tenant: &str) -> Result<(), KvsError> {
+    let valid = !tenant.is_empty()
+        && !is_reserved(tenant)
+        && !tenant
+            .chars()
+            .any(|c| c == SEPARATOR || c == '/' || c == '\\' || c.is_control());
+    if !valid {
+        return Err(KvsError::SerializationError(format!(
+            "Invalid tenant id {tenant:?}"
+        )));
+    }
+    Ok(())
+}
+
+impl<S: Scope> KeyValueStore<Tenant<S>> {
+    /// Opens the keys of `tenant` in the underlying scope.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `tenant` is empty or contains `:`, a path
+    /// separator or a control character, if the underlying scope cannot be
+    /// opened, or if the tenant's keys were written by a newer, unsupported
+    /// format version.
+    pub fn for_tenant(tenant: &str) -> Result<Self, KvsError> {
+        check_tenant(tenant)?;
+        Self::with_backing_store(NamespacedStore::new(S::new()?, tenant))
+    }
+
+    /// Removes all of the tenant's keys.
+    ///
+    /// Store metadata, such as the data version, is kept. Returns the
+    /// number of keys removed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the keys cannot be listed or removed. Keys
+    /// removed before the failure stay removed.
+    pub fn clear(&mut self) -> Result<usize, KvsError> {
+        let keys = self.keys()?;
+        for key in &keys {
+            self.remove(key)?;
+        }
+        Ok(keys.len())
+    }
+
+    /// Removes all of the tenant's keys and metadata.
+    ///
+    /// Opening the tenant again starts from an empty store.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the keys cannot be listed or removed.
+    pub fn destroy(mut self) -> Result<(), KvsError> {
+        self.clear()?;
+        let store = self.backing_store_mut();
+        for key in store.keys()? {
+            store.remove(&key)?;
+        }
+        Ok(())
+    }
+
+    /// Moves all of the tenant's keys and metadata to the trash.
+    ///
+    /// Opening the tenant again starts from an empty store, as after
+    /// [`destroy`](Self::destroy), but the keys can be brought back with
+    /// [`restore_from_trash`](Self::restore_from_trash) until they are
+    /// purged with [`purge_trash`](KeyValueStore::purge_trash). Trashing
+    /// the tenant again replaces its earlier trash.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the keys cannot be read, stored in the trash or
+    /// removed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use zep_kvs::namespace::Tenant;
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<Tenant<scope::User>>::for_tenant("customer-42")?;
+    /// store.store("endpoint", "https://example.com")?;
+    /// store.destroy_to_trash()?;
+    ///
+    /// // Changed our minds
+    /// KeyValueStore::<Tenant<scope::User>>::restore_from_trash("customer-42")?;
+    ///
+    /// // In a scheduled cleanup job
+    /// let mut root = KeyValueStore::<scope::User>::new()?;
+    /// root.purge_trash(Duration::from_secs(30 * 24 * 60 * 60))?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn destroy_to_trash(mut self) -> Result<(), KvsError> {
+        let now = self.now();
+        self.backing_store_mut().trash(now)
+    }
+
+    /// Moves the keys of `tenant` back from the trash, returning `false` if
+    /// there are none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `tenant` is not a valid tenant id, if the
+    /// underlying scope cannot be opened, or if the tenant has stored keys
+    /// since it was trashed, which restoring would overwrite.
+    pub fn restore_from_trash(tenant: &str) -> Result<bool, KvsError> {
+        check_tenant(tenant)?;
+        NamespacedStore::new(S::new()?, tenant).restore()
+    }
+}
+
+impl<S: Scope> KeyValueStore<S> {
+    /// Permanently removes tenants trashed more than `retention` ago,
+    /// returning their ids.
+    ///
+    /// Call this on a store of the scope the tenants are kept in, since
+    /// the trash belongs to no tenant.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the trash cannot be read or removed.
+    pub fn purge_trash(&mut self, retention: Duration) -> Result<Vec<String>, KvsError> {
+        let now = self.now();
+        let store = self.backing_store_mut();
+        let mut purged = Vec::new();
+        for key in store.keys_with_prefix(TRASH_PREFIX)? {
+            let Some(trashed) = store.retrieve(&key)? else {
+                continue;
+            };
+            let (deleted, _) = parse_trash(&trashed)?;
+            if now.duration_since(deleted).is_ok_and(|age| age > retention) {
+                store.remove(&key)?;
+                let tenant = key[TRASH_PREFIX.len()..].trim_end_matches(SEPARATOR);
+                purged.push(tenant.to_string());
+            }
+        }
+        Ok(purged)
+    }
+}
+
+impl<S: Scope> KeyValueStore<S> {
+    /// Switches the store to the keys of the current host.
+    ///
+    /// Returns the same store, with the same configuration, opened as a
+    /// [`PerHost`] scope, for home directories shared between machines.
+    /// Keys stored before switching are not visible through the result.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the hostname cannot be determined, or if the
+    /// host's keys were written by a newer, unsupported format version.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?
+    ///     .with_type_tags()
+    ///     .namespace_hostname()?;
+    /// store.store("window", "100,200")?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn namespace_hostname(self) -> Result<KeyValueStore<PerHost<S>>, KvsError> {
+        let host = hostname()?;
+        self.map_backing_store(|inner| Ok(NamespacedStore::new(inner, &host)))
+    }
+}
+
+#[cfg(unix)]
+mod platform {
+    use std::io;
+    use std::path::Path;
+
+    use crate::error::KvsError;
+
+    pub(super) fn hostname() -> Result<String, KvsError> {
+        let mut buffer = [0u8; 256];
+        // SAFETY: the length passed is that of `buffer`.
+        if unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) } != 0 {
+            return Err(KvsError::io_at(
+                io::Error::last_os_error(),
+                Path::new("hostname"),
+            ));
+        }
+        // The name is truncated, possibly without a terminator, if too long
+        let len = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+        Ok(String::from_utf8_lossy(&buffer[..len]).into_owned())
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::io;
+    use std::path::Path;
+
+    use windows_sys::Win32::System::SystemInformation::{
+        ComputerNameDnsHostname, GetComputerNameExW,
+    };
+
+    use crate::error::KvsError;
+
+    pub(super) fn hostname() -> Result<String, KvsError> {
+        let mut buffer = [0u16; 256];
+        let mut len = buffer.len() as u32;
+        // SAFETY: `len` holds the capacity of `buffer` in characters.
+        if unsafe { GetComputerNameExW(ComputerNameDnsHostname, buffer.as_mut_ptr(), &mut len) }
+            == 0
+        {
+            return Err(KvsError::io_at(
+                io::Error::last_os_error(),
+                Path::new("hostname"),
+            ));
+        }
+        Ok(String::from_utf16_lossy(&buffer[..len as usize]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::scope;
+    use crate::migrate::Migrations;
+    use crate::testing::{Mock, MockClock, MockStore};
+
+    fn open(mock: &MockStore, host: &str) -> KeyValueStore<PerHost<Mock>> {
+        KeyValueStore::with_backing_store(NamespacedStore::new(mock.clone(), host)).unwrap()
+    }
+
+    fn open_tenant(mock: &MockStore, tenant: &str) -> KeyValueStore<Tenant<Mock>> {
+        KeyValueStore::with_backing_store(NamespacedStore::new(mock.clone(), tenant)).unwrap()
+    }
+
+    #[test]
+    fn test_hosts_have_separate_keys() {
+        let mock = MockStore::new();
+        let mut laptop = open(&mock, "laptop");
+        let mut desktop = open(&mock, "desktop");
+        laptop.store("window", "100,200").unwrap();
+        laptop.store("theme", "dark").unwrap();
+        desktop.store("window", "0,0").unwrap();
+
+        let mut keys = laptop.keys().unwrap();
+        keys.sort();
+        assert_eq!(keys, ["theme", "window"]);
+        assert_eq!(desktop.keys().unwrap(), ["window"]);
+        assert_eq!(
+            laptop.retrieve::<_, String>("window").unwrap().as_deref(),
+            Some("100,200")
+        );
+        assert_eq!(desktop.retrieve::<_, String>("theme").unwrap(), None);
+        assert_eq!(laptop.keys_with_prefix("wi").unwrap(), ["window"]);
+
+        desktop.remove("window").unwrap();
+        assert!(laptop.contains_key("window").unwrap());
+        assert!(!desktop.contains_key("window").unwrap());
+    }
+
+    #[test]
+    fn test_metadata_is_kept_per_host() {
+        let mock = MockStore::new();
+        let mut laptop = open(&mock, "laptop");
+        laptop.migrate_to(3, &Migrations::new()).unwrap();
+        assert_eq!(laptop.data_version().unwrap(), 3);
+        assert_eq!(open(&mock, "desktop").data_version().unwrap(), 0);
+        assert_eq!(open(&mock, "laptop").data_version().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_tenants_are_isolated() {
+        let mock = MockStore::new();
+        let mut first = open_tenant(&mock, "customer-1");
+        let mut second = open_tenant(&mock, "customer-2");
+        first.store("endpoint", "a").unwrap();
+        first.store("token", "b").unwrap();
+        first.migrate_to(2, &Migrations::new()).unwrap();
+        second.store("endpoint", "c").unwrap();
+
+        let mut export = Vec::new();
+        assert_eq!(first.export_to(&mut export).unwrap(), 2);
+
+        assert_eq!(first.clear().unwrap(), 2);
+        assert!(first.keys().unwrap().is_empty());
+        assert_eq!(first.data_version().unwrap(), 2);
+        assert_eq!(second.keys().unwrap(), ["endpoint"]);
+
+        first.destroy().unwrap();
+        assert_eq!(open_tenant(&mock, "customer-1").data_version().unwrap(), 0);
+        assert_eq!(second.keys().unwrap(), ["endpoint"]);
+        assert_eq!(second.data_version().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_watching_a_tenant_reports_only_its_keys() {
+        use crate::watch::{ChangeEvent, ChangeKind, WatchOptions};
+        use std::time::Duration;
+
+        let mock = MockStore::new();
+        let mut first = open_tenant(&mock, "customer-1");
+        let mut second = open_tenant(&mock, "customer-2");
+        let watcher = first
+            .watch_with(WatchOptions {
+                interval: Duration::from_millis(10),
+                ..WatchOptions::default()
+            })
+            .unwrap();
+
+        second.store("endpoint", "a").unwrap();
+        first.store("token", "b").unwrap();
+        assert_eq!(
+            watcher.recv_timeout(Duration::from_secs(5)),
+            Some(ChangeEvent {
+                key: String::from("token"),
+                kind: ChangeKind::Stored,
+            })
+        );
+        assert_eq!(watcher.recv_timeout(Duration::from_millis(100)), None);
+    }
+
+    #[test]
+    fn test_trashed_tenant_can_be_restored_until_purged() {
+        let mock = MockStore::new();
+        let clock = MockClock::default();
+        let mut first = open_tenant(&mock, "customer-1").with_clock(clock.clone());
+        first.store("endpoint", "a").unwrap();
+        first.migrate_to(2, &Migrations::new()).unwrap();
+        open_tenant(&mock, "customer-2")
+            .store("endpoint", "b")
+            .unwrap();
+        first.destroy_to_trash().unwrap();
+
+        let mut first = open_tenant(&mock, "customer-1");
+        assert!(first.keys().unwrap().is_empty());
+        assert_eq!(first.data_version().unwrap(), 0);
+        first.store("endpoint", "c").unwrap();
+        assert!(first.backing_store_mut().restore().is_err());
+        first.remove("endpoint").unwrap();
+        assert!(first.backing_store_mut().restore().unwrap());
+        assert_eq!(first.keys().unwrap(), ["endpoint"]);
+        assert_eq!(first.data_version().unwrap(), 2);
+        assert!(!first.backing_store_mut().restore().unwrap());
+
+        let retention = Duration::from_secs(3600);
+        let mut root = KeyValueStore::<Mock>::with_backing_store(mock.clone())
+            .unwrap()
+            .with_clock(clock.clone());
+        open_tenant(&mock, "customer-1")
+            .with_clock(clock.clone())
+            .destroy_to_trash()
+            .unwrap();
+        clock.advance(retention);
+        assert!(root.purge_trash(retention).unwrap().is_empty());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(root.purge_trash(retention).unwrap(), ["customer-1"]);
+        assert!(!first.backing_store_mut().restore().unwrap());
+        assert_eq!(
+            open_tenant(&mock, "customer-2").keys().unwrap(),
+            ["endpoint"]
+        );
+    }
+
+    #[test]
+    fn test_invalid_tenant_ids_are_rejected() {
+        for tenant in ["", "a:b", "a/b", "__zep_x"] {
+            assert!(matches!(
+                KeyValueStore::<Tenant<Mock>>::for_tenant(tenant),
+                Err(KvsError::SerializationError(_))
+            ));
+        }
+        assert!(KeyValueStore::<Tenant<Mock>>::new().is_err());
+    }
+
+    #[test]
+    fn test_namespace_hostname_keeps_configuration() {
+        let mut plain = KeyValueStore::<scope::Ephemeral>::new().unwrap();
+        plain.store("shared", "value").unwrap();
+        let mut store = plain.with_type_tags().namespace_hostname().unwrap();
+        assert_eq!(store.keys().unwrap(), Vec::<String>::new());
+        store.store("name", "alice").unwrap();
+        assert!(matches!(
+            store.retrieve::<_, u32>("name"),
+            Err(KvsError::TypeMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,318 @@
+//! Buffering changes to a store until they are committed.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::api::{KeyValueStore, Scope, is_reserved};
+use crate::convert::{InBytes, OutBytes};
+use crate::error::KvsError;
+use crate::sensitive::wipe;
+
+/// A view of a store that keeps changes in memory until they are
+/// committed.
+///
+/// Returned by [`KeyValueStore::overlay`]. Reads see the buffered changes
+/// on top of the store's contents. Nothing is written until
+/// [`commit`](Self::commit); dropping the overlay, or calling
+/// [`discard`](Self::discard), throws the changes away.
+///
+/// Buffered values are zeroed when the overlay is committed or dropped.
+pub struct Overlay<'a, S: Scope> {
+    store: &'a mut KeyValueStore<S>,
+    /// Buffered changes: a new value, or `None` for a removed key.
+    changes: BTreeMap<String, Option<Vec<u8>>>,
+}
+
+impl<S: Scope> KeyValueStore<S> {
+    /// Starts buffering changes to the store in memory.
+    ///
+    /// The store can't be used directly until the overlay is committed or
+    /// dropped. Changes made to the store by other processes meanwhile are
+    /// visible through the overlay, except for keys it has changed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+    /// store.store("theme", "dark")?;
+    ///
+    /// // The user changes settings, then clicks Cancel
+    /// let mut dialog = store.overlay();
+    /// dialog.store("theme", "light")?;
+    /// assert_eq!(dialog.retrieve::<_, String>("theme")?.as_deref(), Some("light"));
+    /// dialog.discard();
+    /// assert_eq!(store.retrieve::<_, String>("theme")?.as_deref(), Some("dark"));
+    ///
+    /// // ... and again, then clicks OK
+    /// let mut dialog = store.overlay();
+    /// dialog.store("theme", "light")?;
+    /// dialog.commit()?;
+    /// assert_eq!(store.retrieve::<_, String>("theme")?.as_deref(), Some("light"));
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn overlay(&mut self) -> Overlay<'_, S> {
+        Overlay {
+            store: self,
+            changes: BTreeMap::new(),
+        }
+    }
+}
+
+impl<S: Scope> Overlay<'_, S> {
+    /// Returns all keys, including those stored in the overlay and not
+    /// those removed in it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store's keys cannot be listed.
+    pub fn keys(&self) -> Result<Vec<String>, KvsError> {
+        let mut keys: BTreeSet<String> = self.store.keys()?.into_iter().collect();
+        for (key, value) in &self.changes {
+            match value {
+                Some(_) => keys.insert(key.clone()),
+                None => keys.remove(key),
+            };
+        }
+        Ok(keys.into_iter().collect())
+    }
+
+    /// Returns whether `key` exists, taking buffered changes into account.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be read.
+    pub fn contains_key<K: AsRef<str>>(&self, key: K) -> Result<bool, KvsError> {
+        match self.changes.get(key.as_ref()) {
+            Some(value) => Ok(value.is_some()),
+            None => self.store.contains_key(key),
+        }
+    }
+
+    /// Retrieves a value, as buffered in the overlay or else as stored.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`KeyValueStore::retrieve`].
+    pub fn retrieve<K: AsRef<str>, V: InBytes>(&self, key: K) -> Result<Option<V>, KvsError> {
+        match self.changes.get(key.as_ref()) {
+            Some(Some(bytes)) => self.store.decode(key.as_ref(), bytes.clone()).map(Some),
+            Some(None) => Ok(None),
+            None => self.store.retrieve(key),
+        }
+    }
+
+    /// Buffers storing `value` under `key`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value cannot be serialized, or `ReservedKey`
+    /// if the key starts with [`RESERVED_PREFIX`](crate::api::RESERVED_PREFIX).
+    /// The store's quota and value size limit are checked on commit.
+    pub fn store<K: AsRef<str>, V: OutBytes>(&mut self, key: K, value: V) -> Result<(), KvsError> {
+        let key = checked(key.as_ref())?;
+        let mut bytes = self.store.encode(&key, &value)?;
+        let buffered = bytes.to_vec();
+        if let (Cow::Owned(bytes), true) = (&mut bytes, V::is_sensitive()) {
+            wipe(bytes);
+        }
+        if let Some(Some(mut old)) = self.changes.insert(key, Some(buffered)) {
+            wipe(&mut old);
+        }
+        Ok(())
+    }
+
+    /// Buffers removing `key`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ReservedKey` if the key starts with
+    /// [`RESERVED_PREFIX`](crate::api::RESERVED_PREFIX).
+    pub fn remove<K: AsRef<str>>(&mut self, key: K) -> Result<(), KvsError> {
+        let key = checked(key.as_ref())?;
+        if let Some(Some(mut old)) = self.changes.insert(key, None) {
+            wipe(&mut old);
+        }
+        Ok(())
+    }
+
+    /// Returns whether any changes are buffered.
+    pub fn is_modified(&self) -> bool {
+        !self.changes.is_empty()
+    }
+
+    /// Writes the buffered changes to the store.
+    ///
+    /// The store's quota and value size limit are checked for all changes
+    /// before any is written, so a change that doesn't fit leaves the store
+    /// untouched. Stored values are then written as one batch, followed by
+    /// removals. If writing fails part way through, the values the changes
+    /// replaced are written back.
+    ///
+    /// The backends have no transactions, so the commit is not atomic: other
+    /// readers can see part of the changes while it runs, a change made by
+    /// another writer meanwhile can be overwritten by the rollback, and a
+    /// crash can leave part of the changes applied.
+    ///
+    /// # Errors
+    ///
+    /// Returns `QuotaExceeded` or `ValueTooLarge` if the changes exceed the
+    /// store's limits, or an error if the storage backend fails to write
+    /// them or to read the values they replace.
+    pub fn commit(self) -> Result<(), KvsError> {
+        let changes: Vec<(&str, Option<&[u8]>)> = self
+            .changes
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_deref()))
+            .collect();
+        let mut originals = Vec::with_capacity(changes.len());
+        for (key, _) in &changes {
+            originals.push(self.store.retrieve_raw(key)?);
+        }
+        let result = self.store.apply_raw(&changes);
+        match &result {
+            // Limits are checked before anything is written
+            Ok(()) | Err(KvsError::QuotaExceeded(_) | KvsError::ValueTooLarge { .. }) => {}
+            Err(e) => {
+                let undo: Vec<(&str, Option<&[u8]>)> = changes
+                    .iter()
+                    .zip(&originals)
+                    .map(|(&(key, _), original)| (key, original.as_deref()))
+                    .collect();
+                if let Err(undo_error) = self.store.apply_raw(&undo) {
+                    warn!("failed to roll back overlay after {e}: {undo_error}");
+                }
+            }
+        }
+        for original in originals.iter_mut().flatten() {
+            wipe(original);
+        }
+        result
+    }
+
+    /// Throws the buffered changes away.
+    ///
+    /// Dropping the overlay does the same; this just makes the intent
+    /// explicit.
+    pub fn discard(self) {}
+}
+
+impl<S: Scope> Drop for Overlay<'_, S> {
+    fn drop(&mut self) {
+        for value in self.changes.values_mut().flatten() {
+            wipe(value);
+        }
+    }
+}
+
+/// Returns `key` as an owned key, unless it is reserved.
+fn checked(key: &str) -> Result<String, KvsError> {
+    if is_reserved(key) {
+        return Err(KvsError::ReservedKey(key.to_string()));
+    }
+    Ok(key.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    use crate::api::Quota;
+    use crate::api::scope::Ephemeral;
+    use crate::testing::{Mock, MockStore, Operation};
+
+    #[test]
+    fn test_overlay_reads_its_own_changes() {
+        let mut store = KeyValueStore::<Ephemeral>::new().unwrap();
+        store.store("a", "1").unwrap();
+        store.store("b", "2").unwrap();
+
+        let mut overlay = store.overlay();
+        assert!(!overlay.is_modified());
+        overlay.store("c", "3").unwrap();
+        overlay.remove("a").unwrap();
+        assert!(overlay.is_modified());
+        assert_eq!(overlay.keys().unwrap(), ["b", "c"]);
+        assert!(!overlay.contains_key("a").unwrap());
+        assert_eq!(overlay.retrieve::<_, String>("a").unwrap(), None);
+        assert_eq!(
+            overlay.retrieve::<_, String>("c").unwrap().as_deref(),
+            Some("3")
+        );
+        overlay.discard();
+
+        let mut keys = store.keys().unwrap();
+        keys.sort();
+        assert_eq!(keys, ["a", "b"]);
+    }
+
+    #[test]
+    fn test_commit_applies_changes() {
+        let mut store = KeyValueStore::<Ephemeral>::new().unwrap().with_type_tags();
+        store.store("a", "1").unwrap();
+
+        let mut overlay = store.overlay();
+        overlay.store("a", 2u32).unwrap();
+        overlay.store("b", "new").unwrap();
+        overlay.remove("b").unwrap();
+        overlay.remove("missing").unwrap();
+        overlay.commit().unwrap();
+
+        assert_eq!(store.keys().unwrap(), ["a"]);
+        assert_eq!(store.retrieve::<_, u32>("a").unwrap(), Some(2));
+    }
+
+    #[test]
+    fn test_commit_checks_limits_first() {
+        let quota = Quota {
+            max_keys: Some(2),
+            ..Quota::default()
+        };
+        let mut store = KeyValueStore::<Ephemeral>::new()
+            .unwrap()
+            .with_quota(quota)
+            .unwrap();
+        store.store("a", "1").unwrap();
+
+        let mut overlay = store.overlay();
+        overlay.store("b", "2").unwrap();
+        overlay.store("c", "3").unwrap();
+        assert!(matches!(overlay.commit(), Err(KvsError::QuotaExceeded(_))));
+        assert_eq!(store.keys().unwrap(), ["a"]);
+    }
+
+    #[test]
+    fn test_failed_commit_is_rolled_back() {
+        let mock = MockStore::new();
+        let mut store = KeyValueStore::<Mock>::with_backing_store(mock.clone()).unwrap();
+        store.store("a", "1").unwrap();
+        store.store("b", "2").unwrap();
+
+        let mut overlay = store.overlay();
+        overlay.store("a", "changed").unwrap();
+        overlay.store("c", "new").unwrap();
+        overlay.remove("b").unwrap();
+        mock.fail_nth(Operation::Remove, 1, io::ErrorKind::PermissionDenied);
+        assert!(overlay.commit().is_err());
+
+        let mut keys = store.keys().unwrap();
+        keys.sort();
+        assert_eq!(keys, ["a", "b"]);
+        assert_eq!(
+            store.retrieve::<_, String>("a").unwrap().as_deref(),
+            Some("1")
+        );
+    }
+
+    #[test]
+    fn test_reserved_keys_are_rejected() {
+        let mut store = KeyValueStore::<Ephemeral>::new().unwrap();
+        let mut overlay = store.overlay();
+        assert!(matches!(
+            overlay.store("__zep_format", "1"),
+            Err(KvsError::ReservedKey(_))
+        ));
+    }
+}
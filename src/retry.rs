@@ -0,0 +1,142 @@
+//! Retrying operations that fail transiently.
+//!
+//! On user machines, antivirus scanners, indexing services and backup tools
+//! briefly open files and registry keys, causing one-off failures such as
+//! sharing violations. A [`RetryPolicy`] set with
+//! `KeyValueStore::with_retry` retries such failures with exponential
+//! backoff instead of returning them to the application.
+
+use std::io::ErrorKind;
+use std::thread;
+use std::time::Duration;
+
+use crate::error::KvsError;
+
+/// Windows `ERROR_SHARING_VIOLATION`.
+const ERROR_SHARING_VIOLATION: i32 = 32;
+/// Windows `ERROR_LOCK_VIOLATION`.
+const ERROR_LOCK_VIOLATION: i32 = 33;
+
+/// How often and how patiently to retry transient failures.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use zep_kvs::prelude::*;
+/// use zep_kvs::retry::RetryPolicy;
+///
+/// let policy = RetryPolicy {
+///     max_retries: 5,
+///     ..RetryPolicy::default()
+/// };
+/// let store = KeyValueStore::<scope::Ephemeral>::new()?.with_retry(policy);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The number of retries after the first attempt.
+    pub max_retries: u32,
+    /// The delay before the first retry. Each further retry waits twice as
+    /// long as the previous one.
+    pub initial_backoff: Duration,
+    /// The longest delay between retries.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    /// Three retries, waiting 10, 20 and 40 milliseconds.
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Runs `operation`, retrying it while it fails transiently.
+    pub(crate) fn run<T>(
+        policy: Option<RetryPolicy>,
+        mut operation: impl FnMut() -> Result<T, KvsError>,
+    ) -> Result<T, KvsError> {
+        let Some(policy) = policy else {
+            return operation();
+        };
+        let mut backoff = policy.initial_backoff;
+        for _ in 0..policy.max_retries {
+            match operation() {
+                Err(e) if is_transient(&e) => {
+                    debug!("retrying in {backoff:?} after transient error: {e}");
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(policy.max_backoff);
+                }
+                result => return result,
+            }
+        }
+        operation()
+    }
+}
+
+/// Returns whether an error is likely to go away if the operation is retried.
+fn is_transient(error: &KvsError) -> bool {
+    let KvsError::IoError { source, .. } = error else {
+        return false;
+    };
+    matches!(
+        source.kind(),
+        ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+            | ErrorKind::TimedOut
+            | ErrorKind::ResourceBusy
+    ) || (cfg!(windows)
+        && matches!(
+            source.raw_os_error(),
+            Some(ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION)
+        ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::KeyValueStore;
+    use crate::testing::{Mock, MockStore, Operation};
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 2,
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_transient_failures_are_retried() {
+        let mock = MockStore::new();
+        let mut store = KeyValueStore::<Mock>::with_backing_store(mock.clone())
+            .unwrap()
+            .with_retry(policy());
+        mock.fail_nth(Operation::Store, 1, ErrorKind::Interrupted);
+        mock.fail_nth(Operation::Store, 2, ErrorKind::ResourceBusy);
+        store.store("key", "value").unwrap();
+        assert_eq!(mock.calls(Operation::Store), 3);
+
+        mock.fail_always(Operation::Retrieve, ErrorKind::WouldBlock);
+        let retrieves = mock.calls(Operation::Retrieve);
+        assert!(store.retrieve::<_, String>("key").is_err());
+        assert_eq!(mock.calls(Operation::Retrieve), retrieves + 3);
+    }
+
+    #[test]
+    fn test_permanent_failures_are_not_retried() {
+        let mock = MockStore::new();
+        let store = KeyValueStore::<Mock>::with_backing_store(mock.clone())
+            .unwrap()
+            .with_retry(policy());
+        mock.fail_always(Operation::Keys, ErrorKind::PermissionDenied);
+        let calls = mock.calls(Operation::Keys);
+        assert!(store.keys().is_err());
+        assert_eq!(mock.calls(Operation::Keys), calls + 1);
+    }
+}
@@ -0,0 +1,294 @@
+//! Secret values that are wiped from memory when dropped.
+//!
+//! Retrieving a value normally leaves copies of its bytes in freed heap
+//! memory: the buffer read from the backend, and the value itself once it
+//! is dropped. Retrieving or storing a [`Sensitive`] value instead zeroes
+//! the store's intermediate buffers, and the value's own buffer is zeroed
+//! when it is dropped.
+//!
+//! Copies made by the operating system, such as the page cache, and by
+//! the backend itself, such as an in-memory store's entries, are not
+//! covered.
+//!
+//! [`Redacted`] is a lighter wrapper for values that only need to be kept
+//! out of logs.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::atomic::{Ordering, compiler_fence};
+
+use crate::convert::{InBytes, OutBytes};
+use crate::error::KvsError;
+
+/// Overwrites `buffer` with zeros in a way the compiler won't optimize out.
+pub(crate) fn wipe(buffer: &mut [u8]) {
+    for byte in buffer.iter_mut() {
+        // SAFETY: `byte` is a valid, aligned reference.
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// Types whose contents can be overwritten in place.
+pub trait Wipe {
+    /// Overwrites the value's memory with zeros.
+    ///
+    /// The value may be left empty.
+    fn wipe(&mut self);
+}
+
+impl Wipe for Vec<u8> {
+    /// Zeroes the whole allocation, including spare capacity, and leaves
+    /// the vector empty.
+    fn wipe(&mut self) {
+        self.clear();
+        for byte in self.spare_capacity_mut() {
+            // SAFETY: `byte` is a valid, aligned reference.
+            unsafe { ptr::write_volatile(byte, MaybeUninit::new(0)) };
+        }
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+impl Wipe for String {
+    /// Zeroes the whole allocation and leaves the string empty.
+    fn wipe(&mut self) {
+        // SAFETY: the vector is left empty, which is valid UTF-8.
+        unsafe { self.as_mut_vec() }.wipe();
+    }
+}
+
+/// A secret value that is wiped from memory when dropped.
+///
+/// Stores and retrieves exactly like the wrapped type, so a value stored
+/// as a `&str` can be retrieved as a `Sensitive<String>`, but the store
+/// also zeroes the buffers it used along the way. Its `Debug` output
+/// never shows the value.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::prelude::*;
+/// use zep_kvs::sensitive::Sensitive;
+///
+/// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+/// store.store("api_token", &Sensitive::new(String::from("s3cr3t")))?;
+///
+/// let token: Sensitive<String> = store.retrieve("api_token")?.unwrap();
+/// assert_eq!(token.expose(), "s3cr3t");
+/// assert_eq!(format!("{token:?}"), "Sensitive(..)");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Clone, Default)]
+pub struct Sensitive<T: Wipe>(T);
+
+impl<T: Wipe> Sensitive<T> {
+    /// Wraps a secret value.
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    /// Returns the secret value.
+    ///
+    /// Copies made from the returned reference are not wiped.
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Wipe> From<T> for Sensitive<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T: Wipe> Drop for Sensitive<T> {
+    fn drop(&mut self) {
+        self.0.wipe();
+    }
+}
+
+impl<T: Wipe> fmt::Debug for Sensitive<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Sensitive(..)")
+    }
+}
+
+impl OutBytes for &Sensitive<String> {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        Ok(Cow::Borrowed(self.0.as_bytes()))
+    }
+
+    fn type_tag() -> &'static str {
+        <&str>::type_tag()
+    }
+
+    fn is_sensitive() -> bool {
+        true
+    }
+}
+
+impl OutBytes for &Sensitive<Vec<u8>> {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        Ok(Cow::Borrowed(&self.0))
+    }
+
+    fn type_tag() -> &'static str {
+        <&[u8]>::type_tag()
+    }
+
+    fn is_sensitive() -> bool {
+        true
+    }
+}
+
+impl<T: InBytes + Wipe> InBytes for Sensitive<T> {
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        T::in_bytes(bytes).map(Self)
+    }
+
+    fn type_tag() -> &'static str {
+        T::type_tag()
+    }
+
+    fn is_sensitive() -> bool {
+        true
+    }
+}
+
+/// A value whose `Debug` and `Display` output never shows it.
+///
+/// Stores and retrieves exactly like the wrapped type, so values can be
+/// wrapped at the point they are read without changing what is stored.
+/// Unlike [`Sensitive`], the value is not wiped from memory.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::prelude::*;
+/// use zep_kvs::sensitive::Redacted;
+///
+/// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+/// store.store("email", Redacted::new("alice@example.com"))?;
+///
+/// let email: Redacted<String> = store.retrieve("email")?.unwrap();
+/// assert_eq!(format!("{email:?}"), "Redacted(..)");
+/// assert_eq!(email.to_string(), "[redacted]");
+/// assert_eq!(email.into_inner(), "alice@example.com");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Redacted<T>(T);
+
+impl<T> Redacted<T> {
+    /// Wraps a value.
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    /// Returns the value.
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+
+    /// Unwraps the value.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Redacted<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> fmt::Debug for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Redacted(..)")
+    }
+}
+
+impl<T> fmt::Display for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
+
+impl<T: OutBytes> OutBytes for Redacted<T> {
+    fn out_bytes(&self) -> Result<Cow<'_, [u8]>, KvsError> {
+        self.0.out_bytes()
+    }
+
+    fn type_tag() -> &'static str {
+        T::type_tag()
+    }
+
+    fn is_sensitive() -> bool {
+        T::is_sensitive()
+    }
+}
+
+impl<T: InBytes> InBytes for Redacted<T> {
+    fn in_bytes(bytes: &[u8]) -> Result<Self, KvsError> {
+        T::in_bytes(bytes).map(Self)
+    }
+
+    fn type_tag() -> &'static str {
+        T::type_tag()
+    }
+
+    fn is_sensitive() -> bool {
+        T::is_sensitive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wipe_clears_whole_allocation() {
+        let mut value = b"s3cr3t".to_vec();
+        value.truncate(3);
+        value.wipe();
+        assert!(value.is_empty());
+        // SAFETY: the allocation holds at least 6 initialized bytes.
+        let allocation = unsafe { std::slice::from_raw_parts(value.as_ptr(), 6) };
+        assert_eq!(allocation, [0; 6]);
+    }
+
+    #[test]
+    fn test_sensitive_values_round_trip() {
+        let mut store = crate::api::KeyValueStore::<crate::api::scope::Ephemeral>::new()
+            .unwrap()
+            .with_type_tags();
+        store
+            .store("token", &Sensitive::new(String::from("s3cr3t")))
+            .unwrap();
+        store
+            .store("key", &Sensitive::new(vec![1u8, 2, 3]))
+            .unwrap();
+
+        let token: Sensitive<String> = store.retrieve("token").unwrap().unwrap();
+        assert_eq!(token.expose(), "s3cr3t");
+        assert_eq!(
+            store.retrieve::<_, String>("token").unwrap().unwrap(),
+            "s3cr3t"
+        );
+        let key: Sensitive<Vec<u8>> = store.retrieve("key").unwrap().unwrap();
+        assert_eq!(key.expose(), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn test_debug_output_hides_values() {
+        let mut store = crate::api::KeyValueStore::<crate::api::scope::Ephemeral>::new().unwrap();
+        store.store("password", "hunter2").unwrap();
+        let debug = format!("{store:?}");
+        assert!(debug.contains("EphemeralStore"));
+        assert!(!debug.contains("hunter2"));
+        assert!(!debug.contains("password"));
+    }
+}
@@ -0,0 +1,42 @@
+//! Process-wide shared stores.
+//!
+//! Small applications often need settings in many places. Rather than
+//! threading a store through every function or opening it repeatedly,
+//! they can use the store returned by [`user_store`], which is opened on
+//! first use and shared by all threads.
+
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use crate::api::{KeyValueStore, scope::User};
+use crate::error::KvsError;
+
+/// Returns the process-wide user store, opening it on first use.
+///
+/// The store is locked for as long as the returned guard is held, so keep
+/// it only for the duration of a few operations. Calling `user_store` again
+/// on the same thread while holding the guard deadlocks.
+///
+/// If opening the store fails the error is returned, and the next call
+/// tries again.
+///
+/// # Examples
+///
+/// ```no_run
+/// zep_kvs::user_store()?.store("theme", "dark")?;
+/// let theme: Option<String> = zep_kvs::user_store()?.retrieve("theme")?;
+/// # Ok::<(), zep_kvs::error::KvsError>(())
+/// ```
+pub fn user_store() -> Result<MutexGuard<'static, KeyValueStore<User>>, KvsError> {
+    static STORE: OnceLock<Mutex<KeyValueStore<User>>> = OnceLock::new();
+
+    let store = match STORE.get() {
+        Some(store) => store,
+        None => {
+            // Another thread may open the store concurrently, in which case
+            // only one of the two is kept.
+            let opened = KeyValueStore::<User>::new()?;
+            STORE.get_or_init(|| Mutex::new(opened))
+        }
+    };
+    Ok(store.lock().unwrap_or_else(|e| e.into_inner()))
+}
@@ -0,0 +1,119 @@
+//! Reporting of slow storage operations.
+//!
+//! Stores kept on network home directories can stall for seconds on a
+//! single write, which looks to users like the application hanging.
+//! Operations that take longer than a threshold, 100 ms unless set with
+//! [`KeyValueStore::with_slow_operation_threshold`], are logged as warnings
+//! when the `log` feature is enabled, and passed to the handler set with
+//! [`KeyValueStore::with_slow_operation_handler`].
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(doc)]
+use crate::api::KeyValueStore;
+use crate::error::Operation;
+
+/// The threshold used unless a store sets its own.
+const DEFAULT_THRESHOLD: Duration = Duration::from_millis(100);
+
+/// An operation that took longer than the store's threshold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlowOperation {
+    /// What the store was doing.
+    pub operation: Operation,
+    /// The key operated on, for single-key operations.
+    pub key: Option<String>,
+    /// How long the operation took, including any retries.
+    pub duration: Duration,
+}
+
+type Handler = Arc<dyn Fn(&SlowOperation) + Send + Sync>;
+
+/// A store's slow-operation threshold and handler.
+#[derive(Clone)]
+pub(crate) struct SlowOperations {
+    pub(crate) threshold: Duration,
+    handler: Option<Handler>,
+}
+
+impl SlowOperations {
+    /// Sets the handler called for each slow operation.
+    pub(crate) fn set_handler(&mut self, handler: impl Fn(&SlowOperation) + Send + Sync + 'static) {
+        self.handler = Some(Arc::new(handler));
+    }
+
+    /// Reports `operation` if it took longer than the threshold.
+    pub(crate) fn check(&self, operation: Operation, key: Option<&str>, duration: Duration) {
+        if duration <= self.threshold {
+            return;
+        }
+        match key {
+            Some(key) => warn!("{operation} key {key:?} took {duration:?}"),
+            None => warn!("{operation} took {duration:?}"),
+        }
+        if let Some(handler) = &self.handler {
+            handler(&SlowOperation {
+                operation,
+                key: key.map(str::to_string),
+                duration,
+            });
+        }
+    }
+}
+
+impl Default for SlowOperations {
+    fn default() -> Self {
+        Self {
+            threshold: DEFAULT_THRESHOLD,
+            handler: None,
+        }
+    }
+}
+
+impl fmt::Debug for SlowOperations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SlowOperations")
+            .field("threshold", &self.threshold)
+            .field("handler", &self.handler.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::api::KeyValueStore;
+    use crate::testing::{Mock, MockStore};
+
+    #[test]
+    fn test_operations_over_threshold_are_reported() {
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&reported);
+        let mut store = KeyValueStore::<Mock>::with_backing_store(MockStore::new())
+            .unwrap()
+            .with_slow_operation_threshold(Duration::ZERO)
+            .with_slow_operation_handler(move |slow| sink.lock().unwrap().push(slow.clone()));
+        store.store("theme", "dark").unwrap();
+        store.keys().unwrap();
+
+        let reported = reported.lock().unwrap();
+        assert_eq!(reported.len(), 2);
+        assert_eq!(reported[0].operation, Operation::Store);
+        assert_eq!(reported[0].key.as_deref(), Some("theme"));
+        assert_eq!(reported[1].operation, Operation::Keys);
+        assert_eq!(reported[1].key, None);
+    }
+
+    #[test]
+    fn test_fast_operations_are_not_reported() {
+        let slow = SlowOperations {
+            threshold: Duration::from_secs(1),
+            handler: Some(Arc::new(|_: &SlowOperation| panic!("reported"))),
+        };
+        slow.check(Operation::Store, Some("theme"), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,110 @@
+//! Consistent point-in-time views of a store.
+
+use crate::api::scope::Ephemeral;
+use crate::api::{BackingStore, KeyValueStore, Scope};
+use crate::convert::InBytes;
+use crate::ephemeral::EphemeralStore;
+use crate::error::KvsError;
+
+/// A read-only copy of a store's entries at one point in time.
+///
+/// Returned by [`KeyValueStore::snapshot`], and by `as_of` on journaled
+/// stores. Keys and values never change after the snapshot is taken, so
+/// every key listed by [`keys`](Self::keys) can be retrieved, however the
+/// store is modified meanwhile.
+#[derive(Debug)]
+pub struct Snapshot {
+    store: KeyValueStore<Ephemeral>,
+}
+
+impl Snapshot {
+    /// Wraps an in-memory store holding the snapshot's entries.
+    pub(crate) fn from_store(store: KeyValueStore<Ephemeral>) -> Self {
+        Self { store }
+    }
+
+    /// Returns all keys in the snapshot.
+    pub fn keys(&self) -> Result<Vec<String>, KvsError> {
+        self.store.keys()
+    }
+
+    /// Retrieves the value of a key as it was when the snapshot was taken.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value cannot be converted to the requested
+    /// type.
+    pub fn retrieve<K: AsRef<str>, V: InBytes>(&self, key: K) -> Result<Option<V>, KvsError> {
+        self.store.retrieve(key)
+    }
+}
+
+impl<S: Scope> KeyValueStore<S> {
+    /// Takes a snapshot of the store's entries.
+    ///
+    /// All keys and values are copied into memory, so the snapshot stays
+    /// consistent while this or another process modifies the store. Each
+    /// value is read atomically, but a store modified by another process
+    /// while the snapshot is taken may be captured partway through a series
+    /// of writes. Keys removed while the snapshot is taken are left out.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be read.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+    /// store.store("theme", "dark")?;
+    ///
+    /// let snapshot = store.snapshot()?;
+    /// store.remove("theme")?;
+    /// for key in snapshot.keys()? {
+    ///     let value: String = snapshot.retrieve(&key)?.unwrap();
+    ///     assert_eq!(value, "dark");
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn snapshot(&self) -> Result<Snapshot, KvsError> {
+        let mut copy = EphemeralStore::new();
+        for key in self.keys()? {
+            if let Some(value) = self.retrieve_raw(&key)? {
+                copy.store(&key, &value)?;
+            }
+        }
+        let store = KeyValueStore::with_backing_store(copy)?.with_value_format_of(self);
+        Ok(Snapshot::from_store(store))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_is_unaffected_by_later_writes() {
+        let mut store = KeyValueStore::<Ephemeral>::new().unwrap().with_type_tags();
+        store.store("kept", "old").unwrap();
+        store.store("removed", 1u32).unwrap();
+
+        let snapshot = store.snapshot().unwrap();
+        store.store("kept", "new").unwrap();
+        store.store("added", true).unwrap();
+        store.remove("removed").unwrap();
+
+        assert_eq!(snapshot.keys().unwrap(), ["kept", "removed"]);
+        assert_eq!(
+            snapshot.retrieve::<_, String>("kept").unwrap().as_deref(),
+            Some("old")
+        );
+        assert_eq!(snapshot.retrieve::<_, u32>("removed").unwrap(), Some(1));
+        // Type tags carry over to the snapshot
+        assert!(matches!(
+            snapshot.retrieve::<_, u32>("kept"),
+            Err(KvsError::TypeMismatch { .. })
+        ));
+    }
+}
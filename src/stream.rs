@@ -0,0 +1,277 @@
+//! Streaming stores to and from a single byte stream.
+//!
+//! Entries are written and read one at a time, so stores much larger than
+//! the available memory can be backed up and restored.
+//!
+//! # Format
+//!
+//! All integers are big-endian.
+//!
+//! | Field   | Size       | Contents                                  |
+//! |---------|------------|-------------------------------------------|
+//! | Magic   | 6 bytes    | `ZEPKVS`                                  |
+//! | Version | 2 bytes    | Stream format version, currently `1`      |
+//! | Entries | variable   | Zero or more entries, described below     |
+//! | End     | 4 bytes    | `0xFFFFFFFF`                              |
+//!
+//! Each entry is:
+//!
+//! | Field        | Size            | Contents                         |
+//! |--------------|-----------------|----------------------------------|
+//! | Key length   | 4 bytes         | Length of the key in bytes       |
+//! | Key          | key length      | The key, as UTF-8                |
+//! | Value length | 8 bytes         | Length of the value in bytes     |
+//! | Value        | value length    | The value, as stored             |
+//!
+//! Values are written as stored, including type tags if the store uses
+//! them. The store's internal metadata is not included.
+
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::api::{KeyValueStore, Scope};
+use crate::error::KvsError;
+
+/// Identifies a stream written by [`KeyValueStore::export_to`].
+const MAGIC: &[u8; 6] = b"ZEPKVS";
+/// The current stream format version.
+const VERSION: u16 = 1;
+/// Key length marking the end of the stream.
+const END: u32 = u32::MAX;
+
+/// Progress of an import, reported after each entry.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportProgress {
+    /// The number of entries imported so far.
+    pub entries: usize,
+    /// The number of bytes read from the stream so far.
+    pub bytes: u64,
+}
+
+/// Wraps an error reading or writing a stream.
+pub(crate) fn stream_error(e: io::Error) -> KvsError {
+    KvsError::io_at(e, Path::new("<stream>"))
+}
+
+impl<S: Scope> KeyValueStore<S> {
+    /// Writes all entries to `writer` in the [stream format](self).
+    ///
+    /// Values are read and written one at a time, so memory use does not
+    /// grow with the size of the values. Only the list of keys is held in
+    /// memory. Wrap unbuffered writers, such as files, in a
+    /// [`BufWriter`](std::io::BufWriter).
+    ///
+    /// Returns the number of entries written. Keys removed while exporting
+    /// are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be read or the writer fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+    /// store.store("theme", "dark")?;
+    ///
+    /// let mut backup = Vec::new();
+    /// assert_eq!(store.export_to(&mut backup)?, 1);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn export_to<W: Write>(&self, mut writer: W) -> Result<usize, KvsError> {
+        writer.write_all(MAGIC).map_err(stream_error)?;
+        writer
+            .write_all(&VERSION.to_be_bytes())
+            .map_err(stream_error)?;
+        let mut count = 0;
+        for key in self.keys()? {
+            let Some(value) = self.retrieve_raw(&key)? else {
+                continue;
+            };
+            let write = |writer: &mut W| {
+                writer.write_all(&(key.len() as u32).to_be_bytes())?;
+                writer.write_all(key.as_bytes())?;
+                writer.write_all(&(value.len() as u64).to_be_bytes())?;
+                writer.write_all(&value)
+            };
+            write(&mut writer).map_err(stream_error)?;
+            count += 1;
+        }
+        writer.write_all(&END.to_be_bytes()).map_err(stream_error)?;
+        writer.flush().map_err(stream_error)?;
+        Ok(count)
+    }
+}
+
+impl<S: Scope> KeyValueStore<S> {
+    /// Stores the entries of a stream written by
+    /// [`export_to`](Self::export_to).
+    ///
+    /// Entries are read and stored one at a time, replacing existing values
+    /// for the same keys, and `progress` is called after each one. Keys not
+    /// in the stream are kept.
+    ///
+    /// Returns the number of entries imported.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stream is malformed or truncated, was written
+    /// by a newer version, or if a write is rejected by this store. Entries
+    /// imported before the error remain, so a failed import should be
+    /// retried from the start.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::Ephemeral>::new()?;
+    /// store.store("theme", "dark")?;
+    /// let mut backup = Vec::new();
+    /// store.export_to(&mut backup)?;
+    ///
+    /// let mut restored = KeyValueStore::<scope::Ephemeral>::new()?;
+    /// restored.import_from(backup.as_slice(), |progress| {
+    ///     println!("{} entries restored", progress.entries);
+    /// })?;
+    /// assert_eq!(restored.retrieve("theme")?, Some(String::from("dark")));
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn import_from<R: Read>(
+        &mut self,
+        reader: R,
+        mut progress: impl FnMut(ImportProgress),
+    ) -> Result<usize, KvsError> {
+        let mut reader = Counting {
+            inner: reader,
+            bytes: 0,
+        };
+        let mut header = [0; 8];
+        reader.read_exact(&mut header).map_err(stream_error)?;
+        if &header[..6] != MAGIC {
+            return Err(KvsError::SerializationError(
+                "not a zep-kvs stream".to_string(),
+            ));
+        }
+        let version = u16::from_be_bytes([header[6], header[7]]);
+        if version > VERSION {
+            return Err(KvsError::UnsupportedFormat {
+                found: version.into(),
+                supported: VERSION.into(),
+            });
+        }
+
+        let mut entries = 0;
+        loop {
+            let mut len = [0; 4];
+            reader.read_exact(&mut len).map_err(stream_error)?;
+            let key_len = u32::from_be_bytes(len);
+            if key_len == END {
+                break;
+            }
+            let key = String::from_utf8(read_exact(&mut reader, key_len.into())?)?;
+            let mut len = [0; 8];
+            reader.read_exact(&mut len).map_err(stream_error)?;
+            let value = read_exact(&mut reader, u64::from_be_bytes(len))?;
+
+            self.store_raw(&key, &value)?;
+            entries += 1;
+            progress(ImportProgress {
+                entries,
+                bytes: reader.bytes,
+            });
+        }
+        Ok(entries)
+    }
+}
+
+/// Reads exactly `len` bytes, without trusting `len` for the allocation.
+pub(crate) fn read_exact(reader: &mut impl Read, len: u64) -> Result<Vec<u8>, KvsError> {
+    let mut bytes = Vec::new();
+    reader
+        .take(len)
+        .read_to_end(&mut bytes)
+        .map_err(stream_error)?;
+    if bytes.len() as u64 != len {
+        return Err(stream_error(io::ErrorKind::UnexpectedEof.into()));
+    }
+    Ok(bytes)
+}
+
+/// A reader counting the bytes read through it.
+struct Counting<R> {
+    inner: R,
+    bytes: u64,
+}
+
+impl<R: Read> Read for Counting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.bytes += read as u64;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::scope::Ephemeral;
+
+    #[test]
+    fn test_export_format() {
+        let mut store = KeyValueStore::<Ephemeral>::new().unwrap();
+        store.store("ab", [1u8, 2, 3].as_slice()).unwrap();
+
+        let mut out = Vec::new();
+        assert_eq!(store.export_to(&mut out).unwrap(), 1);
+        let mut expected = b"ZEPKVS\0\x01".to_vec();
+        expected.extend_from_slice(b"\0\0\0\x02ab");
+        expected.extend_from_slice(b"\0\0\0\0\0\0\0\x03\x01\x02\x03");
+        expected.extend_from_slice(b"\xff\xff\xff\xff");
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_import_round_trip() {
+        let mut source = KeyValueStore::<Ephemeral>::new().unwrap().with_type_tags();
+        source.store("name", "alice").unwrap();
+        source.store("count", 42u32).unwrap();
+        let mut stream = Vec::new();
+        source.export_to(&mut stream).unwrap();
+
+        let mut target = KeyValueStore::<Ephemeral>::new().unwrap().with_type_tags();
+        target.store("other", true).unwrap();
+        let mut reports = Vec::new();
+        let imported = target
+            .import_from(stream.as_slice(), |p| reports.push(p))
+            .unwrap();
+
+        assert_eq!(imported, 2);
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[1].bytes, stream.len() as u64 - 4);
+        assert_eq!(target.keys().unwrap(), ["count", "name", "other"]);
+        assert_eq!(target.retrieve::<_, u32>("count").unwrap(), Some(42));
+    }
+
+    #[test]
+    fn test_import_rejects_bad_streams() {
+        let mut store = KeyValueStore::<Ephemeral>::new().unwrap();
+        assert!(matches!(
+            store.import_from(b"NOTKVS\0\x01".as_slice(), |_| {}),
+            Err(KvsError::SerializationError(_))
+        ));
+        assert!(matches!(
+            store.import_from(b"ZEPKVS\0\x02".as_slice(), |_| {}),
+            Err(KvsError::UnsupportedFormat { found: 2, .. })
+        ));
+        // Truncated in the middle of a value
+        let truncated = b"ZEPKVS\0\x01\0\0\0\x01k\0\0\0\0\0\0\0\x05ab";
+        assert!(matches!(
+            store.import_from(truncated.as_slice(), |_| {}),
+            Err(KvsError::IoError { .. })
+        ));
+        assert!(store.keys().unwrap().is_empty());
+    }
+}
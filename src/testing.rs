@@ -0,0 +1,463 @@
+//! Test utilities for applications using the key-value store.
+//!
+//! This module provides a [`MockStore`] backing store that keeps data in
+//! memory and can be scripted to fail specific operations, so applications
+//! can exercise their error handling through the real `KeyValueStore` API.
+//!
+//! It also provides [`TestScopeGuard`], which redirects the persistent
+//! `User`, `Machine` and `Session` scopes to a throwaway location so tests
+//! of code that uses them do not touch real user data or collide with each
+//! other, and
+//! [`MockClock`], a manually advanced [`Clock`] for testing time-dependent
+//! behavior deterministically.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::io::ErrorKind;
+//! use zep_kvs::prelude::*;
+//! use zep_kvs::testing::{Mock, MockStore, Operation};
+//!
+//! let mock = MockStore::new();
+//! let mut store = KeyValueStore::<Mock>::with_backing_store(mock.clone())?;
+//!
+//! // The third store() from now fails as if the disk were full
+//! mock.fail_nth(Operation::Store, 3, ErrorKind::StorageFull);
+//! store.store("a", "1")?;
+//! store.store("b", "2")?;
+//! assert!(store.store("c", "3").is_err());
+//! store.store("d", "4")?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+use rand::random;
+
+use crate::api::{BackingStore, Scope, is_reserved};
+use crate::clock::Clock;
+use crate::dynamic::DynBackingStore;
+use crate::error::KvsError;
+
+/// Scope backed by a [`MockStore`].
+///
+/// `KeyValueStore::<Mock>::new()` creates a store with a fresh mock that
+/// never fails. Use `KeyValueStore::with_backing_store` with a clone of a
+/// scripted `MockStore` to inject failures.
+pub struct Mock();
+
+impl Scope for Mock {
+    type Store = MockStore;
+
+    fn new() -> Result<Self::Store, KvsError> {
+        Ok(MockStore::new())
+    }
+}
+
+/// A backing store operation that can be scripted to fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    /// Listing keys with `keys()`.
+    Keys,
+    /// Writing a value with `store()`.
+    Store,
+    /// Reading a value with `retrieve()`.
+    Retrieve,
+    /// Deleting a value with `remove()`.
+    Remove,
+}
+
+/// A scripted failure.
+struct Failure {
+    operation: Operation,
+    /// The call index that fails, or `None` to fail every call.
+    call: Option<usize>,
+    kind: io::ErrorKind,
+}
+
+#[derive(Default)]
+struct MockState {
+    data: HashMap<String, Vec<u8>>,
+    calls: HashMap<Operation, usize>,
+    failures: Vec<Failure>,
+    free_space: Option<u64>,
+    /// Whether calls on reserved keys are counted and failed too.
+    reserved_calls: bool,
+}
+
+/// In-memory backing store with scriptable failures.
+///
+/// Clones share the same data and script, so a clone can be handed to a
+/// `KeyValueStore` while the original is kept to script failures and
+/// inspect call counts.
+///
+/// Calls on keys in the reserved namespace, which hold the store's own
+/// bookkeeping, are neither counted nor failed, so scripts only need to
+/// account for the application's own calls, unless
+/// [`script_reserved_keys`](Self::script_reserved_keys) is called.
+#[derive(Clone, Default)]
+pub struct MockStore {
+    state: Arc<Mutex<MockState>>,
+}
+
+/// Shows the number of entries, never their values.
+impl fmt::Debug for MockStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockStore")
+            .field("entries", &self.lock().data.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl MockStore {
+    /// Creates an empty mock store that never fails.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes the `n`th call of `operation` after this one fail with `kind`.
+    ///
+    /// Calls are counted from 1, so `fail_nth(Operation::Store, 1, ..)`
+    /// fails the very next `store()`.
+    pub fn fail_nth(&self, operation: Operation, n: usize, kind: io::ErrorKind) -> &Self {
+        let mut state = self.lock();
+        let call = state.calls.get(&operation).copied().unwrap_or(0) + n;
+        state.failures.push(Failure {
+            operation,
+            call: Some(call),
+            kind,
+        });
+        self
+    }
+
+    /// Makes every subsequent call of `operation` fail with `kind`.
+    pub fn fail_always(&self, operation: Operation, kind: io::ErrorKind) -> &Self {
+        self.lock().failures.push(Failure {
+            operation,
+            call: None,
+            kind,
+        });
+        self
+    }
+
+    /// Removes all scripted failures.
+    pub fn clear_failures(&self) -> &Self {
+        self.lock().failures.clear();
+        self
+    }
+
+    /// Counts and fails calls on reserved keys like any other, to test how
+    /// the store's own bookkeeping copes with failures.
+    pub fn script_reserved_keys(&self) -> &Self {
+        self.lock().reserved_calls = true;
+        self
+    }
+
+    /// Sets the free space the store reports, which is unknown by default.
+    pub fn set_free_space(&self, bytes: Option<u64>) -> &Self {
+        self.lock().free_space = bytes;
+        self
+    }
+
+    /// Returns how many times `operation` has been called.
+    pub fn calls(&self, operation: Operation) -> usize {
+        self.lock().calls.get(&operation).copied().unwrap_or(0)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Counts a call of `operation` on `key` and returns its scripted
+    /// failure, if any.
+    fn call(
+        &self,
+        operation: Operation,
+        key: Option<&str>,
+    ) -> Result<MutexGuard<'_, MockState>, KvsError> {
+        let mut state = self.lock();
+        if !state.reserved_calls && key.is_some_and(is_reserved) {
+            return Ok(state);
+        }
+        let calls = state.calls.entry(operation).or_insert(0);
+        *calls += 1;
+        let call = *calls;
+        let failure = state
+            .failures
+            .iter()
+            .find(|f| f.operation == operation && f.call.is_none_or(|c| c == call));
+        match failure {
+            Some(failure) => Err(KvsError::io_at(
+                io::Error::from(failure.kind),
+                Path::new("mock"),
+            )),
+            None => Ok(state),
+        }
+    }
+}
+
+impl BackingStore for MockStore {
+    fn keys(&self) -> Result<Vec<String>, KvsError> {
+        Ok(self
+            .call(Operation::Keys, None)?
+            .data
+            .keys()
+            .cloned()
+            .collect())
+    }
+
+    fn store(&mut self, key: &str, value: &[u8]) -> Result<(), KvsError> {
+        self.call(Operation::Store, Some(key))?
+            .data
+            .insert(String::from(key), Vec::from(value));
+        Ok(())
+    }
+
+    fn retrieve(&self, key: &str) -> Result<Option<Vec<u8>>, KvsError> {
+        Ok(self
+            .call(Operation::Retrieve, Some(key))?
+            .data
+            .get(key)
+            .cloned())
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), KvsError> {
+        self.call(Operation::Remove, Some(key))?.data.remove(key);
+        Ok(())
+    }
+
+    fn free_space(&self) -> Result<Option<u64>, KvsError> {
+        Ok(self.lock().free_space)
+    }
+
+    fn reopen(&self) -> Result<Option<DynBackingStore>, KvsError> {
+        Ok(Some(Box::new(self.clone())))
+    }
+}
+
+/// A clock that only moves when told to.
+///
+/// Clones share the same time, so a clone can be handed to the code under
+/// test while the original is kept to advance it.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+/// use zep_kvs::clock::Clock;
+/// use zep_kvs::testing::MockClock;
+///
+/// let clock = MockClock::new(SystemTime::UNIX_EPOCH);
+/// clock.advance(Duration::from_secs(60));
+/// assert_eq!(clock.now(), SystemTime::UNIX_EPOCH + Duration::from_secs(60));
+/// ```
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Arc<Mutex<SystemTime>>,
+}
+
+impl MockClock {
+    /// Creates a clock stopped at `now`.
+    pub fn new(now: SystemTime) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    /// Moves the clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        *self.lock() += duration;
+    }
+
+    /// Sets the clock to `now`, which may be in its past.
+    pub fn set(&self, now: SystemTime) {
+        *self.lock() = now;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SystemTime> {
+        self.now.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for MockClock {
+    /// Creates a clock stopped at the Unix epoch.
+    fn default() -> Self {
+        Self::new(SystemTime::UNIX_EPOCH)
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.lock()
+    }
+}
+
+thread_local! {
+    /// Root that persistent scopes are redirected to on this thread.
+    static REDIRECT: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+/// Returns the location the named persistent scope is redirected to on the
+/// current thread, if a [`TestScopeGuard`] is active.
+#[cfg_attr(feature = "ephemeral-scopes", allow(dead_code))]
+pub(crate) fn redirected(scope: &str) -> Option<PathBuf> {
+    REDIRECT.with_borrow(|root| root.as_ref().map(|root| root.join(scope)))
+}
+
+/// Redirects the `User`, `Machine` and `Session` scopes to an isolated
+/// location.
+///
+/// While the guard is alive, stores created on the current thread with
+/// `KeyValueStore::<User>::new()`, `KeyValueStore::<Machine>::new()` or
+/// `KeyValueStore::<Session>::new()` use a
+/// fresh, uniquely named location instead of the real platform one: a
+/// directory under the system temp directory on Linux and macOS, or a
+/// subkey of `HKEY_CURRENT_USER\Software` on Windows. Dropping the guard
+/// removes that location and restores the previous redirection.
+///
+/// The redirection is per thread, so tests running in parallel each get
+/// their own location. Stores opened on other threads are not redirected.
+///
+/// # Examples
+///
+/// ```
+/// use zep_kvs::prelude::*;
+/// use zep_kvs::testing::TestScopeGuard;
+///
+/// let _guard = TestScopeGuard::new();
+/// let mut store = KeyValueStore::<scope::User>::new()?;
+/// store.store("greeting", "hello")?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct TestScopeGuard {
+    root: PathBuf,
+    previous: Option<PathBuf>,
+}
+
+impl TestScopeGuard {
+    /// Starts redirecting persistent scopes on the current thread.
+    pub fn new() -> Self {
+        let name = format!("{}-test-{}", env!("CARGO_PKG_NAME"), random::<u64>());
+        #[cfg(windows)]
+        let root = PathBuf::from("Software").join(name);
+        #[cfg(not(windows))]
+        let root = std::env::temp_dir().join(name);
+        let previous = REDIRECT.replace(Some(root.clone()));
+        Self { root, previous }
+    }
+
+    /// Returns the location persistent scopes are redirected to.
+    ///
+    /// On Windows this is a registry path relative to `HKEY_CURRENT_USER`.
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+}
+
+impl Default for TestScopeGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for TestScopeGuard {
+    fn drop(&mut self) {
+        REDIRECT.set(self.previous.take());
+        // Cleanup is best effort; the location may never have been created
+        #[cfg(windows)]
+        let _ =
+            winreg::RegKey::predef(winreg::enums::HKEY_CURRENT_USER).delete_subkey_all(&self.root);
+        #[cfg(not(windows))]
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::KeyValueStore;
+
+    #[test]
+    fn test_fail_nth_fails_only_that_call() {
+        let mock = MockStore::new();
+        let mut store = KeyValueStore::<Mock>::with_backing_store(mock.clone()).unwrap();
+        mock.fail_nth(Operation::Store, 2, io::ErrorKind::StorageFull);
+
+        store.store("a", "1").unwrap();
+        match store.store("b", "2") {
+            Err(KvsError::StorageFull {
+                source: Some(source),
+                ..
+            }) => assert_eq!(source.kind(), io::ErrorKind::StorageFull),
+            _ => panic!("Expected StorageFull"),
+        }
+        store.store("c", "3").unwrap();
+
+        assert_eq!(store.retrieve::<_, String>("b").unwrap(), None);
+        assert_eq!(store.keys().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_fail_always_until_cleared() {
+        let mock = MockStore::new();
+        let mut store = KeyValueStore::<Mock>::with_backing_store(mock.clone()).unwrap();
+        store.store("key", "value").unwrap();
+        mock.fail_always(Operation::Retrieve, io::ErrorKind::PermissionDenied);
+
+        assert!(store.retrieve::<_, String>("key").is_err());
+        assert!(store.retrieve::<_, String>("key").is_err());
+        mock.clear_failures();
+        assert_eq!(store.retrieve("key").unwrap(), Some(String::from("value")));
+    }
+
+    #[test]
+    fn test_calls_are_counted() {
+        let mock = MockStore::new();
+        let mut store = KeyValueStore::<Mock>::with_backing_store(mock.clone()).unwrap();
+        let removes = mock.calls(Operation::Remove);
+        store.remove("missing").unwrap();
+        store.remove("missing").unwrap();
+        assert_eq!(mock.calls(Operation::Remove), removes + 2);
+    }
+
+    #[test]
+    fn test_mock_clock_clones_share_time() {
+        let clock = MockClock::default();
+        let shared = clock.clone();
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(
+            shared.now(),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(5)
+        );
+        shared.set(SystemTime::UNIX_EPOCH);
+        assert_eq!(clock.now(), SystemTime::UNIX_EPOCH);
+    }
+
+    #[test]
+    fn test_scope_guard_isolates_and_cleans_up() {
+        let guard = TestScopeGuard::new();
+        let root = guard.path().to_path_buf();
+        {
+            let mut store = KeyValueStore::<crate::api::scope::User>::new().unwrap();
+            assert!(store.keys().unwrap().is_empty());
+            store.store("key", "value").unwrap();
+        }
+        {
+            let inner = TestScopeGuard::new();
+            let store = KeyValueStore::<crate::api::scope::User>::new().unwrap();
+            assert_ne!(inner.path(), root);
+            assert_eq!(store.retrieve::<_, String>("key").unwrap(), None);
+        }
+        assert_eq!(redirected("user"), Some(root.join("user")));
+        drop(guard);
+        assert_eq!(redirected("user"), None);
+        #[cfg(not(windows))]
+        assert!(!root.exists());
+    }
+}
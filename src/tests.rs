@@ -5,6 +5,8 @@
 
 #[cfg(test)]
 use crate::prelude::*;
+#[cfg(test)]
+use crate::testing::TestScopeGuard;
 
 /// Test basic string storage and retrieval functionality.
 ///
@@ -50,6 +52,7 @@ fn can_retrieve_keys() {
 /// correctly. Also tests that non-existent keys return None.
 #[test]
 fn can_store_user_scope() {
+    let _guard = TestScopeGuard::new();
     let mut user = KeyValueStore::<scope::User>::new().unwrap();
     user.store("foo", "bar").unwrap();
     assert!(user.keys().unwrap().contains(&String::from("foo")));
@@ -317,23 +320,42 @@ fn can_store_and_retrieve_primitive_types() {
     );
 
     // Test floating-point values
-    store.store("f32_val", 3.14159f32).unwrap();
-    store.store("f64_val", 2.718281828459045f64).unwrap();
+    store.store("f32_val", std::f32::consts::PI).unwrap();
+    store.store("f64_val", std::f64::consts::E).unwrap();
 
     assert_eq!(
         store.retrieve::<&str, f32>("f32_val").unwrap(),
-        Some(3.14159f32)
+        Some(std::f32::consts::PI)
     );
     assert_eq!(
         store.retrieve::<&str, f64>("f64_val").unwrap(),
-        Some(2.718281828459045f64)
+        Some(std::f64::consts::E)
+    );
+}
+
+/// Verifies that the session scope is kept apart from the user scope.
+#[test]
+#[cfg(not(feature = "ephemeral-scopes"))]
+fn session_scope_is_separate_from_user_scope() {
+    let _guard = TestScopeGuard::new();
+    let mut session = KeyValueStore::<scope::Session>::new().unwrap();
+    session.store("window", "100,200").unwrap();
+    let user = KeyValueStore::<scope::User>::new().unwrap();
+    assert_eq!(user.retrieve::<_, String>("window").unwrap(), None);
+    assert_eq!(user.keys().unwrap(), Vec::<String>::new());
+    let session = KeyValueStore::<scope::Session>::new().unwrap();
+    assert_eq!(
+        session.retrieve("window").unwrap(),
+        Some(String::from("100,200"))
     );
 }
 
 /// Verifies that data stored in user scope persists when the store
 /// is dropped and recreated, confirming backing store persistence.
 #[test]
+#[cfg(not(feature = "ephemeral-scopes"))]
 fn user_scope_persists_across_instances() {
+    let _guard = TestScopeGuard::new();
     let test_key = "user_persistence_test";
     let test_value = "persistent_data";
 
@@ -359,16 +381,304 @@ fn user_scope_persists_across_instances() {
     }
 }
 
+/// Verifies that keys can be renamed on the persistent backend, replacing
+/// any existing value under the new key.
+#[test]
+fn user_scope_renames_keys() {
+    let _guard = TestScopeGuard::new();
+    let mut store = KeyValueStore::<scope::User>::new().unwrap();
+    store.store("old_name", "value").unwrap();
+    store.store("new_name", "replaced").unwrap();
+
+    assert!(store.rename("old_name", "new_name").unwrap());
+    assert_eq!(store.retrieve::<_, String>("old_name").unwrap(), None);
+    assert_eq!(
+        store.retrieve("new_name").unwrap(),
+        Some(String::from("value"))
+    );
+    assert!(!store.rename("old_name", "other").unwrap());
+    assert_eq!(store.keys().unwrap(), ["new_name"]);
+}
+
+/// Verifies that keys can be securely removed on the persistent backend.
+#[test]
+fn user_scope_removes_keys_securely() {
+    let _guard = TestScopeGuard::new();
+    let mut store = KeyValueStore::<scope::User>::new().unwrap();
+    store.store("api_token", "s3cr3t").unwrap();
+    store.store("other", "value").unwrap();
+
+    store.remove_secure("api_token").unwrap();
+    assert_eq!(store.retrieve::<_, String>("api_token").unwrap(), None);
+    assert_eq!(store.keys().unwrap(), ["other"]);
+    assert!(store.remove_secure("__zep_format").is_err());
+}
+
+#[test]
+fn user_scope_reports_found_missing_and_failed_keys() {
+    let _guard = TestScopeGuard::new();
+    let mut store = KeyValueStore::<scope::User>::new().unwrap();
+    store.store("width", 800u32).unwrap();
+    store.store("height", 600u32).unwrap();
+    store.store("title", "a").unwrap();
+
+    let sizes = store
+        .retrieve_many::<_, u32, _>(["width", "height", "title", "depth"])
+        .unwrap();
+    assert_eq!(sizes.found.len(), 2);
+    assert_eq!(sizes.found["width"], 800);
+    assert_eq!(sizes.missing, ["depth"]);
+    assert_eq!(sizes.failed.keys().collect::<Vec<_>>(), ["title"]);
+    assert!(!sizes.is_complete());
+    assert!(
+        store
+            .retrieve_many::<_, u32, _>(["width"])
+            .unwrap()
+            .is_complete()
+    );
+}
+
+#[test]
+fn user_scope_checks_many_keys_at_once() {
+    let _guard = TestScopeGuard::new();
+    let mut store = KeyValueStore::<scope::User>::new().unwrap();
+    for i in (0..100u32).step_by(2) {
+        store.store(format!("item_{i}"), i).unwrap();
+    }
+
+    // Small batches check each key, large ones list the store
+    assert_eq!(
+        store
+            .contains_many(["item_0", "item_1", "__zep_format"])
+            .unwrap(),
+        [true, false, false]
+    );
+    let keys: Vec<String> = (0..100).map(|i| format!("item_{i}")).collect();
+    let found = store.contains_many(&keys).unwrap();
+    assert_eq!(found, (0..100).map(|i| i % 2 == 0).collect::<Vec<_>>());
+}
+
+/// Verifies that only the first of several stores sharing the persistent
+/// backend initializes a key.
+#[test]
+#[cfg(not(feature = "ephemeral-scopes"))]
+fn user_scope_store_if_absent_keeps_first_value() {
+    let _guard = TestScopeGuard::new();
+    let mut first = KeyValueStore::<scope::User>::new().unwrap();
+    let mut second = KeyValueStore::<scope::User>::new().unwrap();
+    assert!(first.store_if_absent("installation_id", "first").unwrap());
+    assert!(!second.store_if_absent("installation_id", "second").unwrap());
+    assert_eq!(
+        second
+            .retrieve::<_, String>("installation_id")
+            .unwrap()
+            .as_deref(),
+        Some("first")
+    );
+    assert!(second.store_if_absent("other", "second").unwrap());
+    assert!(second.store_if_absent("__zep_format", "second").is_err());
+}
+
+/// Verifies that a store holding a stale value can't remove the value
+/// another store sharing the persistent backend replaced it with.
+#[test]
+#[cfg(not(feature = "ephemeral-scopes"))]
+fn user_scope_remove_if_keeps_replaced_value() {
+    let _guard = TestScopeGuard::new();
+    let mut stale = KeyValueStore::<scope::User>::new().unwrap();
+    let mut fresh = KeyValueStore::<scope::User>::new().unwrap();
+    stale.store("token", "expired").unwrap();
+    fresh.store("token", "refreshed").unwrap();
+    assert!(!stale.remove_if("token", "expired").unwrap());
+    assert_eq!(
+        stale.retrieve::<_, String>("token").unwrap().as_deref(),
+        Some("refreshed")
+    );
+    assert!(stale.remove_if("token", "refreshed").unwrap());
+    assert!(!stale.contains_key("token").unwrap());
+    assert!(!stale.remove_if("token", "refreshed").unwrap());
+}
+
+/// Verifies that a health check of the persistent backend reports its
+/// directory and free space, and leaves no probe behind.
+#[test]
+#[cfg(not(feature = "ephemeral-scopes"))]
+fn user_scope_health_check_reports_location() {
+    let _guard = TestScopeGuard::new();
+    let mut store = KeyValueStore::<scope::User>::new().unwrap();
+    let report = store.health_check();
+    assert!(report.is_healthy(), "{report:?}");
+    assert!(report.location.is_some_and(|path| path.is_dir()));
+    assert!(report.free_space.is_some_and(|free| free > 0));
+    assert!(store.keys().unwrap().is_empty());
+}
+
+/// Verifies that bulk operations on the persistent backend store and
+/// retrieve every entry.
+#[test]
+#[cfg(feature = "rayon")]
+fn user_scope_stores_and_retrieves_many_keys_in_parallel() {
+    let _guard = TestScopeGuard::new();
+    let mut store = KeyValueStore::<scope::User>::new().unwrap();
+    store
+        .par_store_many((0..200u32).map(|i| (format!("key_{i}"), i)))
+        .unwrap();
+    assert_eq!(store.keys().unwrap().len(), 200);
+
+    let keys: Vec<String> = (0..201).map(|i| format!("key_{i}")).collect();
+    let values: Vec<Option<u32>> = store.par_retrieve_many(&keys).unwrap();
+    assert_eq!(values[..200], (0..200).map(Some).collect::<Vec<_>>());
+    assert_eq!(values[200], None);
+}
+
+/// Verifies that a watcher on the persistent backend reports changes made
+/// through another instance.
+#[test]
+#[cfg(not(feature = "ephemeral-scopes"))]
+fn user_scope_reports_changes_to_watchers() {
+    use crate::watch::{ChangeEvent, ChangeKind, WatchOptions};
+    use std::time::Duration;
+
+    let _guard = TestScopeGuard::new();
+    let mut store = KeyValueStore::<scope::User>::new().unwrap();
+    let watcher = store
+        .watch_with(WatchOptions {
+            interval: Duration::from_millis(10),
+            ..WatchOptions::default()
+        })
+        .unwrap();
+    store.store("theme", "dark").unwrap();
+    assert_eq!(
+        watcher.recv_timeout(Duration::from_secs(5)),
+        Some(ChangeEvent {
+            key: String::from("theme"),
+            kind: ChangeKind::Stored,
+        })
+    );
+}
+
+/// Verifies that stores signal watchers of other instances, so changes are
+/// reported long before the next poll.
+#[test]
+#[cfg(not(feature = "ephemeral-scopes"))]
+fn user_scope_notifies_watchers_of_changes() {
+    use crate::watch::{ChangeEvent, ChangeKind, WatchOptions};
+    use std::time::Duration;
+
+    let _guard = TestScopeGuard::new();
+    let mut store = KeyValueStore::<scope::User>::new().unwrap();
+    let watcher = store
+        .watch_with(WatchOptions {
+            interval: Duration::from_secs(3600),
+            ..WatchOptions::default()
+        })
+        .unwrap();
+    store.store("theme", "dark").unwrap();
+    assert_eq!(
+        watcher.recv_timeout(Duration::from_secs(5)),
+        Some(ChangeEvent {
+            key: String::from("theme"),
+            kind: ChangeKind::Stored,
+        })
+    );
+    store.remove("theme").unwrap();
+    assert_eq!(
+        watcher.recv_timeout(Duration::from_secs(5)),
+        Some(ChangeEvent {
+            key: String::from("theme"),
+            kind: ChangeKind::Removed,
+        })
+    );
+}
+
+/// Verifies that switching a directory store to the sharded layout keeps
+/// existing keys and that the layout is used when the store is reopened.
+#[test]
+#[cfg(all(
+    any(target_os = "linux", target_os = "macos"),
+    not(feature = "ephemeral-scopes")
+))]
+fn user_scope_migrates_to_sharded_layout() {
+    let _guard = TestScopeGuard::new();
+    let mut store = KeyValueStore::<scope::User>::new().unwrap();
+    for i in 0..20 {
+        store.store(format!("key{i}"), i as u32).unwrap();
+    }
+    store.use_sharded_layout().unwrap();
+    store.use_sharded_layout().unwrap();
+    store.store("key20", 20u32).unwrap();
+    assert!(store.rename("key0", "renamed").unwrap());
+    store.remove("key1").unwrap();
+
+    let store = KeyValueStore::<scope::User>::new().unwrap();
+    let mut keys = store.keys().unwrap();
+    keys.sort();
+    assert_eq!(keys.len(), 20);
+    assert_eq!(keys.last().map(String::as_str), Some("renamed"));
+    assert_eq!(store.retrieve("renamed").unwrap(), Some(0u32));
+    assert_eq!(store.retrieve("key20").unwrap(), Some(20u32));
+    assert_eq!(store.retrieve::<_, u32>("key1").unwrap(), None);
+    assert!(store.modified("key5").unwrap().is_some());
+}
+
+/// Verifies that keys named like the directory store's internal files are
+/// kept apart from them in both layouts.
+#[test]
+#[cfg(all(not(target_os = "windows"), not(feature = "ephemeral-scopes")))]
+fn user_scope_keeps_keys_named_like_internal_files() {
+    let _guard = TestScopeGuard::new();
+    let mut store = KeyValueStore::<scope::User>::new().unwrap();
+    let names = [".sharded", ".watchers", ".tmp_1", ".key_x", "x"];
+    for name in names {
+        store.store(name, name).unwrap();
+    }
+
+    let mut store = KeyValueStore::<scope::User>::new().unwrap();
+    let mut keys = store.keys().unwrap();
+    keys.sort();
+    assert_eq!(keys, [".key_x", ".sharded", ".tmp_1", ".watchers", "x"]);
+    store.use_sharded_layout().unwrap();
+    for name in names {
+        assert_eq!(
+            store.retrieve::<_, String>(name).unwrap().as_deref(),
+            Some(name)
+        );
+    }
+    assert_eq!(store.keys().unwrap().len(), names.len());
+}
+
+/// Verifies that the persistent backend reports modification times and
+/// that touching a key updates them without changing the value.
+#[test]
+#[cfg(any(not(target_os = "windows"), feature = "ephemeral-scopes"))]
+fn user_scope_tracks_modification_times() {
+    use std::time::{Duration, SystemTime};
+
+    let _guard = TestScopeGuard::new();
+    let mut store = KeyValueStore::<scope::User>::new().unwrap();
+    store.store("key", "value").unwrap();
+    assert!(store.modified("key").unwrap().is_some());
+    assert_eq!(store.modified("missing").unwrap(), None);
+
+    let past = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    let mut store = store.with_clock(crate::testing::MockClock::new(past));
+    assert!(store.touch("key").unwrap());
+    assert_eq!(store.modified("key").unwrap(), Some(past));
+    assert_eq!(store.retrieve("key").unwrap(), Some(String::from("value")));
+    assert!(!store.touch("missing").unwrap());
+}
+
 /// Verifies that user scope can handle all primitive types
 #[test]
 fn user_scope_handles_primitive_types() {
+    let _guard = TestScopeGuard::new();
     let mut store = KeyValueStore::<scope::User>::new().unwrap();
 
     // Test a representative sample of primitive types
     store.store("user_bool", true).unwrap();
     store.store("user_i32", 42i32).unwrap();
     store.store("user_u64", 1234567890u64).unwrap();
-    store.store("user_f64", 3.14159f64).unwrap();
+    store.store("user_f64", std::f64::consts::PI).unwrap();
     store.store("user_char", '🔥').unwrap();
 
     assert_eq!(
@@ -385,7 +695,7 @@ fn user_scope_handles_primitive_types() {
     );
     assert_eq!(
         store.retrieve::<&str, f64>("user_f64").unwrap(),
-        Some(3.14159f64)
+        Some(std::f64::consts::PI)
     );
     assert_eq!(
         store.retrieve::<&str, char>("user_char").unwrap(),
@@ -404,6 +714,7 @@ fn user_scope_handles_primitive_types() {
 /// and special characters in keys.
 #[test]
 fn user_scope_handles_binary_and_edge_cases() {
+    let _guard = TestScopeGuard::new();
     let mut store = KeyValueStore::<scope::User>::new().unwrap();
 
     // Test binary data with null bytes
@@ -434,6 +745,7 @@ fn user_scope_handles_binary_and_edge_cases() {
 /// Test user scope key operations (overwrite, remove, list).
 #[test]
 fn user_scope_key_operations() {
+    let _guard = TestScopeGuard::new();
     let mut store = KeyValueStore::<scope::User>::new().unwrap();
 
     // Test overwriting
@@ -473,6 +785,7 @@ fn user_scope_key_operations() {
 /// Verifies that user scope properly handles Unicode data
 #[test]
 fn user_scope_handles_unicode() {
+    let _guard = TestScopeGuard::new();
     let mut store = KeyValueStore::<scope::User>::new().unwrap();
 
     let unicode_strings = [
@@ -508,6 +821,7 @@ fn user_scope_handles_unicode() {
 /// multiple store, retrieve, and remove operations.
 #[test]
 fn user_scope_data_consistency() {
+    let _guard = TestScopeGuard::new();
     let mut store = KeyValueStore::<scope::User>::new().unwrap();
 
     // Perform multiple operations to test consistency
@@ -571,6 +885,7 @@ fn user_scope_data_consistency() {
 /// and that operations on one scope don't affect the other.
 #[test]
 fn storage_scopes_are_independent() {
+    let _guard = TestScopeGuard::new();
     let mut ephemeral_store = KeyValueStore::<scope::Ephemeral>::new().unwrap();
     let mut user_store = KeyValueStore::<scope::User>::new().unwrap();
 
@@ -633,3 +948,58 @@ fn storage_scopes_are_independent() {
     user_store.remove("scope_test").unwrap();
     user_store.remove("user_only").unwrap();
 }
+
+/// Verifies that stores with type tags enabled report type mismatches
+/// and still allow retrieval through compatible types.
+#[test]
+fn type_tags_detect_mismatched_types() {
+    let mut store = KeyValueStore::<scope::Ephemeral>::new()
+        .unwrap()
+        .with_type_tags();
+    store.store("name", "alice").unwrap();
+    store.store("count", 42u32).unwrap();
+    store.store("data", [1u8, 2u8].as_slice()).unwrap();
+
+    assert_eq!(store.retrieve("name").unwrap(), Some(String::from("alice")));
+    assert_eq!(store.retrieve("count").unwrap(), Some(42u32));
+    assert_eq!(store.retrieve("data").unwrap(), Some([1u8, 2u8]));
+
+    match store.retrieve::<_, u32>("name") {
+        Err(crate::error::KvsError::TypeMismatch { expected, found }) => {
+            assert_eq!(expected, "u32");
+            assert_eq!(found, "String");
+        }
+        _ => panic!("Expected TypeMismatch"),
+    }
+    assert!(store.retrieve::<_, i32>("count").is_err());
+}
+
+/// Verifies that the `ephemeral-scopes` feature makes persistent scopes
+/// in-memory, so nothing survives between store instances.
+#[test]
+#[cfg(feature = "ephemeral-scopes")]
+fn ephemeral_scopes_do_not_persist() {
+    {
+        let mut user = KeyValueStore::<scope::User>::new().unwrap();
+        user.store("key", "value").unwrap();
+        let mut machine = KeyValueStore::<scope::Machine>::new().unwrap();
+        machine.store("key", "value").unwrap();
+    }
+    let user = KeyValueStore::<scope::User>::new().unwrap();
+    assert_eq!(user.retrieve::<_, String>("key").unwrap(), None);
+    let machine = KeyValueStore::<scope::Machine>::new().unwrap();
+    assert_eq!(machine.retrieve::<_, String>("key").unwrap(), None);
+}
+
+/// Tests that the process-wide user store is shared between threads. Only
+/// run with in-memory scopes, since the shared store outlives any
+/// `TestScopeGuard`.
+#[test]
+#[cfg(feature = "ephemeral-scopes")]
+fn shared_user_store_is_shared_between_threads() {
+    std::thread::spawn(|| crate::user_store().unwrap().store("shared", 7u32).unwrap())
+        .join()
+        .unwrap();
+    let value: Option<u32> = crate::user_store().unwrap().retrieve("shared").unwrap();
+    assert_eq!(value, Some(7));
+}
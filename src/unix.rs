@@ -0,0 +1,266 @@
+//! Storage on behalf of other users on Unix systems.
+//!
+//! Privileged processes, such as programs run with `sudo`, have their own
+//! home directory. This module resolves other users' home directories
+//! through the passwd database so that their User-scoped stores can be
+//! opened and written with the correct ownership.
+
+use std::env;
+use std::ffi::{CStr, CString, OsStr};
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::ptr;
+
+use crate::api::KeyValueStore;
+use crate::api::scope::User;
+use crate::directory::DirectoryStore;
+use crate::error::KvsError;
+#[cfg(target_os = "linux")]
+use crate::linux::user_data_dir;
+#[cfg(target_os = "macos")]
+use crate::macos::user_data_dir;
+
+/// A user account from the passwd database.
+#[derive(Debug)]
+pub(crate) struct Account {
+    pub(crate) uid: u32,
+    pub(crate) gid: u32,
+    pub(crate) home: PathBuf,
+}
+
+impl Account {
+    /// Looks up the account with the given user name, returning `None` if
+    /// there is no such user.
+    pub(crate) fn by_name(name: &str) -> io::Result<Option<Account>> {
+        let name = CString::new(name).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        let mut buffer = vec![0; 1024];
+        loop {
+            let mut entry = MaybeUninit::<libc::passwd>::uninit();
+            let mut found = ptr::null_mut();
+            // SAFETY: All pointers are valid for the duration of the call,
+            // and `buffer.len()` is the size of `buffer`.
+            let rc = unsafe {
+                libc::getpwnam_r(
+                    name.as_ptr(),
+                    entry.as_mut_ptr(),
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                    &mut found,
+                )
+            };
+            match rc {
+                libc::ERANGE => buffer.resize(buffer.len() * 2, 0),
+                0 if found.is_null() => return Ok(None),
+                0 => {
+                    // SAFETY: getpwnam_r succeeded, so `entry` is initialized
+                    // and its strings point into `buffer`.
+                    let entry = unsafe { entry.assume_init() };
+                    let home = unsafe { CStr::from_ptr(entry.pw_dir) };
+                    return Ok(Some(Account {
+                        uid: entry.pw_uid,
+                        gid: entry.pw_gid,
+                        home: PathBuf::from(OsStr::from_bytes(home.to_bytes())),
+                    }));
+                }
+                rc => return Err(io::Error::from_raw_os_error(rc)),
+            }
+        }
+    }
+}
+
+impl KeyValueStore<User> {
+    /// Opens the User-scoped store of the user who invoked `sudo`.
+    ///
+    /// Under `sudo`, `$HOME` usually refers to root's home directory, so
+    /// [`KeyValueStore::new`] would silently store "user" data for root.
+    /// When running as root with `SUDO_USER` set, this opens that user's
+    /// store instead, resolving their home directory through the passwd
+    /// database, and gives everything it writes to that user. Otherwise it
+    /// behaves exactly like [`KeyValueStore::new`].
+    ///
+    /// On Linux the invoking user's `XDG_DATA_HOME` cannot be known, so
+    /// their store is always opened under `~/.local/share`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoUserScope` if the invoking user cannot be found in the
+    /// passwd database, or for the same reasons as [`KeyValueStore::new`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::User>::for_invoking_user()?;
+    /// store.store("installed", true)?;
+    /// # Ok::<(), zep_kvs::error::KvsError>(())
+    /// ```
+    pub fn for_invoking_user() -> Result<Self, KvsError> {
+        // SAFETY: geteuid has no preconditions and cannot fail.
+        let root = unsafe { libc::geteuid() } == 0;
+        match env::var("SUDO_USER") {
+            Ok(name) if root && name != "root" => Self::for_user(&name),
+            _ => Self::new(),
+        }
+    }
+
+    /// Opens the User-scoped store of the named user.
+    ///
+    /// This lets root daemons and system installers write into a specific
+    /// user's store, for example to pre-seed per-user defaults. The user's
+    /// home directory is resolved through the passwd database, and the
+    /// store is read and written with that user's credentials, so that
+    /// everything created belongs to them and their primary group and they
+    /// can later modify the store themselves. Links the user has planted in
+    /// their home directory are followed with only their permissions.
+    ///
+    /// On Linux the user's `XDG_DATA_HOME` cannot be known, so the store is
+    /// always opened under `~/.local/share`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoUserScope` if:
+    /// - The user cannot be found in the passwd database
+    /// - The process lacks permission to act as the user, which normally
+    ///   requires root privileges, or the user lacks permission to create
+    ///   the store
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use zep_kvs::prelude::*;
+    ///
+    /// for user in ["alice", "bob"] {
+    ///     let mut store = KeyValueStore::<scope::User>::for_user(user)?;
+    ///     store.store("telemetry", false)?;
+    /// }
+    /// # Ok::<(), zep_kvs::error::KvsError>(())
+    /// ```
+    pub fn for_user(name: &str) -> Result<Self, KvsError> {
+        let account = Account::by_name(name)
+            .map_err(|e| KvsError::NoUserScope {
+                reason: format!("cannot look up user {name:?}"),
+                source: Some(e),
+            })?
+            .ok_or_else(|| KvsError::NoUserScope {
+                reason: format!("no such user {name:?}"),
+                source: None,
+            })?;
+        let store = DirectoryStore::with_owner(
+            user_data_dir(&account.home),
+            Some((account.uid, account.gid)),
+        )
+        .map_err(KvsError::no_user_scope)?;
+        KeyValueStore::with_backing_store(store)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_account_by_name() {
+        let root = Account::by_name("root").unwrap().unwrap();
+        assert_eq!(root.uid, 0);
+        assert!(root.home.is_absolute());
+        assert!(Account::by_name("zep-kvs-no-such-user").unwrap().is_none());
+        assert!(Account::by_name("nul\0").is_err());
+    }
+
+    #[test]
+    fn test_owned_store_sets_file_ownership() {
+        use crate::api::BackingStore;
+        use crate::testing::TestScopeGuard;
+        use std::os::unix::fs::MetadataExt;
+
+        let guard = TestScopeGuard::new();
+        // SAFETY: getuid and getgid have no preconditions and cannot fail.
+        let owner = unsafe { (libc::getuid(), libc::getgid()) };
+        let mut store = DirectoryStore::with_owner(guard.path().join("home"), Some(owner)).unwrap();
+        store.store("key", b"value").unwrap();
+
+        let file = guard
+            .path()
+            .join("home")
+            .join(env!("CARGO_PKG_NAME"))
+            .join(env!("ZEP_KVS_APP_NAME"))
+            .join("key");
+        let metadata = std::fs::metadata(file).unwrap();
+        assert_eq!((metadata.uid(), metadata.gid()), owner);
+    }
+
+    #[test]
+    fn test_owned_store_is_watched_in_place() {
+        use crate::api::scope;
+        use crate::testing::TestScopeGuard;
+        use crate::watch::{ChangeEvent, ChangeKind, WatchOptions};
+        use std::time::Duration;
+
+        let guard = TestScopeGuard::new();
+        // SAFETY: getuid and getgid have no preconditions and cannot fail.
+        let owner = unsafe { (libc::getuid(), libc::getgid()) };
+        let inner = DirectoryStore::with_owner(guard.path().join("home"), Some(owner)).unwrap();
+        let mut store = KeyValueStore::<scope::User>::with_backing_store(inner).unwrap();
+        let watcher = store
+            .watch_with(WatchOptions {
+                interval: Duration::from_millis(10),
+                ..WatchOptions::default()
+            })
+            .unwrap();
+
+        // The caller's own store is a different one
+        let mut own = KeyValueStore::<scope::User>::new().unwrap();
+        own.store("theme", "light").unwrap();
+        store.store("telemetry", false).unwrap();
+        assert_eq!(
+            watcher.recv_timeout(Duration::from_secs(5)),
+            Some(ChangeEvent {
+                key: String::from("telemetry"),
+                kind: ChangeKind::Stored,
+            })
+        );
+        assert_eq!(watcher.recv_timeout(Duration::from_millis(100)), None);
+    }
+
+    #[test]
+    fn test_owned_store_does_not_follow_links_as_root() {
+        use crate::api::BackingStore;
+        use crate::testing::TestScopeGuard;
+        use std::fs;
+        use std::os::unix::fs::{MetadataExt, chown, symlink};
+
+        let Some(nobody) = Account::by_name("nobody").unwrap() else {
+            return;
+        };
+        // SAFETY: geteuid has no preconditions and cannot fail.
+        if unsafe { libc::geteuid() } != 0 {
+            return; // Only root can act as another user
+        }
+        let guard = TestScopeGuard::new();
+        let home = guard.path().join("home");
+        let victim = guard.path().join("victim");
+        fs::create_dir_all(home.join(env!("CARGO_PKG_NAME"))).unwrap();
+        fs::create_dir_all(&victim).unwrap();
+        for dir in [home.clone(), home.join(env!("CARGO_PKG_NAME"))] {
+            chown(dir, Some(nobody.uid), Some(nobody.gid)).unwrap();
+        }
+        // The user points their store at a directory only root can write
+        symlink(
+            &victim,
+            home.join(env!("CARGO_PKG_NAME"))
+                .join(env!("ZEP_KVS_APP_NAME")),
+        )
+        .unwrap();
+
+        let owner = Some((nobody.uid, nobody.gid));
+        if let Ok(mut store) = DirectoryStore::with_owner(home, owner) {
+            assert!(store.store("key", b"value").is_err());
+        }
+        let metadata = fs::metadata(&victim).unwrap();
+        assert_eq!(metadata.uid(), 0);
+        assert_eq!(fs::read_dir(&victim).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,576 @@
+//! Watching a store for changes.
+//!
+//! A [`Watcher`] reports keys stored or removed by any instance of a store,
+//! including instances in other processes, as [`ChangeEvent`]s. It polls a
+//! second handle on the store's data on a background thread, comparing the
+//! values of all keys with those seen by the previous poll, so it works the
+//! same way on every backend. Polling reads every value, which is cheap for
+//! the small stores this library is designed for.
+//!
+//! Values are compared as the backend holds them, so in an encrypted store
+//! a value rewritten unchanged is reported too, having been sealed afresh.
+//!
+//! Ephemeral stores are private to their instance, so watching one never
+//! reports changes.
+//!
+//! Backends with a change notification channel also wake watchers as soon
+//! as another instance writes, so changes are reported without waiting for
+//! the next poll. Directory stores signal watchers through Unix domain
+//! sockets in the store directory, and the registry notifies watchers of
+//! changes to the store's key.
+//!
+//! With the `tokio` feature, [`AsyncWatcher`] delivers the same events as
+//! a `futures_core::Stream`.
+
+use std::collections::BTreeMap;
+use std::hash::{DefaultHasher, Hasher};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::api::{BackingStore, KeyValueStore, Scope, is_reserved};
+use crate::error::KvsError;
+
+impl<S: Scope> KeyValueStore<S>
+where
+    S::Store: Send + 'static,
+{
+    /// Watches the store for changes, checking twice a second.
+    ///
+    /// See [`watch_with`](Self::watch_with).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be opened or read, or the
+    /// watcher thread cannot be started.
+    pub fn watch(&self) -> Result<Watcher, KvsError> {
+        self.watch_with(WatchOptions::default())
+    }
+
+    /// Watches a single key for changes, checking twice a second.
+    ///
+    /// Only the key itself is read when checking, and only its changes are
+    /// reported, so settings screens can follow the values they show
+    /// without examining the whole store. See [`watch_with`](Self::watch_with).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be opened or read, or the
+    /// watcher thread cannot be started.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let store = KeyValueStore::<scope::User>::new()?;
+    /// for _ in store.watch_key("theme")? {
+    ///     let theme: Option<String> = store.retrieve("theme")?;
+    ///     println!("theme is now {theme:?}");
+    /// }
+    /// # Ok::<(), zep_kvs::error::KvsError>(())
+    /// ```
+    pub fn watch_key<K: AsRef<str>>(&self, key: K) -> Result<Watcher, KvsError> {
+        self.watch_with(WatchOptions {
+            keys: KeyFilter::Key(key.as_ref().to_string()),
+            ..WatchOptions::default()
+        })
+    }
+
+    /// Watches the keys starting with `prefix` for changes, checking twice
+    /// a second.
+    ///
+    /// Only matching keys are listed and read when checking. See
+    /// [`watch_with`](Self::watch_with).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be opened or read, or the
+    /// watcher thread cannot be started.
+    pub fn watch_prefix<P: AsRef<str>>(&self, prefix: P) -> Result<Watcher, KvsError> {
+        self.watch_with(WatchOptions {
+            keys: KeyFilter::Prefix(prefix.as_ref().to_string()),
+            ..WatchOptions::default()
+        })
+    }
+
+    /// Watches the store for changes made by any instance of it, including
+    /// instances in other processes.
+    ///
+    /// The watcher opens a handle of its own on the same data, so changes
+    /// made through this instance are reported like any other. Changes made after this
+    /// method returns are reported, each as soon as the next poll notices
+    /// it. A key changed several times between two polls is reported once.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be opened or read, or the
+    /// watcher thread cannot be started.
+    pub fn watch_with(&self, options: WatchOptions) -> Result<Watcher, KvsError> {
+        match self.backing_store().reopen()? {
+            Some(store) => Watcher::spawn(store, options),
+            None => Watcher::spawn(S::new()?, options),
+        }
+    }
+
+    /// Watches the store for changes, delivering them as an asynchronous
+    /// stream.
+    ///
+    /// Behaves exactly like [`watch_with`](Self::watch_with). Polling runs
+    /// on a thread of its own, so no async runtime is required.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be opened or read, or the
+    /// watcher thread cannot be started.
+    #[cfg(feature = "tokio")]
+    pub fn watch_async(&self, options: WatchOptions) -> Result<AsyncWatcher, KvsError> {
+        match self.backing_store().reopen()? {
+            Some(store) => AsyncWatcher::spawn(store, options),
+            None => AsyncWatcher::spawn(S::new()?, options),
+        }
+    }
+}
+
+/// How a key changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChangeKind {
+    /// The key was created or given a new value.
+    Stored,
+    /// The key was removed.
+    Removed,
+}
+
+/// A change to a key observed by a watcher.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ChangeEvent {
+    /// The key that changed.
+    pub key: String,
+    /// How the key changed.
+    pub kind: ChangeKind,
+}
+
+/// Wakes a watcher when another instance of its store signals a change.
+///
+/// Returned by `BackingStore::listen` on backends with a change
+/// notification channel. Watchers still poll at their interval, so a
+/// missed signal only delays an event.
+pub trait ChangeListener: Send {
+    /// Waits up to `timeout` for a change signal, returning whether one
+    /// arrived.
+    fn wait(&mut self, timeout: Duration) -> bool;
+}
+
+/// Which keys a watcher reports changes to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum KeyFilter {
+    /// Every key in the store.
+    #[default]
+    All,
+    /// A single key.
+    Key(String),
+    /// The keys starting with a prefix.
+    Prefix(String),
+}
+
+/// How a store is watched.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use zep_kvs::watch::{KeyFilter, WatchOptions};
+///
+/// let options = WatchOptions {
+///     interval: Duration::from_millis(100),
+///     keys: KeyFilter::Prefix(String::from("window/")),
+///     debounce: Some(Duration::from_millis(250)),
+/// };
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchOptions {
+    /// How often the store is checked for changes.
+    pub interval: Duration,
+    /// Which keys to report changes to. Only these keys are read when the
+    /// store is checked, so watching a single key stays cheap however
+    /// large the store is.
+    pub keys: KeyFilter,
+    /// How long the store must go without changes before they are
+    /// reported, or `None` to report changes as soon as they are seen.
+    ///
+    /// A burst of writes, such as saving a whole settings page, is then
+    /// reported as one event per key, describing its final state. A key
+    /// created and removed again within the burst is not reported at all.
+    /// Changes are held for as long as writes continue.
+    pub debounce: Option<Duration>,
+}
+
+impl Default for WatchOptions {
+    /// Checks every key for changes every 500 milliseconds, reporting them
+    /// without debouncing.
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(500),
+            keys: KeyFilter::All,
+            debounce: None,
+        }
+    }
+}
+
+/// Receives the changes made to a store.
+///
+/// Created by `KeyValueStore::watch` and `KeyValueStore::watch_with`.
+/// Iterating over a watcher blocks until the next change. Dropping the
+/// watcher stops its background thread.
+///
+/// # Examples
+///
+/// ```no_run
+/// use zep_kvs::prelude::*;
+///
+/// let store = KeyValueStore::<scope::User>::new()?;
+/// for event in store.watch()? {
+///     println!("{} was {:?}", event.key, event.kind);
+/// }
+/// # Ok::<(), zep_kvs::error::KvsError>(())
+/// ```
+#[derive(Debug)]
+pub struct Watcher {
+    events: Receiver<ChangeEvent>,
+    _poller: Poller,
+}
+
+impl Watcher {
+    /// Starts watching `store`, which should be an instance of its own.
+    pub(crate) fn spawn<B: BackingStore + Send + 'static>(
+        store: B,
+        options: WatchOptions,
+    ) -> Result<Self, KvsError> {
+        let (sender, events) = mpsc::channel();
+        let poller = Poller::spawn(store, options, move |event| sender.send(event).is_ok())?;
+        Ok(Self {
+            events,
+            _poller: poller,
+        })
+    }
+
+    /// Returns the next change, if one has already been observed.
+    pub fn try_recv(&self) -> Option<ChangeEvent> {
+        self.events.try_recv().ok()
+    }
+
+    /// Waits up to `timeout` for the next change.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<ChangeEvent> {
+        self.events.recv_timeout(timeout).ok()
+    }
+}
+
+impl Iterator for Watcher {
+    type Item = ChangeEvent;
+
+    /// Waits for the next change.
+    fn next(&mut self) -> Option<ChangeEvent> {
+        self.events.recv().ok()
+    }
+}
+
+/// Receives the changes made to a store as an asynchronous stream.
+///
+/// Created by `KeyValueStore::watch_async`. Dropping the watcher stops its
+/// background thread.
+///
+/// # Examples
+///
+/// ```no_run
+/// use futures::StreamExt;
+/// use zep_kvs::prelude::*;
+/// use zep_kvs::watch::WatchOptions;
+///
+/// # async fn run() -> Result<(), zep_kvs::error::KvsError> {
+/// let store = KeyValueStore::<scope::User>::new()?;
+/// let mut watcher = store.watch_async(WatchOptions::default())?;
+/// while let Some(event) = watcher.next().await {
+///     println!("{} was {:?}", event.key, event.kind);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct AsyncWatcher {
+    events: tokio::sync::mpsc::UnboundedReceiver<ChangeEvent>,
+    _poller: Poller,
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncWatcher {
+    /// Starts watching `store`, which should be an instance of its own.
+    pub(crate) fn spawn<B: BackingStore + Send + 'static>(
+        store: B,
+        options: WatchOptions,
+    ) -> Result<Self, KvsError> {
+        let (sender, events) = tokio::sync::mpsc::unbounded_channel();
+        let poller = Poller::spawn(store, options, move |event| sender.send(event).is_ok())?;
+        Ok(Self {
+            events,
+            _poller: poller,
+        })
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl futures_core::Stream for AsyncWatcher {
+    type Item = ChangeEvent;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<ChangeEvent>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+/// Handle to the background thread polling a store.
+///
+/// Dropping it tells the thread to stop without waiting for it, so
+/// dropping a watcher never blocks on a poll in progress.
+#[derive(Debug)]
+struct Poller {
+    _stop: Sender<()>,
+}
+
+impl Poller {
+    /// Records the current state of `store`, then polls it on a new thread,
+    /// passing each change to `deliver` until it returns false or the
+    /// poller is dropped.
+    fn spawn<B, F>(store: B, options: WatchOptions, mut deliver: F) -> Result<Self, KvsError>
+    where
+        B: BackingStore + Send + 'static,
+        F: FnMut(ChangeEvent) -> bool + Send + 'static,
+    {
+        // Listen before reading the store, so no change can be missed
+        let mut listener = store.listen()?;
+        // The state last reported, and the state found by the last poll
+        let mut delivered = fingerprints(&store, &options.keys)?;
+        let mut seen = delivered.clone();
+        let mut last_change = Instant::now();
+        let (stop, stopped) = mpsc::channel::<()>();
+        let poll = move || {
+            loop {
+                // Poll early when another instance signals a change
+                match &mut listener {
+                    Some(listener) => {
+                        listener.wait(options.interval);
+                    }
+                    None => {
+                        let _ = stopped.recv_timeout(options.interval);
+                    }
+                }
+                if !matches!(stopped.try_recv(), Err(TryRecvError::Empty)) {
+                    return;
+                }
+                let current = match fingerprints(&store, &options.keys) {
+                    Ok(current) => current,
+                    Err(e) => {
+                        warn!("failed to poll store for changes: {e}");
+                        continue;
+                    }
+                };
+                if current != seen {
+                    last_change = Instant::now();
+                    seen = current;
+                }
+                let settled = options
+                    .debounce
+                    .is_none_or(|debounce| last_change.elapsed() >= debounce);
+                if !settled || seen == delivered {
+                    continue;
+                }
+                for event in changes(&delivered, &seen) {
+                    if !deliver(event) {
+                        return;
+                    }
+                }
+                delivered = seen.clone();
+            }
+        };
+        thread::Builder::new()
+            .name("zep-kvs-watch".to_string())
+            .spawn(poll)
+            .map_err(|e| KvsError::io_at(e, Path::new("<watcher thread>")))?;
+        Ok(Self { _stop: stop })
+    }
+}
+
+/// Returns a fingerprint of the value of every key in `store` selected by
+/// `filter`.
+fn fingerprints<B: BackingStore>(
+    store: &B,
+    filter: &KeyFilter,
+) -> Result<BTreeMap<String, u64>, KvsError> {
+    let keys = match filter {
+        KeyFilter::All => store.keys()?,
+        KeyFilter::Key(key) => vec![key.clone()],
+        KeyFilter::Prefix(prefix) => store.keys_with_prefix(prefix)?,
+    };
+    let mut fingerprints = BTreeMap::new();
+    for key in keys {
+        if is_reserved(&key) {
+            continue;
+        }
+        // Keys removed since they were listed are simply not recorded
+        if let Some(value) = store.retrieve(&key)? {
+            let mut hasher = DefaultHasher::new();
+            hasher.write(&value);
+            fingerprints.insert(key, hasher.finish());
+        }
+    }
+    Ok(fingerprints)
+}
+
+/// Lists the changes between two sets of fingerprints, in key order.
+fn changes(before: &BTreeMap<String, u64>, after: &BTreeMap<String, u64>) -> Vec<ChangeEvent> {
+    let stored = after
+        .iter()
+        .filter(|(key, value)| before.get(*key) != Some(value))
+        .map(|(key, _)| ChangeEvent {
+            key: key.clone(),
+            kind: ChangeKind::Stored,
+        });
+    let removed = before
+        .keys()
+        .filter(|key| !after.contains_key(*key))
+        .map(|key| ChangeEvent {
+            key: key.clone(),
+            kind: ChangeKind::Removed,
+        });
+    let mut changes: Vec<ChangeEvent> = stored.chain(removed).collect();
+    changes.sort_by(|a, b| a.key.cmp(&b.key));
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockStore;
+
+    const OPTIONS: WatchOptions = WatchOptions {
+        interval: Duration::from_millis(10),
+        keys: KeyFilter::All,
+        debounce: None,
+    };
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn stored(key: &str) -> Option<ChangeEvent> {
+        Some(ChangeEvent {
+            key: key.to_string(),
+            kind: ChangeKind::Stored,
+        })
+    }
+
+    #[test]
+    fn test_watcher_reports_changes() {
+        let mut mock = MockStore::new();
+        mock.store("existing", b"1").unwrap();
+        let watcher = Watcher::spawn(mock.clone(), OPTIONS).unwrap();
+
+        mock.store("new", b"1").unwrap();
+        assert_eq!(watcher.recv_timeout(TIMEOUT), stored("new"));
+        mock.store("existing", b"2").unwrap();
+        assert_eq!(watcher.recv_timeout(TIMEOUT), stored("existing"));
+        // Rewriting the same value is not a change
+        mock.store("new", b"1").unwrap();
+        mock.remove("existing").unwrap();
+        assert_eq!(
+            watcher.recv_timeout(TIMEOUT),
+            Some(ChangeEvent {
+                key: "existing".to_string(),
+                kind: ChangeKind::Removed,
+            })
+        );
+        mock.store("__zep_internal", b"1").unwrap();
+        assert_eq!(watcher.recv_timeout(Duration::from_millis(100)), None);
+    }
+
+    #[test]
+    fn test_filtered_watcher_only_reports_matching_keys() {
+        let mut mock = MockStore::new();
+        let key = Watcher::spawn(
+            mock.clone(),
+            WatchOptions {
+                keys: KeyFilter::Key(String::from("theme")),
+                ..OPTIONS
+            },
+        )
+        .unwrap();
+        let prefix = Watcher::spawn(
+            mock.clone(),
+            WatchOptions {
+                keys: KeyFilter::Prefix(String::from("window/")),
+                ..OPTIONS
+            },
+        )
+        .unwrap();
+
+        mock.store("themes", b"1").unwrap();
+        mock.store("window/x", b"1").unwrap();
+        mock.store("theme", b"dark").unwrap();
+        assert_eq!(key.recv_timeout(TIMEOUT), stored("theme"));
+        assert_eq!(prefix.recv_timeout(TIMEOUT), stored("window/x"));
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(key.try_recv(), None);
+        assert_eq!(prefix.try_recv(), None);
+    }
+
+    #[test]
+    fn test_debounced_watcher_coalesces_bursts() {
+        let mut mock = MockStore::new();
+        mock.store("a", b"0").unwrap();
+        let watcher = Watcher::spawn(
+            mock.clone(),
+            WatchOptions {
+                debounce: Some(Duration::from_millis(200)),
+                ..OPTIONS
+            },
+        )
+        .unwrap();
+
+        for i in 1..=5u8 {
+            mock.store("a", &[i]).unwrap();
+            mock.store("temp", &[i]).unwrap();
+            thread::sleep(Duration::from_millis(20));
+        }
+        mock.remove("temp").unwrap();
+        mock.store("b", b"1").unwrap();
+        assert_eq!(watcher.recv_timeout(TIMEOUT), stored("a"));
+        assert_eq!(watcher.try_recv(), stored("b"));
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(watcher.try_recv(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "tokio")]
+    fn test_async_watcher_streams_changes() {
+        use futures_core::Stream;
+        use std::pin::Pin;
+        use std::task::{Context, Poll, Waker};
+
+        let mut mock = MockStore::new();
+        let mut watcher = AsyncWatcher::spawn(mock.clone(), OPTIONS).unwrap();
+        mock.store("key", b"1").unwrap();
+
+        let mut cx = Context::from_waker(Waker::noop());
+        let started = std::time::Instant::now();
+        let event = loop {
+            match Pin::new(&mut watcher).poll_next(&mut cx) {
+                Poll::Ready(event) => break event,
+                Poll::Pending if started.elapsed() < TIMEOUT => {
+                    thread::sleep(Duration::from_millis(5))
+                }
+                Poll::Pending => panic!("no event received"),
+            }
+        };
+        assert_eq!(event, stored("key"));
+    }
+}
@@ -5,16 +5,43 @@
 //! in registry keys under appropriate hives for user and machine scope.
 
 use winreg::RegKey;
-use winreg::enums::{HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE, KEY_SET_VALUE, RegType};
+use winreg::enums::{
+    HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE, HKEY_USERS, KEY_ALL_ACCESS, KEY_NOTIFY, KEY_SET_VALUE,
+    REG_OPTION_VOLATILE, RegType,
+};
 use winreg::reg_key::HKEY;
 use winreg::reg_value::RegValue;
 
-use crate::api::scope::{Machine, User};
-use crate::api::{BackingStore, Scope};
+use crate::api::scope::{Machine, Session, User};
+use crate::api::{BackingStore, KeyValueStore, Scope};
+use crate::dynamic::DynBackingStore;
 use crate::error::KvsError;
+use crate::sensitive::wipe;
+use crate::watch::ChangeListener;
 
-use std::io::ErrorKind;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::{self, ErrorKind};
 use std::path::PathBuf;
+use std::ptr;
+use std::thread;
+use std::time::Duration;
+
+use windows_sys::Win32::Foundation::{
+    CloseHandle, ERROR_SUCCESS, HANDLE, LocalFree, WAIT_OBJECT_0,
+};
+use windows_sys::Win32::Security::Authorization::ConvertSidToStringSidW;
+use windows_sys::Win32::Security::{
+    GetTokenInformation, TOKEN_QUERY, TOKEN_USER, TokenUser, TokenVirtualizationEnabled,
+};
+use windows_sys::Win32::System::Registry::{
+    REG_NOTIFY_CHANGE_LAST_SET, REG_NOTIFY_CHANGE_NAME, RegNotifyChangeKeyValue,
+};
+use windows_sys::Win32::System::RemoteDesktop::{WTSGetActiveConsoleSessionId, WTSQueryUserToken};
+use windows_sys::Win32::System::Threading::{
+    CreateEventW, GetCurrentProcess, GetCurrentThread, OpenProcessToken, OpenThreadToken,
+    WaitForSingleObject,
+};
 
 /// Windows Registry-based key-value store.
 ///
@@ -39,12 +66,26 @@ use std::path::PathBuf;
 /// All values are stored as `REG_BINARY` type to handle arbitrary byte data.
 /// This allows the store to handle any serializable data type consistently.
 pub struct RegistryStore {
-    /// The registry hive (HKEY_CURRENT_USER or HKEY_LOCAL_MACHINE)
+    /// The registry hive (HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE or HKEY_USERS)
     scope: HKEY,
     /// The registry path relative to the hive root
     path: PathBuf,
 }
 
+impl fmt::Debug for RegistryStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RegistryStore")
+            .field("path", &self.full_path())
+            .finish()
+    }
+}
+
+// SAFETY: `scope` is always one of the predefined root keys, which are
+// process-wide pseudo-handles that are valid on every thread and are never
+// closed. All other registry handles are opened and closed per operation.
+unsafe impl Send for RegistryStore {}
+unsafe impl Sync for RegistryStore {}
+
 impl RegistryStore {
     /// Creates a new registry store for the specified hive.
     ///
@@ -76,8 +117,14 @@ impl RegistryStore {
     /// # Ok::<(), zep_kvs::error::KvsError>(())
     /// ```
     pub(crate) fn new(scope: HKEY) -> Result<Self, KvsError> {
-        let path = PathBuf::new()
-            .join("Software")
+        Self::with_base(scope, PathBuf::from("Software"))
+    }
+
+    /// Creates a registry store under `base` instead of `Software`.
+    ///
+    /// Used to redirect stores to an isolated subkey during tests.
+    pub(crate) fn with_base(scope: HKEY, base: PathBuf) -> Result<Self, KvsError> {
+        let path = base
             .join(env!("CARGO_PKG_NAME"))
             .join(env!("ZEP_KVS_APP_NAME"));
         let result = Self { scope, path };
@@ -87,6 +134,24 @@ impl RegistryStore {
         Ok(result)
     }
 
+    /// Creates a registry store in a volatile `Session` subkey of the store
+    /// under `base`.
+    ///
+    /// The registry discards volatile keys when their hive is unloaded, so
+    /// the store's contents are lost when the user logs off or the machine
+    /// restarts.
+    pub(crate) fn volatile(scope: HKEY, base: PathBuf) -> Result<Self, KvsError> {
+        let parent = Self::with_base(scope, base)?;
+        let result = Self {
+            scope,
+            path: parent.path.join("Session"),
+        };
+        RegKey::predef(result.scope)
+            .create_subkey_with_options_flags(&result.path, REG_OPTION_VOLATILE, KEY_ALL_ACCESS)
+            .map_err(|e| KvsError::io_at(e, &result.full_path()))?;
+        Ok(result)
+    }
+
     /// Returns the full registry path for error reporting.
     ///
     /// Constructs a human-readable path string that includes the hive name
@@ -95,7 +160,8 @@ impl RegistryStore {
         match self.scope {
             HKEY_CURRENT_USER => PathBuf::from("winreg:HKEY_CURRENT_USER"),
             HKEY_LOCAL_MACHINE => PathBuf::from("winreg:HKEY_LOCAL_MACHINE"),
-            _ => unreachable!(),
+            HKEY_USERS => PathBuf::from("winreg:HKEY_USERS"),
+            _ => PathBuf::from("winreg:"),
         }
         .join(self.path.clone())
     }
@@ -195,6 +261,104 @@ impl BackingStore for RegistryStore {
         self.delete_value(key)
             .map_err(|e| KvsError::io_at(e, &self.full_path()))
     }
+
+    fn contains_many(&self, keys: &[&str]) -> Result<Vec<bool>, KvsError> {
+        // One enumeration of the values answers for every key
+        let existing: BTreeSet<String> = self.keys()?.into_iter().collect();
+        Ok(keys.iter().map(|key| existing.contains(*key)).collect())
+    }
+
+    fn remove_secure(&mut self, key: &str) -> Result<(), KvsError> {
+        let overwrite = || {
+            let Some(mut value) = self.get_value(key)? else {
+                return Ok(());
+            };
+            wipe(&mut value);
+            self.set_value(key, &value)
+        };
+        overwrite().map_err(|e| KvsError::io_at(e, &self.full_path()))?;
+        self.remove(key)
+    }
+
+    fn listen(&self) -> Result<Option<Box<dyn ChangeListener>>, KvsError> {
+        let key = RegKey::predef(self.scope)
+            .open_subkey_with_flags(&self.path, KEY_NOTIFY)
+            .map_err(|e| KvsError::io_at(e, &self.full_path()))?;
+        // SAFETY: creates an unnamed, unsignaled auto-reset event.
+        let event = unsafe { CreateEventW(ptr::null(), 0, 0, ptr::null()) };
+        if event.is_null() {
+            return Err(KvsError::io_at(
+                io::Error::last_os_error(),
+                &self.full_path(),
+            ));
+        }
+        Ok(Some(Box::new(RegistryListener {
+            key,
+            event,
+            armed: false,
+        })))
+    }
+
+    fn reopen(&self) -> Result<Option<DynBackingStore>, KvsError> {
+        Ok(Some(Box::new(Self {
+            scope: self.scope,
+            path: self.path.clone(),
+        })))
+    }
+}
+
+/// Receives notifications of changes to the values of a store's registry
+/// key, which every write to the store triggers.
+struct RegistryListener {
+    key: RegKey,
+    event: HANDLE,
+    /// Whether a notification is registered to signal `event`.
+    armed: bool,
+}
+
+// SAFETY: the event handle is owned by the listener and may be waited on
+// from any thread.
+unsafe impl Send for RegistryListener {}
+
+impl ChangeListener for RegistryListener {
+    fn wait(&mut self, timeout: Duration) -> bool {
+        if !self.armed {
+            // Registered from the waiting thread, since a notification is
+            // cancelled when the thread that registered it exits
+            // SAFETY: the key and event handles are valid for the call.
+            let status = unsafe {
+                RegNotifyChangeKeyValue(
+                    self.key.raw_handle() as _,
+                    0,
+                    REG_NOTIFY_CHANGE_NAME | REG_NOTIFY_CHANGE_LAST_SET,
+                    self.event,
+                    1,
+                )
+            };
+            if status != ERROR_SUCCESS {
+                debug!("failed to register for registry change notifications: {status}");
+                thread::sleep(timeout);
+                return false;
+            }
+            self.armed = true;
+        }
+        // Waiting `INFINITE` (`u32::MAX`) would never time out
+        let millis =
+            u32::try_from(timeout.as_millis()).map_or(u32::MAX - 1, |ms| ms.min(u32::MAX - 1));
+        // SAFETY: `event` is a valid event handle.
+        if unsafe { WaitForSingleObject(self.event, millis) } != WAIT_OBJECT_0 {
+            return false;
+        }
+        self.armed = false;
+        true
+    }
+}
+
+impl Drop for RegistryListener {
+    fn drop(&mut self) {
+        // SAFETY: `event` is owned by the listener and closed only here.
+        unsafe { CloseHandle(self.event) };
+    }
 }
 
 impl Scope for Machine {
@@ -222,9 +386,28 @@ impl Scope for Machine {
     /// Returns `NoMachineScope` if:
     /// - The process lacks permissions to create or write to registry keys in HKLM
     /// - Registry access is restricted by security policies
+    /// - Registry virtualization is enabled for the process, which would
+    ///   silently redirect its writes to a per-user `VirtualStore`
     /// - The registry operation fails for other reasons
     fn new() -> Result<Self::Store, KvsError> {
-        RegistryStore::new(HKEY_LOCAL_MACHINE)
+        #[cfg(any(test, feature = "testing"))]
+        if let Some(base) = crate::testing::redirected("machine") {
+            return RegistryStore::with_base(HKEY_CURRENT_USER, base)
+                .map_err(KvsError::no_machine_scope);
+        }
+        match virtualized() {
+            Ok(true) => {
+                return Err(KvsError::NoMachineScope {
+                    reason: "registry virtualization would redirect writes to the user's \
+                             VirtualStore; run elevated or with an application manifest"
+                        .to_string(),
+                    source: None,
+                });
+            }
+            Ok(false) => {}
+            Err(e) => debug!("cannot check for registry virtualization: {e}"),
+        }
+        RegistryStore::new(HKEY_LOCAL_MACHINE).map_err(KvsError::no_machine_scope)
     }
 }
 
@@ -259,6 +442,202 @@ impl Scope for User {
     /// - The user profile is corrupted or inaccessible
     /// - The registry operation fails for other reasons
     fn new() -> Result<Self::Store, KvsError> {
-        RegistryStore::new(HKEY_CURRENT_USER)
+        #[cfg(any(test, feature = "testing"))]
+        if let Some(base) = crate::testing::redirected("user") {
+            return RegistryStore::with_base(HKEY_CURRENT_USER, base)
+                .map_err(KvsError::no_user_scope);
+        }
+        RegistryStore::new(HKEY_CURRENT_USER).map_err(KvsError::no_user_scope)
     }
 }
+
+impl Scope for Session {
+    type Store = RegistryStore;
+
+    /// Creates a login session storage scope for Windows.
+    ///
+    /// Uses a volatile registry key, which Windows discards when the user
+    /// logs off or the machine restarts, under the `User` scope's key.
+    ///
+    /// # Storage Location
+    ///
+    /// Data is stored in:
+    /// `HKEY_CURRENT_USER\Software\{package_name}\{app_name}\Session\`
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoUserScope` if:
+    /// - Registry access fails due to security restrictions
+    /// - The registry operation fails for other reasons
+    fn new() -> Result<Self::Store, KvsError> {
+        #[cfg(any(test, feature = "testing"))]
+        if let Some(base) = crate::testing::redirected("session") {
+            return RegistryStore::volatile(HKEY_CURRENT_USER, base)
+                .map_err(KvsError::no_user_scope);
+        }
+        RegistryStore::volatile(HKEY_CURRENT_USER, PathBuf::from("Software"))
+            .map_err(KvsError::no_user_scope)
+    }
+}
+
+impl KeyValueStore<User> {
+    /// Opens the User-scoped store of the user with the given security
+    /// identifier, such as `S-1-5-21-...-1001`.
+    ///
+    /// The store is opened through `HKEY_USERS\{sid}`, which lets services
+    /// and installers running as another account, such as `SYSTEM`, read and
+    /// write the settings of a specific user. The data is the same as that
+    /// user sees through [`KeyValueStore::new`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoUserScope` if:
+    /// - `sid` is empty or contains a backslash
+    /// - The user's registry hive is not loaded, which is the case when the
+    ///   user is not logged on and their profile has not been loaded
+    /// - The process lacks permissions to access the user's hive
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use zep_kvs::prelude::*;
+    ///
+    /// let mut store = KeyValueStore::<scope::User>::for_user_sid("S-1-5-21-1-2-3-1001")?;
+    /// store.store("provisioned", true)?;
+    /// # Ok::<(), zep_kvs::error::KvsError>(())
+    /// ```
+    pub fn for_user_sid(sid: &str) -> Result<Self, KvsError> {
+        if sid.is_empty() || sid.contains('\\') {
+            return Err(KvsError::NoUserScope {
+                reason: format!("invalid security identifier {sid:?}"),
+                source: None,
+            });
+        }
+        let base = PathBuf::from(sid).join("Software");
+        let store = RegistryStore::with_base(HKEY_USERS, base).map_err(KvsError::no_user_scope)?;
+        KeyValueStore::with_backing_store(store)
+    }
+
+    /// Opens the User-scoped store of the user the current thread is
+    /// impersonating.
+    ///
+    /// While impersonating, `HKEY_CURRENT_USER` still refers to the
+    /// process's own account, typically the service account, so
+    /// [`KeyValueStore::new`] would open the wrong store.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoUserScope` if the current thread is not impersonating a
+    /// user, or for the reasons given for [`for_user_sid`](Self::for_user_sid).
+    pub fn for_impersonated_user() -> Result<Self, KvsError> {
+        let mut token = ptr::null_mut();
+        // SAFETY: The pseudo-handle of the current thread is always valid,
+        // and `token` receives a handle that `user_sid` closes.
+        let opened = unsafe { OpenThreadToken(GetCurrentThread(), TOKEN_QUERY, 1, &mut token) };
+        if opened == 0 {
+            return Err(KvsError::NoUserScope {
+                reason: "the current thread is not impersonating a user".to_string(),
+                source: Some(io::Error::last_os_error()),
+            });
+        }
+        Self::for_user_sid(&user_sid(token)?)
+    }
+
+    /// Opens the User-scoped store of the user logged on to the physical
+    /// console.
+    ///
+    /// This is intended for services that act on behalf of the interactive
+    /// user, and requires the process to run as `LocalSystem`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoUserScope` if no user is logged on to the console, if the
+    /// process does not run as `LocalSystem`, or for the reasons given for
+    /// [`for_user_sid`](Self::for_user_sid).
+    pub fn for_console_user() -> Result<Self, KvsError> {
+        // SAFETY: This function has no preconditions.
+        let session = unsafe { WTSGetActiveConsoleSessionId() };
+        let mut token = ptr::null_mut();
+        // SAFETY: `token` receives a handle that `user_sid` closes.
+        if session == u32::MAX || unsafe { WTSQueryUserToken(session, &mut token) } == 0 {
+            return Err(KvsError::NoUserScope {
+                reason: "no user is logged on to the console".to_string(),
+                source: Some(io::Error::last_os_error()),
+            });
+        }
+        Self::for_user_sid(&user_sid(token)?)
+    }
+}
+
+/// Returns whether registry virtualization is enabled for the current
+/// process.
+///
+/// Windows enables it for 32-bit interactive processes without an
+/// application manifest. Their writes to `HKEY_LOCAL_MACHINE\Software`
+/// that would be denied then succeed, but land in the user's
+/// `HKEY_CURRENT_USER\Software\Classes\VirtualStore` instead.
+fn virtualized() -> io::Result<bool> {
+    let mut token = ptr::null_mut();
+    // SAFETY: The pseudo-handle of the current process is always valid,
+    // and `token` receives a handle that is closed below.
+    if unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut enabled = 0u32;
+    let mut len = 0;
+    // SAFETY: `token` is a valid token handle opened with TOKEN_QUERY
+    // access, and this information class returns a single DWORD.
+    let queried = unsafe {
+        GetTokenInformation(
+            token,
+            TokenVirtualizationEnabled,
+            (&raw mut enabled).cast(),
+            size_of::<u32>() as u32,
+            &mut len,
+        )
+    };
+    let result = if queried == 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(enabled != 0)
+    };
+    // SAFETY: `token` is a valid handle owned by this function.
+    unsafe { CloseHandle(token) };
+    result
+}
+
+/// Returns the security identifier of the user of an access token, and
+/// closes the token.
+fn user_sid(token: HANDLE) -> Result<String, KvsError> {
+    let no_scope = |source: io::Error| KvsError::NoUserScope {
+        reason: "cannot determine the user's security identifier".to_string(),
+        source: Some(source),
+    };
+
+    // SAFETY: `token` is a valid token handle opened with TOKEN_QUERY
+    // access. The buffer is sized by the first call and aligned for
+    // TOKEN_USER, and the string allocated by ConvertSidToStringSidW is
+    // freed after it has been copied.
+    let result = unsafe {
+        let mut len = 0;
+        GetTokenInformation(token, TokenUser, ptr::null_mut(), 0, &mut len);
+        let mut buffer = vec![0u64; (len as usize).div_ceil(size_of::<u64>())];
+        if GetTokenInformation(token, TokenUser, buffer.as_mut_ptr().cast(), len, &mut len) == 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            let user = &*buffer.as_ptr().cast::<TOKEN_USER>();
+            let mut wide = ptr::null_mut();
+            if ConvertSidToStringSidW(user.User.Sid, &mut wide) == 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                let len = (0..).take_while(|&i| *wide.add(i) != 0).count();
+                let sid = String::from_utf16_lossy(std::slice::from_raw_parts(wide, len));
+                LocalFree(wide.cast());
+                Ok(sid)
+            }
+        }
+    };
+    // SAFETY: `token` is a valid handle owned by this function.
+    unsafe { CloseHandle(token) };
+    result.map_err(no_scope)
+}